@@ -0,0 +1,67 @@
+use std::{collections::HashSet, fs, path::Path, time::Instant};
+
+use libp2p::{Multiaddr, PeerId, relay};
+
+/// Peers permitted to hold reservations on a private relay. Plugged into the relay as a
+/// reservation rate limiter that never admits anyone outside the list, so unknown peers
+/// are denied with `RESOURCE_LIMIT_EXCEEDED` before a reservation is granted.
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist {
+    peers: HashSet<PeerId>
+}
+
+impl Allowlist {
+    /// Parses one peer id per line. Blank lines and lines starting with `#` are ignored.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let peers = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.parse::<PeerId>().map_err(|err| format!("Invalid peer id '{}' in allowlist: {}", line, err)))
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        Ok(Self { peers })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read allowlist {}: {}", path.display(), err))?;
+
+        Self::parse(&contents)
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains(peer_id)
+    }
+}
+
+impl relay::RateLimiter for Allowlist {
+    fn try_next(&mut self, peer: PeerId, _addr: &Multiaddr, _now: Instant) -> bool {
+        self.contains(&peer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libp2p::relay::RateLimiter;
+
+    #[test]
+    fn test_allowlisted_peer_is_accepted_and_others_rejected() {
+        let (allowed, stranger) = (PeerId::random(), PeerId::random());
+        let mut allowlist = Allowlist::parse(&format!("# friends\n{}\n\n", allowed)).expect("parse failed");
+        let addr = Multiaddr::empty();
+
+        assert_eq!(allowlist.len(), 1);
+        assert!(allowlist.try_next(allowed, &addr, Instant::now()));
+        assert!(!allowlist.try_next(stranger, &addr, Instant::now()));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_peer_ids() {
+        assert!(Allowlist::parse("not-a-peer-id").is_err());
+    }
+}
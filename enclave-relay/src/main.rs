@@ -1,11 +1,88 @@
-use std::{fs, path::Path};
+mod allowlist;
+mod metrics;
+
+use std::{fs, path::{Path, PathBuf}, time::Duration};
 
 use libp2p::{
     PeerId, SwarmBuilder, futures::StreamExt, identity, noise, relay, swarm::SwarmEvent, tcp, yamux
 };
 
+use allowlist::Allowlist;
+use metrics::{RelayMetrics, SUMMARY_INTERVAL};
+
+/// Caps on what a single relay will hand out, so a self-hosted relay can't be exhausted
+/// by one misbehaving peer. Defaults match `relay::Config::default()`.
+/// When `allowlist` is set, only the peers listed in that file may reserve a slot.
+#[derive(Debug, Clone, PartialEq)]
+struct RelayLimits {
+    max_reservations: usize,
+    max_circuits: usize,
+    max_circuits_per_peer: usize,
+    reservation_duration: Duration,
+    allowlist: Option<PathBuf>
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        let config = relay::Config::default();
+        Self {
+            max_reservations: config.max_reservations,
+            max_circuits: config.max_circuits,
+            max_circuits_per_peer: config.max_circuits_per_peer,
+            reservation_duration: config.reservation_duration,
+            allowlist: None
+        }
+    }
+}
+
+impl RelayLimits {
+    /// Parses `--max-reservations`, `--max-circuits`, `--max-circuits-per-peer`,
+    /// `--reservation-duration-secs` and `--allowlist`, falling back to the defaults for
+    /// anything omitted.
+    fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut limits = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("Missing value for {}", flag))?;
+
+            if flag == "--allowlist" {
+                limits.allowlist = Some(PathBuf::from(value));
+                continue;
+            }
+
+            let parsed = value.parse::<u64>().map_err(|err| format!("Invalid value for {}: {}", flag, err))?;
+
+            if parsed == 0 {
+                return Err(format!("{} must be greater than zero", flag));
+            }
+
+            match flag.as_str() {
+                "--max-reservations" => limits.max_reservations = parsed as usize,
+                "--max-circuits" => limits.max_circuits = parsed as usize,
+                "--max-circuits-per-peer" => limits.max_circuits_per_peer = parsed as usize,
+                "--reservation-duration-secs" => limits.reservation_duration = Duration::from_secs(parsed),
+                _ => return Err(format!("Unknown flag: {}", flag))
+            }
+        }
+
+        Ok(limits)
+    }
+
+    fn relay_config(&self) -> relay::Config {
+        relay::Config {
+            max_reservations: self.max_reservations,
+            max_circuits: self.max_circuits,
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            reservation_duration: self.reservation_duration,
+            ..Default::default()
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let limits = RelayLimits::from_args(std::env::args().skip(1))?;
     let key_file = "relay_key.bin";
 
     let local_key = if Path::new(key_file).exists() {
@@ -23,8 +100,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let local_peer_id = PeerId::from(local_key.public());
 
     println!("Relay Peer ID: {}", local_peer_id);
+    println!(
+        "Relay limits: {} reservations ({}s each), {} circuits, {} circuits per peer",
+        limits.max_reservations,
+        limits.reservation_duration.as_secs(),
+        limits.max_circuits,
+        limits.max_circuits_per_peer
+    );
 
-    let relay_behaviour = relay::Behaviour::new(local_peer_id, Default::default());
+    let mut relay_config = limits.relay_config();
+
+    if let Some(path) = &limits.allowlist {
+        let allowlist = Allowlist::load(path)?;
+        println!("Private relay: reservations restricted to {} allowlisted peers", allowlist.len());
+        relay_config.reservation_rate_limiters.push(Box::new(allowlist));
+    }
+
+    let relay_behaviour = relay::Behaviour::new(local_peer_id, relay_config);
 
     let mut swarm = SwarmBuilder::with_existing_identity(local_key)
         .with_tokio()
@@ -40,15 +132,85 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Relay server started");
 
+    let mut metrics = RelayMetrics::default();
+    let mut summary_interval = tokio::time::interval(SUMMARY_INTERVAL);
+
     loop {
-        match swarm.select_next_some().await {
-            SwarmEvent::NewListenAddr { address, .. } => {
-                println!("Listening on {}", address);
-            },
-            SwarmEvent::Behaviour(event) => {
-                println!("Relay event: {:?}", event);
+        tokio::select! {
+            _ = summary_interval.tick() => {
+                println!("Relay usage: {}", metrics);
             },
-            _ => {}
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::NewListenAddr { address, .. } => {
+                    println!("Listening on {}", address);
+                },
+                SwarmEvent::Behaviour(event) => {
+                    metrics.record(&event);
+
+                    match event {
+                        relay::Event::ReservationReqDenied { src_peer_id, status } => {
+                            println!("Reservation from {} denied ({:?}), peer is not allowlisted or a reservation limit was hit", src_peer_id, status);
+                        },
+                        relay::Event::CircuitReqDenied { src_peer_id, dst_peer_id, status } => {
+                            println!("Circuit from {} to {} denied ({:?}), circuit limit may have been hit", src_peer_id, dst_peer_id, status);
+                        },
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_limits_default_to_relay_config_defaults() {
+        let limits = RelayLimits::from_args(Vec::new()).expect("parse failed");
+        let config = limits.relay_config();
+        let default = relay::Config::default();
+
+        assert_eq!(config.max_reservations, default.max_reservations);
+        assert_eq!(config.max_circuits, default.max_circuits);
+        assert_eq!(config.max_circuits_per_peer, default.max_circuits_per_peer);
+        assert_eq!(config.reservation_duration, default.reservation_duration);
+    }
+
+    #[test]
+    fn test_custom_limits_are_applied_to_relay_behaviour() {
+        let limits = RelayLimits::from_args(args(&[
+            "--max-reservations", "8",
+            "--max-circuits", "6",
+            "--max-circuits-per-peer", "2",
+            "--reservation-duration-secs", "600",
+            "--allowlist", "friends.txt"
+        ])).expect("parse failed");
+
+        assert_eq!(limits.allowlist, Some(PathBuf::from("friends.txt")));
+
+        let config = limits.relay_config();
+        assert_eq!(config.max_reservations, 8);
+        assert_eq!(config.max_circuits, 6);
+        assert_eq!(config.max_circuits_per_peer, 2);
+        assert_eq!(config.reservation_duration, Duration::from_secs(600));
+        assert_eq!(config.max_circuit_bytes, relay::Config::default().max_circuit_bytes);
+
+        let _ = relay::Behaviour::new(PeerId::random(), config);
+    }
+
+    #[test]
+    fn test_invalid_flags_are_rejected() {
+        assert!(RelayLimits::from_args(args(&["--max-reservations"])).is_err());
+        assert!(RelayLimits::from_args(args(&["--max-reservations", "lots"])).is_err());
+        assert!(RelayLimits::from_args(args(&["--max-circuits-per-peer", "0"])).is_err());
+        assert!(RelayLimits::from_args(args(&["--max-friends", "4"])).is_err());
+        assert!(RelayLimits::from_args(args(&["--allowlist"])).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use std::{collections::HashSet, fmt, time::Duration};
+
+use libp2p::{PeerId, relay};
+
+pub const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Usage counters maintained from relay events and logged periodically.
+/// The relay behaviour doesn't report per-circuit traffic, so bytes relayed
+/// aren't tracked here.
+#[derive(Debug, Default)]
+pub struct RelayMetrics {
+    reservations: HashSet<PeerId>,
+    active_circuits: usize,
+    total_circuits: u64,
+    denied_reservations: u64,
+    denied_circuits: u64,
+    peers_served: HashSet<PeerId>
+}
+
+impl RelayMetrics {
+    pub fn record(&mut self, event: &relay::Event) {
+        match event {
+            relay::Event::ReservationReqAccepted { src_peer_id, .. } => {
+                self.reservations.insert(*src_peer_id);
+                self.peers_served.insert(*src_peer_id);
+            },
+            relay::Event::ReservationClosed { src_peer_id }
+            | relay::Event::ReservationTimedOut { src_peer_id } => {
+                self.reservations.remove(src_peer_id);
+            },
+            relay::Event::ReservationReqDenied { .. } => self.denied_reservations += 1,
+            relay::Event::CircuitReqAccepted { src_peer_id, dst_peer_id } => {
+                self.active_circuits += 1;
+                self.total_circuits += 1;
+                self.peers_served.insert(*src_peer_id);
+                self.peers_served.insert(*dst_peer_id);
+            },
+            relay::Event::CircuitClosed { .. } => {
+                self.active_circuits = self.active_circuits.saturating_sub(1);
+            },
+            relay::Event::CircuitReqDenied { .. } => self.denied_circuits += 1,
+            _ => {}
+        }
+    }
+
+    pub fn active_reservations(&self) -> usize {
+        self.reservations.len()
+    }
+
+    pub fn active_circuits(&self) -> usize {
+        self.active_circuits
+    }
+
+    pub fn unique_peers(&self) -> usize {
+        self.peers_served.len()
+    }
+}
+
+impl fmt::Display for RelayMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} active reservations, {} active circuits ({} total), {} unique peers served, {} reservations and {} circuits denied",
+            self.active_reservations(),
+            self.active_circuits(),
+            self.total_circuits,
+            self.unique_peers(),
+            self.denied_reservations,
+            self.denied_circuits
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libp2p::relay::StatusCode;
+
+    #[test]
+    fn test_reservations_are_counted_until_closed_or_timed_out() {
+        let mut metrics = RelayMetrics::default();
+        let (a, b) = (PeerId::random(), PeerId::random());
+
+        metrics.record(&relay::Event::ReservationReqAccepted { src_peer_id: a, renewed: false });
+        metrics.record(&relay::Event::ReservationReqAccepted { src_peer_id: a, renewed: true });
+        metrics.record(&relay::Event::ReservationReqAccepted { src_peer_id: b, renewed: false });
+        assert_eq!(metrics.active_reservations(), 2);
+
+        metrics.record(&relay::Event::ReservationClosed { src_peer_id: a });
+        metrics.record(&relay::Event::ReservationTimedOut { src_peer_id: b });
+        assert_eq!(metrics.active_reservations(), 0);
+        assert_eq!(metrics.unique_peers(), 2);
+    }
+
+    #[test]
+    fn test_circuits_track_active_total_and_denied() {
+        let mut metrics = RelayMetrics::default();
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        metrics.record(&relay::Event::CircuitReqAccepted { src_peer_id: a, dst_peer_id: b });
+        metrics.record(&relay::Event::CircuitReqAccepted { src_peer_id: c, dst_peer_id: b });
+        metrics.record(&relay::Event::CircuitReqDenied { src_peer_id: a, dst_peer_id: c, status: StatusCode::ResourceLimitExceeded });
+        metrics.record(&relay::Event::ReservationReqDenied { src_peer_id: c, status: StatusCode::ResourceLimitExceeded });
+        metrics.record(&relay::Event::CircuitClosed { src_peer_id: a, dst_peer_id: b, error: None });
+        metrics.record(&relay::Event::CircuitClosed { src_peer_id: a, dst_peer_id: b, error: None });
+        metrics.record(&relay::Event::CircuitClosed { src_peer_id: c, dst_peer_id: b, error: None });
+
+        assert_eq!(metrics.active_circuits(), 0);
+        assert_eq!(metrics.total_circuits, 2);
+        assert_eq!(metrics.denied_circuits, 1);
+        assert_eq!(metrics.denied_reservations, 1);
+        assert_eq!(metrics.unique_peers(), 3);
+    }
+}
@@ -1,3 +1,40 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() {
+    // Build metadata for the About screen. Builds outside a git checkout just omit the commit.
+    let git_commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    if let Some(git_commit) = git_commit {
+        println!("cargo:rustc-env=ENCLAVE_GIT_COMMIT={}", git_commit.trim());
+    }
+
+    if let Ok(build_time) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        println!("cargo:rustc-env=ENCLAVE_BUILD_TIMESTAMP={}", build_time.as_secs());
+    }
+
+    // A commit moves the branch HEAD points at rather than HEAD itself, so watch that ref too,
+    // and packed-refs for when the branch only lives there. Missing files aren't watched, since
+    // cargo would rerun the build script on every build for them.
+    let git_dir = Path::new("../../.git");
+    let mut watched = vec![git_dir.join("HEAD"), git_dir.join("packed-refs")];
+
+    if let Ok(head) = fs::read_to_string(git_dir.join("HEAD")) {
+        if let Some(reference) = head.trim().strip_prefix("ref: ") {
+            watched.push(git_dir.join(reference));
+        }
+    }
+
+    for path in watched.iter().filter(|path| path.exists()) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+
     tauri_build::build()
 }
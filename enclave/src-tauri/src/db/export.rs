@@ -0,0 +1,166 @@
+use rusqlite::{Connection, types::ValueRef};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+#[cfg(unix)]
+use std::{fs::OpenOptions, os::unix::fs::{OpenOptionsExt, PermissionsExt}};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// One file per kind of user-owned data, each holding a JSON array of rows keyed by column.
+const EXPORTS: [(&str, &str); 5] = [
+    ("friends.json", "SELECT tbl_users.peer_id, tbl_users.multiaddr, tbl_users.nickname, tbl_friends.created_at, tbl_friends.last_synch
+                      FROM tbl_friends
+                      INNER JOIN tbl_users ON tbl_users.id=tbl_friends.user_id
+                      ORDER BY tbl_friends.id ASC;"),
+    ("conversations.json", "SELECT from_peer_id, to_peer_id, content, created_at, edited_at, read, pending
+                            FROM tbl_direct_messages
+                            ORDER BY created_at ASC, id ASC;"),
+    ("posts.json", "SELECT content, created_at, edited_at
+                    FROM tbl_posts
+                    WHERE author_peer_id=(SELECT peer_id FROM tbl_identity)
+                    ORDER BY created_at ASC, id ASC;"),
+    ("settings.json", "SELECT key, value FROM tbl_settings ORDER BY key ASC;"),
+    ("block_list.json", "SELECT tbl_users.peer_id, tbl_blocked_users.blocked_at
+                         FROM tbl_blocked_users
+                         INNER JOIN tbl_users ON tbl_users.id=tbl_blocked_users.user_id
+                         ORDER BY tbl_blocked_users.blocked_at ASC, tbl_blocked_users.id ASC;")
+];
+
+const IDENTITY_EXPORT: (&str, &str) = ("identity.json", "SELECT keypair, peer_id, port_number, created_at FROM tbl_identity;");
+
+/// Writes the account's data to JSON files in `dest_dir`, returning the paths written.
+/// The identity file contains the private keypair, so it is only written when asked for, and
+/// only the owner can read it.
+pub fn export_account_data(db: Arc<Mutex<Connection>>, dest_dir: &Path, include_identity: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let exports = include_identity
+        .then_some(IDENTITY_EXPORT)
+        .into_iter()
+        .chain(EXPORTS);
+
+    // Read everything up front so the database isn't locked while the files are written.
+    let tables = {
+        let db_guard = db.lock()
+            .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+        exports
+            .map(|(file_name, sql)| Ok((file_name, export_query(&db_guard, sql)?)))
+            .collect::<anyhow::Result<Vec<(&str, Vec<serde_json::Value>)>>>()?
+    };
+
+    fs::create_dir_all(dest_dir)?;
+
+    let mut written = Vec::new();
+
+    for (file_name, rows) in tables {
+        let path = dest_dir.join(file_name);
+        write_export(&path, &rows, file_name == IDENTITY_EXPORT.0)?;
+        log::info!("Exported {} rows to {}", rows.len(), path.display());
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+fn export_query(db: &Connection, sql: &str) -> anyhow::Result<Vec<serde_json::Value>> {
+    let mut query = db.prepare(sql)?;
+    let columns = query.column_names().into_iter().map(String::from).collect::<Vec<String>>();
+
+    let mut rows = query.query(())?;
+    let mut objects = Vec::new();
+
+    while let Some(row) = rows.next()? {
+        let mut object = serde_json::Map::new();
+        for (index, column) in columns.iter().enumerate() {
+            object.insert(column.clone(), json_value(row.get_ref(index)?));
+        }
+
+        objects.push(serde_json::Value::Object(object));
+    }
+
+    Ok(objects)
+}
+
+fn write_export(path: &Path, rows: &[serde_json::Value], private: bool) -> anyhow::Result<()> {
+    let file = if private { create_private(path)? } else { File::create(path)? };
+
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, rows)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> std::io::Result<File> {
+    let file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+
+    // `mode` only applies when the file is created, so also tighten one left by an earlier export.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> std::io::Result<File> {
+    File::create(path)
+}
+
+fn json_value(value: ValueRef) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(integer) => integer.into(),
+        ValueRef::Real(real) => real.into(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).into(),
+        ValueRef::Blob(blob) => blob.to_vec().into()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::db;
+
+    fn row_count(path: &Path) -> usize {
+        let contents = fs::read_to_string(path).expect("export file missing");
+        serde_json::from_str::<Vec<serde_json::Value>>(&contents).expect("export is not a JSON array").len()
+    }
+
+    #[test]
+    pub fn test_export_account_data_writes_each_file() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let dest_dir = std::env::temp_dir().join(format!("enclave-export-{}", rand::random::<u64>()));
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let friend = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let blocked = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        db::create_identity(db.clone(), vec![1, 2, 3], identity.clone(), 4001).unwrap();
+        let friend_user_id = db::create_user(db.clone(), friend.clone(), "/ip4/127.0.0.1/tcp/4002".into(), false).unwrap();
+        db::create_friend(db.clone(), friend_user_id).unwrap();
+        db::create_direct_message(db.clone(), identity.clone(), friend.clone(), "Hi".into()).unwrap();
+        db::create_direct_message(db.clone(), friend.clone(), identity.clone(), "Hello".into()).unwrap();
+        db::create_post(db.clone(), identity.clone(), "Mine".into()).unwrap();
+        db::create_post(db.clone(), friend.clone(), "Theirs".into()).unwrap();
+        db::set_setting(db.clone(), "theme", "dark").unwrap();
+        db::block_peer_id(db.clone(), blocked).unwrap();
+
+        let written = export_account_data(db.clone(), &dest_dir, false).expect("export_account_data failed");
+
+        let expected = [("friends.json", 1), ("conversations.json", 2), ("posts.json", 1), ("settings.json", 1), ("block_list.json", 1)];
+        assert_eq!(written, expected.iter().map(|(file_name, _)| dest_dir.join(file_name)).collect::<Vec<_>>());
+
+        for (file_name, rows) in expected {
+            assert_eq!(row_count(&dest_dir.join(file_name)), rows, "{file_name}");
+        }
+        assert!(!dest_dir.join("identity.json").exists());
+
+        let written = export_account_data(db, &dest_dir, true).expect("export_account_data failed");
+        assert_eq!(written.len(), 6);
+        assert_eq!(row_count(&dest_dir.join("identity.json")), 1);
+
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(dest_dir.join("identity.json")).unwrap().permissions().mode() & 0o777, 0o600);
+
+        fs::remove_dir_all(dest_dir).unwrap();
+    }
+}
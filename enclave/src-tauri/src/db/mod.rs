@@ -1,11 +1,13 @@
 #![allow(dead_code)]
 
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
 
-use crate::db::models::{blocked_user::BlockedUser, direct_message::DirectMessage, friend::Friend, friend_request::FriendRequest, identity::Identity, post::Post, user::User};
+use crate::db::models::{blocked_user::BlockedUser, conversation_storage::ConversationStorage, conversation_summary::{ConversationSummary, DeliveryStatus}, dead_letter::DeadLetter, direct_message::{ContentType, DirectMessage}, draft::Draft, friend::Friend, friend_request::FriendRequest, friend_request_history::{FriendRequestHistoryEntry, FriendRequestOutcome}, identity::Identity, key_change::KeyChange, message_activity::MessageActivity, peer_appearance::PeerAppearance, post::Post, post_tombstone::PostTombstone, post_with_author::PostWithAuthor, quarantined_message::QuarantinedMessage, user::{User, UNKNOWN_MULTIADDR}, user_address::UserAddress};
 
+pub mod export;
 pub mod models;
 
 pub static DATABASE: once_cell::sync::Lazy<Arc<std::sync::Mutex<Connection>>> =
@@ -16,6 +18,12 @@ pub static DATABASE: once_cell::sync::Lazy<Arc<std::sync::Mutex<Connection>>> =
 pub fn init_db(path: &str) -> anyhow::Result<Arc<Mutex<Connection>>> {
     log::info!("Initilising database...");
 
+    // Connection::open creates the file but not a configured data dir that doesn't exist yet.
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| anyhow::anyhow!("Failed to create database directory {}: {}", parent.display(), err))?;
+    }
+
     let db = Connection::open(path)?;
     log::info!("Created enclave database.");
 
@@ -28,11 +36,23 @@ pub fn init_db(path: &str) -> anyhow::Result<Arc<Mutex<Connection>>> {
                             peer_id TEXT NOT NULL,
                             port_number INTEGER NOT NULL,
                             created_at INTEGER NOT NULL,
-                            last_login INTEGER NOT NULL
+                            last_login INTEGER NOT NULL,
+                            sync_enabled BOOLEAN DEFAULT 1,
+                            max_friends INTEGER
                         );", ())?;
         log::info!("Created identity table.");
     }
 
+    if !db.column_exists(None, "tbl_identity", "sync_enabled")? {
+        db.execute("ALTER TABLE tbl_identity ADD COLUMN sync_enabled BOOLEAN DEFAULT 1;", ())?;
+        log::info!("Added sync_enabled column to identity table.");
+    }
+
+    if !db.column_exists(None, "tbl_identity", "max_friends")? {
+        db.execute("ALTER TABLE tbl_identity ADD COLUMN max_friends INTEGER;", ())?;
+        log::info!("Added max_friends column to identity table.");
+    }
+
     if !db.table_exists(None, "tbl_users")? {
         db.execute("CREATE TABLE tbl_users (
                             id INTEGER PRIMARY KEY,
@@ -45,6 +65,22 @@ pub fn init_db(path: &str) -> anyhow::Result<Arc<Mutex<Connection>>> {
         log::info!("Created connections table.");
     }
 
+    if !db.column_exists(None, "tbl_users", "paused")? {
+        db.execute("ALTER TABLE tbl_users ADD COLUMN paused BOOLEAN DEFAULT 0;", ())?;
+        log::info!("Added paused column to users table.");
+    }
+
+    if !db.column_exists(None, "tbl_users", "avatar")? {
+        db.execute("ALTER TABLE tbl_users ADD COLUMN avatar TEXT;", ())?;
+        db.execute("ALTER TABLE tbl_users ADD COLUMN color TEXT;", ())?;
+        log::info!("Added appearance columns to users table.");
+    }
+
+    if !db.column_exists(None, "tbl_users", "alias")? {
+        db.execute("ALTER TABLE tbl_users ADD COLUMN alias TEXT;", ())?;
+        log::info!("Added alias column to users table.");
+    }
+
     if !db.table_exists(None, "tbl_friend_requests")? {
         db.execute("CREATE TABLE tbl_friend_requests (
                             id INTEGER PRIMARY KEY,
@@ -54,23 +90,49 @@ pub fn init_db(path: &str) -> anyhow::Result<Arc<Mutex<Connection>>> {
                             to_multiaddr TEXT NOT NULL,
                             message TEXT,
                             created_at INTEGER NOT NULL,
-                            pending BOOLEAN DEFAULT 1
+                            pending BOOLEAN DEFAULT 1,
+                            seen BOOLEAN DEFAULT 0
                         );", ())?;
         log::info!("Created friend requests table.");
     }
 
+    if !db.column_exists(None, "tbl_friend_requests", "seen")? {
+        db.execute("ALTER TABLE tbl_friend_requests ADD COLUMN seen BOOLEAN DEFAULT 0;", ())?;
+        log::info!("Added seen column to friend requests table.");
+    }
+
     if !db.table_exists(None, "tbl_friends")? {
         db.execute("CREATE TABLE tbl_friends (
                             id INTEGER PRIMARY KEY,
                             user_id INTEGER NOT NULL,
                             created_at INTEGER NOT NULL,
                             last_synch INTEGER NOT NULL,
-                            FOREIGN KEY (user_id) REFERENCES tbl_users(id),
+                            FOREIGN KEY (user_id) REFERENCES tbl_users(id) ON DELETE CASCADE,
                             UNIQUE(user_id)
                         );", ())?;
         log::info!("Created friends table.");
     }
 
+    if !user_foreign_key_cascades(&db, "tbl_friends")? {
+        db.execute_batch("PRAGMA foreign_keys = OFF;
+                          BEGIN;
+                          CREATE TABLE tbl_friends_migrated (
+                              id INTEGER PRIMARY KEY,
+                              user_id INTEGER NOT NULL,
+                              created_at INTEGER NOT NULL,
+                              last_synch INTEGER NOT NULL,
+                              FOREIGN KEY (user_id) REFERENCES tbl_users(id) ON DELETE CASCADE,
+                              UNIQUE(user_id)
+                          );
+                          INSERT INTO tbl_friends_migrated (id, user_id, created_at, last_synch)
+                              SELECT id, user_id, created_at, last_synch FROM tbl_friends;
+                          DROP TABLE tbl_friends;
+                          ALTER TABLE tbl_friends_migrated RENAME TO tbl_friends;
+                          COMMIT;
+                          PRAGMA foreign_keys = ON;")?;
+        log::info!("Added ON DELETE CASCADE to friends table.");
+    }
+
     if !db.table_exists(None, "tbl_direct_messages")? {
         db.execute("CREATE TABLE tbl_direct_messages (
                             id INTEGER PRIMARY KEY,
@@ -85,6 +147,11 @@ pub fn init_db(path: &str) -> anyhow::Result<Arc<Mutex<Connection>>> {
         log::info!("Created direct messages table.");
     }
 
+    if !db.column_exists(None, "tbl_direct_messages", "content_type")? {
+        db.execute("ALTER TABLE tbl_direct_messages ADD COLUMN content_type TEXT DEFAULT 'text';", ())?;
+        log::info!("Added content type column to direct messages table.");
+    }
+
     if !db.table_exists(None, "tbl_posts")? {
         db.execute("CREATE TABLE tbl_posts (
                             id INTEGER PRIMARY KEY,
@@ -101,38 +168,174 @@ pub fn init_db(path: &str) -> anyhow::Result<Arc<Mutex<Connection>>> {
                             id INTEGER PRIMARY KEY,
                             user_id INTEGER NOT NULL,
                             blocked_at INTEGER NOT NULL,
-                            FOREIGN KEY (user_id) REFERENCES tbl_users(id),
+                            FOREIGN KEY (user_id) REFERENCES tbl_users(id) ON DELETE CASCADE,
                             UNIQUE(user_id)
                         );", ())?;
         log::info!("Created blocked users table.");
     }
 
+    if !user_foreign_key_cascades(&db, "tbl_blocked_users")? {
+        db.execute_batch("PRAGMA foreign_keys = OFF;
+                          BEGIN;
+                          CREATE TABLE tbl_blocked_users_migrated (
+                              id INTEGER PRIMARY KEY,
+                              user_id INTEGER NOT NULL,
+                              blocked_at INTEGER NOT NULL,
+                              FOREIGN KEY (user_id) REFERENCES tbl_users(id) ON DELETE CASCADE,
+                              UNIQUE(user_id)
+                          );
+                          INSERT INTO tbl_blocked_users_migrated (id, user_id, blocked_at)
+                              SELECT id, user_id, blocked_at FROM tbl_blocked_users;
+                          DROP TABLE tbl_blocked_users;
+                          ALTER TABLE tbl_blocked_users_migrated RENAME TO tbl_blocked_users;
+                          COMMIT;
+                          PRAGMA foreign_keys = ON;")?;
+        log::info!("Added ON DELETE CASCADE to blocked users table.");
+    }
+
+    if !db.table_exists(None, "tbl_dead_letters")? {
+        db.execute("CREATE TABLE tbl_dead_letters (
+                            id INTEGER PRIMARY KEY,
+                            from_peer_id TEXT NOT NULL,
+                            to_peer_id TEXT NOT NULL,
+                            content TEXT NOT NULL,
+                            created_at INTEGER NOT NULL,
+                            failed_at INTEGER NOT NULL,
+                            reason TEXT NOT NULL
+                        );", ())?;
+        log::info!("Created dead letters table.");
+    }
+
+    if !db.table_exists(None, "tbl_user_addresses")? {
+        db.execute("CREATE TABLE tbl_user_addresses (
+                            id INTEGER PRIMARY KEY,
+                            user_id INTEGER NOT NULL,
+                            multiaddr TEXT NOT NULL,
+                            updated_at INTEGER NOT NULL,
+                            FOREIGN KEY (user_id) REFERENCES tbl_users(id) ON DELETE CASCADE,
+                            UNIQUE(user_id, multiaddr)
+                        );", ())?;
+        log::info!("Created user addresses table.");
+    }
+
+    if !db.table_exists(None, "tbl_post_tombstones")? {
+        db.execute("CREATE TABLE tbl_post_tombstones (
+                            id INTEGER PRIMARY KEY,
+                            author_peer_id TEXT NOT NULL,
+                            content TEXT NOT NULL,
+                            deleted_at INTEGER NOT NULL,
+                            UNIQUE(author_peer_id, content)
+                        );", ())?;
+        log::info!("Created post tombstones table.");
+    }
+
+    if !db.table_exists(None, "tbl_settings")? {
+        db.execute("CREATE TABLE tbl_settings (
+                            key TEXT PRIMARY KEY,
+                            value TEXT NOT NULL
+                        );", ())?;
+        log::info!("Created settings table.");
+    }
+
+    if !db.table_exists(None, "tbl_drafts")? {
+        db.execute("CREATE TABLE tbl_drafts (
+                            peer_id TEXT PRIMARY KEY,
+                            content TEXT NOT NULL,
+                            updated_at INTEGER NOT NULL
+                        );", ())?;
+        log::info!("Created drafts table.");
+    }
+
+    if !db.table_exists(None, "tbl_buffered_posts")? {
+        db.execute("CREATE TABLE tbl_buffered_posts (
+                            post_id INTEGER NOT NULL,
+                            peer_id TEXT NOT NULL,
+                            created_at INTEGER NOT NULL,
+                            FOREIGN KEY (post_id) REFERENCES tbl_posts(id) ON DELETE CASCADE,
+                            PRIMARY KEY (post_id, peer_id)
+                        );", ())?;
+        log::info!("Created buffered posts table.");
+    }
+
+    if !db.table_exists(None, "tbl_quarantined_messages")? {
+        db.execute("CREATE TABLE tbl_quarantined_messages (
+                            id INTEGER PRIMARY KEY,
+                            from_peer_id TEXT NOT NULL,
+                            content TEXT NOT NULL,
+                            received_at INTEGER NOT NULL
+                        );", ())?;
+        log::info!("Created quarantined messages table.");
+    }
+
+    if !db.table_exists(None, "tbl_conversation_retention")? {
+        db.execute("CREATE TABLE tbl_conversation_retention (
+                            peer_id TEXT PRIMARY KEY,
+                            days INTEGER NOT NULL
+                        );", ())?;
+        log::info!("Created conversation retention table.");
+    }
+
+    if !db.table_exists(None, "tbl_key_changes")? {
+        db.execute("CREATE TABLE tbl_key_changes (
+                            peer_id TEXT PRIMARY KEY,
+                            new_peer_id TEXT NOT NULL,
+                            detected_at INTEGER NOT NULL
+                        );", ())?;
+        log::info!("Created key changes table.");
+    }
+
+    if !db.table_exists(None, "tbl_friend_request_history")? {
+        db.execute("CREATE TABLE tbl_friend_request_history (
+                            id INTEGER PRIMARY KEY,
+                            from_peer_id TEXT NOT NULL,
+                            to_peer_id TEXT NOT NULL,
+                            message TEXT,
+                            outcome TEXT NOT NULL,
+                            requested_at INTEGER NOT NULL,
+                            resolved_at INTEGER NOT NULL
+                        );", ())?;
+        log::info!("Created friend request history table.");
+    }
+
     Ok(Arc::new(Mutex::new(db)))
 }
 
+/// Whether every foreign key from `table` to `tbl_users` deletes dependent rows with the user.
+fn user_foreign_key_cascades(db: &Connection, table: &str) -> anyhow::Result<bool> {
+    let non_cascading: i64 = db.query_row(
+        "SELECT COUNT(*) FROM pragma_foreign_key_list(?1) WHERE \"table\"='tbl_users' AND on_delete<>'CASCADE';",
+        rusqlite::params![table],
+        |row| row.get(0)
+    )?;
+
+    Ok(non_cascading == 0)
+}
+
 pub fn fetch_identity(db: Arc<Mutex<Connection>>) -> anyhow::Result<Identity> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, keypair, peer_id, port_number, created_at, last_login FROM tbl_identity")?;
+    let mut query = db_guard.prepare("SELECT id, keypair, peer_id, port_number, created_at, last_login, sync_enabled, max_friends FROM tbl_identity")?;
 
     if !query.exists(())? {
         return Err(anyhow::anyhow!("No identity data was found."));
     }
 
-    let (id, keypair, peer_id, port_number, created_at, last_login): (i64, Vec<u8>, String, i64, i64, i64) = query.query_row((), |row| {
-        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+    let (id, keypair, peer_id, port_number, created_at, last_login, sync_enabled, max_friends): (i64, Vec<u8>, String, i64, i64, i64, bool, Option<i64>) = query.query_row((), |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?))
     })?;
 
     Ok(
-        Identity::new(
-            id, 
-            keypair, 
-            peer_id, 
+        Identity {
+            id,
+            keypair,
+            peer_id,
             port_number,
             created_at,
-            last_login
-        )
+            last_login,
+            sync_enabled,
+            max_friends
+        }
     )
 }
 
@@ -156,7 +359,7 @@ pub fn create_identity(db: Arc<Mutex<Connection>>, keypair: Vec<u8>, peer_id: St
     Ok(db_guard.last_insert_rowid())
 }
 
-pub fn update_identity(db: Arc<Mutex<Connection>>, id: i64, last_login: Option<i64>) -> anyhow::Result<()> {
+pub fn update_identity(db: Arc<Mutex<Connection>>, id: i64, last_login: Option<i64>, sync_enabled: Option<bool>) -> anyhow::Result<()> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
@@ -167,9 +370,269 @@ pub fn update_identity(db: Arc<Mutex<Connection>>, id: i64, last_login: Option<i
         )?;
     }
 
+    if let Some(sync_enabled) = sync_enabled {
+        db_guard.execute(
+            "UPDATE tbl_identity SET sync_enabled=?1 WHERE id=?2;",
+            rusqlite::params![sync_enabled, id]
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The TCP port the node listens on, chosen when the identity was created.
+pub fn fetch_listen_port(db: Arc<Mutex<Connection>>) -> anyhow::Result<u16> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let port_number: i64 = db_guard.query_row("SELECT port_number FROM tbl_identity;", (), |row| row.get(0))?;
+
+    Ok(u16::try_from(port_number)?)
+}
+
+/// Listen ports below this need elevated privileges on most systems.
+pub const MIN_LISTEN_PORT: i64 = 1024;
+pub const MAX_LISTEN_PORT: i64 = 65535;
+
+/// Changes the port the node listens on, along with the identity's own user address. The
+/// swarm binds its port at startup, so this takes effect the next time the node starts.
+pub fn update_listen_port(db: Arc<Mutex<Connection>>, port: i64) -> anyhow::Result<()> {
+    if !(MIN_LISTEN_PORT..=MAX_LISTEN_PORT).contains(&port) {
+        return Err(anyhow::anyhow!("Listen port must be between {MIN_LISTEN_PORT} and {MAX_LISTEN_PORT}, got {port}."));
+    }
+
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let tx = db_guard.unchecked_transaction()?;
+
+    let updated = tx.execute(
+        "UPDATE tbl_identity SET port_number=?1;",
+        rusqlite::params![port]
+    )?;
+
+    if updated == 0 {
+        return Err(anyhow::anyhow!("No identity data was found."));
+    }
+
+    tx.execute(
+        "UPDATE tbl_users SET multiaddr=?1 WHERE is_identity=1;",
+        rusqlite::params![format!("/ip4/0.0.0.0/tcp/{port}")]
+    )?;
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Rewrites the identity's peer id, along with the user row that represents the identity.
+pub fn update_identity_peer_id(db: Arc<Mutex<Connection>>, id: i64, peer_id: String) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let tx = db_guard.unchecked_transaction()?;
+
+    tx.execute(
+        "UPDATE tbl_identity SET peer_id=?1 WHERE id=?2;",
+        rusqlite::params![peer_id, id]
+    )?;
+
+    tx.execute(
+        "UPDATE tbl_users SET peer_id=?1 WHERE is_identity=1;",
+        rusqlite::params![peer_id]
+    )?;
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Sets the maximum number of friends, or removes the limit when `None`.
+pub fn set_max_friends(db: Arc<Mutex<Connection>>, max_friends: Option<i64>) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    db_guard.execute(
+        "UPDATE tbl_identity SET max_friends=?1;",
+        rusqlite::params![max_friends]
+    )?;
+
+    Ok(())
+}
+
+/// Returns the configured limit when there is no room for another friend, or `None` when
+/// a new friendship can be added.
+pub fn friend_limit_reached(db: Arc<Mutex<Connection>>) -> anyhow::Result<Option<usize>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let (max_friends, friend_count): (Option<i64>, i64) = db_guard.query_row(
+        "SELECT (SELECT max_friends FROM tbl_identity), (SELECT COUNT(*) FROM tbl_friends);",
+        (),
+        |row| Ok((row.get(0)?, row.get(1)?))
+    )?;
+
+    Ok(max_friends
+        .filter(|&max_friends| friend_count >= max_friends)
+        .map(|max_friends| max_friends.max(0) as usize))
+}
+
+/// Whether the P2P node should be started as soon as the app launches.
+pub const AUTO_START_SETTING: &str = "auto_start";
+
+/// Set once the user has finished the onboarding flow.
+pub const ONBOARDING_COMPLETE_SETTING: &str = "onboarding_complete";
+
+/// Whether a friend's stored address follows them when they reconnect from somewhere new.
+/// Enabled unless turned off.
+pub const AUTO_UPDATE_FRIEND_ADDRESSES_SETTING: &str = "auto_update_friend_addresses";
+
+/// Whether reading a conversation tells the sender. Enabled unless turned off.
+pub const READ_RECEIPTS_SETTING: &str = "read_receipts_enabled";
+
+/// How many undelivered direct messages are kept per peer before the oldest are dead-lettered.
+pub const MAX_PENDING_DIRECT_MESSAGES_SETTING: &str = "max_pending_direct_messages";
+
+pub const DEFAULT_MAX_PENDING_DIRECT_MESSAGES: usize = 1000;
+
+/// Dead letter reason for pending messages pushed out by newer ones.
+pub const PENDING_OVERFLOW_REASON: &str = "Too many pending messages";
+
+/// Rows written per transaction by `bulk_insert_with_yield`.
+pub const BULK_WRITE_BATCH_SIZE: usize = 100;
+
+/// Writes `rows` in transactions of `batch_size`, releasing the lock and yielding to the
+/// runtime between batches so commands from the UI aren't starved during a large import or
+/// sync. A failing row rolls back its batch and stops the write; earlier batches stay
+/// committed, so callers that want to carry on past a bad row should handle its error in
+/// `insert`. Returns the number of transactions committed.
+pub async fn bulk_insert_with_yield<T>(
+    db: Arc<Mutex<Connection>>,
+    rows: Vec<T>,
+    batch_size: usize,
+    mut insert: impl FnMut(&Connection, T) -> anyhow::Result<()>
+) -> anyhow::Result<usize> {
+    let mut rows = rows.into_iter().peekable();
+    let mut transactions = 0;
+
+    while rows.peek().is_some() {
+        {
+            let db_guard = db.lock()
+                .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+            let transaction = db_guard.unchecked_transaction()?;
+
+            for row in rows.by_ref().take(batch_size.max(1)) {
+                insert(&transaction, row)?;
+            }
+
+            transaction.commit()?;
+        }
+
+        transactions += 1;
+        tokio::task::yield_now().await;
+    }
+
+    Ok(transactions)
+}
+
+pub fn get_setting(db: Arc<Mutex<Connection>>, key: &str) -> anyhow::Result<Option<String>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    Ok(db_guard.query_row(
+        "SELECT value FROM tbl_settings WHERE key=?1;",
+        rusqlite::params![key],
+        |row| row.get(0)
+    ).optional()?)
+}
+
+pub fn set_setting(db: Arc<Mutex<Connection>>, key: &str, value: &str) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    db_guard.execute(
+        "INSERT INTO tbl_settings (key, value) VALUES (?1, ?2)
+            ON CONFLICT(key) DO UPDATE SET value=excluded.value;",
+        rusqlite::params![key, value]
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_setting(db: Arc<Mutex<Connection>>, key: &str) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    db_guard.execute(
+        "DELETE FROM tbl_settings WHERE key=?1;",
+        rusqlite::params![key]
+    )?;
+
     Ok(())
 }
 
+pub fn get_auto_start(db: Arc<Mutex<Connection>>) -> anyhow::Result<bool> {
+    Ok(get_setting(db, AUTO_START_SETTING)?.is_some_and(|value| value == "true"))
+}
+
+pub fn set_auto_start(db: Arc<Mutex<Connection>>, auto_start: bool) -> anyhow::Result<()> {
+    set_setting(db, AUTO_START_SETTING, &auto_start.to_string())
+}
+
+pub fn has_identity(db: Arc<Mutex<Connection>>) -> anyhow::Result<bool> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id FROM tbl_identity;")?;
+
+    Ok(query.exists(())?)
+}
+
+/// The app has never been set up: no identity has been created and onboarding was never
+/// completed.
+pub fn is_first_run(db: Arc<Mutex<Connection>>) -> anyhow::Result<bool> {
+    if has_identity(db.clone())? {
+        return Ok(false);
+    }
+
+    Ok(get_setting(db, ONBOARDING_COMPLETE_SETTING)?.is_none_or(|value| value != "true"))
+}
+
+pub fn complete_onboarding(db: Arc<Mutex<Connection>>) -> anyhow::Result<()> {
+    set_setting(db, ONBOARDING_COMPLETE_SETTING, "true")
+}
+
+pub fn get_auto_update_friend_addresses(db: Arc<Mutex<Connection>>) -> anyhow::Result<bool> {
+    Ok(get_setting(db, AUTO_UPDATE_FRIEND_ADDRESSES_SETTING)?.is_none_or(|value| value == "true"))
+}
+
+pub fn set_auto_update_friend_addresses(db: Arc<Mutex<Connection>>, enabled: bool) -> anyhow::Result<()> {
+    set_setting(db, AUTO_UPDATE_FRIEND_ADDRESSES_SETTING, &enabled.to_string())
+}
+
+pub fn get_read_receipts_enabled(db: Arc<Mutex<Connection>>) -> anyhow::Result<bool> {
+    Ok(get_setting(db, READ_RECEIPTS_SETTING)?.is_none_or(|value| value == "true"))
+}
+
+pub fn set_read_receipts_enabled(db: Arc<Mutex<Connection>>, enabled: bool) -> anyhow::Result<()> {
+    set_setting(db, READ_RECEIPTS_SETTING, &enabled.to_string())
+}
+
+pub fn get_max_pending_direct_messages(db: Arc<Mutex<Connection>>) -> anyhow::Result<usize> {
+    Ok(get_setting(db, MAX_PENDING_DIRECT_MESSAGES_SETTING)?
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_PENDING_DIRECT_MESSAGES))
+}
+
+pub fn set_max_pending_direct_messages(db: Arc<Mutex<Connection>>, max_pending: usize) -> anyhow::Result<()> {
+    if max_pending == 0 {
+        return Err(anyhow::anyhow!("The pending message limit must be at least 1."));
+    }
+
+    set_setting(db, MAX_PENDING_DIRECT_MESSAGES_SETTING, &max_pending.to_string())
+}
+
 pub fn fetch_user_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<User> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
@@ -259,6 +722,41 @@ pub fn fetch_all_users(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<User>>
     }).collect::<anyhow::Result<Vec<User>>>()
 }
 
+/// Every friend's user record, in one query.
+pub fn fetch_friend_users(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<User>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT tbl_users.id, peer_id, multiaddr, nickname, is_identity, tbl_users.created_at FROM tbl_users
+                                          INNER JOIN tbl_friends ON tbl_friends.user_id=tbl_users.id;")?;
+
+    let rows = query.query_map((), |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(
+            User::new(
+                row.0,
+                row.1,
+                row.2,
+                row.3,
+                row.4,
+                row.5
+            )
+        )
+    }).collect::<anyhow::Result<Vec<User>>>()
+}
+
 pub fn create_user(db: Arc<Mutex<Connection>>, peer_id: String, multiaddr: String, is_identity: bool) -> anyhow::Result<i64> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
@@ -294,31 +792,111 @@ pub fn update_user(db: Arc<Mutex<Connection>>, id: i64, multiaddr: Option<String
     Ok(())
 }
 
+/// Deletes a user along with their friend and block rows (via `ON DELETE CASCADE`) and any
+/// friend requests to or from them. Friend requests and direct messages are keyed by peer id
+/// rather than user id, so requests are removed here explicitly while direct messages are kept
+/// as conversation history.
 pub fn delete_user(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    db_guard.execute(
+    let transaction = db_guard.unchecked_transaction()?;
+
+    transaction.execute(
+        "DELETE FROM tbl_friend_requests WHERE from_peer_id=(SELECT peer_id FROM tbl_users WHERE id=?1) OR to_peer_id=(SELECT peer_id FROM tbl_users WHERE id=?1);",
+        rusqlite::params![id]
+    )?;
+
+    transaction.execute(
         "DELETE FROM tbl_users WHERE id=?1;", 
         rusqlite::params![id]
     )?;
 
+    transaction.commit()?;
+
     Ok(())
 }
 
-pub fn fetch_friend_request_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<FriendRequest> {
+/// Returns every address known for a user, most recently seen first.
+pub fn fetch_user_addresses(db: Arc<Mutex<Connection>>, user_id: i64) -> anyhow::Result<Vec<UserAddress>> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending FROM tbl_friend_requests WHERE id=?1;")?;
-
-    if !query.exists(rusqlite::params![id])? {
-        return Err(anyhow::anyhow!("A friend request with id {id} was not found."));
-    }
+    let mut query = db_guard.prepare("SELECT id, user_id, multiaddr, updated_at FROM tbl_user_addresses WHERE user_id=?1 ORDER BY updated_at DESC, id DESC;")?;
 
-    let (id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending): (i64, String, String, String, String, String, i64, bool) = query.query_row(rusqlite::params![id], |row| {
-        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?))
-    })?;
+    let rows = query.query_map(rusqlite::params![user_id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(UserAddress::new(
+            row.0,
+            row.1,
+            row.2,
+            row.3
+        ))
+    }).collect::<anyhow::Result<Vec<UserAddress>>>()
+}
+
+/// Records an address for a user, refreshing its timestamp if it is already known.
+pub fn upsert_user_address(db: Arc<Mutex<Connection>>, user_id: i64, multiaddr: String) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let updated_at = chrono::Utc::now().timestamp();
+
+    db_guard.execute(
+        "INSERT INTO tbl_user_addresses (user_id, multiaddr, updated_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT(user_id, multiaddr) DO UPDATE SET updated_at=excluded.updated_at;",
+        rusqlite::params![user_id, multiaddr, updated_at]
+    )?;
+
+    Ok(())
+}
+
+/// Deletes users that aren't the identity and aren't referenced by a friend, block, friend
+/// request or direct message, returning how many were removed.
+pub fn prune_orphaned_users(db: Arc<Mutex<Connection>>) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    let pruned = transaction.execute(
+        "DELETE FROM tbl_users
+            WHERE is_identity=0
+            AND id NOT IN (SELECT user_id FROM tbl_friends)
+            AND id NOT IN (SELECT user_id FROM tbl_blocked_users)
+            AND peer_id NOT IN (SELECT from_peer_id FROM tbl_friend_requests UNION SELECT to_peer_id FROM tbl_friend_requests)
+            AND peer_id NOT IN (SELECT from_peer_id FROM tbl_direct_messages UNION SELECT to_peer_id FROM tbl_direct_messages);",
+        ()
+    )?;
+
+    transaction.commit()?;
+
+    Ok(pruned)
+}
+
+pub fn fetch_friend_request_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<FriendRequest> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending, seen FROM tbl_friend_requests WHERE id=?1;")?;
+
+    if !query.exists(rusqlite::params![id])? {
+        return Err(anyhow::anyhow!("A friend request with id {id} was not found."));
+    }
+
+    let (id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending, seen): (i64, String, String, String, String, String, i64, bool, bool) = query.query_row(rusqlite::params![id], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?, row.get(8)?))
+    })?;
 
     Ok(
         FriendRequest::new(
@@ -329,7 +907,8 @@ pub fn fetch_friend_request_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow
             to_multiaddr,
             message,
             created_at,
-            pending
+            pending,
+            seen
         )
     )
 }
@@ -338,7 +917,7 @@ pub fn fetch_friend_requests_from_peer(db: Arc<Mutex<Connection>>, peer_id: Stri
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending FROM tbl_friend_requests WHERE from_peer_id=?1;")?;
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending, seen FROM tbl_friend_requests WHERE from_peer_id=?1;")?;
 
     if !query.exists(rusqlite::params![peer_id])? {
         return Err(anyhow::anyhow!("A friend request with from_peer_id {peer_id} was not found."));
@@ -353,7 +932,8 @@ pub fn fetch_friend_requests_from_peer(db: Arc<Mutex<Connection>>, peer_id: Stri
             row.get(4)?,
             row.get(5)?,
             row.get(6)?,
-            row.get(7)?
+            row.get(7)?,
+            row.get(8)?
         ))
     })?;
 
@@ -369,7 +949,8 @@ pub fn fetch_friend_requests_from_peer(db: Arc<Mutex<Connection>>, peer_id: Stri
                 row.4,
                 row.5,
                 row.6,
-                row.7
+                row.7,
+                row.8
             )
         )
     }).collect::<anyhow::Result<Vec<FriendRequest>>>()
@@ -379,7 +960,7 @@ pub fn fetch_friend_requests_to_peer(db: Arc<Mutex<Connection>>, peer_id: String
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending FROM tbl_friend_requests WHERE to_peer_id=?1;")?;
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending, seen FROM tbl_friend_requests WHERE to_peer_id=?1;")?;
 
     if !query.exists(rusqlite::params![peer_id])? {
         return Err(anyhow::anyhow!("A friend request with from_peer_id {peer_id} was not found."));
@@ -394,7 +975,8 @@ pub fn fetch_friend_requests_to_peer(db: Arc<Mutex<Connection>>, peer_id: String
             row.get(4)?,
             row.get(5)?,
             row.get(6)?,
-            row.get(7)?
+            row.get(7)?,
+            row.get(8)?
         ))
     })?;
 
@@ -410,7 +992,8 @@ pub fn fetch_friend_requests_to_peer(db: Arc<Mutex<Connection>>, peer_id: String
                 row.4,
                 row.5,
                 row.6,
-                row.7
+                row.7,
+                row.8
             )
         )
     }).collect::<anyhow::Result<Vec<FriendRequest>>>()
@@ -420,7 +1003,7 @@ pub fn fetch_all_friend_requests(db: Arc<Mutex<Connection>>) -> anyhow::Result<V
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending FROM tbl_friend_requests;")?;
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, from_multiaddr, to_peer_id, to_multiaddr, message, created_at, pending, seen FROM tbl_friend_requests;")?;
 
     if !query.exists(())? {
         return Err(anyhow::anyhow!("No friend request data was found."));
@@ -435,7 +1018,8 @@ pub fn fetch_all_friend_requests(db: Arc<Mutex<Connection>>) -> anyhow::Result<V
             row.get(4)?,
             row.get(5)?,
             row.get(6)?,
-            row.get(7)?
+            row.get(7)?,
+            row.get(8)?
         ))
     })?;
 
@@ -451,7 +1035,8 @@ pub fn fetch_all_friend_requests(db: Arc<Mutex<Connection>>) -> anyhow::Result<V
                 row.4,
                 row.5,
                 row.6,
-                row.7
+                row.7,
+                row.8
             )
         )
     }).collect::<anyhow::Result<Vec<FriendRequest>>>()
@@ -485,6 +1070,45 @@ pub fn update_friend_request(db: Arc<Mutex<Connection>>, id: i64, pending: Optio
     Ok(())
 }
 
+pub fn mark_friend_request_seen(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    db_guard.execute(
+        "UPDATE tbl_friend_requests SET seen=1 WHERE id=?1;",
+        rusqlite::params![id]
+    )?;
+
+    Ok(())
+}
+
+pub fn count_unseen_friend_requests(db: Arc<Mutex<Connection>>, to_peer_id: String) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let count: i64 = db_guard.query_row(
+        "SELECT COUNT(*) FROM tbl_friend_requests WHERE to_peer_id=?1 AND seen=0;",
+        rusqlite::params![to_peer_id],
+        |row| row.get(0)
+    )?;
+
+    Ok(count as usize)
+}
+
+/// Counts the friend requests sent by `from_peer_id` that are still awaiting a response.
+pub fn count_pending_outbound_friend_requests(db: Arc<Mutex<Connection>>, from_peer_id: String) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let count: i64 = db_guard.query_row(
+        "SELECT COUNT(*) FROM tbl_friend_requests WHERE from_peer_id=?1 AND pending=1;",
+        rusqlite::params![from_peer_id],
+        |row| row.get(0)
+    )?;
+
+    Ok(count as usize)
+}
+
 pub fn delete_friend_request(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
@@ -497,6 +1121,105 @@ pub fn delete_friend_request(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Res
     Ok(())
 }
 
+pub fn delete_friend_requests_to_peer(db: Arc<Mutex<Connection>>, to_peer_id: String) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let deleted = db_guard.execute(
+        "DELETE FROM tbl_friend_requests WHERE to_peer_id=?1;",
+        rusqlite::params![to_peer_id]
+    )?;
+
+    Ok(deleted)
+}
+
+/// Deletes the requests from `from_peer_id` to `to_peer_id`, recording `outcome` for each in
+/// the friend request history. Returns how many were resolved.
+pub fn resolve_friend_request(db: Arc<Mutex<Connection>>, from_peer_id: String, to_peer_id: String, outcome: FriendRequestOutcome) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    let resolved = transaction.execute(
+        "INSERT INTO tbl_friend_request_history (from_peer_id, to_peer_id, message, outcome, requested_at, resolved_at)
+            SELECT from_peer_id, to_peer_id, message, ?3, created_at, ?4 FROM tbl_friend_requests WHERE from_peer_id=?1 AND to_peer_id=?2 ORDER BY id ASC;",
+        rusqlite::params![from_peer_id, to_peer_id, outcome.as_str(), chrono::Utc::now().timestamp()]
+    )?;
+
+    transaction.execute(
+        "DELETE FROM tbl_friend_requests WHERE from_peer_id=?1 AND to_peer_id=?2;",
+        rusqlite::params![from_peer_id, to_peer_id]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(resolved)
+}
+
+/// Like `delete_friend_requests_to_peer`, but records every deleted request as denied.
+pub fn deny_friend_requests_to_peer(db: Arc<Mutex<Connection>>, to_peer_id: String) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    transaction.execute(
+        "INSERT INTO tbl_friend_request_history (from_peer_id, to_peer_id, message, outcome, requested_at, resolved_at)
+            SELECT from_peer_id, to_peer_id, message, ?2, created_at, ?3 FROM tbl_friend_requests WHERE to_peer_id=?1 ORDER BY id ASC;",
+        rusqlite::params![to_peer_id, FriendRequestOutcome::Denied.as_str(), chrono::Utc::now().timestamp()]
+    )?;
+
+    let deleted = transaction.execute(
+        "DELETE FROM tbl_friend_requests WHERE to_peer_id=?1;",
+        rusqlite::params![to_peer_id]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(deleted)
+}
+
+/// Every answered friend request, most recently answered first.
+pub fn fetch_friend_request_history(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<FriendRequestHistoryEntry>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, message, outcome, requested_at, resolved_at FROM tbl_friend_request_history
+                                          ORDER BY resolved_at DESC, id DESC;")?;
+
+    let rows = query.query_map((), |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get::<_, Option<String>>(3)?,
+            row.get::<_, String>(4)?,
+            row.get(5)?,
+            row.get(6)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        let outcome = FriendRequestOutcome::from_tag(&row.4)
+            .ok_or_else(|| anyhow::anyhow!("Unknown friend request outcome {}.", row.4))?;
+
+        Ok(
+            FriendRequestHistoryEntry::new(
+                row.0,
+                row.1,
+                row.2,
+                row.3.unwrap_or_default(),
+                outcome,
+                row.5,
+                row.6
+            )
+        )
+    }).collect::<anyhow::Result<Vec<FriendRequestHistoryEntry>>>()
+}
+
 pub fn fetch_friend_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<Friend> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
@@ -592,6 +1315,33 @@ pub fn create_friend(db: Arc<Mutex<Connection>>, user_id: i64) -> anyhow::Result
     Ok(db_guard.last_insert_rowid())
 }
 
+/// Adds the user as a friend and notes it in the conversation with them.
+pub fn befriend(db: Arc<Mutex<Connection>>, user_id: i64) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let created_at = chrono::Utc::now().timestamp();
+    let transaction = db_guard.unchecked_transaction()?;
+
+    transaction.execute(
+        "INSERT INTO tbl_friends (user_id, created_at, last_synch) VALUES (?1, ?2, ?2);",
+        rusqlite::params![user_id, created_at]
+    )?;
+
+    let friend_id = transaction.last_insert_rowid();
+
+    let peer_id: String = transaction.query_row(
+        "SELECT peer_id FROM tbl_users WHERE id=?1;",
+        rusqlite::params![user_id],
+        |row| row.get(0)
+    )?;
+
+    insert_system_message(&transaction, &peer_id, BECAME_FRIENDS_MESSAGE)?;
+    transaction.commit()?;
+
+    Ok(friend_id)
+}
+
 pub fn update_friend(db: Arc<Mutex<Connection>>, id: i64, last_synch: Option<i64>) -> anyhow::Result<()> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
@@ -618,18 +1368,46 @@ pub fn delete_friend(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()>
     Ok(())
 }
 
+/// Peer ids of every friend, in the order they were added. Empty when there are no friends.
+pub fn fetch_friend_peer_ids(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<String>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT tbl_users.peer_id FROM tbl_friends
+                                          INNER JOIN tbl_users ON tbl_users.id=tbl_friends.user_id
+                                          ORDER BY tbl_friends.id ASC;")?;
+
+    let rows = query.query_map((), |row| row.get::<_, String>(0))?;
+
+    rows.map(|row_result| Ok(row_result?))
+        .collect::<anyhow::Result<Vec<String>>>()
+}
+
+/// Deletes friend rows whose user no longer exists, returning how many were removed.
+pub fn delete_dangling_friends(db: Arc<Mutex<Connection>>) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let deleted = db_guard.execute(
+        "DELETE FROM tbl_friends WHERE user_id NOT IN (SELECT id FROM tbl_users);",
+        ()
+    )?;
+
+    Ok(deleted)
+}
+
 pub fn fetch_direct_message_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<DirectMessage> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending FROM tbl_direct_messages WHERE id=?1;")?;
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending, content_type FROM tbl_direct_messages WHERE id=?1;")?;
 
     if !query.exists(rusqlite::params![id])? {
         return Err(anyhow::anyhow!("A direct message with id {id} was not found."));
     }
 
-    let (id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending): (i64, String, String, String, i64, Option<i64>, bool, bool) = query.query_row(rusqlite::params![id], |row| {
-        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?))
+    let (id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending, content_type): (i64, String, String, String, i64, Option<i64>, bool, bool, String) = query.query_row(rusqlite::params![id], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?, row.get(8)?))
     })?;
 
     Ok(
@@ -642,7 +1420,7 @@ pub fn fetch_direct_message_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow
             edited_at,
             read,
             pending
-        )
+        ).with_content_type(ContentType::from_tag(&content_type))
     )
 }
 
@@ -650,7 +1428,7 @@ pub fn fetch_direct_messages_with_peer(db: Arc<Mutex<Connection>>, peer_id: Stri
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending FROM tbl_direct_messages WHERE from_peer_id=?1 OR to_peer_id=?1;")?;
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending, content_type FROM tbl_direct_messages WHERE from_peer_id=?1 OR to_peer_id=?1;")?;
 
     if !query.exists(rusqlite::params![peer_id])? {
         return Err(anyhow::anyhow!("A direct message with user_id {peer_id} was not found."));
@@ -665,7 +1443,8 @@ pub fn fetch_direct_messages_with_peer(db: Arc<Mutex<Connection>>, peer_id: Stri
             row.get(4)?, 
             row.get(5)?, 
             row.get(6)?,
-            row.get(7)?
+            row.get(7)?,
+            row.get::<_, String>(8)?
         ))
     })?;
 
@@ -681,7 +1460,47 @@ pub fn fetch_direct_messages_with_peer(db: Arc<Mutex<Connection>>, peer_id: Stri
             row.5, 
             row.6,
             row.7
+        ).with_content_type(ContentType::from_tag(&row.8)))
+    }).collect::<anyhow::Result<Vec<DirectMessage>>>()
+}
+
+pub fn fetch_direct_messages_in_range(db: Arc<Mutex<Connection>>, peer_id: String, start: i64, end: i64) -> anyhow::Result<Vec<DirectMessage>> {
+    if start > end {
+        return Err(anyhow::anyhow!("Invalid date range: start ({start}) is after end ({end})."));
+    }
+
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending, content_type FROM tbl_direct_messages WHERE (from_peer_id=?1 OR to_peer_id=?1) AND created_at BETWEEN ?2 AND ?3 ORDER BY created_at ASC;")?;
+
+    let rows = query.query_map(rusqlite::params![peer_id, start, end], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get(6)?,
+            row.get(7)?,
+            row.get::<_, String>(8)?
         ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(DirectMessage::new(
+            row.0,
+            row.1,
+            row.2,
+            row.3,
+            row.4,
+            row.5,
+            row.6,
+            row.7
+        ).with_content_type(ContentType::from_tag(&row.8)))
     }).collect::<anyhow::Result<Vec<DirectMessage>>>()
 }
 
@@ -689,7 +1508,7 @@ pub fn fetch_all_direct_messages(db: Arc<Mutex<Connection>>) -> anyhow::Result<V
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending FROM tbl_direct_messages;")?;
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending, content_type FROM tbl_direct_messages;")?;
 
     if !query.exists(())? {
         return Err(anyhow::anyhow!("No direct message data was found."));
@@ -704,7 +1523,8 @@ pub fn fetch_all_direct_messages(db: Arc<Mutex<Connection>>) -> anyhow::Result<V
             row.get(4)?,
             row.get(5)?,
             row.get(6)?,
-            row.get(7)?
+            row.get(7)?,
+            row.get::<_, String>(8)?
         ))
     })?;
 
@@ -721,71 +1541,472 @@ pub fn fetch_all_direct_messages(db: Arc<Mutex<Connection>>) -> anyhow::Result<V
                 row.5,
                 row.6,
                  row.7
-            )
+            ).with_content_type(ContentType::from_tag(&row.8))
         )
     }).collect::<anyhow::Result<Vec<DirectMessage>>>()
 }
 
-pub fn create_direct_message(db: Arc<Mutex<Connection>>, from_peer_id: String, to_peer_id: String, content: String) -> anyhow::Result<i64> {
+pub fn fetch_pending_direct_messages(db: Arc<Mutex<Connection>>, from_peer_id: String, to_peer_id: String) -> anyhow::Result<Vec<DirectMessage>> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let created_at = chrono::Utc::now().timestamp();
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, edited_at, read, pending, content_type FROM tbl_direct_messages WHERE from_peer_id=?1 AND to_peer_id=?2 AND pending=1 ORDER BY created_at ASC;")?;
 
-    db_guard.execute(
-        "INSERT INTO tbl_direct_messages (from_peer_id, to_peer_id, content, created_at) VALUES (?1, ?2, ?3, ?4);", 
-        rusqlite::params![from_peer_id, to_peer_id, content, created_at]
-    )?;
-    
-    Ok(db_guard.last_insert_rowid())
+    if !query.exists(rusqlite::params![from_peer_id, to_peer_id])? {
+        return Err(anyhow::anyhow!("No pending direct messages to {to_peer_id} were found."));
+    }
+
+    let rows = query.query_map(rusqlite::params![from_peer_id, to_peer_id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get(6)?,
+            row.get(7)?,
+            row.get::<_, String>(8)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(DirectMessage::new(
+            row.0,
+            row.1,
+            row.2,
+            row.3,
+            row.4,
+            row.5,
+            row.6,
+            row.7
+        ).with_content_type(ContentType::from_tag(&row.8)))
+    }).collect::<anyhow::Result<Vec<DirectMessage>>>()
 }
 
-pub fn update_direct_message(db: Arc<Mutex<Connection>>, id: i64, content: Option<String>, pending: Option<bool>) -> anyhow::Result<()> {
+pub fn count_pending_direct_messages(db: Arc<Mutex<Connection>>, from_peer_id: String) -> anyhow::Result<HashMap<String, usize>> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let edited_at = chrono::Utc::now().timestamp();
+    let mut query = db_guard.prepare("SELECT to_peer_id, COUNT(*) FROM tbl_direct_messages WHERE from_peer_id=?1 AND pending=1 GROUP BY to_peer_id;")?;
 
-    if let Some(content) = content {
-        db_guard.execute(
-            "UPDATE tbl_direct_messages SET content=?1, edited_at=?2 WHERE id=?3;", 
-            rusqlite::params![content, edited_at, id]
-        )?;
-    }
+    let rows = query.query_map(rusqlite::params![from_peer_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+    })?;
 
-    if let Some(pending) = pending {
-        db_guard.execute(
-            "UPDATE tbl_direct_messages SET pending=?1, edited_at=?2 WHERE id=?3;", 
-            rusqlite::params![pending, edited_at, id]
-        )?;
-    }
-    
-    Ok(())
+    rows.map(|row_result| {
+        let (to_peer_id, count) = row_result?;
+
+        Ok((to_peer_id, count as usize))
+    }).collect::<anyhow::Result<HashMap<String, usize>>>()
 }
 
-pub fn delete_direct_message(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
+/// Returns the latest message with every peer the identity has exchanged messages with,
+/// most recent conversation first. Direction and unread state are computed relative to
+/// `identity_peer_id`.
+/// Fetches one summary per conversation, either only with blocked peers or only with everyone else.
+pub fn fetch_conversation_summaries(db: Arc<Mutex<Connection>>, identity_peer_id: String, blocked: bool) -> anyhow::Result<Vec<ConversationSummary>> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    db_guard.execute(
-        "DELETE FROM tbl_direct_messages WHERE id=?1;",
-        rusqlite::params![id]
-    )?;
+    let mut query = db_guard.prepare("SELECT peer_id, content, LENGTH(content), created_at, from_peer_id=?1, read, pending, unread_count FROM (
+                                          SELECT *,
+                                              ROW_NUMBER() OVER (PARTITION BY peer_id ORDER BY created_at DESC, id DESC) AS row_number,
+                                              SUM(from_peer_id<>?1 AND read=0) OVER (PARTITION BY peer_id) AS unread_count
+                                          FROM (
+                                              SELECT *, CASE WHEN from_peer_id=?1 THEN to_peer_id ELSE from_peer_id END AS peer_id
+                                              FROM tbl_direct_messages
+                                              WHERE from_peer_id=?1 OR to_peer_id=?1
+                                          )
+                                      )
+                                      WHERE row_number=1
+                                          AND (peer_id IN (
+                                              SELECT tbl_users.peer_id FROM tbl_users
+                                              INNER JOIN tbl_blocked_users ON tbl_blocked_users.user_id=tbl_users.id
+                                          ))=?2
+                                      ORDER BY created_at DESC;")?;
+
+    let rows = query.query_map(rusqlite::params![identity_peer_id, blocked], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, i64>(3)?,
+            row.get::<_, bool>(4)?,
+            row.get::<_, bool>(5)?,
+            row.get::<_, bool>(6)?,
+            row.get::<_, i64>(7)?
+        ))
+    })?;
 
-    Ok(())
+    rows.map(|row_result| {
+        let (peer_id, content, length, created_at, from_me, read, pending, unread_count) = row_result?;
+
+        Ok(ConversationSummary {
+            peer_id,
+            last_message: content,
+            last_message_length: length as usize,
+            last_message_at: created_at,
+            from_me,
+            unread: !from_me && !read,
+            unread_count: unread_count as usize,
+            status: DeliveryStatus::from_message(from_me, pending)
+        })
+    }).collect::<anyhow::Result<Vec<ConversationSummary>>>()
 }
 
-pub fn fetch_post_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<Post> {
+pub fn create_direct_message(db: Arc<Mutex<Connection>>, from_peer_id: String, to_peer_id: String, content: String) -> anyhow::Result<i64> {
+    create_typed_direct_message(db, from_peer_id, to_peer_id, content, ContentType::Text)
+}
+
+pub const BECAME_FRIENDS_MESSAGE: &str = "You are now friends";
+
+/// Records something that happened in the conversation with `peer_id`, e.g. becoming friends.
+/// System messages are never sent. They're stored from and to the peer so they belong to
+/// neither side and are left out of conversation summaries, and as already read.
+pub fn create_system_message(db: Arc<Mutex<Connection>>, peer_id: String, content: String) -> anyhow::Result<i64> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, author_peer_id, content, created_at, edited_at FROM tbl_posts WHERE id=?1;")?;
+    insert_system_message(&db_guard, &peer_id, &content)
+}
 
-    if !query.exists(rusqlite::params![id])? {
-        return Err(anyhow::anyhow!("A post with id {id} was not found."));
-    }
+fn insert_system_message(conn: &Connection, peer_id: &str, content: &str) -> anyhow::Result<i64> {
+    let created_at = chrono::Utc::now().timestamp();
 
-    let (id, author_peer_id, content, created_at, edited_at): (i64, String, String, i64, Option<i64>) = query.query_row(rusqlite::params![id], |row| {
+    conn.execute(
+        "INSERT INTO tbl_direct_messages (from_peer_id, to_peer_id, content, created_at, read, pending, content_type) VALUES (?1, ?1, ?2, ?3, 1, 0, ?4);",
+        rusqlite::params![peer_id, content, created_at, ContentType::System.as_str()]
+    )?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn create_typed_direct_message(db: Arc<Mutex<Connection>>, from_peer_id: String, to_peer_id: String, content: String, content_type: ContentType) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let created_at = chrono::Utc::now().timestamp();
+
+    db_guard.execute(
+        "INSERT INTO tbl_direct_messages (from_peer_id, to_peer_id, content, created_at, content_type) VALUES (?1, ?2, ?3, ?4, ?5);", 
+        rusqlite::params![from_peer_id, to_peer_id, content, created_at, content_type.as_str()]
+    )?;
+    
+    Ok(db_guard.last_insert_rowid())
+}
+
+/// Stores a message we're sending and clears the draft it was composed from.
+pub fn create_outbound_direct_message(db: Arc<Mutex<Connection>>, from_peer_id: String, to_peer_id: String, content: String) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let created_at = chrono::Utc::now().timestamp();
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    transaction.execute(
+        "INSERT INTO tbl_direct_messages (from_peer_id, to_peer_id, content, created_at) VALUES (?1, ?2, ?3, ?4);",
+        rusqlite::params![from_peer_id, to_peer_id, content, created_at]
+    )?;
+
+    let id = transaction.last_insert_rowid();
+
+    transaction.execute(
+        "DELETE FROM tbl_drafts WHERE peer_id=?1;",
+        rusqlite::params![to_peer_id]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(id)
+}
+
+/// Per-day direct message counts for the `days` days up to and including the day of `now`,
+/// oldest first, with quiet days counted as zero. Scoped to one conversation when `peer_id` is set.
+pub fn fetch_message_activity(db: Arc<Mutex<Connection>>, peer_id: Option<String>, days: i64, now: i64) -> anyhow::Result<Vec<MessageActivity>> {
+    if days <= 0 {
+        return Err(anyhow::anyhow!("Activity window must be at least one day, got {days}"));
+    }
+
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let today = chrono::DateTime::from_timestamp(now, 0)
+        .ok_or_else(|| anyhow::anyhow!("Invalid timestamp {now}"))?
+        .date_naive();
+    let first_day = today - chrono::Days::new((days - 1) as u64);
+    let since = first_day.and_hms_opt(0, 0, 0)
+        .ok_or_else(|| anyhow::anyhow!("Invalid start of day {first_day}"))?
+        .and_utc()
+        .timestamp();
+
+    let mut query = db_guard.prepare("SELECT date(created_at, 'unixepoch') AS day, COUNT(*)
+                                      FROM tbl_direct_messages
+                                      WHERE created_at >= ?1 AND (?2 IS NULL OR from_peer_id=?2 OR to_peer_id=?2)
+                                      GROUP BY day;")?;
+
+    let counts = query.query_map(rusqlite::params![since, peer_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?
+        ))
+    })?.collect::<Result<HashMap<String, i64>, _>>()?;
+
+    Ok(first_day.iter_days()
+        .take(days as usize)
+        .map(|day| {
+            let day = day.format("%Y-%m-%d").to_string();
+            let count = counts.get(&day).copied().unwrap_or(0);
+            MessageActivity::new(day, count)
+        })
+        .collect())
+}
+
+pub fn fetch_conversation_storage(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<ConversationStorage> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let (message_count, estimated_bytes) = db_guard.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(CAST(content AS BLOB))), 0)
+            FROM tbl_direct_messages
+            WHERE from_peer_id=?1 OR to_peer_id=?1;",
+        rusqlite::params![peer_id],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    )?;
+
+    Ok(ConversationStorage::new(peer_id, message_count, estimated_bytes))
+}
+
+/// Returns up to `limit` conversations, biggest first.
+pub fn fetch_largest_conversations(db: Arc<Mutex<Connection>>, identity_peer_id: String, limit: usize) -> anyhow::Result<Vec<ConversationStorage>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT peer_id, COUNT(*), SUM(LENGTH(CAST(content AS BLOB))) AS estimated_bytes FROM (
+                                          SELECT content, CASE WHEN from_peer_id=?1 THEN to_peer_id ELSE from_peer_id END AS peer_id
+                                          FROM tbl_direct_messages
+                                      )
+                                      GROUP BY peer_id
+                                      ORDER BY estimated_bytes DESC, peer_id ASC
+                                      LIMIT ?2;")?;
+
+    let rows = query.query_map(rusqlite::params![identity_peer_id, limit as i64], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, i64>(2)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let (peer_id, message_count, estimated_bytes) = row_result?;
+
+        Ok(ConversationStorage::new(peer_id, message_count, estimated_bytes))
+    }).collect::<anyhow::Result<Vec<ConversationStorage>>>()
+}
+
+pub fn save_draft(db: Arc<Mutex<Connection>>, peer_id: String, content: String) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let updated_at = chrono::Utc::now().timestamp();
+
+    db_guard.execute(
+        "INSERT INTO tbl_drafts (peer_id, content, updated_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT(peer_id) DO UPDATE SET content=excluded.content, updated_at=excluded.updated_at;",
+        rusqlite::params![peer_id, content, updated_at]
+    )?;
+
+    Ok(())
+}
+
+pub fn fetch_draft(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<Option<Draft>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let draft = db_guard.query_row(
+        "SELECT peer_id, content, updated_at FROM tbl_drafts WHERE peer_id=?1;",
+        rusqlite::params![peer_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    ).optional()?;
+
+    Ok(draft.map(|(peer_id, content, updated_at)| Draft::new(peer_id, content, updated_at)))
+}
+
+pub fn clear_draft(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    db_guard.execute(
+        "DELETE FROM tbl_drafts WHERE peer_id=?1;",
+        rusqlite::params![peer_id]
+    )?;
+
+    Ok(())
+}
+
+/// Sets how many days messages with `peer_id` are kept for. `None` keeps them indefinitely.
+pub fn set_retention(db: Arc<Mutex<Connection>>, peer_id: String, days: Option<u32>) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    match days {
+        Some(0) => return Err(anyhow::anyhow!("Retention must be at least one day")),
+        Some(days) => db_guard.execute(
+            "INSERT INTO tbl_conversation_retention (peer_id, days) VALUES (?1, ?2)
+                ON CONFLICT(peer_id) DO UPDATE SET days=excluded.days;",
+            rusqlite::params![peer_id, days]
+        )?,
+        None => db_guard.execute(
+            "DELETE FROM tbl_conversation_retention WHERE peer_id=?1;",
+            rusqlite::params![peer_id]
+        )?
+    };
+
+    Ok(())
+}
+
+pub fn get_retention(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<Option<u32>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    Ok(db_guard.query_row(
+        "SELECT days FROM tbl_conversation_retention WHERE peer_id=?1;",
+        rusqlite::params![peer_id],
+        |row| row.get(0)
+    ).optional()?)
+}
+
+/// Deletes direct messages older than their conversation's retention as of `now`, returning
+/// the ids of the deleted messages.
+pub fn delete_expired_direct_messages(db: Arc<Mutex<Connection>>, now: i64) -> anyhow::Result<Vec<i64>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    let ids = {
+        let mut query = transaction.prepare(
+            "SELECT dm.id FROM tbl_direct_messages dm
+                JOIN tbl_conversation_retention r ON r.peer_id IN (dm.from_peer_id, dm.to_peer_id)
+                WHERE dm.created_at < ?1 - r.days * 86400
+                ORDER BY dm.id ASC;"
+        )?;
+
+        let ids = query.query_map(rusqlite::params![now], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        ids
+    };
+
+    for id in &ids {
+        transaction.execute("DELETE FROM tbl_direct_messages WHERE id=?1;", rusqlite::params![id])?;
+    }
+
+    transaction.commit()?;
+
+    Ok(ids)
+}
+
+pub fn update_direct_message(db: Arc<Mutex<Connection>>, id: i64, content: Option<String>, pending: Option<bool>) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let edited_at = chrono::Utc::now().timestamp();
+
+    if let Some(content) = content {
+        db_guard.execute(
+            "UPDATE tbl_direct_messages SET content=?1, edited_at=?2 WHERE id=?3;", 
+            rusqlite::params![content, edited_at, id]
+        )?;
+    }
+
+    if let Some(pending) = pending {
+        db_guard.execute(
+            "UPDATE tbl_direct_messages SET pending=?1, edited_at=?2 WHERE id=?3;", 
+            rusqlite::params![pending, edited_at, id]
+        )?;
+    }
+    
+    Ok(())
+}
+
+/// Marks every unread message `peer_id` sent us as read, returning how many were updated.
+pub fn mark_conversation_read(db: Arc<Mutex<Connection>>, identity_peer_id: String, peer_id: String) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    Ok(db_guard.execute(
+        "UPDATE tbl_direct_messages SET read=1 WHERE from_peer_id=?1 AND to_peer_id=?2 AND read=0;",
+        rusqlite::params![peer_id, identity_peer_id]
+    )?)
+}
+
+/// Marks every unread message sent to us read, across all conversations at once. Returns how
+/// many were marked in each conversation, keyed by the sender's peer id.
+pub fn mark_all_read(db: Arc<Mutex<Connection>>, identity_peer_id: String) -> anyhow::Result<HashMap<String, usize>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    let marked = {
+        let mut query = transaction.prepare("SELECT from_peer_id, COUNT(*) FROM tbl_direct_messages
+                                                 WHERE to_peer_id=?1 AND from_peer_id<>?1 AND read=0
+                                                 GROUP BY from_peer_id;")?;
+
+        let marked = query.query_map(rusqlite::params![identity_peer_id], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?
+            .collect::<Result<HashMap<String, usize>, _>>()?;
+        marked
+    };
+
+    transaction.execute(
+        "UPDATE tbl_direct_messages SET read=1 WHERE to_peer_id=?1 AND from_peer_id<>?1 AND read=0;",
+        rusqlite::params![identity_peer_id]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(marked)
+}
+
+/// Applies a read receipt from `peer_id`: every message delivered to them is now read.
+/// Returns how many were updated.
+pub fn mark_outbound_messages_read(db: Arc<Mutex<Connection>>, identity_peer_id: String, peer_id: String) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    Ok(db_guard.execute(
+        "UPDATE tbl_direct_messages SET read=1 WHERE from_peer_id=?1 AND to_peer_id=?2 AND pending=0 AND read=0;",
+        rusqlite::params![identity_peer_id, peer_id]
+    )?)
+}
+
+pub fn delete_direct_message(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    db_guard.execute(
+        "DELETE FROM tbl_direct_messages WHERE id=?1;",
+        rusqlite::params![id]
+    )?;
+
+    Ok(())
+}
+
+pub fn fetch_post_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<Post> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, author_peer_id, content, created_at, edited_at FROM tbl_posts WHERE id=?1;")?;
+
+    if !query.exists(rusqlite::params![id])? {
+        return Err(anyhow::anyhow!("A post with id {id} was not found."));
+    }
+
+    let (id, author_peer_id, content, created_at, edited_at): (i64, String, String, i64, Option<i64>) = query.query_row(rusqlite::params![id], |row| {
         Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
     })?;
 
@@ -835,16 +2056,49 @@ pub fn fetch_all_posts(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<Post>>
     }).collect::<anyhow::Result<Vec<Post>>>()
 }
 
-pub fn fetch_posts_from_peer(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<Vec<Post>> {
+/// Records a post for delivery to friends who were offline when it was published.
+pub fn buffer_post(db: Arc<Mutex<Connection>>, post_id: i64, peer_ids: &[String]) -> anyhow::Result<usize> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, author_peer_id, content, created_at, edited_at FROM tbl_posts WHERE author_peer_id=?1;")?;
+    let created_at = chrono::Utc::now().timestamp();
+    let transaction = db_guard.unchecked_transaction()?;
+    let mut buffered = 0;
 
-    if !query.exists(rusqlite::params![peer_id])? {
-        return Err(anyhow::anyhow!("No posts were found from peer {peer_id}."));
+    for peer_id in peer_ids {
+        buffered += transaction.execute(
+            "INSERT OR IGNORE INTO tbl_buffered_posts (post_id, peer_id, created_at) VALUES (?1, ?2, ?3);",
+            rusqlite::params![post_id, peer_id, created_at]
+        )?;
     }
 
+    transaction.commit()?;
+
+    Ok(buffered)
+}
+
+/// Counts buffered post deliveries across every peer.
+pub fn count_buffered_posts(db: Arc<Mutex<Connection>>) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let count: i64 = db_guard.query_row("SELECT COUNT(*) FROM tbl_buffered_posts;", (), |row| row.get(0))?;
+
+    Ok(count as usize)
+}
+
+/// Returns the posts buffered for `peer_id`, oldest first, with their current content. They
+/// stay buffered until `remove_buffered_post` confirms each one was delivered.
+pub fn fetch_buffered_posts(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<Vec<Post>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT p.id, p.author_peer_id, p.content, p.created_at, p.edited_at
+                                      FROM tbl_buffered_posts b
+                                      JOIN tbl_posts p ON p.id = b.post_id
+                                      WHERE b.peer_id=?1
+                                      ORDER BY p.created_at ASC, p.id ASC;")?;
+
     let rows = query.query_map(rusqlite::params![peer_id], |row| {
         Ok((
             row.get(0)?,
@@ -870,77 +2124,424 @@ pub fn fetch_posts_from_peer(db: Arc<Mutex<Connection>>, peer_id: String) -> any
     }).collect::<anyhow::Result<Vec<Post>>>()
 }
 
-pub fn create_post(db: Arc<Mutex<Connection>>, author_peer_id: String, content: String) -> anyhow::Result<i64> {
-    let db_guard = db.lock()
-        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
-
-    let created_at = chrono::Utc::now().timestamp();
-
-    db_guard.execute(
-        "INSERT INTO tbl_posts (author_peer_id, content, created_at) VALUES (?1, ?2, ?3);", 
-        rusqlite::params![author_peer_id, content, created_at]
-    )?;
-
-    Ok(db_guard.last_insert_rowid())
-}
-
-pub fn update_post(db: Arc<Mutex<Connection>>, id: i64, content: String) -> anyhow::Result<()> {
+/// Marks a buffered post as delivered to `peer_id`. Returns `false` if it wasn't buffered.
+pub fn remove_buffered_post(db: Arc<Mutex<Connection>>, post_id: i64, peer_id: String) -> anyhow::Result<bool> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let edited_at = chrono::Utc::now().timestamp();
-
-    db_guard.execute(
-        "UPDATE tbl_posts SET content=?1, edited_at=?2 WHERE id=?3;", 
-        rusqlite::params![content, edited_at, id]
+    let removed = db_guard.execute(
+        "DELETE FROM tbl_buffered_posts WHERE post_id=?1 AND peer_id=?2;",
+        rusqlite::params![post_id, peer_id]
     )?;
 
-    Ok(())
+    Ok(removed > 0)
 }
 
-pub fn delete_post(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
+/// Drops every post buffered for `peer_id`, e.g. once it turns out they can't receive them.
+pub fn clear_buffered_posts(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<usize> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    db_guard.execute(
-        "DELETE FROM tbl_posts WHERE id=?1;", 
-        rusqlite::params![id]
-    )?;
-
-    Ok(())
+    Ok(db_guard.execute(
+        "DELETE FROM tbl_buffered_posts WHERE peer_id=?1;",
+        rusqlite::params![peer_id]
+    )?)
 }
 
-pub fn fetch_blocked_users(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<BlockedUser>> {
+pub fn fetch_posts_from_peer(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<Vec<Post>> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, user_id, blocked_at FROM tbl_blocked_users;")?;
+    let mut query = db_guard.prepare("SELECT id, author_peer_id, content, created_at, edited_at FROM tbl_posts WHERE author_peer_id=?1;")?;
 
-    if !query.exists(())? {
-        return Err(anyhow::anyhow!("No blocked user data was found."));
+    if !query.exists(rusqlite::params![peer_id])? {
+        return Err(anyhow::anyhow!("No posts were found from peer {peer_id}."));
     }
 
-    let rows = query.query_map((), |row| {
+    let rows = query.query_map(rusqlite::params![peer_id], |row| {
         Ok((
             row.get(0)?,
             row.get(1)?,
-            row.get(2)?
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?
         ))
     })?;
 
     rows.map(|row_result| {
         let row = row_result?;
-        
-        Ok(BlockedUser::new(
-            row.0,
-            row.1,
-            row.2
-        ))
-    }).collect::<anyhow::Result<Vec<BlockedUser>>>()
 
+        Ok(
+            Post::new(
+                row.0,
+                row.1,
+                row.2,
+                row.3,
+                row.4
+            )
+        )
+    }).collect::<anyhow::Result<Vec<Post>>>()
 }
 
-pub fn fetch_blocked_user_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<BlockedUser> {
+/// Posts containing `search`, ignoring case, newest first. `author` limits the results to one
+/// peer's posts. `%` and `_` in `search` match literally.
+pub fn search_posts(db: Arc<Mutex<Connection>>, search: String, author: Option<String>) -> anyhow::Result<Vec<Post>> {
+    if search.trim().is_empty() {
+        return Err(anyhow::anyhow!("A search query can't be empty."));
+    }
+
+    let pattern = format!("%{}%", search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, author_peer_id, content, created_at, edited_at FROM tbl_posts
+                                          WHERE content LIKE ?1 ESCAPE '\\' AND (?2 IS NULL OR author_peer_id=?2)
+                                          ORDER BY created_at DESC, id DESC;")?;
+
+    let rows = query.query_map(rusqlite::params![pattern, author], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(
+            Post::new(
+                row.0,
+                row.1,
+                row.2,
+                row.3,
+                row.4
+            )
+        )
+    }).collect::<anyhow::Result<Vec<Post>>>()
+}
+
+/// Fetches the posts stored locally for a known peer, oldest first. Posts are kept once
+/// synced, so this works whether or not the peer is online and returns an empty list for a
+/// peer we have no posts from yet.
+pub fn fetch_board_posts(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<Vec<Post>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut user_query = db_guard.prepare("SELECT id FROM tbl_users WHERE peer_id=?1;")?;
+
+    if !user_query.exists(rusqlite::params![peer_id])? {
+        return Err(anyhow::anyhow!("A user with peer id {peer_id} was not found."));
+    }
+
+    let mut query = db_guard.prepare("SELECT id, author_peer_id, content, created_at, edited_at FROM tbl_posts WHERE author_peer_id=?1 ORDER BY created_at ASC, id ASC;")?;
+
+    let rows = query.query_map(rusqlite::params![peer_id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(
+            Post::new(
+                row.0,
+                row.1,
+                row.2,
+                row.3,
+                row.4
+            )
+        )
+    }).collect::<anyhow::Result<Vec<Post>>>()
+}
+
+pub fn fetch_feed_posts(db: Arc<Mutex<Connection>>, identity_peer_id: String) -> anyhow::Result<Vec<Post>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, author_peer_id, content, created_at, edited_at FROM tbl_posts
+                                            WHERE author_peer_id=?1
+                                            OR author_peer_id IN (SELECT u.peer_id FROM tbl_users u INNER JOIN tbl_friends f ON f.user_id=u.id)
+                                            ORDER BY created_at ASC;")?;
+
+    let rows = query.query_map(rusqlite::params![identity_peer_id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(
+            Post::new(
+                row.0,
+                row.1,
+                row.2,
+                row.3,
+                row.4
+            )
+        )
+    }).collect::<anyhow::Result<Vec<Post>>>()
+}
+
+/// The same posts as `fetch_feed_posts`, each with its author's display name, resolved in one
+/// query rather than a lookup per post. An empty feed is an empty list rather than an error.
+pub fn fetch_feed_posts_with_authors(db: Arc<Mutex<Connection>>, identity_peer_id: String) -> anyhow::Result<Vec<PostWithAuthor>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT p.id, p.author_peer_id, COALESCE(NULLIF(u.alias, ''), NULLIF(u.nickname, ''), p.author_peer_id), p.content, p.created_at, p.edited_at
+                                            FROM tbl_posts p
+                                            LEFT JOIN tbl_users u ON u.id=(SELECT id FROM tbl_users WHERE peer_id=p.author_peer_id ORDER BY id ASC LIMIT 1)
+                                            WHERE p.author_peer_id=?1
+                                            OR p.author_peer_id IN (SELECT u.peer_id FROM tbl_users u INNER JOIN tbl_friends f ON f.user_id=u.id)
+                                            ORDER BY p.created_at ASC;")?;
+
+    let rows = query.query_map(rusqlite::params![identity_peer_id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(
+            PostWithAuthor::new(
+                row.0,
+                row.1,
+                row.2,
+                row.3,
+                row.4,
+                row.5
+            )
+        )
+    }).collect::<anyhow::Result<Vec<PostWithAuthor>>>()
+}
+
+pub fn post_exists(db: Arc<Mutex<Connection>>, author_peer_id: String, content: String) -> anyhow::Result<bool> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id FROM tbl_posts WHERE author_peer_id=?1 AND content=?2;")?;
+
+    Ok(query.exists(rusqlite::params![author_peer_id, content])?)
+}
+
+pub fn create_post(db: Arc<Mutex<Connection>>, author_peer_id: String, content: String) -> anyhow::Result<i64> {
+    create_post_at(db, author_peer_id, content, chrono::Utc::now().timestamp())
+}
+
+/// Stores a post with the time its author created it, so later edits synced from the author
+/// can find it again.
+pub fn create_post_at(db: Arc<Mutex<Connection>>, author_peer_id: String, content: String, created_at: i64) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    db_guard.execute(
+        "INSERT INTO tbl_posts (author_peer_id, content, created_at) VALUES (?1, ?2, ?3);", 
+        rusqlite::params![author_peer_id, content, created_at]
+    )?;
+
+    Ok(db_guard.last_insert_rowid())
+}
+
+/// Stores a post received through sync on an already locked connection, unless we already
+/// have it or its author has deleted it. An edited post we hold the original of is updated
+/// in place instead. Returns whether a new post was inserted.
+pub fn insert_synced_post(conn: &Connection, post: &Post) -> anyhow::Result<bool> {
+    let exists = conn.prepare("SELECT id FROM tbl_posts WHERE author_peer_id=?1 AND content=?2;")?
+        .exists(rusqlite::params![post.author_peer_id, post.content])?;
+    let tombstoned = conn.prepare("SELECT id FROM tbl_post_tombstones WHERE author_peer_id=?1 AND content=?2;")?
+        .exists(rusqlite::params![post.author_peer_id, post.content])?;
+
+    if exists || tombstoned {
+        return Ok(false);
+    }
+
+    if post.edited_at.is_some() && update_synced_post(conn, post)? {
+        return Ok(false);
+    }
+
+    conn.execute(
+        "INSERT INTO tbl_posts (author_peer_id, content, created_at, edited_at) VALUES (?1, ?2, ?3, ?4);",
+        rusqlite::params![post.author_peer_id, post.content, post.created_at, post.edited_at]
+    )?;
+
+    Ok(true)
+}
+
+/// Applies an edit synced from a post's author. Row ids differ between peers, so the local
+/// copy is found by its author and original creation time. Returns whether a post was updated.
+pub fn apply_synced_edit(db: Arc<Mutex<Connection>>, post: &Post) -> anyhow::Result<bool> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    update_synced_post(&db_guard, post)
+}
+
+fn update_synced_post(conn: &Connection, post: &Post) -> anyhow::Result<bool> {
+    let edited_at = post.edited_at.unwrap_or_else(|| chrono::Utc::now().timestamp());
+
+    let updated = conn.execute(
+        "UPDATE tbl_posts SET content=?1, edited_at=?2
+            WHERE id=(SELECT id FROM tbl_posts WHERE author_peer_id=?3 AND created_at=?4 ORDER BY id ASC LIMIT 1);",
+        rusqlite::params![post.content, edited_at, post.author_peer_id, post.created_at]
+    )?;
+
+    Ok(updated > 0)
+}
+
+pub fn update_post(db: Arc<Mutex<Connection>>, id: i64, content: String) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let edited_at = chrono::Utc::now().timestamp();
+
+    db_guard.execute(
+        "UPDATE tbl_posts SET content=?1, edited_at=?2 WHERE id=?3;", 
+        rusqlite::params![content, edited_at, id]
+    )?;
+
+    Ok(())
+}
+
+/// Deletes a post, leaving a tombstone behind so the deletion reaches friends on their next sync.
+pub fn delete_post(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let deleted_at = chrono::Utc::now().timestamp();
+    let transaction = db_guard.unchecked_transaction()?;
+
+    transaction.execute(
+        "INSERT INTO tbl_post_tombstones (author_peer_id, content, deleted_at)
+            SELECT author_peer_id, content, ?2 FROM tbl_posts WHERE id=?1
+            ON CONFLICT(author_peer_id, content) DO UPDATE SET deleted_at=excluded.deleted_at;",
+        rusqlite::params![id, deleted_at]
+    )?;
+
+    transaction.execute(
+        "DELETE FROM tbl_posts WHERE id=?1;", 
+        rusqlite::params![id]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Fetches tombstones for posts by `author_peer_id` deleted strictly after `since`.
+pub fn fetch_post_tombstones(db: Arc<Mutex<Connection>>, author_peer_id: String, since: i64) -> anyhow::Result<Vec<PostTombstone>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, author_peer_id, content, deleted_at FROM tbl_post_tombstones WHERE author_peer_id=?1 AND deleted_at>?2 ORDER BY deleted_at ASC;")?;
+
+    let rows = query.query_map(rusqlite::params![author_peer_id, since], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(PostTombstone::new(
+            row.0,
+            row.1,
+            row.2,
+            row.3
+        ))
+    }).collect::<anyhow::Result<Vec<PostTombstone>>>()
+}
+
+pub fn post_is_tombstoned(db: Arc<Mutex<Connection>>, author_peer_id: String, content: String) -> anyhow::Result<bool> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id FROM tbl_post_tombstones WHERE author_peer_id=?1 AND content=?2;")?;
+
+    Ok(query.exists(rusqlite::params![author_peer_id, content])?)
+}
+
+/// Stores a tombstone received from a friend and deletes the matching post, returning how
+/// many posts were removed.
+pub fn apply_post_tombstone(db: Arc<Mutex<Connection>>, author_peer_id: String, content: String, deleted_at: i64) -> anyhow::Result<usize> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    transaction.execute(
+        "INSERT INTO tbl_post_tombstones (author_peer_id, content, deleted_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT(author_peer_id, content) DO UPDATE SET deleted_at=MAX(deleted_at, excluded.deleted_at);",
+        rusqlite::params![author_peer_id, content, deleted_at]
+    )?;
+
+    let deleted = transaction.execute(
+        "DELETE FROM tbl_posts WHERE author_peer_id=?1 AND content=?2;",
+        rusqlite::params![author_peer_id, content]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(deleted)
+}
+
+pub fn fetch_blocked_users(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<BlockedUser>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, user_id, blocked_at FROM tbl_blocked_users;")?;
+
+    if !query.exists(())? {
+        return Err(anyhow::anyhow!("No blocked user data was found."));
+    }
+
+    let rows = query.query_map((), |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+        
+        Ok(BlockedUser::new(
+            row.0,
+            row.1,
+            row.2
+        ))
+    }).collect::<anyhow::Result<Vec<BlockedUser>>>()
+
+}
+
+pub fn fetch_blocked_user_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<BlockedUser> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
@@ -975,7 +2576,524 @@ pub fn fetch_blocked_user_by_user_id(db: Arc<Mutex<Connection>>, user_id: i64) -
         return Err(anyhow::anyhow!("A blocked user with user_id {user_id} was not found."));
     }
 
-    let (id, user_id, blocked_at) = query.query_row(rusqlite::params![user_id], |row| {
+    let (id, user_id, blocked_at) = query.query_row(rusqlite::params![user_id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?
+        ))
+    })?;
+
+    Ok(BlockedUser::new(
+        id,
+        user_id,
+        blocked_at
+    ))
+}
+
+pub fn is_user_blocked(db: Arc<Mutex<Connection>>, user_id: i64) -> anyhow::Result<bool> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, user_id, blocked_at FROM tbl_blocked_users WHERE user_id=?1;")?;
+
+    query.exists(rusqlite::params![user_id])
+        .map_err(|err| anyhow::anyhow!(err.to_string()))
+}
+
+pub fn create_blocked_user(db: Arc<Mutex<Connection>>, user_id: i64) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let blocked_at = chrono::Utc::now().timestamp();
+
+    db_guard.execute(
+        "INSERT INTO tbl_blocked_users (user_id, blocked_at) VALUES (?1, ?2);", 
+        rusqlite::params![user_id, blocked_at]
+    )?;
+
+    Ok(db_guard.last_insert_rowid())
+}
+
+/// Blocks a peer by id, creating a user row with `UNKNOWN_MULTIADDR` if we've never seen
+/// them, and returns the user id. Blocking an already blocked peer is a no-op.
+pub fn block_peer_id(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+    let (user_id, _) = insert_blocked_peer(&transaction, &peer_id)?;
+    transaction.commit()?;
+
+    Ok(user_id)
+}
+
+/// Blocks `peer_id` on an already locked connection, returning the user id and whether the
+/// peer wasn't blocked before.
+pub fn insert_blocked_peer(conn: &Connection, peer_id: &str) -> anyhow::Result<(i64, bool)> {
+    let now = chrono::Utc::now().timestamp();
+
+    let existing_user_id = conn.query_row(
+        "SELECT id FROM tbl_users WHERE peer_id=?1 ORDER BY id ASC LIMIT 1;",
+        rusqlite::params![peer_id],
+        |row| row.get::<_, i64>(0)
+    ).optional()?;
+
+    let user_id = match existing_user_id {
+        Some(id) => id,
+        None => {
+            conn.execute(
+                "INSERT INTO tbl_users (peer_id, multiaddr, is_identity, created_at) VALUES (?1, ?2, 0, ?3);",
+                rusqlite::params![peer_id, UNKNOWN_MULTIADDR, now]
+            )?;
+            conn.last_insert_rowid()
+        }
+    };
+
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO tbl_blocked_users (user_id, blocked_at) VALUES (?1, ?2);",
+        rusqlite::params![user_id, now]
+    )?;
+
+    Ok((user_id, inserted > 0))
+}
+
+pub fn fetch_blocked_peer_ids(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<String>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT tbl_users.peer_id FROM tbl_blocked_users
+                                          INNER JOIN tbl_users ON tbl_users.id=tbl_blocked_users.user_id
+                                          ORDER BY tbl_blocked_users.blocked_at ASC, tbl_blocked_users.id ASC;")?;
+
+    let rows = query.query_map((), |row| row.get::<_, String>(0))?;
+
+    rows.map(|row_result| Ok(row_result?))
+        .collect::<anyhow::Result<Vec<String>>>()
+}
+
+pub fn is_peer_blocked(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<bool> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT tbl_blocked_users.id FROM tbl_blocked_users
+                                          INNER JOIN tbl_users ON tbl_users.id=tbl_blocked_users.user_id
+                                          WHERE tbl_users.peer_id=?1;")?;
+
+    Ok(query.exists(rusqlite::params![peer_id])?)
+}
+
+/// Sets or, with `None`, clears the local name shown for `peer_id` in place of their nickname.
+pub fn set_peer_alias(db: Arc<Mutex<Connection>>, peer_id: String, alias: Option<String>) -> anyhow::Result<()> {
+    let alias = alias
+        .map(|alias| alias.trim().to_string())
+        .filter(|alias| !alias.is_empty());
+
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let updated = db_guard.execute(
+        "UPDATE tbl_users SET alias=?1 WHERE peer_id=?2;",
+        rusqlite::params![alias, peer_id]
+    )?;
+
+    if updated == 0 {
+        return Err(anyhow::anyhow!("A user with peer_id {peer_id} was not found."));
+    }
+
+    Ok(())
+}
+
+/// Pausing a peer keeps the friendship but drops their inbound direct messages until resumed.
+pub fn set_peer_paused(db: Arc<Mutex<Connection>>, peer_id: String, paused: bool) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let updated = db_guard.execute(
+        "UPDATE tbl_users SET paused=?1 WHERE peer_id=?2;",
+        rusqlite::params![paused, peer_id]
+    )?;
+
+    if updated == 0 {
+        return Err(anyhow::anyhow!("A user with peer_id {peer_id} was not found."));
+    }
+
+    Ok(())
+}
+
+pub fn is_peer_paused(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<bool> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id FROM tbl_users WHERE peer_id=?1 AND paused=1;")?;
+
+    Ok(query.exists(rusqlite::params![peer_id])?)
+}
+
+/// The longest avatar accepted, in chars. Room for emoji built from several code points.
+pub const MAX_AVATAR_CHARS: usize = 16;
+
+/// Sets or, with `None`, clears how `peer_id` is shown locally.
+pub fn set_peer_appearance(db: Arc<Mutex<Connection>>, peer_id: String, appearance: PeerAppearance) -> anyhow::Result<()> {
+    if let Some(avatar) = &appearance.avatar {
+        if avatar.trim().is_empty() || avatar.chars().count() > MAX_AVATAR_CHARS {
+            return Err(anyhow::anyhow!("An avatar must be between 1 and {MAX_AVATAR_CHARS} characters."));
+        }
+    }
+
+    if let Some(color) = &appearance.color {
+        let is_hex_color = color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+        if !is_hex_color {
+            return Err(anyhow::anyhow!("{color} is not a #rrggbb color."));
+        }
+    }
+
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let updated = db_guard.execute(
+        "UPDATE tbl_users SET avatar=?1, color=?2 WHERE peer_id=?3;",
+        rusqlite::params![appearance.avatar, appearance.color.map(|color| color.to_lowercase()), peer_id]
+    )?;
+
+    if updated == 0 {
+        return Err(anyhow::anyhow!("A user with peer_id {peer_id} was not found."));
+    }
+
+    Ok(())
+}
+
+pub fn fetch_peer_appearance(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<PeerAppearance> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let appearance = db_guard.query_row(
+        "SELECT avatar, color FROM tbl_users WHERE peer_id=?1 ORDER BY id ASC LIMIT 1;",
+        rusqlite::params![peer_id],
+        |row| Ok(PeerAppearance::new(row.get(0)?, row.get(1)?))
+    ).optional()?;
+
+    appearance.ok_or_else(|| anyhow::anyhow!("A user with peer_id {peer_id} was not found."))
+}
+
+/// Every friend's appearance, keyed by peer id.
+pub fn fetch_friend_appearances(db: Arc<Mutex<Connection>>) -> anyhow::Result<HashMap<String, PeerAppearance>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT peer_id, avatar, color FROM tbl_users
+                                          INNER JOIN tbl_friends ON tbl_friends.user_id=tbl_users.id;")?;
+
+    let appearances = query.query_map((), |row| {
+        Ok((row.get(0)?, PeerAppearance::new(row.get(1)?, row.get(2)?)))
+    })?.collect::<Result<HashMap<String, PeerAppearance>, _>>()?;
+
+    Ok(appearances)
+}
+
+pub fn delete_blocked_user(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    db_guard.execute(
+        "DELETE FROM tbl_blocked_users WHERE id=?1;",
+        rusqlite::params![id]
+    )?;
+
+    Ok(())
+}
+
+pub fn fetch_dead_letter_by_id(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<DeadLetter> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, failed_at, reason FROM tbl_dead_letters WHERE id=?1;")?;
+
+    if !query.exists(rusqlite::params![id])? {
+        return Err(anyhow::anyhow!("A dead letter with id {id} was not found."));
+    }
+
+    let (id, from_peer_id, to_peer_id, content, created_at, failed_at, reason) = query.query_row(rusqlite::params![id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get(6)?
+        ))
+    })?;
+
+    Ok(DeadLetter::new(
+        id,
+        from_peer_id,
+        to_peer_id,
+        content,
+        created_at,
+        failed_at,
+        reason
+    ))
+}
+
+pub fn fetch_all_dead_letters(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<DeadLetter>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, to_peer_id, content, created_at, failed_at, reason FROM tbl_dead_letters ORDER BY failed_at DESC;")?;
+
+    let rows = query.query_map((), |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get(6)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(DeadLetter::new(
+            row.0,
+            row.1,
+            row.2,
+            row.3,
+            row.4,
+            row.5,
+            row.6
+        ))
+    }).collect::<anyhow::Result<Vec<DeadLetter>>>()
+}
+
+pub fn create_dead_letter(db: Arc<Mutex<Connection>>, from_peer_id: String, to_peer_id: String, content: String, reason: String) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let failed_at = chrono::Utc::now().timestamp();
+
+    db_guard.execute(
+        "INSERT INTO tbl_dead_letters (from_peer_id, to_peer_id, content, created_at, failed_at, reason) VALUES (?1, ?2, ?3, ?4, ?4, ?5);",
+        rusqlite::params![from_peer_id, to_peer_id, content, failed_at, reason]
+    )?;
+
+    Ok(db_guard.last_insert_rowid())
+}
+
+/// Moves an undeliverable direct message out of `tbl_direct_messages` into `tbl_dead_letters`,
+/// returning the id of the new dead letter.
+pub fn move_direct_message_to_dead_letters(db: Arc<Mutex<Connection>>, direct_message_id: i64, reason: String) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let failed_at = chrono::Utc::now().timestamp();
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    let moved = transaction.execute(
+        "INSERT INTO tbl_dead_letters (from_peer_id, to_peer_id, content, created_at, failed_at, reason)
+            SELECT from_peer_id, to_peer_id, content, created_at, ?2, ?3 FROM tbl_direct_messages WHERE id=?1;",
+        rusqlite::params![direct_message_id, failed_at, reason]
+    )?;
+
+    if moved == 0 {
+        return Err(anyhow::anyhow!("A direct message with id {direct_message_id} was not found."));
+    }
+
+    let dead_letter_id = transaction.last_insert_rowid();
+
+    transaction.execute(
+        "DELETE FROM tbl_direct_messages WHERE id=?1;",
+        rusqlite::params![direct_message_id]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(dead_letter_id)
+}
+
+/// Moves the oldest pending messages from `from_peer_id` to `to_peer_id` into dead letters
+/// until at most `max_pending` remain, returning the ids of the new dead letters.
+pub fn dead_letter_pending_overflow(db: Arc<Mutex<Connection>>, from_peer_id: String, to_peer_id: String, max_pending: usize) -> anyhow::Result<Vec<i64>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let failed_at = chrono::Utc::now().timestamp();
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    let overflow = {
+        let mut query = transaction.prepare(
+            "SELECT id FROM tbl_direct_messages WHERE from_peer_id=?1 AND to_peer_id=?2 AND pending=1
+                ORDER BY created_at DESC, id DESC LIMIT -1 OFFSET ?3;"
+        )?;
+
+        let ids = query.query_map(rusqlite::params![from_peer_id, to_peer_id, max_pending as i64], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        ids
+    };
+
+    let mut dead_letter_ids = Vec::with_capacity(overflow.len());
+
+    for direct_message_id in overflow {
+        transaction.execute(
+            "INSERT INTO tbl_dead_letters (from_peer_id, to_peer_id, content, created_at, failed_at, reason)
+                SELECT from_peer_id, to_peer_id, content, created_at, ?2, ?3 FROM tbl_direct_messages WHERE id=?1;",
+            rusqlite::params![direct_message_id, failed_at, PENDING_OVERFLOW_REASON]
+        )?;
+
+        dead_letter_ids.push(transaction.last_insert_rowid());
+
+        transaction.execute(
+            "DELETE FROM tbl_direct_messages WHERE id=?1;",
+            rusqlite::params![direct_message_id]
+        )?;
+    }
+
+    transaction.commit()?;
+
+    Ok(dead_letter_ids)
+}
+
+/// Moves a dead letter back into `tbl_direct_messages` as a pending message, returning the id
+/// of the restored direct message.
+pub fn restore_dead_letter(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    let restored = transaction.execute(
+        "INSERT INTO tbl_direct_messages (from_peer_id, to_peer_id, content, created_at, pending)
+            SELECT from_peer_id, to_peer_id, content, created_at, 1 FROM tbl_dead_letters WHERE id=?1;",
+        rusqlite::params![id]
+    )?;
+
+    if restored == 0 {
+        return Err(anyhow::anyhow!("A dead letter with id {id} was not found."));
+    }
+
+    let direct_message_id = transaction.last_insert_rowid();
+
+    transaction.execute(
+        "DELETE FROM tbl_dead_letters WHERE id=?1;",
+        rusqlite::params![id]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(direct_message_id)
+}
+
+/// Most quarantined messages kept from a single sender, and across all senders. Past either,
+/// the oldest are dropped so a stranger can't fill the disk.
+pub const MAX_QUARANTINED_MESSAGES_PER_SENDER: usize = 100;
+pub const MAX_QUARANTINED_MESSAGES: usize = 1000;
+
+pub fn create_quarantined_message(db: Arc<Mutex<Connection>>, from_peer_id: String, content: String) -> anyhow::Result<i64> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let received_at = chrono::Utc::now().timestamp();
+    let transaction = db_guard.unchecked_transaction()?;
+
+    transaction.execute(
+        "INSERT INTO tbl_quarantined_messages (from_peer_id, content, received_at) VALUES (?1, ?2, ?3);",
+        rusqlite::params![from_peer_id, content, received_at]
+    )?;
+    let quarantined_message_id = transaction.last_insert_rowid();
+
+    transaction.execute(
+        "DELETE FROM tbl_quarantined_messages
+         WHERE from_peer_id=?1 AND id NOT IN (SELECT id FROM tbl_quarantined_messages WHERE from_peer_id=?1 ORDER BY id DESC LIMIT ?2);",
+        rusqlite::params![from_peer_id, MAX_QUARANTINED_MESSAGES_PER_SENDER as i64]
+    )?;
+
+    transaction.execute(
+        "DELETE FROM tbl_quarantined_messages
+         WHERE id NOT IN (SELECT id FROM tbl_quarantined_messages ORDER BY id DESC LIMIT ?1);",
+        rusqlite::params![MAX_QUARANTINED_MESSAGES as i64]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(quarantined_message_id)
+}
+
+pub fn fetch_quarantined_messages(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<QuarantinedMessage>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT id, from_peer_id, content, received_at FROM tbl_quarantined_messages ORDER BY received_at ASC, id ASC;")?;
+
+    let rows = query.query_map((), |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?
+        ))
+    })?;
+
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(QuarantinedMessage::new(
+            row.0,
+            row.1,
+            row.2,
+            row.3
+        ))
+    }).collect::<anyhow::Result<Vec<QuarantinedMessage>>>()
+}
+
+pub const SAFETY_NUMBER_CHANGED_MESSAGE: &str = "Safety number changed. Their address answered with a different identity; verify it's still them before trusting new messages";
+
+/// Records that `peer_id`'s address answered as `new_peer_id` and adds a notice to the
+/// conversation. Returns the notice's id, or `None` if this change is already awaiting
+/// acknowledgement.
+pub fn record_key_change(db: Arc<Mutex<Connection>>, peer_id: String, new_peer_id: String) -> anyhow::Result<Option<i64>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let transaction = db_guard.unchecked_transaction()?;
+
+    let already_recorded = transaction.query_row(
+        "SELECT EXISTS(SELECT 1 FROM tbl_key_changes WHERE peer_id=?1 AND new_peer_id=?2);",
+        rusqlite::params![peer_id, new_peer_id],
+        |row| row.get::<_, bool>(0)
+    )?;
+
+    if already_recorded {
+        return Ok(None);
+    }
+
+    transaction.execute(
+        "INSERT INTO tbl_key_changes (peer_id, new_peer_id, detected_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT(peer_id) DO UPDATE SET new_peer_id=excluded.new_peer_id, detected_at=excluded.detected_at;",
+        rusqlite::params![peer_id, new_peer_id, chrono::Utc::now().timestamp()]
+    )?;
+
+    let message_id = insert_system_message(&transaction, &peer_id, SAFETY_NUMBER_CHANGED_MESSAGE)?;
+    transaction.commit()?;
+
+    Ok(Some(message_id))
+}
+
+/// Key changes the user hasn't acknowledged yet.
+pub fn fetch_key_changes(db: Arc<Mutex<Connection>>) -> anyhow::Result<Vec<KeyChange>> {
+    let db_guard = db.lock()
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let mut query = db_guard.prepare("SELECT peer_id, new_peer_id, detected_at FROM tbl_key_changes ORDER BY detected_at DESC;")?;
+
+    let rows = query.query_map((), |row| {
         Ok((
             row.get(0)?,
             row.get(1)?,
@@ -983,47 +3101,52 @@ pub fn fetch_blocked_user_by_user_id(db: Arc<Mutex<Connection>>, user_id: i64) -
         ))
     })?;
 
-    Ok(BlockedUser::new(
-        id,
-        user_id,
-        blocked_at
-    ))
+    rows.map(|row_result| {
+        let row = row_result?;
+
+        Ok(KeyChange::new(
+            row.0,
+            row.1,
+            row.2
+        ))
+    }).collect::<anyhow::Result<Vec<KeyChange>>>()
 }
 
-pub fn is_user_blocked(db: Arc<Mutex<Connection>>, user_id: i64) -> anyhow::Result<bool> {
+/// Clears the warning for `peer_id`, returning whether there was one.
+pub fn acknowledge_key_change(db: Arc<Mutex<Connection>>, peer_id: String) -> anyhow::Result<bool> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let mut query = db_guard.prepare("SELECT id, user_id, blocked_at FROM tbl_blocked_users WHERE user_id=?1;")?;
+    let deleted = db_guard.execute(
+        "DELETE FROM tbl_key_changes WHERE peer_id=?1;",
+        rusqlite::params![peer_id]
+    )?;
 
-    query.exists(rusqlite::params![user_id])
-        .map_err(|err| anyhow::anyhow!(err.to_string()))
+    Ok(deleted > 0)
 }
 
-pub fn create_blocked_user(db: Arc<Mutex<Connection>>, user_id: i64) -> anyhow::Result<i64> {
+/// Moves every quarantined message from `peer_id` into the conversation with them, keeping
+/// when each was received. Returns the number of messages moved.
+pub fn approve_quarantined_sender(db: Arc<Mutex<Connection>>, identity_peer_id: String, peer_id: String) -> anyhow::Result<usize> {
     let db_guard = db.lock()
         .map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let blocked_at = chrono::Utc::now().timestamp();
+    let transaction = db_guard.unchecked_transaction()?;
 
-    db_guard.execute(
-        "INSERT INTO tbl_blocked_users (user_id, blocked_at) VALUES (?1, ?2);", 
-        rusqlite::params![user_id, blocked_at]
+    let moved = transaction.execute(
+        "INSERT INTO tbl_direct_messages (from_peer_id, to_peer_id, content, created_at)
+            SELECT from_peer_id, ?2, content, received_at FROM tbl_quarantined_messages WHERE from_peer_id=?1 ORDER BY id ASC;",
+        rusqlite::params![peer_id, identity_peer_id]
     )?;
 
-    Ok(db_guard.last_insert_rowid())
-}
-
-pub fn delete_blocked_user(db: Arc<Mutex<Connection>>, id: i64) -> anyhow::Result<()> {
-    let db_guard = db.lock()
-        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
-
-    db_guard.execute(
-        "DELETE FROM tbl_blocked_users WHERE id=?1;",
-        rusqlite::params![id]
+    transaction.execute(
+        "DELETE FROM tbl_quarantined_messages WHERE from_peer_id=?1;",
+        rusqlite::params![peer_id]
     )?;
 
-    Ok(())
+    transaction.commit()?;
+
+    Ok(moved)
 }
 
 #[cfg(test)]
@@ -1065,6 +3188,19 @@ pub mod test {
         assert_eq!(identity.last_login, 55);
     }
 
+    #[test]
+    pub fn test_create_identity_stores_creation_timestamp() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        create_identity(db.clone(), vec![1u8, 2, 3], "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into(), 5555)
+            .expect("create_identity failed");
+
+        let identity = fetch_identity(db).expect("fetch_identity failed");
+
+        assert!(identity.created_at > 0);
+        assert_eq!(identity.created_at, identity.last_login);
+    }
+
     #[test]
     pub fn test_create_identity_fails_when_identity_already_exists() {
         let db = init_db(":memory:".into()).expect("db init failed");
@@ -1101,6 +3237,179 @@ pub mod test {
         assert!(identity.last_login != 0);
     }
 
+    #[test]
+    pub fn test_fetch_listen_port_returns_stored_port() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        assert!(fetch_listen_port(db.clone()).is_err());
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        create_identity(db.clone(), vec![10u8, 20, 30, 40], peer_id, 5555).expect("create_identity failed");
+
+        assert_eq!(fetch_listen_port(db.clone()).expect("fetch_listen_port failed"), 5555);
+        assert_eq!(i64::from(fetch_listen_port(db.clone()).unwrap()), fetch_identity(db).unwrap().port_number);
+    }
+
+    #[test]
+    pub fn test_update_listen_port_validates_range() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        create_identity(db.clone(), vec![10u8, 20, 30, 40], peer_id.clone(), 5555).expect("create_identity failed");
+        create_user(db.clone(), peer_id.clone(), "/ip4/0.0.0.0/tcp/5555".into(), true).unwrap();
+
+        update_listen_port(db.clone(), 40000).expect("update_listen_port failed");
+        assert_eq!(fetch_listen_port(db.clone()).unwrap(), 40000);
+        assert_eq!(fetch_user_by_peer_id(db.clone(), peer_id).unwrap().multiaddr, "/ip4/0.0.0.0/tcp/40000");
+
+        assert!(update_listen_port(db.clone(), 80).is_err());
+        assert!(update_listen_port(db.clone(), 65536).is_err());
+        assert_eq!(fetch_listen_port(db).unwrap(), 40000);
+    }
+
+    #[test]
+    pub fn test_peer_appearance_round_trips() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let user_id = create_user(db.clone(), peer_id.clone(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+        create_friend(db.clone(), user_id).unwrap();
+
+        assert_eq!(fetch_peer_appearance(db.clone(), peer_id.clone()).unwrap(), PeerAppearance::default());
+
+        let appearance = PeerAppearance::new(Some("🦊".into()), Some("#FF8800".into()));
+        set_peer_appearance(db.clone(), peer_id.clone(), appearance).expect("set_peer_appearance failed");
+
+        let stored = PeerAppearance::new(Some("🦊".into()), Some("#ff8800".into()));
+        assert_eq!(fetch_peer_appearance(db.clone(), peer_id.clone()).unwrap(), stored);
+        assert_eq!(fetch_friend_appearances(db.clone()).unwrap().get(&peer_id), Some(&stored));
+
+        assert!(set_peer_appearance(db.clone(), peer_id.clone(), PeerAppearance::new(None, Some("orange".into()))).is_err());
+        assert!(set_peer_appearance(db.clone(), peer_id.clone(), PeerAppearance::new(Some("x".repeat(MAX_AVATAR_CHARS + 1)), None)).is_err());
+        assert!(set_peer_appearance(db.clone(), "unknown".into(), PeerAppearance::default()).is_err());
+        assert_eq!(fetch_peer_appearance(db.clone(), peer_id.clone()).unwrap(), stored);
+
+        set_peer_appearance(db.clone(), peer_id.clone(), PeerAppearance::default()).expect("set_peer_appearance failed");
+        assert_eq!(fetch_peer_appearance(db, peer_id).unwrap(), PeerAppearance::default());
+    }
+
+    #[test]
+    pub fn test_search_posts_matches_content_and_scopes_to_author() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        let author = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let other_author = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let older = create_post(db.clone(), author.clone(), "Hiking in the Alps".into()).unwrap();
+        create_post(db.clone(), author.clone(), "Nothing to see here".into()).unwrap();
+        let newer = create_post(db.clone(), other_author.clone(), "more HIKING photos".into()).unwrap();
+        let literal = create_post(db.clone(), other_author.clone(), "100% done".into()).unwrap();
+
+        let ids = |posts: Vec<Post>| posts.iter().map(|post| post.id).collect::<Vec<i64>>();
+
+        assert_eq!(ids(search_posts(db.clone(), "hiking".into(), None).unwrap()), vec![newer, older]);
+        assert_eq!(ids(search_posts(db.clone(), "hiking".into(), Some(author)).unwrap()), vec![older]);
+        assert_eq!(ids(search_posts(db.clone(), "0%".into(), None).unwrap()), vec![literal]);
+        assert!(search_posts(db.clone(), "sailing".into(), Some(other_author)).unwrap().is_empty());
+        assert!(search_posts(db, "  ".into(), None).is_err());
+    }
+
+    #[test]
+    pub fn test_feed_posts_resolve_author_display_name() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let aliased = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let nicknamed = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+        let unnamed = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsC".to_string();
+
+        for peer_id in [&aliased, &nicknamed, &unnamed] {
+            let user_id = create_user(db.clone(), peer_id.clone(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+            create_friend(db.clone(), user_id).unwrap();
+            create_post(db.clone(), peer_id.clone(), format!("Post by {peer_id}")).unwrap();
+        }
+
+        let aliased_user = fetch_user_by_peer_id(db.clone(), aliased.clone()).unwrap();
+        update_user(db.clone(), aliased_user.id, None, Some("Their nickname".into())).unwrap();
+        set_peer_alias(db.clone(), aliased.clone(), Some("My alias".into())).unwrap();
+
+        let nicknamed_user = fetch_user_by_peer_id(db.clone(), nicknamed.clone()).unwrap();
+        update_user(db.clone(), nicknamed_user.id, None, Some("Nick".into())).unwrap();
+
+        let feed = fetch_feed_posts_with_authors(db.clone(), identity).unwrap();
+        let names = feed.iter()
+            .map(|post| (post.author_peer_id.clone(), post.author_display_name.clone()))
+            .collect::<HashMap<String, String>>();
+
+        assert_eq!(feed.len(), 3);
+        assert_eq!(names[&aliased], "My alias");
+        assert_eq!(names[&nicknamed], "Nick");
+        assert_eq!(names[&unnamed], unnamed);
+
+        set_peer_alias(db.clone(), aliased.clone(), None).unwrap();
+        let feed = fetch_feed_posts_with_authors(db, "identity".into()).unwrap();
+        assert!(feed.iter().any(|post| post.author_peer_id == aliased && post.author_display_name == "Their nickname"));
+    }
+
+    #[test]
+    pub fn test_mark_all_read_only_marks_inbound_messages() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let alice = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let bob = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        create_direct_message(db.clone(), alice.clone(), identity.clone(), "Hi".into()).unwrap();
+        create_direct_message(db.clone(), alice.clone(), identity.clone(), "Hello?".into()).unwrap();
+        create_direct_message(db.clone(), bob.clone(), identity.clone(), "Hey".into()).unwrap();
+        let outbound = create_direct_message(db.clone(), identity.clone(), bob.clone(), "Hey Bob".into()).unwrap();
+
+        let marked = mark_all_read(db.clone(), identity.clone()).expect("mark_all_read failed");
+
+        assert_eq!(marked, HashMap::from([(alice.clone(), 2), (bob.clone(), 1)]));
+        assert!(fetch_direct_messages_with_peer(db.clone(), alice).unwrap().iter().all(|dm| dm.read));
+        assert!(fetch_direct_messages_with_peer(db.clone(), bob.clone()).unwrap().iter().filter(|dm| dm.from_peer_id == bob).all(|dm| dm.read));
+        assert!(!fetch_direct_message_by_id(db.clone(), outbound).unwrap().read);
+
+        assert!(mark_all_read(db, identity).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_resolving_friend_requests_records_history() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let accepted = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let denied = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+        let cleared = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsC".to_string();
+        let multiaddr = "/ip4/127.0.0.1/tcp/4001".to_string();
+
+        for peer_id in [&accepted, &denied, &cleared] {
+            create_friend_request(db.clone(), peer_id.clone(), multiaddr.clone(), identity.clone(), multiaddr.clone(), format!("Hi from {peer_id}")).unwrap();
+        }
+
+        assert_eq!(resolve_friend_request(db.clone(), accepted.clone(), identity.clone(), FriendRequestOutcome::Accepted).unwrap(), 1);
+        assert_eq!(resolve_friend_request(db.clone(), denied.clone(), identity.clone(), FriendRequestOutcome::Denied).unwrap(), 1);
+        assert_eq!(resolve_friend_request(db.clone(), denied.clone(), identity.clone(), FriendRequestOutcome::Denied).unwrap(), 0);
+
+        let history = fetch_friend_request_history(db.clone()).unwrap();
+        assert_eq!(history.len(), 2);
+
+        let entry = |peer_id: &String| history.iter().find(|entry| &entry.from_peer_id == peer_id).unwrap().clone();
+        assert_eq!(entry(&accepted).outcome, FriendRequestOutcome::Accepted);
+        assert_eq!(entry(&accepted).to_peer_id, identity);
+        assert_eq!(entry(&accepted).message, format!("Hi from {accepted}"));
+        assert_eq!(entry(&denied).outcome, FriendRequestOutcome::Denied);
+        assert!(entry(&denied).resolved_at >= entry(&denied).requested_at);
+
+        assert_eq!(deny_friend_requests_to_peer(db.clone(), identity.clone()).unwrap(), 1);
+        assert!(fetch_friend_requests_to_peer(db.clone(), identity).is_err());
+
+        let history = fetch_friend_request_history(db).unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history.iter().any(|entry| entry.from_peer_id == cleared && entry.outcome == FriendRequestOutcome::Denied));
+    }
+
     #[test]
     pub fn test_update_identity_correctly_updates_last_login() {
         let db = init_db(":memory:".into()).expect("db init failed");
@@ -1109,7 +3418,7 @@ pub mod test {
 
         let identity_id = create_identity(db.clone(), vec![10u8, 20, 30, 40], peer_id, 5555).expect("create_identity failed");
 
-        update_identity(db.clone(), identity_id, Some(0))
+        update_identity(db.clone(), identity_id, Some(0), None)
             .expect("update_identity failed");
 
         let updated_identity = fetch_identity(db)
@@ -1118,6 +3427,25 @@ pub mod test {
         assert_eq!(updated_identity.last_login, 0);
     }
 
+    #[test]
+    pub fn test_update_identity_correctly_updates_sync_enabled() {
+        let db = init_db(":memory:").expect("db init failed");
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+
+        let identity_id = create_identity(db.clone(), vec![10u8, 20, 30, 40], peer_id, 5555).expect("create_identity failed");
+
+        assert!(fetch_identity(db.clone()).unwrap().sync_enabled);
+
+        update_identity(db.clone(), identity_id, None, Some(false))
+            .expect("update_identity failed");
+
+        let updated_identity = fetch_identity(db)
+            .expect("fetch_identity failed");
+
+        assert!(!updated_identity.sync_enabled);
+    }
+
     #[test]
     pub fn test_fetch_user_by_id_errors_invalid_id() {
         let db = init_db(":memory:".into()).expect("db init failed");
@@ -1322,6 +3650,148 @@ pub mod test {
         assert!(result.is_err(), "expected error when fetching deleted user");
     }
 
+    #[test]
+    pub fn test_upsert_user_address_deduplicates_addresses() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let user_id = create_user(db.clone(), peer_id, "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+
+        upsert_user_address(db.clone(), user_id, "/ip4/10.0.0.1/tcp/4001".into()).unwrap();
+        upsert_user_address(db.clone(), user_id, "/ip4/10.0.0.2/tcp/4001".into()).unwrap();
+        upsert_user_address(db.clone(), user_id, "/ip4/10.0.0.1/tcp/4001".into()).unwrap();
+
+        let addresses = fetch_user_addresses(db.clone(), user_id).unwrap();
+        assert_eq!(addresses.len(), 2);
+
+        delete_user(db.clone(), user_id).unwrap();
+        assert!(fetch_user_addresses(db, user_id).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_delete_user_cascades_to_dependent_rows() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let multiaddr = "/ip4/127.0.0.1/tcp/4001".to_string();
+        let identity_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let user_id = create_user(db.clone(), peer_id.clone(), multiaddr.clone(), false).unwrap();
+        let friend_id = create_friend(db.clone(), user_id).unwrap();
+        let blocked_user_id = create_blocked_user(db.clone(), user_id).unwrap();
+        let friend_request_id = create_friend_request(db.clone(), peer_id.clone(), multiaddr.clone(), identity_peer_id.clone(), multiaddr, "Hi".into()).unwrap();
+        create_direct_message(db.clone(), peer_id.clone(), identity_peer_id, "Hello".into()).unwrap();
+
+        delete_user(db.clone(), user_id).expect("delete_user failed");
+
+        assert!(fetch_user_by_id(db.clone(), user_id).is_err());
+        assert!(fetch_friend_by_id(db.clone(), friend_id).is_err());
+        assert!(fetch_blocked_user_by_id(db.clone(), blocked_user_id).is_err());
+        assert!(fetch_friend_request_by_id(db.clone(), friend_request_id).is_err());
+        assert_eq!(fetch_direct_messages_with_peer(db, peer_id).unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn test_init_db_migrates_user_foreign_keys_to_cascade() {
+        let path = std::env::temp_dir().join(format!("enclave-fk-migration-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let db = Connection::open(&path).unwrap();
+            db.execute_batch("CREATE TABLE tbl_users (id INTEGER PRIMARY KEY, peer_id TEXT NOT NULL, multiaddr TEXT NOT NULL, nickname TEXT, is_identity BOOLEAN DEFAULT 0, created_at INTEGER NOT NULL);
+                              CREATE TABLE tbl_friends (id INTEGER PRIMARY KEY, user_id INTEGER NOT NULL, created_at INTEGER NOT NULL, last_synch INTEGER NOT NULL, FOREIGN KEY (user_id) REFERENCES tbl_users(id), UNIQUE(user_id));
+                              INSERT INTO tbl_users (id, peer_id, multiaddr, created_at) VALUES (1, 'peer', '/ip4/127.0.0.1/tcp/4001', 0);
+                              INSERT INTO tbl_friends (id, user_id, created_at, last_synch) VALUES (1, 1, 0, 0);").unwrap();
+        }
+
+        let db = init_db(path.to_str().unwrap()).expect("DB init failed");
+
+        assert!(user_foreign_key_cascades(&db.lock().unwrap(), "tbl_friends").unwrap());
+        assert!(fetch_friend_by_id(db.clone(), 1).is_ok());
+
+        delete_user(db.clone(), 1).expect("delete_user failed");
+        assert!(fetch_friend_by_id(db.clone(), 1).is_err());
+
+        drop(db);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    pub fn test_init_db_creates_missing_parent_directories() {
+        let root = std::env::temp_dir().join(format!("enclave-nested-{}", rand::random::<u64>()));
+        let path = root.join("data").join("enclave").join("enclave.db");
+
+        let db = init_db(path.to_str().unwrap()).expect("DB init failed");
+
+        assert!(path.exists());
+        assert!(fetch_identity(db.clone()).is_err());
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    pub fn test_buffered_posts_stay_until_delivery_is_confirmed() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let author = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let offline_friend = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let other_friend = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        let first_post_id = create_post(db.clone(), author.clone(), "First".into()).expect("create_post failed");
+        let second_post_id = create_post(db.clone(), author.clone(), "Second".into()).expect("create_post failed");
+
+        assert_eq!(buffer_post(db.clone(), first_post_id, &[offline_friend.clone(), other_friend.clone()]).unwrap(), 2);
+        assert_eq!(buffer_post(db.clone(), second_post_id, std::slice::from_ref(&offline_friend)).unwrap(), 1);
+        assert_eq!(buffer_post(db.clone(), second_post_id, std::slice::from_ref(&offline_friend)).unwrap(), 0);
+
+        let flushed = fetch_buffered_posts(db.clone(), offline_friend.clone()).unwrap();
+        assert_eq!(flushed.iter().map(|post| post.id).collect::<Vec<_>>(), vec![first_post_id, second_post_id]);
+
+        // Posts stay buffered until each delivery is confirmed.
+        assert_eq!(fetch_buffered_posts(db.clone(), offline_friend.clone()).unwrap().len(), 2);
+        assert!(remove_buffered_post(db.clone(), first_post_id, offline_friend.clone()).unwrap());
+        assert!(!remove_buffered_post(db.clone(), first_post_id, offline_friend.clone()).unwrap());
+        assert_eq!(fetch_buffered_posts(db.clone(), offline_friend.clone()).unwrap().iter().map(|post| post.id).collect::<Vec<_>>(), vec![second_post_id]);
+
+        assert_eq!(clear_buffered_posts(db.clone(), offline_friend.clone()).unwrap(), 1);
+        assert!(fetch_buffered_posts(db.clone(), offline_friend).unwrap().is_empty());
+        assert_eq!(fetch_buffered_posts(db, other_friend).unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn test_prune_orphaned_users_only_removes_orphans() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let multiaddr = "/ip4/127.0.0.1/tcp/4001".to_string();
+        let identity_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let friend_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let blocked_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+        let requester_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsC".to_string();
+        let messenger_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsD".to_string();
+        let orphan_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsE".to_string();
+
+        create_user(db.clone(), identity_peer_id.clone(), multiaddr.clone(), true).unwrap();
+        let friend_user_id = create_user(db.clone(), friend_peer_id, multiaddr.clone(), false).unwrap();
+        let blocked_user_id = create_user(db.clone(), blocked_peer_id, multiaddr.clone(), false).unwrap();
+        create_user(db.clone(), requester_peer_id.clone(), multiaddr.clone(), false).unwrap();
+        create_user(db.clone(), messenger_peer_id.clone(), multiaddr.clone(), false).unwrap();
+        let orphan_user_id = create_user(db.clone(), orphan_peer_id, multiaddr.clone(), false).unwrap();
+
+        create_friend(db.clone(), friend_user_id).unwrap();
+        create_blocked_user(db.clone(), blocked_user_id).unwrap();
+        create_friend_request(db.clone(), requester_peer_id, multiaddr.clone(), identity_peer_id.clone(), multiaddr, "Hi".into()).unwrap();
+        create_direct_message(db.clone(), identity_peer_id, messenger_peer_id, "Hello".into()).unwrap();
+
+        let pruned = prune_orphaned_users(db.clone()).expect("prune_orphaned_users failed");
+
+        assert_eq!(pruned, 1);
+        assert!(fetch_user_by_id(db.clone(), orphan_user_id).is_err());
+        assert_eq!(fetch_all_users(db.clone()).unwrap().len(), 5);
+
+        assert_eq!(prune_orphaned_users(db).unwrap(), 0);
+    }
+
     #[test]
     pub fn test_fetch_friend_request_by_id_errors_invalid_id() {
         let db = init_db(":memory:".into()).expect("DB init failed");
@@ -1527,6 +3997,65 @@ pub mod test {
         assert_eq!(updated_pending, false);
     }
 
+    #[test]
+    pub fn test_mark_friend_request_seen_correctly_updates_friend_request_seen() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let multiaddr_1 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let multiaddr_2 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let friend_request_id = create_friend_request(db.clone(), peer_id_1, multiaddr_1, peer_id_2, multiaddr_2, "Test Message".into()).unwrap();
+
+        let initial_seen: bool = {
+            let conn = db.lock().unwrap();
+            conn.query_row(
+                "SELECT seen FROM tbl_friend_requests WHERE id=?1;",
+                [friend_request_id],
+                |r| r.get(0)
+            ).unwrap()
+        };
+
+        assert!(!initial_seen);
+
+        mark_friend_request_seen(db.clone(), friend_request_id).unwrap();
+
+        let updated_seen: bool = {
+            let conn = db.lock().unwrap();
+            conn.query_row(
+                "SELECT seen FROM tbl_friend_requests WHERE id=?1;",
+                [friend_request_id],
+                |r| r.get(0)
+            ).unwrap()
+        };
+
+        assert!(updated_seen);
+    }
+
+    #[test]
+    pub fn test_fetch_friend_requests_to_peer_reflects_seen_state() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let multiaddr_1 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let multiaddr_2 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let peer_id_3 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+        let multiaddr_3 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        let seen_request_id = create_friend_request(db.clone(), peer_id_1.clone(), multiaddr_1, peer_id_2.clone(), multiaddr_2.clone(), "Seen".into()).unwrap();
+        create_friend_request(db.clone(), peer_id_3.clone(), multiaddr_3, peer_id_2.clone(), multiaddr_2, "Unseen".into()).unwrap();
+
+        mark_friend_request_seen(db.clone(), seen_request_id).unwrap();
+
+        let friend_requests = fetch_friend_requests_to_peer(db.clone(), peer_id_2).expect("fetch_friend_requests_to_peer failed");
+
+        assert_eq!(friend_requests.len(), 2);
+        assert!(friend_requests.iter().any(|r| r.from_peer_id == peer_id_1 && r.seen));
+        assert!(friend_requests.iter().any(|r| r.from_peer_id == peer_id_3 && !r.seen));
+    }
+
     #[test]
     pub fn test_delete_friend_request_correctly_deletes_friend_request_data() {
         let db = init_db(":memory:".into()).expect("DB init failed");
@@ -1552,6 +4081,50 @@ pub mod test {
         assert_eq!(remaining_count, 0, "Friend request table should be empty after deletion");
     }
 
+    #[test]
+    pub fn test_count_unseen_friend_requests_only_counts_unseen_inbound_requests() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let multiaddr_1 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let multiaddr_2 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let peer_id_3 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+        let multiaddr_3 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        let seen_request_id = create_friend_request(db.clone(), peer_id_1.clone(), multiaddr_1.clone(), peer_id_2.clone(), multiaddr_2.clone(), "Seen".into()).unwrap();
+        create_friend_request(db.clone(), peer_id_3.clone(), multiaddr_3, peer_id_2.clone(), multiaddr_2.clone(), "Unseen".into()).unwrap();
+        create_friend_request(db.clone(), peer_id_2.clone(), multiaddr_2, peer_id_1, multiaddr_1, "Outbound".into()).unwrap();
+
+        mark_friend_request_seen(db.clone(), seen_request_id).unwrap();
+
+        let count = count_unseen_friend_requests(db, peer_id_2).expect("count_unseen_friend_requests failed");
+
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    pub fn test_delete_friend_requests_to_peer_only_deletes_inbound_requests() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let multiaddr_1 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let multiaddr_2 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let peer_id_3 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+        let multiaddr_3 = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        create_friend_request(db.clone(), peer_id_1.clone(), multiaddr_1.clone(), peer_id_2.clone(), multiaddr_2.clone(), "Inbound 1".into()).unwrap();
+        create_friend_request(db.clone(), peer_id_3, multiaddr_3, peer_id_2.clone(), multiaddr_2.clone(), "Inbound 2".into()).unwrap();
+        create_friend_request(db.clone(), peer_id_2.clone(), multiaddr_2, peer_id_1, multiaddr_1, "Outbound".into()).unwrap();
+
+        let deleted = delete_friend_requests_to_peer(db.clone(), peer_id_2.clone()).expect("delete_friend_requests_to_peer failed");
+
+        assert_eq!(deleted, 2);
+        assert!(fetch_friend_requests_to_peer(db.clone(), peer_id_2.clone()).is_err());
+        assert_eq!(fetch_friend_requests_from_peer(db, peer_id_2).unwrap().len(), 1);
+    }
+
     #[test]
     pub fn test_fetch_friend_by_id_errors_invalid_id() {
         let db = init_db(":memory:".into()).expect("DB init failed");
@@ -1943,7 +4516,79 @@ pub mod test {
             ).unwrap()
         };
 
-        assert_eq!(updated_pending, false);
+        assert_eq!(updated_pending, false);
+    }
+
+    #[test]
+    pub fn test_fetch_pending_direct_messages_only_fetches_outbound_pending_messages() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        create_direct_message(db.clone(), peer_id_1.clone(), peer_id_2.clone(), "Pending".into()).unwrap();
+        let sent_id = create_direct_message(db.clone(), peer_id_1.clone(), peer_id_2.clone(), "Sent".into()).unwrap();
+        create_direct_message(db.clone(), peer_id_2.clone(), peer_id_1.clone(), "Inbound".into()).unwrap();
+
+        update_direct_message(db.clone(), sent_id, None, Some(false)).unwrap();
+
+        let pending = fetch_pending_direct_messages(db.clone(), peer_id_1, peer_id_2).expect("fetch_pending_direct_messages failed");
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].content, "Pending");
+    }
+
+    #[test]
+    pub fn test_fetch_direct_messages_in_range_only_fetches_messages_within_range() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let peer_id_3 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        for (content, created_at) in [("Before", 1000), ("Start", 2000), ("Middle", 2500), ("End", 3000), ("After", 4000)] {
+            let id = create_direct_message(db.clone(), peer_id_1.clone(), peer_id_2.clone(), content.into()).unwrap();
+            db.lock().unwrap().execute("UPDATE tbl_direct_messages SET created_at=?1 WHERE id=?2;", params![created_at, id]).unwrap();
+        }
+
+        let other_id = create_direct_message(db.clone(), peer_id_1.clone(), peer_id_3.clone(), "Other peer".into()).unwrap();
+        db.lock().unwrap().execute("UPDATE tbl_direct_messages SET created_at=2500 WHERE id=?1;", params![other_id]).unwrap();
+
+        let messages = fetch_direct_messages_in_range(db.clone(), peer_id_2, 2000, 3000).expect("fetch_direct_messages_in_range failed");
+
+        let contents = messages.iter().map(|m| m.content.as_str()).collect::<Vec<_>>();
+        assert_eq!(contents, vec!["Start", "Middle", "End"]);
+    }
+
+    #[test]
+    pub fn test_fetch_direct_messages_in_range_rejects_inverted_range() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+
+        assert!(fetch_direct_messages_in_range(db, peer_id, 3000, 2000).is_err());
+    }
+
+    #[test]
+    pub fn test_count_pending_direct_messages_counts_buffered_messages_per_peer() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let peer_id_3 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        create_direct_message(db.clone(), peer_id_1.clone(), peer_id_2.clone(), "Offline 1".into()).unwrap();
+        create_direct_message(db.clone(), peer_id_1.clone(), peer_id_2.clone(), "Offline 2".into()).unwrap();
+        let delivered_id = create_direct_message(db.clone(), peer_id_1.clone(), peer_id_3.clone(), "Delivered".into()).unwrap();
+        create_direct_message(db.clone(), peer_id_3.clone(), peer_id_1.clone(), "Inbound".into()).unwrap();
+
+        update_direct_message(db.clone(), delivered_id, None, Some(false)).unwrap();
+
+        let counts = count_pending_direct_messages(db.clone(), peer_id_1).expect("count_pending_direct_messages failed");
+
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts.get(&peer_id_2), Some(&2));
+        assert_eq!(counts.get(&peer_id_3), None);
     }
 
     #[test]
@@ -2076,6 +4721,40 @@ pub mod test {
         assert!(posts.iter().any(|p| p.content == "User Post 2"));
     }
 
+    #[test]
+    pub fn test_fetch_feed_posts_returns_empty_feed_without_post_data() {
+        let db = init_db(":memory:".into()).expect("DB init failed");
+
+        let posts = fetch_feed_posts(db.clone(), "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into()).expect("fetch_feed_posts failed");
+
+        assert!(posts.is_empty());
+    }
+
+    #[test]
+    pub fn test_fetch_feed_posts_only_fetches_posts_from_friends_and_self() {
+        let db = init_db(":memory:".into()).expect("DB init failed");
+
+        let identity_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let friend_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let stranger_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        create_user(db.clone(), identity_peer_id.clone(), "/ip4/127.0.0.1/tcp/4001".into(), true).unwrap();
+        let friend_user_id = create_user(db.clone(), friend_peer_id.clone(), "/ip4/127.0.0.1/tcp/4002".into(), false).unwrap();
+        create_user(db.clone(), stranger_peer_id.clone(), "/ip4/127.0.0.1/tcp/4003".into(), false).unwrap();
+        create_friend(db.clone(), friend_user_id).unwrap();
+
+        create_post(db.clone(), identity_peer_id.clone(), "My Post".into()).unwrap();
+        create_post(db.clone(), friend_peer_id.clone(), "Friend Post".into()).unwrap();
+        create_post(db.clone(), stranger_peer_id.clone(), "Stranger Post".into()).unwrap();
+
+        let posts = fetch_feed_posts(db.clone(), identity_peer_id).expect("fetch_feed_posts failed");
+
+        assert_eq!(posts.len(), 2);
+        assert!(posts.iter().any(|p| p.content == "My Post"));
+        assert!(posts.iter().any(|p| p.content == "Friend Post"));
+        assert!(!posts.iter().any(|p| p.content == "Stranger Post"));
+    }
+
     #[test]
     pub fn test_create_post_correctly_inserts_post_data() {
         let db = init_db(":memory:".into()).expect("DB init failed");
@@ -2122,6 +4801,240 @@ pub mod test {
         assert!(result.unwrap_err().to_string().contains("was not found"));
     }
 
+    #[test]
+    pub fn test_fetch_conversation_summaries_computes_direction_and_status() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_a = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let peer_b = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        let insert = |from: &str, to: &str, content: &str, created_at: i64, read: bool, pending: bool| {
+            db.lock().unwrap().execute(
+                "INSERT INTO tbl_direct_messages (from_peer_id, to_peer_id, content, created_at, read, pending) VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+                params![from, to, content, created_at, read, pending]
+            ).unwrap();
+        };
+
+        // Peer A: incoming then an outgoing reply that hasn't been delivered yet.
+        insert(&peer_a, &identity, "Hi there", 100, false, false);
+        insert(&identity, &peer_a, "Héllo", 200, false, true);
+        // Peer B: outgoing then two unread incoming messages.
+        insert(&identity, &peer_b, "Ping", 150, false, false);
+        insert(&peer_b, &identity, "Pong", 250, false, false);
+        insert(&peer_b, &identity, "Still there?", 300, false, false);
+
+        let summaries = fetch_conversation_summaries(db.clone(), identity.clone(), false).unwrap();
+
+        assert_eq!(summaries.len(), 2);
+
+        let summary_b = &summaries[0];
+        assert_eq!(summary_b.peer_id, peer_b);
+        assert_eq!(summary_b.last_message, "Still there?");
+        assert_eq!(summary_b.last_message_length, 12);
+        assert!(!summary_b.from_me);
+        assert!(summary_b.unread);
+        assert_eq!(summary_b.unread_count, 2);
+        assert_eq!(summary_b.status, DeliveryStatus::Received);
+
+        let summary_a = &summaries[1];
+        assert_eq!(summary_a.peer_id, peer_a);
+        assert_eq!(summary_a.last_message_length, 5);
+        assert!(summary_a.from_me);
+        assert!(!summary_a.unread);
+        assert_eq!(summary_a.unread_count, 1);
+        assert_eq!(summary_a.status, DeliveryStatus::Pending);
+
+        db.lock().unwrap().execute("UPDATE tbl_direct_messages SET pending=0 WHERE to_peer_id=?1;", params![peer_a]).unwrap();
+
+        let summaries = fetch_conversation_summaries(db, identity, false).unwrap();
+        assert_eq!(summaries[1].status, DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    pub fn test_fetch_conversation_summaries_separates_blocked_peers() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_a = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let peer_b = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        create_direct_message(db.clone(), peer_a.clone(), identity.clone(), "Hi".into()).unwrap();
+        create_direct_message(db.clone(), identity.clone(), peer_b.clone(), "Hey".into()).unwrap();
+
+        create_user(db.clone(), peer_a.clone(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+        let user_b = create_user(db.clone(), peer_b.clone(), "/ip4/127.0.0.1/tcp/4002".into(), false).unwrap();
+        create_blocked_user(db.clone(), user_b).unwrap();
+
+        let conversations = fetch_conversation_summaries(db.clone(), identity.clone(), false).unwrap();
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].peer_id, peer_a);
+
+        let blocked_conversations = fetch_conversation_summaries(db, identity, true).unwrap();
+        assert_eq!(blocked_conversations.len(), 1);
+        assert_eq!(blocked_conversations[0].peer_id, peer_b);
+    }
+
+    #[test]
+    pub fn test_fetch_board_posts_reads_cached_posts_for_offline_friend() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let offline_friend = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let quiet_friend = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+        let stranger = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsC".to_string();
+
+        let offline_user = create_user(db.clone(), offline_friend.clone(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+        let quiet_user = create_user(db.clone(), quiet_friend.clone(), "/ip4/127.0.0.1/tcp/4002".into(), false).unwrap();
+        create_friend(db.clone(), offline_user).unwrap();
+        create_friend(db.clone(), quiet_user).unwrap();
+
+        create_post(db.clone(), offline_friend.clone(), "Synced before going offline".into()).unwrap();
+        create_post(db.clone(), offline_friend.clone(), "Another one".into()).unwrap();
+
+        let posts = fetch_board_posts(db.clone(), offline_friend).expect("fetch_board_posts failed");
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[0].content, "Synced before going offline");
+
+        assert!(fetch_board_posts(db.clone(), quiet_friend).expect("fetch_board_posts failed").is_empty());
+        assert!(fetch_board_posts(db, stranger).is_err());
+    }
+
+    #[test]
+    pub fn test_apply_synced_edit_only_updates_authors_post() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let friend_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let own_post_id = create_post_at(db.clone(), identity_peer_id, "Mine".into(), 100).unwrap();
+        let friend_post_id = create_post_at(db.clone(), friend_peer_id.clone(), "Theirs".into(), 200).unwrap();
+
+        // The friend's row id for their post happens to match our own post's id locally.
+        let edit = Post::new(own_post_id, friend_peer_id.clone(), "Theirs, edited".into(), 200, Some(300));
+        assert!(apply_synced_edit(db.clone(), &edit).expect("apply_synced_edit failed"));
+
+        assert_eq!(fetch_post_by_id(db.clone(), own_post_id).unwrap().content, "Mine");
+        let edited = fetch_post_by_id(db.clone(), friend_post_id).unwrap();
+        assert_eq!(edited.content, "Theirs, edited");
+        assert_eq!(edited.edited_at, Some(300));
+
+        let unknown = Post::new(friend_post_id, friend_peer_id, "Never seen".into(), 999, Some(1000));
+        assert!(!apply_synced_edit(db, &unknown).expect("apply_synced_edit failed"));
+    }
+
+    #[test]
+    pub fn test_insert_synced_post_dedupes_resent_and_edited_posts() {
+        let db = init_db(":memory:").expect("DB init failed");
+        let author = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let original = Post::new(7, author.clone(), "Hello".into(), 100, None);
+        let edited = Post::new(7, author.clone(), "Hello again".into(), 100, Some(150));
+
+        let conn = db.lock().unwrap();
+        assert!(insert_synced_post(&conn, &original).unwrap());
+        assert!(!insert_synced_post(&conn, &original).unwrap());
+        assert!(!insert_synced_post(&conn, &edited).unwrap());
+        drop(conn);
+
+        let contents = db.lock().unwrap()
+            .prepare("SELECT content FROM tbl_posts;").unwrap()
+            .query_map((), |row| row.get::<_, String>(0)).unwrap()
+            .collect::<Result<Vec<String>, _>>().unwrap();
+        assert_eq!(contents, vec!["Hello again"]);
+    }
+
+    #[test]
+    pub fn test_fetch_board_posts_only_returns_that_peers_posts() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let friend_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let other_friend_peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        create_user(db.clone(), identity_peer_id.clone(), "/ip4/127.0.0.1/tcp/4001".into(), true).unwrap();
+        let friend_user_id = create_user(db.clone(), friend_peer_id.clone(), "/ip4/127.0.0.1/tcp/4002".into(), false).unwrap();
+        let other_friend_user_id = create_user(db.clone(), other_friend_peer_id.clone(), "/ip4/127.0.0.1/tcp/4003".into(), false).unwrap();
+        create_friend(db.clone(), friend_user_id).unwrap();
+        create_friend(db.clone(), other_friend_user_id).unwrap();
+
+        create_post(db.clone(), identity_peer_id, "My Post".into()).unwrap();
+        create_post(db.clone(), friend_peer_id.clone(), "Friend Post".into()).unwrap();
+        create_post(db.clone(), other_friend_peer_id, "Other Friend Post".into()).unwrap();
+
+        let posts = fetch_board_posts(db, friend_peer_id.clone()).expect("fetch_board_posts failed");
+
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].author_peer_id, friend_peer_id);
+        assert_eq!(posts[0].content, "Friend Post");
+    }
+
+    #[test]
+    pub fn test_block_peer_id_persists_block_for_unknown_peer() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let other = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        assert!(!is_peer_blocked(db.clone(), peer_id.clone()).unwrap());
+
+        let user_id = block_peer_id(db.clone(), peer_id.clone()).expect("block_peer_id failed");
+        assert_eq!(block_peer_id(db.clone(), peer_id.clone()).expect("block_peer_id failed"), user_id);
+
+        assert!(is_peer_blocked(db.clone(), peer_id.clone()).unwrap());
+        assert!(!is_peer_blocked(db.clone(), other).unwrap());
+        assert_eq!(fetch_blocked_users(db.clone()).unwrap().len(), 1);
+
+        let user = fetch_user_by_peer_id(db.clone(), peer_id).unwrap();
+        assert_eq!(user.id, user_id);
+        assert!(!user.has_known_address());
+
+        // Placeholder users are kept while blocked.
+        assert_eq!(prune_orphaned_users(db).unwrap(), 0);
+    }
+
+    #[test]
+    pub fn test_friend_limit_rejects_new_friends_beyond_limit() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        create_identity(db.clone(), vec![1, 2, 3], "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into(), 4001).unwrap();
+
+        let user_a = create_user(db.clone(), "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".into(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+        let user_b = create_user(db.clone(), "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".into(), "/ip4/127.0.0.1/tcp/4002".into(), false).unwrap();
+        create_friend(db.clone(), user_a).unwrap();
+        create_friend(db.clone(), user_b).unwrap();
+
+        assert_eq!(fetch_identity(db.clone()).unwrap().max_friends, None);
+        assert_eq!(friend_limit_reached(db.clone()).unwrap(), None);
+
+        set_max_friends(db.clone(), Some(3)).unwrap();
+        assert_eq!(friend_limit_reached(db.clone()).unwrap(), None);
+
+        set_max_friends(db.clone(), Some(2)).unwrap();
+        assert_eq!(friend_limit_reached(db.clone()).unwrap(), Some(2));
+
+        // Lowering the limit below the current count keeps existing friends.
+        set_max_friends(db.clone(), Some(1)).unwrap();
+        assert_eq!(friend_limit_reached(db.clone()).unwrap(), Some(1));
+        assert_eq!(fetch_all_friends(db.clone()).unwrap().len(), 2);
+
+        set_max_friends(db.clone(), None).unwrap();
+        assert_eq!(friend_limit_reached(db).unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_post_exists_matches_author_and_content() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        create_post(db.clone(), peer_id_1.clone(), "Hello World".into()).unwrap();
+
+        assert!(post_exists(db.clone(), peer_id_1.clone(), "Hello World".into()).unwrap());
+        assert!(!post_exists(db.clone(), peer_id_1, "Goodbye".into()).unwrap());
+        assert!(!post_exists(db, peer_id_2, "Hello World".into()).unwrap());
+    }
+
     #[test]
     pub fn test_fetch_blocked_users_errors_no_blocked_user_data() {
         let db = init_db(":memory:".into()).unwrap();
@@ -2372,4 +5285,411 @@ pub mod test {
 
         assert_eq!(count, 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_move_direct_message_to_dead_letters_correctly_moves_message() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let direct_message_id = create_direct_message(db.clone(), peer_id_1.clone(), peer_id_2.clone(), "Undeliverable".into()).unwrap();
+        let direct_message = fetch_direct_message_by_id(db.clone(), direct_message_id).unwrap();
+
+        let dead_letter_id = move_direct_message_to_dead_letters(db.clone(), direct_message_id, "Recipient is not a friend".into())
+            .expect("move_direct_message_to_dead_letters failed");
+
+        let dead_letter = fetch_dead_letter_by_id(db.clone(), dead_letter_id).expect("fetch_dead_letter_by_id failed");
+
+        assert_eq!(dead_letter.from_peer_id, peer_id_1);
+        assert_eq!(dead_letter.to_peer_id, peer_id_2);
+        assert_eq!(dead_letter.content, "Undeliverable");
+        assert_eq!(dead_letter.created_at, direct_message.created_at);
+        assert_eq!(dead_letter.reason, "Recipient is not a friend");
+        assert!(fetch_direct_message_by_id(db.clone(), direct_message_id).is_err());
+        assert_eq!(fetch_all_dead_letters(db).unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn test_approve_quarantined_sender_moves_messages_into_conversation() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let stranger = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let other = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        create_quarantined_message(db.clone(), stranger.clone(), "Hi, we met at the meetup".into()).unwrap();
+        create_quarantined_message(db.clone(), stranger.clone(), "Add me?".into()).unwrap();
+        create_quarantined_message(db.clone(), other.clone(), "Buy now".into()).unwrap();
+        assert_eq!(fetch_quarantined_messages(db.clone()).unwrap().len(), 3);
+
+        let moved = approve_quarantined_sender(db.clone(), identity.clone(), stranger.clone()).expect("approve_quarantined_sender failed");
+        assert_eq!(moved, 2);
+
+        let conversation = fetch_direct_messages_with_peer(db.clone(), stranger.clone()).unwrap();
+        assert_eq!(conversation.iter().map(|dm| dm.content.as_str()).collect::<Vec<_>>(), vec!["Hi, we met at the meetup", "Add me?"]);
+        assert!(conversation.iter().all(|dm| dm.from_peer_id == stranger && dm.to_peer_id == identity));
+
+        let remaining = fetch_quarantined_messages(db).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].from_peer_id, other);
+    }
+
+    #[test]
+    pub fn test_create_quarantined_message_drops_oldest_past_caps() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let spammer = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        for i in 0..=MAX_QUARANTINED_MESSAGES_PER_SENDER {
+            create_quarantined_message(db.clone(), spammer.clone(), format!("Spam {i}")).unwrap();
+        }
+
+        let quarantined = fetch_quarantined_messages(db.clone()).unwrap();
+        assert_eq!(quarantined.len(), MAX_QUARANTINED_MESSAGES_PER_SENDER);
+        assert_eq!(quarantined[0].content, "Spam 1");
+
+        // Enough distinct senders to pass the total cap without any reaching their own.
+        for i in 0..MAX_QUARANTINED_MESSAGES {
+            create_quarantined_message(db.clone(), format!("sender-{}", i % 20), format!("Hello {i}")).unwrap();
+        }
+
+        let quarantined = fetch_quarantined_messages(db).unwrap();
+        assert_eq!(quarantined.len(), MAX_QUARANTINED_MESSAGES);
+        assert!(quarantined.iter().all(|message| message.from_peer_id != spammer));
+    }
+
+    #[test]
+    pub fn test_move_direct_message_to_dead_letters_errors_invalid_id() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let result = move_direct_message_to_dead_letters(db.clone(), 999, "Reason".into());
+
+        assert!(result.is_err());
+        assert!(fetch_all_dead_letters(db).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_pending_overflow_dead_letters_oldest_messages() {
+        let db = init_db(":memory:").expect("DB init failed");
+        let local_peer_id = "12D3KooWLocal".to_string();
+        let peer_id = "12D3KooWPeer".to_string();
+
+        assert_eq!(get_max_pending_direct_messages(db.clone()).unwrap(), DEFAULT_MAX_PENDING_DIRECT_MESSAGES);
+        assert!(set_max_pending_direct_messages(db.clone(), 0).is_err());
+        set_max_pending_direct_messages(db.clone(), 2).expect("set_max_pending_direct_messages failed");
+        let max_pending = get_max_pending_direct_messages(db.clone()).unwrap();
+
+        for content in ["First", "Second", "Third"] {
+            create_outbound_direct_message(db.clone(), local_peer_id.clone(), peer_id.clone(), content.into()).unwrap();
+        }
+
+        let dead_letter_ids = dead_letter_pending_overflow(db.clone(), local_peer_id.clone(), peer_id.clone(), max_pending)
+            .expect("dead_letter_pending_overflow failed");
+        assert_eq!(dead_letter_ids.len(), 1);
+
+        let dead_letter = fetch_dead_letter_by_id(db.clone(), dead_letter_ids[0]).unwrap();
+        assert_eq!(dead_letter.content, "First");
+        assert_eq!(dead_letter.reason, PENDING_OVERFLOW_REASON);
+
+        let pending = fetch_pending_direct_messages(db.clone(), local_peer_id.clone(), peer_id.clone()).unwrap();
+        assert_eq!(pending.iter().map(|dm| dm.content.as_str()).collect::<Vec<_>>(), vec!["Second", "Third"]);
+
+        assert!(dead_letter_pending_overflow(db, local_peer_id, peer_id, max_pending).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_restore_dead_letter_correctly_requeues_pending_message() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id_1 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_id_2 = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let dead_letter_id = create_dead_letter(db.clone(), peer_id_1.clone(), peer_id_2.clone(), "Retry me".into(), "Timeout".into()).unwrap();
+
+        let direct_message_id = restore_dead_letter(db.clone(), dead_letter_id).expect("restore_dead_letter failed");
+
+        let direct_message = fetch_direct_message_by_id(db.clone(), direct_message_id).expect("fetch_direct_message_by_id failed");
+
+        assert_eq!(direct_message.content, "Retry me");
+        assert!(direct_message.pending);
+        assert!(fetch_dead_letter_by_id(db.clone(), dead_letter_id).is_err());
+        assert_eq!(fetch_pending_direct_messages(db, peer_id_1, peer_id_2).unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn test_settings_round_trip() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        set_setting(db.clone(), "theme", "dark").expect("set_setting failed");
+        assert_eq!(get_setting(db.clone(), "theme").unwrap(), Some("dark".into()));
+
+        set_setting(db.clone(), "theme", "light").expect("set_setting failed");
+        assert_eq!(get_setting(db.clone(), "theme").unwrap(), Some("light".into()));
+    }
+
+    #[test]
+    pub fn test_set_setting_upserts_single_row() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        set_setting(db.clone(), "log_level", "info").unwrap();
+        set_setting(db.clone(), "log_level", "debug").unwrap();
+
+        let count: i64 = db.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM tbl_settings WHERE key='log_level';", (), |row| row.get(0))
+            .unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(get_setting(db, "log_level").unwrap(), Some("debug".into()));
+    }
+
+    #[test]
+    pub fn test_get_setting_returns_none_for_missing_key() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        assert_eq!(get_setting(db, "missing").unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_delete_setting_removes_only_that_key() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        set_setting(db.clone(), "theme", "dark").unwrap();
+        set_setting(db.clone(), "log_level", "info").unwrap();
+
+        delete_setting(db.clone(), "theme").expect("delete_setting failed");
+        delete_setting(db.clone(), "missing").expect("deleting a missing key should be a no-op");
+
+        assert_eq!(get_setting(db.clone(), "theme").unwrap(), None);
+        assert_eq!(get_setting(db, "log_level").unwrap(), Some("info".into()));
+    }
+
+    #[test]
+    pub fn test_auto_start_defaults_to_off() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        assert!(!get_auto_start(db.clone()).unwrap());
+
+        set_auto_start(db.clone(), true).expect("set_auto_start failed");
+        assert!(get_auto_start(db.clone()).unwrap());
+
+        set_auto_start(db.clone(), false).expect("set_auto_start failed");
+        assert!(!get_auto_start(db).unwrap());
+    }
+
+    #[test]
+    pub fn test_is_first_run_until_identity_is_created() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        assert!(is_first_run(db.clone()).unwrap());
+
+        create_identity(db.clone(), vec![1, 2, 3], "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into(), 4001).unwrap();
+        assert!(!is_first_run(db).unwrap());
+    }
+
+    #[test]
+    pub fn test_complete_onboarding_ends_first_run() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        complete_onboarding(db.clone()).expect("complete_onboarding failed");
+        assert!(!is_first_run(db).unwrap());
+    }
+
+    #[test]
+    pub fn test_save_draft_overwrites_and_clears() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        assert!(fetch_draft(db.clone(), peer_id.clone()).unwrap().is_none());
+
+        save_draft(db.clone(), peer_id.clone(), "Hel".into()).expect("save_draft failed");
+        save_draft(db.clone(), peer_id.clone(), "Hello".into()).expect("save_draft failed");
+        assert_eq!(fetch_draft(db.clone(), peer_id.clone()).unwrap().unwrap().content, "Hello");
+
+        clear_draft(db.clone(), peer_id.clone()).expect("clear_draft failed");
+        assert!(fetch_draft(db, peer_id).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn test_delete_expired_direct_messages_only_deletes_old_messages_for_configured_peer() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let other = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        let old_inbound = create_direct_message(db.clone(), peer.clone(), identity.clone(), "Old".into()).unwrap();
+        let old_outbound = create_outbound_direct_message(db.clone(), identity.clone(), peer.clone(), "Old reply".into()).unwrap();
+        let recent = create_direct_message(db.clone(), peer.clone(), identity.clone(), "Recent".into()).unwrap();
+        let other_old = create_direct_message(db.clone(), other.clone(), identity.clone(), "Old but kept".into()).unwrap();
+
+        let now = chrono::Utc::now().timestamp();
+        {
+            let db_guard = db.lock().unwrap();
+            for id in [old_inbound, old_outbound, other_old] {
+                db_guard.execute("UPDATE tbl_direct_messages SET created_at=?1 WHERE id=?2;", rusqlite::params![now - 31 * 86400, id]).unwrap();
+            }
+        }
+
+        set_retention(db.clone(), peer.clone(), Some(30)).expect("set_retention failed");
+        assert_eq!(get_retention(db.clone(), peer.clone()).unwrap(), Some(30));
+        assert_eq!(get_retention(db.clone(), other.clone()).unwrap(), None);
+        assert!(set_retention(db.clone(), other.clone(), Some(0)).is_err());
+
+        let expired = delete_expired_direct_messages(db.clone(), now).expect("delete_expired_direct_messages failed");
+        assert_eq!(expired, vec![old_inbound, old_outbound]);
+
+        assert_eq!(fetch_direct_message_by_id(db.clone(), recent).unwrap().content, "Recent");
+        assert_eq!(fetch_direct_message_by_id(db.clone(), other_old).unwrap().content, "Old but kept");
+
+        set_retention(db.clone(), peer.clone(), None).unwrap();
+        assert_eq!(get_retention(db, peer).unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_befriend_adds_system_message_to_conversation() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let user_id = create_user(db.clone(), peer.clone(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+
+        let friend_id = befriend(db.clone(), user_id).expect("befriend failed");
+        assert_eq!(fetch_friend_by_user_id(db.clone(), user_id).unwrap().id, friend_id);
+
+        let conversation = fetch_direct_messages_with_peer(db.clone(), peer.clone()).unwrap();
+        assert_eq!(conversation.len(), 1);
+        assert_eq!(conversation[0].content, BECAME_FRIENDS_MESSAGE);
+        assert_eq!(conversation[0].content_type, ContentType::System);
+        assert!(conversation[0].read && !conversation[0].pending);
+
+        // Notices alone don't put a conversation in the list.
+        assert!(fetch_conversation_summaries(db, identity, false).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_direct_message_round_trips_content_type() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let text_id = create_direct_message(db.clone(), peer.clone(), identity.clone(), "Hello".into()).unwrap();
+        let image_id = create_typed_direct_message(db.clone(), peer.clone(), identity.clone(), "images/cat.png".into(), ContentType::ImageRef).unwrap();
+
+        assert_eq!(fetch_direct_message_by_id(db.clone(), text_id).unwrap().content_type, ContentType::Text);
+
+        let image = fetch_direct_message_by_id(db.clone(), image_id).unwrap();
+        assert_eq!(image.content_type, ContentType::ImageRef);
+        assert_eq!(fetch_direct_messages_with_peer(db, peer).unwrap().iter().filter(|dm| dm.content_type == ContentType::ImageRef).count(), 1);
+
+        let payload = serde_json::to_string(&image).unwrap();
+        assert!(payload.contains("\"contentType\":\"image_ref\""));
+        assert_eq!(serde_json::from_str::<DirectMessage>(&payload).unwrap().content_type, ContentType::ImageRef);
+
+        // Older peers omit the type and newer ones may send types we don't know; both are text.
+        let legacy = payload.replace(",\"contentType\":\"image_ref\"", "");
+        assert_eq!(serde_json::from_str::<DirectMessage>(&legacy).unwrap().content_type, ContentType::Text);
+        let unknown = payload.replace("image_ref", "hologram");
+        assert_eq!(serde_json::from_str::<DirectMessage>(&unknown).unwrap().content_type, ContentType::Text);
+    }
+
+    #[test]
+    pub fn test_create_outbound_direct_message_clears_recipient_draft() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let recipient = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let other = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        save_draft(db.clone(), recipient.clone(), "Hello".into()).unwrap();
+        save_draft(db.clone(), other.clone(), "Later".into()).unwrap();
+
+        let id = create_outbound_direct_message(db.clone(), identity, recipient.clone(), "Hello".into()).expect("create_outbound_direct_message failed");
+
+        assert_eq!(fetch_direct_message_by_id(db.clone(), id).unwrap().content, "Hello");
+        assert!(fetch_draft(db.clone(), recipient).unwrap().is_none());
+        assert_eq!(fetch_draft(db, other).unwrap().unwrap().content, "Later");
+    }
+
+    #[test]
+    pub fn test_conversation_storage_tracks_seeded_content_lengths() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let chatty = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let quiet = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        create_direct_message(db.clone(), chatty.clone(), identity.clone(), "a".repeat(1000)).unwrap();
+        create_outbound_direct_message(db.clone(), identity.clone(), chatty.clone(), "b".repeat(500)).unwrap();
+        create_direct_message(db.clone(), quiet.clone(), identity.clone(), "Hi".into()).unwrap();
+        // Multi-byte characters count by their encoded size.
+        create_outbound_direct_message(db.clone(), identity.clone(), quiet.clone(), "👋".into()).unwrap();
+
+        let storage = fetch_conversation_storage(db.clone(), chatty.clone()).expect("fetch_conversation_storage failed");
+        assert_eq!(storage, ConversationStorage::new(chatty.clone(), 2, 1500));
+
+        let empty = fetch_conversation_storage(db.clone(), "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsC".into()).unwrap();
+        assert_eq!((empty.message_count, empty.estimated_bytes), (0, 0));
+
+        let largest = fetch_largest_conversations(db.clone(), identity.clone(), 10).expect("fetch_largest_conversations failed");
+        assert_eq!(largest, vec![ConversationStorage::new(chatty.clone(), 2, 1500), ConversationStorage::new(quiet, 2, 6)]);
+
+        assert_eq!(fetch_largest_conversations(db, identity, 1).unwrap(), vec![ConversationStorage::new(chatty, 2, 1500)]);
+    }
+
+    #[test]
+    pub fn test_fetch_message_activity_buckets_counts_by_day() {
+        let db = init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let peer_a = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let peer_b = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+
+        // 2025-01-31 12:00:00 UTC
+        let now = 1_738_324_800;
+        let day = 86_400;
+
+        for (to_peer_id, created_at) in [
+            (&peer_a, now),
+            (&peer_a, now - 11 * 3_600),
+            (&peer_b, now - 13 * 3_600),
+            (&peer_a, now - 2 * day),
+            (&peer_a, now - 5 * day)
+        ] {
+            let id = create_outbound_direct_message(db.clone(), identity.clone(), to_peer_id.clone(), "Hello".into()).unwrap();
+            db.lock().unwrap().execute("UPDATE tbl_direct_messages SET created_at=?1 WHERE id=?2;", params![created_at, id]).unwrap();
+        }
+
+        let activity = fetch_message_activity(db.clone(), None, 3, now).expect("fetch_message_activity failed");
+        assert_eq!(activity, vec![
+            MessageActivity::new("2025-01-29".into(), 1),
+            MessageActivity::new("2025-01-30".into(), 1),
+            MessageActivity::new("2025-01-31".into(), 2)
+        ]);
+
+        let activity = fetch_message_activity(db.clone(), Some(peer_a), 3, now).unwrap();
+        assert_eq!(activity.iter().map(|day| day.count).collect::<Vec<_>>(), vec![1, 0, 2]);
+
+        assert!(fetch_message_activity(db, None, 0, now).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_bulk_insert_with_yield_commits_in_batches() {
+        let db = init_db(":memory:").expect("DB init failed");
+        let author = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        let transactions = bulk_insert_with_yield(db.clone(), (0..1000).collect(), BULK_WRITE_BATCH_SIZE, |conn, i: i32| {
+            conn.execute(
+                "INSERT INTO tbl_posts (author_peer_id, content, created_at) VALUES (?1, ?2, 0);",
+                params![author, format!("Post {i}")]
+            )?;
+            Ok(())
+        }).await.expect("bulk_insert_with_yield failed");
+
+        assert_eq!(transactions, 10);
+
+        let count: i64 = db.lock().unwrap().query_row("SELECT COUNT(*) FROM tbl_posts;", (), |row| row.get(0)).unwrap();
+        assert_eq!(count, 1000);
+    }
+}
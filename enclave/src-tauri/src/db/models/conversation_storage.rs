@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// How much space the direct messages exchanged with one peer take up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationStorage {
+    pub peer_id: String,
+    pub message_count: i64,
+    /// Total UTF-8 size of the messages' content. Row overhead isn't counted.
+    pub estimated_bytes: i64
+}
+
+impl ConversationStorage {
+    pub fn new(peer_id: String, message_count: i64, estimated_bytes: i64) -> Self {
+        Self {
+            peer_id,
+            message_count,
+            estimated_bytes
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Received
+}
+
+impl DeliveryStatus {
+    pub fn from_message(from_me: bool, pending: bool) -> Self {
+        match (from_me, pending) {
+            (true, true) => DeliveryStatus::Pending,
+            (true, false) => DeliveryStatus::Delivered,
+            (false, _) => DeliveryStatus::Received
+        }
+    }
+}
+
+/// The latest message exchanged with a peer, as shown in the conversation list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationSummary {
+    pub peer_id: String,
+    pub last_message: String,
+    pub last_message_length: usize,
+    pub last_message_at: i64,
+    pub from_me: bool,
+    pub unread: bool,
+    pub unread_count: usize,
+    pub status: DeliveryStatus
+}
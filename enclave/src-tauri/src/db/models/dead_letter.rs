@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub id: i64,
+    pub from_peer_id: String,
+    pub to_peer_id: String,
+    pub content: String,
+    pub created_at: i64,
+    pub failed_at: i64,
+    pub reason: String
+}
+
+impl DeadLetter {
+    pub fn new(id: i64, from_peer_id: String, to_peer_id: String, content: String, created_at: i64, failed_at: i64, reason: String) -> Self {
+        Self {
+            id,
+            from_peer_id,
+            to_peer_id,
+            content,
+            created_at,
+            failed_at,
+            reason
+        }
+    }
+}
\ No newline at end of file
@@ -1,5 +1,46 @@
 use serde::{Deserialize, Serialize};
 
+/// What a direct message's `content` holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    /// A reference to an image rather than the image itself.
+    ImageRef,
+    /// A notice generated by the app rather than typed by a user.
+    System,
+    /// Plain text. Types from newer peers that this version doesn't know are read as text.
+    #[default]
+    #[serde(other)]
+    Text
+}
+
+impl ContentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::ImageRef => "image_ref",
+            ContentType::System => "system",
+            ContentType::Text => "text"
+        }
+    }
+
+    /// The type to keep for a message received from a peer. Only the app creates `System`
+    /// notices, so a peer claiming one gets plain text instead.
+    pub fn received(self) -> Self {
+        match self {
+            ContentType::System => ContentType::Text,
+            other => other
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Self {
+        match tag {
+            "image_ref" => ContentType::ImageRef,
+            "system" => ContentType::System,
+            _ => ContentType::Text
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DirectMessage {
@@ -10,7 +51,10 @@ pub struct DirectMessage {
     pub created_at: i64,
     pub edited_at: Option<i64>,
     pub read: bool,
-    pub pending: bool
+    pub pending: bool,
+    /// Missing from messages sent by older peers, which only send text.
+    #[serde(default)]
+    pub content_type: ContentType
 }
 
 impl DirectMessage {
@@ -23,7 +67,13 @@ impl DirectMessage {
             created_at,
             edited_at,
             read,
-            pending
+            pending,
+            content_type: ContentType::Text
         }
     }
+
+    pub fn with_content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type = content_type;
+        self
+    }
 }
\ No newline at end of file
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// An unsent message, kept per conversation so it survives switching chats or restarting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Draft {
+    pub peer_id: String,
+    pub content: String,
+    pub updated_at: i64
+}
+
+impl Draft {
+    pub fn new(peer_id: String, content: String, updated_at: i64) -> Self {
+        Self {
+            peer_id,
+            content,
+            updated_at
+        }
+    }
+}
@@ -9,11 +9,17 @@ pub struct FriendRequest {
     pub to_multiaddr: String,
     pub message: String,
     pub created_at: i64,
-    pub pending: bool
+    pub pending: bool,
+    #[serde(default)]
+    pub seen: bool,
+    /// Every address the sender advertised, best first. Only carried over the wire; on receipt
+    /// they're stored in `tbl_user_addresses`.
+    #[serde(default)]
+    pub addresses: Vec<String>
 }
 
 impl FriendRequest {
-    pub fn new(id: i64, from_peer_id: String, from_multiaddr: String, to_peer_id: String, to_multiaddr: String, message: String, created_at: i64, pending: bool) -> Self {
+    pub fn new(id: i64, from_peer_id: String, from_multiaddr: String, to_peer_id: String, to_multiaddr: String, message: String, created_at: i64, pending: bool, seen: bool) -> Self {
         Self {
             id,
             from_peer_id,
@@ -22,7 +28,14 @@ impl FriendRequest {
             to_multiaddr,
             message,
             created_at,
-            pending
+            pending,
+            seen,
+            addresses: Vec::new()
         }
     }
+
+    pub fn with_addresses(mut self, addresses: Vec<String>) -> Self {
+        self.addresses = addresses;
+        self
+    }
 }
\ No newline at end of file
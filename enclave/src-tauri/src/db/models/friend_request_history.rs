@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FriendRequestOutcome {
+    Accepted,
+    Denied
+}
+
+impl FriendRequestOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FriendRequestOutcome::Accepted => "accepted",
+            FriendRequestOutcome::Denied => "denied"
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "accepted" => Some(FriendRequestOutcome::Accepted),
+            "denied" => Some(FriendRequestOutcome::Denied),
+            _ => None
+        }
+    }
+}
+
+/// A friend request that has been answered, kept after the request itself is deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FriendRequestHistoryEntry {
+    pub id: i64,
+    pub from_peer_id: String,
+    pub to_peer_id: String,
+    pub message: String,
+    pub outcome: FriendRequestOutcome,
+    pub requested_at: i64,
+    pub resolved_at: i64
+}
+
+impl FriendRequestHistoryEntry {
+    pub fn new(id: i64, from_peer_id: String, to_peer_id: String, message: String, outcome: FriendRequestOutcome, requested_at: i64, resolved_at: i64) -> Self {
+        Self {
+            id,
+            from_peer_id,
+            to_peer_id,
+            message,
+            outcome,
+            requested_at,
+            resolved_at
+        }
+    }
+}
@@ -7,18 +7,8 @@ pub struct Identity {
     pub peer_id: String,
     pub port_number: i64,
     pub created_at: i64,
-    pub last_login: i64
+    pub last_login: i64,
+    pub sync_enabled: bool,
+    /// `None` means friendships are unlimited.
+    pub max_friends: Option<i64>
 }
-
-impl Identity {
-    pub fn new(id: i64, keypair: Vec<u8>, peer_id: String, port_number: i64, created_at: i64, last_login: i64) -> Self {
-        Self {
-            id,
-            keypair,    
-            peer_id,
-            port_number,
-            created_at,
-            last_login
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// A friend's address answering with a different identity than the one we know them by,
+/// shown as a safety number warning until the user acknowledges it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyChange {
+    pub peer_id: String,
+    pub new_peer_id: String,
+    pub detected_at: i64
+}
+
+impl KeyChange {
+    pub fn new(peer_id: String, new_peer_id: String, detected_at: i64) -> Self {
+        Self {
+            peer_id,
+            new_peer_id,
+            detected_at
+        }
+    }
+}
\ No newline at end of file
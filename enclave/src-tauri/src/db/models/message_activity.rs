@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// How many direct messages were sent or received on one UTC day (`YYYY-MM-DD`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageActivity {
+    pub day: String,
+    pub count: i64
+}
+
+impl MessageActivity {
+    pub fn new(day: String, count: i64) -> Self {
+        Self {
+            day,
+            count
+        }
+    }
+}
@@ -1,7 +1,19 @@
 pub mod blocked_user;
+pub mod conversation_storage;
+pub mod conversation_summary;
+pub mod dead_letter;
 pub mod direct_message;
+pub mod draft;
 pub mod friend_request;
+pub mod friend_request_history;
 pub mod friend;
 pub mod identity;
+pub mod key_change;
+pub mod message_activity;
+pub mod peer_appearance;
 pub mod post;
-pub mod user;
\ No newline at end of file
+pub mod post_tombstone;
+pub mod post_with_author;
+pub mod quarantined_message;
+pub mod user;
+pub mod user_address;
\ No newline at end of file
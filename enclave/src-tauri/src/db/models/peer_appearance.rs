@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// How a contact is shown locally, to tell them apart at a glance. Never sent to peers.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerAppearance {
+    /// Usually a single emoji.
+    pub avatar: Option<String>,
+    /// A `#rrggbb` hex color.
+    pub color: Option<String>
+}
+
+impl PeerAppearance {
+    pub fn new(avatar: Option<String>, color: Option<String>) -> Self {
+        Self {
+            avatar,
+            color
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// Records that a post was deleted, so the deletion can be synced to friends and the post
+/// isn't recreated when a friend re-sends it. Posts have no id shared between nodes, so a
+/// tombstone identifies the post by author and content, like sync deduplication does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostTombstone {
+    pub id: i64,
+    pub author_peer_id: String,
+    pub content: String,
+    pub deleted_at: i64
+}
+
+impl PostTombstone {
+    pub fn new(id: i64, author_peer_id: String, content: String, deleted_at: i64) -> Self {
+        Self {
+            id,
+            author_peer_id,
+            content,
+            deleted_at
+        }
+    }
+}
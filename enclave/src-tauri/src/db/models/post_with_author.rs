@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// A feed post with its author's name resolved for display: the local alias if one is set,
+/// then the nickname the author chose, then their peer id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostWithAuthor {
+    pub id: i64,
+    pub author_peer_id: String,
+    pub author_display_name: String,
+    pub content: String,
+    pub created_at: i64,
+    pub edited_at: Option<i64>
+}
+
+impl PostWithAuthor {
+    pub fn new(id: i64, author_peer_id: String, author_display_name: String, content: String, created_at: i64, edited_at: Option<i64>) -> Self {
+        Self {
+            id,
+            author_peer_id,
+            author_display_name,
+            content,
+            created_at,
+            edited_at
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// A direct message from someone who isn't a friend, held until the user approves the sender.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedMessage {
+    pub id: i64,
+    pub from_peer_id: String,
+    pub content: String,
+    pub received_at: i64
+}
+
+impl QuarantinedMessage {
+    pub fn new(id: i64, from_peer_id: String, content: String, received_at: i64) -> Self {
+        Self {
+            id,
+            from_peer_id,
+            content,
+            received_at
+        }
+    }
+}
\ No newline at end of file
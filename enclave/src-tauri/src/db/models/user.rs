@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+/// Stored for users we only know by peer id, e.g. ones blocked before ever making contact.
+pub const UNKNOWN_MULTIADDR: &str = "";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: i64,
@@ -21,4 +24,9 @@ impl User {
             created_at
         }
     }
+
+    /// Whether there is an address worth dialing for this user.
+    pub fn has_known_address(&self) -> bool {
+        self.multiaddr != UNKNOWN_MULTIADDR
+    }
 }
\ No newline at end of file
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserAddress {
+    pub id: i64,
+    pub user_id: i64,
+    pub multiaddr: String,
+    pub updated_at: i64
+}
+
+impl UserAddress {
+    pub fn new(id: i64, user_id: i64, multiaddr: String, updated_at: i64) -> Self {
+        Self {
+            id,
+            user_id,
+            multiaddr,
+            updated_at
+        }
+    }
+}
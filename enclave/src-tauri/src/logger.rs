@@ -1,6 +1,37 @@
-use std::{fs::{File, OpenOptions, create_dir_all}, io::{BufWriter, Write}, path::Path, sync::Mutex};
+use std::{fs::{self, File, OpenOptions, create_dir_all}, io::{BufWriter, Write}, path::Path, sync::Mutex};
 
+use chrono::NaiveDate;
 use log::{LevelFilter, Record};
+use serde::Serialize;
+
+pub const LOGS_DIR: &str = "./logs";
+/// Log files are named after the day they were opened, e.g. `20250131.log`.
+pub const LOG_DATE_FORMAT: &str = "%Y%m%d";
+/// Records logged to this target are written whatever the level, for per-peer tracing.
+pub const TRACE_TARGET: &str = "enclave::trace";
+
+/// The max level from before per-peer tracing raised it, restored once nothing is traced.
+static LEVEL_BEFORE_TRACING: Mutex<Option<LevelFilter>> = Mutex::new(None);
+
+/// Raises the max level to debug so trace records reach the logger.
+pub fn begin_tracing() {
+    let mut saved = LEVEL_BEFORE_TRACING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if saved.is_none() {
+        *saved = Some(log::max_level());
+    }
+
+    log::set_max_level(log::max_level().max(LevelFilter::Debug));
+}
+
+/// Restores the max level from before `begin_tracing`.
+pub fn end_tracing() {
+    let mut saved = LEVEL_BEFORE_TRACING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if let Some(level) = saved.take() {
+        log::set_max_level(level);
+    }
+}
 
 pub struct Logger {
     level: LevelFilter,
@@ -27,7 +58,7 @@ impl Logger {
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= self.level
+        metadata.level() <= self.level || metadata.target() == TRACE_TARGET
     }
 
     fn log(&self, record: &Record) {
@@ -54,4 +85,119 @@ impl log::Log for Logger {
             let _ = writer.flush();
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilesSummary {
+    pub files: usize,
+    pub bytes: u64
+}
+
+/// The date a `{date}.log` file was written, or `None` for anything else in the directory.
+pub fn log_file_date(file_name: &str) -> Option<NaiveDate> {
+    let date = file_name.strip_suffix(".log")?;
+    NaiveDate::parse_from_str(date, LOG_DATE_FORMAT).ok()
+}
+
+pub fn logs_size(dir: &Path) -> std::io::Result<LogFilesSummary> {
+    let mut summary = LogFilesSummary::default();
+
+    if !dir.exists() {
+        return Ok(summary);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let metadata = entry?.metadata()?;
+
+        if metadata.is_file() {
+            summary.files += 1;
+            summary.bytes += metadata.len();
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Deletes log files dated more than `keep_days` before `today`. Files that don't follow the
+/// `{date}.log` naming are left alone, as is everything when the window reaches back past the
+/// earliest representable date. Returns what was removed.
+pub fn clean_old_logs(dir: &Path, keep_days: u32, today: NaiveDate) -> std::io::Result<LogFilesSummary> {
+    let mut removed = LogFilesSummary::default();
+
+    if !dir.exists() {
+        return Ok(removed);
+    }
+
+    let Some(cutoff) = today.checked_sub_days(chrono::Days::new(keep_days.into())) else {
+        return Ok(removed);
+    };
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        let expired = entry.file_name()
+            .to_str()
+            .and_then(log_file_date)
+            .is_some_and(|date| date < cutoff);
+
+        if metadata.is_file() && expired {
+            fs::remove_file(entry.path())?;
+            removed.files += 1;
+            removed.bytes += metadata.len();
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_log_file_date_parses_dated_file_names() {
+        assert_eq!(log_file_date("20250131.log"), NaiveDate::from_ymd_opt(2025, 1, 31));
+        assert_eq!(log_file_date("20250131.txt"), None);
+        assert_eq!(log_file_date("notes.log"), None);
+    }
+
+    #[test]
+    pub fn test_clean_old_logs_only_removes_files_beyond_window() {
+        let dir = std::env::temp_dir().join(format!("enclave-logs-{}", rand::random::<u64>()));
+        create_dir_all(&dir).unwrap();
+
+        for name in ["20250101.log", "20250124.log", "20250125.log", "20250131.log", "notes.log"] {
+            fs::write(dir.join(name), "[INFO] hello\n").unwrap();
+        }
+
+        let today = NaiveDate::from_ymd_opt(2025, 1, 31).unwrap();
+        let removed = clean_old_logs(&dir, 6, today).expect("clean_old_logs failed");
+
+        assert_eq!(removed, LogFilesSummary { files: 2, bytes: 26 });
+        assert!(!dir.join("20250101.log").exists());
+        assert!(!dir.join("20250124.log").exists());
+        assert!(dir.join("20250125.log").exists());
+        assert!(dir.join("20250131.log").exists());
+        assert!(dir.join("notes.log").exists());
+        assert_eq!(logs_size(&dir).unwrap(), LogFilesSummary { files: 3, bytes: 39 });
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    pub fn test_clean_old_logs_keeps_everything_when_window_overflows() {
+        let dir = std::env::temp_dir().join(format!("enclave-logs-{}", rand::random::<u64>()));
+        create_dir_all(&dir).unwrap();
+        fs::write(dir.join("20250101.log"), "[INFO] hello\n").unwrap();
+
+        let today = NaiveDate::from_ymd_opt(2025, 1, 31).unwrap();
+        let removed = clean_old_logs(&dir, u32::MAX, today).expect("clean_old_logs failed");
+
+        assert_eq!(removed, LogFilesSummary::default());
+        assert!(dir.join("20250101.log").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -4,21 +4,22 @@
 mod db;
 mod logger;
 mod p2p;
+mod version;
 
 use chrono::Utc;
 use log::LevelFilter;
-use p2p::{P2PNode, P2PEvent};
-use tauri::Emitter;
+use p2p::{P2PNode, P2PEvent, ErrorSeverity, AddressProbeResult, ConnectionType, config::NetworkConfigInfo, explicit_peers::ExplicitPeersResync, session_stats::SessionStats, connection_policy::ConnectionPolicy, topic_mesh::NetworkStats, HolepunchStatus, RelayInfo, SyncSummary, crypto::EncryptionStatus, startup::StartupReport};
+use tauri::{Emitter, Manager};
 use tokio::sync::Mutex;
-use std::{str::FromStr, sync::Arc};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 use libp2p::{PeerId, Multiaddr};
 
-use crate::{db::models::{direct_message::DirectMessage, friend_request::FriendRequest, post::Post}, logger::Logger, p2p::MyInfo};
+use crate::{db::models::{conversation_storage::ConversationStorage, conversation_summary::ConversationSummary, dead_letter::DeadLetter, direct_message::DirectMessage, draft::Draft, friend_request::FriendRequest, friend_request_history::FriendRequestHistoryEntry, key_change::KeyChange, message_activity::MessageActivity, peer_appearance::PeerAppearance, post::Post, post_with_author::PostWithAuthor, quarantined_message::QuarantinedMessage}, logger::{LogFilesSummary, Logger}, p2p::{FriendProfile, IdentityInfo, InitialState, MyInfo, PendingWork}, version::VersionInfo};
 
 static LOGGER: once_cell::sync::Lazy<Logger> =
     once_cell::sync::Lazy::new(|| {
-        let date_string = Utc::now().format("%Y%m%d").to_string();
-        Logger::new(format!("./logs/{date_string}.log").as_str(), LevelFilter::Info).expect("failed to create logger")
+        let date_string = Utc::now().format(logger::LOG_DATE_FORMAT).to_string();
+        Logger::new(format!("{}/{date_string}.log", logger::LOGS_DIR).as_str(), LevelFilter::Info).expect("failed to create logger")
     });
 
 struct AppState {
@@ -27,9 +28,29 @@ struct AppState {
 
 #[tauri::command]
 async fn start_p2p(state: tauri::State<'_, AppState>, app: tauri::AppHandle) -> Result<String, String> {
+    if state.p2p_node.lock().await.is_some() {
+        log::warn!("start_p2p called but P2P node already started");
+        return Err("P2P node already started".into());
+    }
+
     let relay_address = None;
+    let namespace = match p2p::config::load_namespace(db::DATABASE.clone()) {
+        Ok(namespace) => Some(namespace),
+        Err(err) => {
+            log::error!("start_p2p: {err}");
+            return Err(err.to_string());
+        }
+    };
+
+    match p2p::startup::verify_startup_state(db::DATABASE.clone()) {
+        Ok(report) if !report.issues.is_empty() => {
+            app.emit("startup-issues", report.issues).ok();
+        },
+        Ok(_) => {},
+        Err(err) => log::error!("start_p2p: {err}")
+    }
 
-    let (node, mut event_receiver) = match P2PNode::new(relay_address).await {
+    let (node, mut event_receiver) = match P2PNode::new(relay_address, namespace).await {
         Ok((node, event_receiver)) => (node, event_receiver),
         Err(err) => {
             log::error!("start_p2p: {err}");
@@ -78,140 +99,1918 @@ async fn start_p2p(state: tauri::State<'_, AppState>, app: tauri::AppHandle) ->
                 P2PEvent::FriendRequestAccepted { peer } => {
                     app.emit("friend-request-accepted", peer.to_string()).ok();
                 },
-                P2PEvent::FriendRequestDenied { peer } => {
-                    app.emit("friend-request-denied", peer.to_string()).ok();
+                P2PEvent::FriendRequestAcknowledged { peer } => {
+                    app.emit("friend-request-acknowledged", peer.to_string()).ok();
                 },
-                P2PEvent::Error { context, error } => {
+                P2PEvent::FriendRequestDenied { peer, reason } => {
+                    app.emit("friend-request-denied", (peer.to_string(), reason)).ok();
+                },
+                P2PEvent::FriendLimitReached { peer, limit } => {
+                    app.emit("friend-limit-reached", (peer.to_string(), limit)).ok();
+                },
+                P2PEvent::Error { context, error, severity: ErrorSeverity::Fatal } => {
+                    log::error!("Fatal error in {context}: {error}");
+                    app.emit("fatal-error", (context, error)).ok();
+                },
+                P2PEvent::Error { context, error, severity: ErrorSeverity::Recoverable } => {
                     log::error!("{context}: {error}");
                 },
+                P2PEvent::Error { context, error, severity: ErrorSeverity::Warning } => {
+                    log::warn!("{context}: {error}");
+                },
                 P2PEvent::PostSynch => {
                     app.emit("load-feed", ()).ok();
+                },
+                P2PEvent::RelayStatusChanged(status) => {
+                    app.emit("relay-status-changed", status).ok();
+                },
+                P2PEvent::HolepunchResult { peer, status } => {
+                    app.emit("holepunch-result", (peer.to_string(), status)).ok();
+                },
+                P2PEvent::ConnectionTypeChanged { peer, connection_type } => {
+                    app.emit("connection-type-changed", (peer.to_string(), connection_type)).ok();
+                },
+                P2PEvent::NodeStalled { silent_for_secs } => {
+                    app.emit("node-stalled", silent_for_secs).ok();
+                },
+                P2PEvent::FriendNicknameChanged { peer, nickname } => {
+                    app.emit("friend-nickname-changed", (peer.to_string(), nickname)).ok();
+                },
+                P2PEvent::MessageDeadLettered(dead_letter) => {
+                    app.emit("message-dead-lettered", dead_letter).ok();
+                },
+                P2PEvent::SyncCancelled { peer } => {
+                    app.emit("sync-cancelled", peer.to_string()).ok();
+                },
+                P2PEvent::MessagesRead { peer } => {
+                    app.emit("messages-read", peer.to_string()).ok();
+                },
+                P2PEvent::MessagesExpired { ids } => {
+                    app.emit("messages-expired", ids).ok();
+                },
+                P2PEvent::ClockSkewDetected { peer, offset_ms } => {
+                    app.emit("clock-skew-detected", (peer.to_string(), offset_ms)).ok();
+                },
+                P2PEvent::DirectMessageQuarantined { peer } => {
+                    app.emit("direct-message-quarantined", peer.to_string()).ok();
+                },
+                P2PEvent::SafetyNumberChanged { peer, new_peer, notice } => {
+                    app.emit("safety-number-changed", (peer.to_string(), new_peer.to_string(), notice)).ok();
                 }
             }
         }
-    });
+    });
+
+    Ok(peer_id)
+}
+
+#[tauri::command]
+async fn get_my_info(state: tauri::State<'_, AppState>) -> Result<MyInfo, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_my_info called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let addresses = node.get_listen_addresses().await;
+
+    let multiaddr = match addresses.first() {
+        Some(addr) => addr.to_string(),
+        None => {
+            log::error!(
+                "get_my_info: node {} has no listening addresses",
+                node.get_peer_id()
+            );
+            return Err("No listening addresses".into());
+        }
+    };
+
+    let keypair = node.get_keypair().to_protobuf_encoding()
+        .map_err(|err| err.to_string())?;
+
+    Ok(MyInfo {
+        peer_id: node.get_peer_id().to_string(),
+        keypair,
+        multiaddr,
+    })
+}
+
+#[tauri::command]
+async fn get_identity_info() -> Result<IdentityInfo, String> {
+    let identity = match db::fetch_identity(db::DATABASE.clone()) {
+        Ok(identity) => identity,
+        Err(err) => {
+            log::error!("get_identity_info: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(IdentityInfo::from_identity(&identity, Utc::now().timestamp()))
+}
+
+#[tauri::command]
+async fn get_setting(key: String) -> Result<Option<String>, String> {
+    match db::get_setting(db::DATABASE.clone(), &key) {
+        Ok(value) => Ok(value),
+        Err(err) => {
+            log::error!("get_setting: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn set_setting(key: String, value: String) -> Result<(), String> {
+    if let Err(err) = db::set_setting(db::DATABASE.clone(), &key, &value) {
+        log::error!("set_setting: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn save_draft(peer_id: String, content: String) -> Result<(), String> {
+    if let Err(err) = db::save_draft(db::DATABASE.clone(), peer_id, content) {
+        log::error!("save_draft: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_draft(peer_id: String) -> Result<Option<Draft>, String> {
+    match db::fetch_draft(db::DATABASE.clone(), peer_id) {
+        Ok(draft) => Ok(draft),
+        Err(err) => {
+            log::error!("get_draft: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn clear_draft(peer_id: String) -> Result<(), String> {
+    if let Err(err) = db::clear_draft(db::DATABASE.clone(), peer_id) {
+        log::error!("clear_draft: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_auto_start() -> Result<bool, String> {
+    match db::get_auto_start(db::DATABASE.clone()) {
+        Ok(auto_start) => Ok(auto_start),
+        Err(err) => {
+            log::error!("get_auto_start: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// `true` until an identity exists or onboarding has been completed, so the frontend can
+/// tell a brand new install apart from a node that simply isn't running.
+#[tauri::command]
+async fn is_first_run() -> Result<bool, String> {
+    match db::is_first_run(db::DATABASE.clone()) {
+        Ok(first_run) => Ok(first_run),
+        Err(err) => {
+            log::error!("is_first_run: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn complete_onboarding() -> Result<(), String> {
+    if let Err(err) = db::complete_onboarding(db::DATABASE.clone()) {
+        log::error!("complete_onboarding: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Onboarding completed");
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_auto_start(auto_start: bool) -> Result<(), String> {
+    if let Err(err) = db::set_auto_start(db::DATABASE.clone(), auto_start) {
+        log::error!("set_auto_start: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Set auto start: {}", auto_start);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_retention(peer_id: String) -> Result<Option<u32>, String> {
+    match db::get_retention(db::DATABASE.clone(), peer_id) {
+        Ok(days) => Ok(days),
+        Err(err) => {
+            log::error!("get_retention: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Expired messages are deleted by the node's next hourly cleanup.
+#[tauri::command]
+async fn set_retention(peer_id: String, days: Option<u32>) -> Result<(), String> {
+    if let Err(err) = db::set_retention(db::DATABASE.clone(), peer_id.clone(), days) {
+        log::error!("set_retention: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Set retention for {} to {:?} days", peer_id, days);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_auto_update_friend_addresses() -> Result<bool, String> {
+    match db::get_auto_update_friend_addresses(db::DATABASE.clone()) {
+        Ok(enabled) => Ok(enabled),
+        Err(err) => {
+            log::error!("get_auto_update_friend_addresses: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn set_auto_update_friend_addresses(enabled: bool) -> Result<(), String> {
+    if let Err(err) = db::set_auto_update_friend_addresses(db::DATABASE.clone(), enabled) {
+        log::error!("set_auto_update_friend_addresses: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Set auto update friend addresses: {}", enabled);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_read_receipts_enabled() -> Result<bool, String> {
+    match db::get_read_receipts_enabled(db::DATABASE.clone()) {
+        Ok(enabled) => Ok(enabled),
+        Err(err) => {
+            log::error!("get_read_receipts_enabled: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Read once per `mark_conversation_read`, so takes effect without restarting the node.
+#[tauri::command]
+async fn set_read_receipts_enabled(enabled: bool) -> Result<(), String> {
+    if let Err(err) = db::set_read_receipts_enabled(db::DATABASE.clone(), enabled) {
+        log::error!("set_read_receipts_enabled: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Set read receipts enabled: {}", enabled);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_max_pending_direct_messages() -> Result<usize, String> {
+    match db::get_max_pending_direct_messages(db::DATABASE.clone()) {
+        Ok(max_pending) => Ok(max_pending),
+        Err(err) => {
+            log::error!("get_max_pending_direct_messages: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Applies from the next send; older messages beyond the new limit are dead-lettered then.
+#[tauri::command]
+async fn set_max_pending_direct_messages(max_pending: usize) -> Result<(), String> {
+    if let Err(err) = db::set_max_pending_direct_messages(db::DATABASE.clone(), max_pending) {
+        log::error!("set_max_pending_direct_messages: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Set max pending direct messages per peer: {}", max_pending);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_connection_policy(state: tauri::State<'_, AppState>, policy: ConnectionPolicy) -> Result<(), String> {
+    if let Err(err) = p2p::connection_policy::save_connection_policy(db::DATABASE.clone(), policy) {
+        log::error!("set_connection_policy: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    // The policy is read when the node starts, so only a running node needs telling.
+    if let Some(node) = state.p2p_node.lock().await.as_ref() {
+        if let Err(err) = node.set_connection_policy(policy).await {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    }
+
+    log::info!("Set connection policy: {:?}", policy);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_network_namespace() -> Result<String, String> {
+    match p2p::config::load_namespace(db::DATABASE.clone()) {
+        Ok(namespace) => Ok(namespace),
+        Err(err) => {
+            log::error!("get_network_namespace: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Applies the next time the node starts.
+#[tauri::command]
+async fn set_network_namespace(namespace: String) -> Result<(), String> {
+    if let Err(err) = p2p::config::save_namespace(db::DATABASE.clone(), &namespace) {
+        log::error!("set_network_namespace: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Set network namespace: {}", namespace);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_logs_size() -> Result<LogFilesSummary, String> {
+    match logger::logs_size(std::path::Path::new(logger::LOGS_DIR)) {
+        Ok(summary) => Ok(summary),
+        Err(err) => {
+            log::error!("get_logs_size: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn clean_old_logs(keep_days: u32) -> Result<LogFilesSummary, String> {
+    let removed = match logger::clean_old_logs(std::path::Path::new(logger::LOGS_DIR), keep_days, Utc::now().date_naive()) {
+        Ok(removed) => removed,
+        Err(err) => {
+            log::error!("clean_old_logs: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    log::info!("Removed {} old log files ({} bytes)", removed.files, removed.bytes);
+    Ok(removed)
+}
+
+#[tauri::command]
+async fn get_message_activity(peer_id: Option<String>, days: i64) -> Result<Vec<MessageActivity>, String> {
+    match db::fetch_message_activity(db::DATABASE.clone(), peer_id, days, Utc::now().timestamp()) {
+        Ok(activity) => Ok(activity),
+        Err(err) => {
+            log::error!("get_message_activity: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_conversation_storage(peer_id: String) -> Result<ConversationStorage, String> {
+    match db::fetch_conversation_storage(db::DATABASE.clone(), peer_id) {
+        Ok(storage) => Ok(storage),
+        Err(err) => {
+            log::error!("get_conversation_storage: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_largest_conversations(limit: usize) -> Result<Vec<ConversationStorage>, String> {
+    let identity = match db::fetch_identity(db::DATABASE.clone()) {
+        Ok(identity) => identity,
+        Err(err) => {
+            log::error!("get_largest_conversations: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    match db::fetch_largest_conversations(db::DATABASE.clone(), identity.peer_id, limit) {
+        Ok(conversations) => Ok(conversations),
+        Err(err) => {
+            log::error!("get_largest_conversations: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn prune_orphaned_users() -> Result<usize, String> {
+    let pruned = match db::prune_orphaned_users(db::DATABASE.clone()) {
+        Ok(pruned) => pruned,
+        Err(err) => {
+            log::error!("prune_orphaned_users: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    log::info!("Pruned {} orphaned users", pruned);
+
+    Ok(pruned)
+}
+
+#[tauri::command]
+async fn get_initial_state(state: tauri::State<'_, AppState>) -> Result<InitialState, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_initial_state called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let initial_state = match node.get_initial_state().await {
+        Ok(initial_state) => initial_state,
+        Err(err) => {
+            log::error!("get_initial_state: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(initial_state)
+}
+
+#[tauri::command]
+async fn block_peer_id(state: tauri::State<'_, AppState>, peer_id: String) -> Result<(), String> {
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("block_peer_id: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    if let Err(err) = db::block_peer_id(db::DATABASE.clone(), peer.to_string()) {
+        log::error!("block_peer_id: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Blocked peer {}", peer);
+
+    if let Some(node) = state.p2p_node.lock().await.as_ref() {
+        if let Err(err) = node.disconnect_peer(peer).await {
+            log::error!("block_peer_id: {}", err.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn pause_peer(peer_id: String) -> Result<(), String> {
+    set_peer_paused(peer_id, true)
+}
+
+#[tauri::command]
+async fn resume_peer(peer_id: String) -> Result<(), String> {
+    set_peer_paused(peer_id, false)
+}
+
+fn set_peer_paused(peer_id: String, paused: bool) -> Result<(), String> {
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("set_peer_paused: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    if let Err(err) = db::set_peer_paused(db::DATABASE.clone(), peer.to_string(), paused) {
+        log::error!("set_peer_paused: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    log::info!("Set peer {} paused: {}", peer, paused);
+    Ok(())
+}
+
+/// Sets the avatar and color `peer_id` is shown with locally. `None` clears either.
+#[tauri::command]
+async fn set_peer_appearance(peer_id: String, avatar: Option<String>, color: Option<String>) -> Result<(), String> {
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("set_peer_appearance: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    if let Err(err) = db::set_peer_appearance(db::DATABASE.clone(), peer.to_string(), PeerAppearance::new(avatar, color)) {
+        log::error!("set_peer_appearance: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+/// Sets the name `peer_id` is shown with locally, overriding their nickname. `None` clears it.
+#[tauri::command]
+async fn set_peer_alias(peer_id: String, alias: Option<String>) -> Result<(), String> {
+    if let Err(err) = db::set_peer_alias(db::DATABASE.clone(), peer_id, alias) {
+        log::error!("set_peer_alias: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_peer_appearance(peer_id: String) -> Result<PeerAppearance, String> {
+    match db::fetch_peer_appearance(db::DATABASE.clone(), peer_id) {
+        Ok(appearance) => Ok(appearance),
+        Err(err) => {
+            log::error!("get_peer_appearance: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn export_account_data(dest_dir: String, include_identity: Option<bool>) -> Result<Vec<String>, String> {
+    let written = match db::export::export_account_data(db::DATABASE.clone(), std::path::Path::new(&dest_dir), include_identity.unwrap_or(false)) {
+        Ok(written) => written,
+        Err(err) => {
+            log::error!("export_account_data: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    log::info!("Exported account data to {}", dest_dir);
+
+    Ok(written.into_iter().map(|path| path.display().to_string()).collect())
+}
+
+/// Signs the locally recorded conversation with `peer_id`. This proves what we recorded, not
+/// that the peer agrees with it.
+#[tauri::command]
+async fn export_signed_transcript(peer_id: String) -> Result<String, String> {
+    let peer_id = match PeerId::from_str(&peer_id) {
+        Ok(p) => p,
+        Err(err) => {
+            log::error!("export_signed_transcript: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    match p2p::transcript::export_signed_transcript(db::DATABASE.clone(), &peer_id, Utc::now().timestamp()) {
+        Ok(transcript) => Ok(transcript),
+        Err(err) => {
+            log::error!("export_signed_transcript: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn verify_signed_transcript(data: String) -> Result<bool, String> {
+    match p2p::transcript::verify_signed_transcript(&data) {
+        Ok(valid) => Ok(valid),
+        Err(err) => {
+            log::error!("verify_signed_transcript: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn export_block_list() -> Result<String, String> {
+    match p2p::block_list::export_block_list(db::DATABASE.clone()) {
+        Ok(json) => Ok(json),
+        Err(err) => {
+            log::error!("export_block_list: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn import_block_list(state: tauri::State<'_, AppState>, json: String) -> Result<usize, String> {
+    let blocked = match p2p::block_list::import_block_list(db::DATABASE.clone(), &json).await {
+        Ok(blocked) => blocked,
+        Err(err) => {
+            log::error!("import_block_list: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    log::info!("Imported block list, {} peers newly blocked", blocked.len());
+
+    if let Some(node) = state.p2p_node.lock().await.as_ref() {
+        for peer in blocked.iter() {
+            if let Err(err) = node.disconnect_peer(*peer).await {
+                log::error!("import_block_list: {}", err.to_string());
+            }
+        }
+    }
+
+    Ok(blocked.len())
+}
+
+#[tauri::command]
+async fn set_max_friends(max_friends: Option<u32>) -> Result<(), String> {
+    if let Err(err) = db::set_max_friends(db::DATABASE.clone(), max_friends.map(i64::from)) {
+        log::error!("set_max_friends: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+/// The port to forward on the router for direct connections from outside the LAN.
+#[tauri::command]
+async fn get_listen_port() -> Result<u16, String> {
+    match db::fetch_listen_port(db::DATABASE.clone()) {
+        Ok(port) => Ok(port),
+        Err(err) => {
+            log::error!("get_listen_port: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Stores a new listen port, returning whether the running node must be restarted for it
+/// to take effect.
+#[tauri::command]
+async fn set_listen_port(state: tauri::State<'_, AppState>, port: u32) -> Result<bool, String> {
+    if let Err(err) = db::update_listen_port(db::DATABASE.clone(), i64::from(port)) {
+        log::error!("set_listen_port: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    let restart_required = state.p2p_node.lock().await.is_some();
+    if restart_required {
+        log::warn!("Listen port changed to {}, restart the node for it to take effect", port);
+    } else {
+        log::info!("Listen port changed to {}", port);
+    }
+
+    Ok(restart_required)
+}
+
+#[tauri::command]
+async fn get_version_info() -> Result<VersionInfo, String> {
+    Ok(version::version_info())
+}
+
+#[tauri::command]
+async fn verify_startup_state() -> Result<StartupReport, String> {
+    match p2p::startup::verify_startup_state(db::DATABASE.clone()) {
+        Ok(report) => Ok(report),
+        Err(err) => {
+            log::error!("verify_startup_state: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Fixes a stored peer id that no longer matches the keypair. Restart the node afterwards so
+/// it picks up the corrected identity.
+#[tauri::command]
+async fn repair_identity_peer_id() -> Result<Option<String>, String> {
+    match p2p::startup::repair_identity_peer_id(db::DATABASE.clone()) {
+        Ok(repaired) => {
+            if let Some(peer_id) = &repaired {
+                log::info!("Repaired identity peer id to {}", peer_id);
+            }
+            Ok(repaired)
+        },
+        Err(err) => {
+            log::error!("repair_identity_peer_id: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn set_my_nickname(state: tauri::State<'_, AppState>, nickname: String) -> Result<(), String> {
+    let nickname = nickname.trim().to_string();
+    if nickname.is_empty() {
+        log::warn!("set_my_nickname called with an empty nickname");
+        return Err("Nickname cannot be empty".into());
+    }
+
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("set_my_nickname called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    if let Err(err) = node.broadcast_nickname(nickname).await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn send_friend_request(
+    state: tauri::State<'_, AppState>,
+    peer_id: String,
+    multiaddr: String,
+    message: String
+) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("send_friend_request called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("send_friend_request: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let address = match multiaddr.parse::<Multiaddr>(){
+        Ok(address) => address,
+        Err(err) => {
+            log::error!("send_friend_request: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let _ = match node.send_friend_request(peer, address, message).await {
+        Ok(_) => (),
+        Err(err) => {
+            log::error!("send_friend_request: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn accept_friend_request(state: tauri::State<'_, AppState>, peer_id: String) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("accept_friend_request called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("accept_friend_request: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let _ = match node.accept_friend_request(peer).await {
+        Ok(_) => (),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn deny_friend_request(state: tauri::State<'_, AppState>, peer_id: String, reason: Option<String>) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("deny_friend_request called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("deny_friend_request: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let _ = match node.deny_friend_request(peer, reason).await {
+        Ok(_) => (),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn send_post(state: tauri::State<'_, AppState>, content: String) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("send_post called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let _ = match node.send_post(content).await {
+        Ok(_) => (),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn send_direct_message(state: tauri::State<'_, AppState>, peer_id: String, content: String) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("send_direct_message called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("send_direct_message: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let address = match db::fetch_user_by_peer_id(db::DATABASE.clone(), peer_id) {
+        Ok(user) => match Multiaddr::from_str(&user.multiaddr) {
+            Ok(address) => address,
+            Err(err) => {
+                log::error!("send_direct_message: {}", err.to_string());
+                return Err(err.to_string())
+            }
+        },
+        Err(err) => {
+            log::error!("send_direct_message: {}", err.to_string());
+            return Err(err.to_string())
+        }
+    };
+
+    let _ = match node.send_direct_message(peer, address, content).await {
+        Ok(_) => (),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+    
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_friend_list(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_friend_list called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let friends = match node.get_friend_list().await {
+        Ok(friends) => friends,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(friends.iter().map(|p| p.to_string()).collect())
+}
+
+/// Nickname, presence, unread count and last message for every friend, in one call.
+#[tauri::command]
+async fn get_friend_profiles(state: tauri::State<'_, AppState>) -> Result<Vec<FriendProfile>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_friend_profiles called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let profiles = match node.get_friend_profiles().await {
+        Ok(profiles) => profiles,
+        Err(err) => {
+            log::error!("get_friend_profiles: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(profiles)
+}
+
+#[tauri::command]
+async fn count_online_friends(state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("count_online_friends called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    match node.count_online_friends().await {
+        Ok(count) => Ok(count),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Lets the frontend warn before quitting with undelivered messages or syncs in flight.
+#[tauri::command]
+async fn get_pending_work(state: tauri::State<'_, AppState>) -> Result<PendingWork, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_pending_work called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    match node.get_pending_work().await {
+        Ok(pending_work) => Ok(pending_work),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_inbound_friend_requests(state: tauri::State<'_, AppState>) -> Result<Vec<FriendRequest>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_inbound_friend_requests called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let friend_requests = match node.get_inbound_friend_requests().await {
+        Ok(friend_requests) => friend_requests,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(friend_requests)
+}
+
+#[tauri::command]
+async fn mark_friend_request_seen(state: tauri::State<'_, AppState>, peer_id: String) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("mark_friend_request_seen called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("mark_friend_request_seen: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let _ = match node.mark_friend_request_seen(peer).await {
+        Ok(_) => (),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_friend_request_count(state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_friend_request_count called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let count = match node.get_friend_request_count().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(count)
+}
+
+#[tauri::command]
+async fn clear_all_friend_requests(state: tauri::State<'_, AppState>, confirm: bool) -> Result<(), String> {
+    if !confirm {
+        log::warn!("clear_all_friend_requests called without confirmation");
+        return Err("Clearing all friend requests requires confirmation".into());
+    }
+
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("clear_all_friend_requests called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    if let Err(err) = node.clear_all_friend_requests().await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_direct_messages(state: tauri::State<'_, AppState>, peer_id: String) -> Result<Vec<DirectMessage>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_direct_messages called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer_id = match PeerId::from_str(&peer_id) {
+        Ok(p) => p,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let direct_messages = match node.get_direct_messages(peer_id).await {
+        Ok(dms) => dms,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(direct_messages)
+}
+
+#[tauri::command]
+async fn mark_conversation_read(state: tauri::State<'_, AppState>, peer_id: String) -> Result<usize, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("mark_conversation_read called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer_id = match PeerId::from_str(&peer_id) {
+        Ok(p) => p,
+        Err(err) => {
+            log::error!("mark_conversation_read: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    match node.mark_conversation_read(peer_id).await {
+        Ok(marked) => Ok(marked),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Marks every conversation read at once, sending read receipts if they're enabled.
+#[tauri::command]
+async fn mark_all_read(state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("mark_all_read called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    match node.mark_all_read().await {
+        Ok(marked) => Ok(marked),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_pending_message_counts(state: tauri::State<'_, AppState>) -> Result<HashMap<String, usize>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_pending_message_counts called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let counts = match node.get_pending_counts().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(counts.into_iter().map(|(peer, count)| (peer.to_string(), count)).collect())
+}
+
+#[tauri::command]
+async fn get_pending_messages(state: tauri::State<'_, AppState>, peer_id: String) -> Result<Vec<DirectMessage>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_pending_messages called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer_id = match PeerId::from_str(&peer_id) {
+        Ok(p) => p,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let pending_messages = match node.get_pending_messages(peer_id).await {
+        Ok(dms) => dms,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(pending_messages)
+}
+
+#[tauri::command]
+async fn get_messages_in_range(state: tauri::State<'_, AppState>, peer_id: String, start: i64, end: i64) -> Result<Vec<DirectMessage>, String> {
+    if start > end {
+        log::warn!("get_messages_in_range called with start {} after end {}", start, end);
+        return Err("Start of range must not be after the end".into());
+    }
+
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_messages_in_range called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer_id = match PeerId::from_str(&peer_id) {
+        Ok(p) => p,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let messages = match node.get_messages_in_range(peer_id, start, end).await {
+        Ok(dms) => dms,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(messages)
+}
+
+#[tauri::command]
+fn peer_id_to_public_key(peer_id: String) -> Result<Vec<u8>, String> {
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("peer_id_to_public_key: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    match p2p::crypto::peer_id_to_public_key(&peer) {
+        Ok(public_key) => Ok(public_key.to_vec()),
+        Err(err) => {
+            log::warn!("peer_id_to_public_key: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+fn is_conversation_encrypted(peer_id: String) -> Result<EncryptionStatus, String> {
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("is_conversation_encrypted: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(p2p::crypto::encryption_status(&peer))
+}
+
+#[cfg(feature = "debug-crypto")]
+#[tauri::command]
+async fn debug_encrypt(state: tauri::State<'_, AppState>, peer_id: String, plaintext: String) -> Result<Vec<u8>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("debug_encrypt called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("debug_encrypt: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    p2p::crypto::encrypt_for_peer(&node.get_keypair(), &peer, plaintext.as_bytes())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(feature = "debug-crypto")]
+#[tauri::command]
+async fn debug_decrypt(state: tauri::State<'_, AppState>, peer_id: String, ciphertext: Vec<u8>) -> Result<String, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("debug_decrypt called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("debug_decrypt: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let plaintext = p2p::crypto::decrypt_from_peer(&node.get_keypair(), &peer, &ciphertext)
+        .map_err(|err| err.to_string())?;
+
+    String::from_utf8(plaintext).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn refresh_peer_address(state: tauri::State<'_, AppState>, peer_id: String) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("refresh_peer_address called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("refresh_peer_address: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    if let Err(err) = node.refresh_peer_address(peer).await {
+        log::error!("refresh_peer_address: {}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_holepunch_status(state: tauri::State<'_, AppState>, peer_id: String) -> Result<HolepunchStatus, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_holepunch_status called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("get_holepunch_status: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let status = match node.get_holepunch_status(peer).await {
+        Ok(status) => status,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(status)
+}
+
+#[tauri::command]
+async fn get_connection_type(state: tauri::State<'_, AppState>, peer_id: String) -> Result<Option<ConnectionType>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_connection_type called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("get_connection_type: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let connection_type = match node.get_connection_type(peer).await {
+        Ok(connection_type) => connection_type,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(connection_type)
+}
+
+#[tauri::command]
+async fn measure_latency(state: tauri::State<'_, AppState>, peer_id: String) -> Result<u64, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("measure_latency called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("measure_latency: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let round_trip_ms = match node.measure_latency(peer).await {
+        Ok(round_trip_ms) => round_trip_ms,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(round_trip_ms)
+}
+
+/// Positive when the peer's clock is ahead of ours. Offsets large enough to reorder messages
+/// also emit `clock-skew-detected`.
+#[tauri::command]
+async fn get_clock_skew(state: tauri::State<'_, AppState>, peer_id: String) -> Result<i64, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_clock_skew called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("get_clock_skew: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let offset_ms = match node.get_clock_skew(peer).await {
+        Ok(offset_ms) => offset_ms,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(offset_ms)
+}
+
+/// Dials `multiaddr` on a throwaway connection, without creating a user, to check that it's
+/// reachable before a friend request is sent to it.
+#[tauri::command]
+async fn probe_address(state: tauri::State<'_, AppState>, multiaddr: String) -> Result<AddressProbeResult, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("probe_address called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let address = match multiaddr.parse::<Multiaddr>() {
+        Ok(address) => address,
+        Err(err) => {
+            log::error!("probe_address: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let result = match node.probe_address(address).await {
+        Ok(result) => result,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(result)
+}
+
+#[tauri::command]
+async fn peer_supports_feature(state: tauri::State<'_, AppState>, peer_id: String, feature: String) -> Result<bool, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("peer_supports_feature called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(err) => {
+            log::error!("peer_supports_feature: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    match node.peer_supports_feature(peer, feature).await {
+        Ok(supported) => Ok(supported),
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[cfg(feature = "net-sim")]
+#[tauri::command]
+async fn set_simulated_latency(state: tauri::State<'_, AppState>, ms: u64) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("set_simulated_latency called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    if let Err(err) = node.set_simulated_latency(std::time::Duration::from_millis(ms)).await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "net-sim")]
+#[tauri::command]
+async fn set_packet_loss(state: tauri::State<'_, AppState>, pct: u8) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("set_packet_loss called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    if pct > 100 {
+        return Err(format!("Packet loss must be between 0 and 100%, got {pct}%"));
+    }
+
+    if let Err(err) = node.set_packet_loss(pct).await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "debug-events")]
+#[tauri::command]
+async fn get_recent_events(state: tauri::State<'_, AppState>, n: usize) -> Result<Vec<p2p::recent_events::RecentEvent>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_recent_events called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let recent_events = match node.get_recent_events(n).await {
+        Ok(recent_events) => recent_events,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(recent_events)
+}
+
+#[tauri::command]
+async fn reload_friend_list(state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("reload_friend_list called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let count = match node.reload_friend_list().await {
+        Ok(count) => count,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    Ok(count)
+}
+
+#[tauri::command]
+async fn get_relay_info(state: tauri::State<'_, AppState>) -> Result<Option<RelayInfo>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_relay_info called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let relay_info = match node.get_relay_info().await {
+        Ok(relay_info) => relay_info,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
 
-    Ok(peer_id)
+    Ok(relay_info)
 }
 
 #[tauri::command]
-async fn get_my_info(state: tauri::State<'_, AppState>) -> Result<MyInfo, String> {
+async fn get_conversations(state: tauri::State<'_, AppState>) -> Result<Vec<ConversationSummary>, String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("get_my_info called but P2P node not started");
+            log::warn!("get_conversations called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
 
-    let addresses = node.get_listen_addresses().await;
-
-    let multiaddr = match addresses.first() {
-        Some(addr) => addr.to_string(),
-        None => {
-            log::error!(
-                "get_my_info: node {} has no listening addresses",
-                node.get_peer_id()
-            );
-            return Err("No listening addresses".into());
+    let conversations = match node.get_conversations(false).await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
         }
     };
 
-    let keypair = node.get_keypair().to_protobuf_encoding()
-        .map_err(|err| err.to_string())?;
-
-    Ok(MyInfo {
-        peer_id: node.get_peer_id().to_string(),
-        keypair,
-        multiaddr,
-    })
+    Ok(conversations)
 }
 
 #[tauri::command]
-async fn send_friend_request(
-    state: tauri::State<'_, AppState>,
-    peer_id: String,
-    multiaddr: String,
-    message: String
-) -> Result<(), String> {
+async fn get_blocked_conversations(state: tauri::State<'_, AppState>) -> Result<Vec<ConversationSummary>, String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("send_friend_request called but P2P node not started");
+            log::warn!("get_blocked_conversations called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
 
-    let peer = match peer_id.parse::<PeerId>() {
-        Ok(peer) => peer,
+    let conversations = match node.get_conversations(true).await {
+        Ok(c) => c,
         Err(err) => {
-            log::error!("send_friend_request: {}", err.to_string());
+            log::error!("{}", err.to_string());
             return Err(err.to_string());
         }
     };
 
-    let address = match multiaddr.parse::<Multiaddr>(){
-        Ok(address) => address,
-        Err(err) => {
-            log::error!("send_friend_request: {}", err.to_string());
-            return Err(err.to_string());
+    Ok(conversations)
+}
+
+#[tauri::command]
+async fn get_dead_letters(state: tauri::State<'_, AppState>) -> Result<Vec<DeadLetter>, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_dead_letters called but P2P node not started");
+            return Err("P2P node not started".into());
         }
     };
 
-    let _ = match node.send_friend_request(peer, address, message) {
-        Ok(_) => (),
+    let dead_letters = match node.get_dead_letters().await {
+        Ok(d) => d,
         Err(err) => {
-            log::error!("send_friend_request: {}", err.to_string());
+            log::error!("{}", err.to_string());
             return Err(err.to_string());
         }
     };
 
-    Ok(())
+    Ok(dead_letters)
 }
 
 #[tauri::command]
-async fn accept_friend_request(state: tauri::State<'_, AppState>, peer_id: String) -> Result<(), String> {
+async fn retry_dead_letter(state: tauri::State<'_, AppState>, id: i64) -> Result<(), String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("accept_friend_request called but P2P node not started");
+            log::warn!("retry_dead_letter called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
 
+    if let Err(err) = node.retry_dead_letter(id).await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_quarantined_messages() -> Result<Vec<QuarantinedMessage>, String> {
+    match db::fetch_quarantined_messages(db::DATABASE.clone()) {
+        Ok(messages) => Ok(messages),
+        Err(err) => {
+            log::error!("get_quarantined_messages: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Friends whose address answered with a different identity, until acknowledged.
+#[tauri::command]
+async fn get_key_changes() -> Result<Vec<KeyChange>, String> {
+    match db::fetch_key_changes(db::DATABASE.clone()) {
+        Ok(key_changes) => Ok(key_changes),
+        Err(err) => {
+            log::error!("get_key_changes: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Friend requests that have been accepted or denied, most recent first.
+#[tauri::command]
+async fn get_friend_request_history() -> Result<Vec<FriendRequestHistoryEntry>, String> {
+    match db::fetch_friend_request_history(db::DATABASE.clone()) {
+        Ok(history) => Ok(history),
+        Err(err) => {
+            log::error!("get_friend_request_history: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Clears the safety number warning for `peer_id`. The notice stays in the conversation.
+#[tauri::command]
+async fn acknowledge_key_change(peer_id: String) -> Result<bool, String> {
+    match db::acknowledge_key_change(db::DATABASE.clone(), peer_id) {
+        Ok(acknowledged) => Ok(acknowledged),
+        Err(err) => {
+            log::error!("acknowledge_key_change: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Moves the sender's quarantined messages into a normal conversation. Only friends' messages
+/// bypass quarantine, so unless `add_as_friend` is set, later messages are quarantined again.
+#[tauri::command]
+async fn approve_quarantined_sender(state: tauri::State<'_, AppState>, peer_id: String, add_as_friend: bool) -> Result<usize, String> {
     let peer = match peer_id.parse::<PeerId>() {
         Ok(peer) => peer,
         Err(err) => {
-            log::error!("accept_friend_request: {}", err.to_string());
+            log::error!("approve_quarantined_sender: {}", err.to_string());
             return Err(err.to_string());
         }
     };
 
-    let _ = match node.accept_friend_request(peer) {
-        Ok(_) => (),
+    // Check everything adding them as a friend needs before moving their messages, so a
+    // failure doesn't leave them approved but not added.
+    let node_guard = state.p2p_node.lock().await;
+
+    let friend_target = if add_as_friend {
+        let node = match node_guard.as_ref() {
+            Some(node) => node,
+            None => {
+                log::warn!("approve_quarantined_sender called but P2P node not started");
+                return Err("P2P node not started".into());
+            }
+        };
+
+        let address = match db::fetch_user_by_peer_id(db::DATABASE.clone(), peer_id.clone())
+            .and_then(|user| user.multiaddr.parse::<Multiaddr>().map_err(anyhow::Error::from)) {
+            Ok(address) => address,
+            Err(err) => {
+                log::error!("approve_quarantined_sender: {}", err.to_string());
+                return Err(err.to_string());
+            }
+        };
+
+        Some((node, address))
+    } else {
+        None
+    };
+
+    let identity = match db::fetch_identity(db::DATABASE.clone()) {
+        Ok(identity) => identity,
+        Err(err) => {
+            log::error!("approve_quarantined_sender: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    let moved = match db::approve_quarantined_sender(db::DATABASE.clone(), identity.peer_id, peer_id.clone()) {
+        Ok(moved) => moved,
         Err(err) => {
+            log::error!("approve_quarantined_sender: {}", err.to_string());
+            return Err(err.to_string());
+        }
+    };
+
+    log::info!("Approved {} quarantined messages from {}", moved, peer_id);
+
+    if let Some((node, address)) = friend_target {
+        if let Err(err) = node.add_friend_directly(peer, address, None).await {
             log::error!("{}", err.to_string());
             return Err(err.to_string());
         }
+    }
+
+    Ok(moved)
+}
+
+#[tauri::command]
+async fn set_sync_enabled(state: tauri::State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("set_sync_enabled called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
     };
 
+    if let Err(err) = node.set_sync_enabled(enabled).await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
     Ok(())
 }
 
+/// Trusted import of a contact exchanged out of band. The peer id is taken on trust, so the UI
+/// should only offer this for ids the user got from the friend directly.
 #[tauri::command]
-async fn deny_friend_request(state: tauri::State<'_, AppState>, peer_id: String) -> Result<(), String> {
+async fn add_friend_directly(state: tauri::State<'_, AppState>, peer_id: String, multiaddr: String, nickname: Option<String>) -> Result<(), String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("deny_friend_request called but P2P node not started");
+            log::warn!("add_friend_directly called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
@@ -219,175 +2018,263 @@ async fn deny_friend_request(state: tauri::State<'_, AppState>, peer_id: String)
     let peer = match peer_id.parse::<PeerId>() {
         Ok(peer) => peer,
         Err(err) => {
-            log::error!("deny_friend_request: {}", err.to_string());
+            log::error!("add_friend_directly: {}", err.to_string());
             return Err(err.to_string());
         }
     };
 
-    let _ = match node.deny_friend_request(peer) {
-        Ok(_) => (),
+    let address = match multiaddr.parse::<Multiaddr>() {
+        Ok(address) => address,
         Err(err) => {
-            log::error!("{}", err.to_string());
+            log::error!("add_friend_directly: {}", err.to_string());
             return Err(err.to_string());
         }
     };
 
+    if let Err(err) = node.add_friend_directly(peer, address, nickname).await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
     Ok(())
 }
 
 #[tauri::command]
-async fn send_post(state: tauri::State<'_, AppState>, content: String) -> Result<(), String> {
+async fn cancel_sync(state: tauri::State<'_, AppState>, peer_id: String) -> Result<(), String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("send_post called but P2P node not started");
+            log::warn!("cancel_sync called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
 
-    let _ = match node.send_post(content) {
-        Ok(_) => (),
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
         Err(err) => {
-            log::error!("{}", err.to_string());
+            log::error!("cancel_sync: {}", err.to_string());
             return Err(err.to_string());
         }
     };
 
+    if let Err(err) = node.cancel_sync(peer).await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
     Ok(())
 }
 
 #[tauri::command]
-async fn send_direct_message(state: tauri::State<'_, AppState>, peer_id: String, content: String) -> Result<(), String> {
+async fn get_explicit_peers(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("send_direct_message called but P2P node not started");
+            log::warn!("get_explicit_peers called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
 
-    let peer = match peer_id.parse::<PeerId>() {
-        Ok(peer) => peer,
+    let peers = match node.get_explicit_peers().await {
+        Ok(peers) => peers,
         Err(err) => {
-            log::error!("send_direct_message: {}", err.to_string());
+            log::error!("{}", err.to_string());
             return Err(err.to_string());
         }
     };
 
-    let address = match db::fetch_user_by_peer_id(db::DATABASE.clone(), peer_id) {
-        Ok(user) => match Multiaddr::from_str(&user.multiaddr) {
-            Ok(address) => address,
-            Err(err) => {
-                log::error!("send_direct_message: {}", err.to_string());
-                return Err(err.to_string())
-            }
-        },
-        Err(err) => {
-            log::error!("send_direct_message: {}", err.to_string());
-            return Err(err.to_string())
+    Ok(peers.iter().map(|p| p.to_string()).collect())
+}
+
+/// Adds any friend missing from the explicit gossipsub peers and removes any non-friend.
+#[tauri::command]
+async fn resync_explicit_peers(state: tauri::State<'_, AppState>) -> Result<ExplicitPeersResync, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("resync_explicit_peers called but P2P node not started");
+            return Err("P2P node not started".into());
         }
     };
 
-    let _ = match node.send_direct_message(peer, address, content) {
-        Ok(_) => (),
+    let resync = match node.resync_explicit_peers().await {
+        Ok(resync) => resync,
         Err(err) => {
             log::error!("{}", err.to_string());
             return Err(err.to_string());
         }
     };
-    
-    Ok(())
+
+    Ok(resync)
 }
 
+/// Activity since the P2P node was started, or since the stats were last reset.
 #[tauri::command]
-async fn get_friend_list(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
+async fn get_session_stats(state: tauri::State<'_, AppState>) -> Result<SessionStats, String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("get_friend_list called but P2P node not started");
+            log::warn!("get_session_stats called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
 
-    let friends = match node.get_friend_list().await {
-        Ok(friends) => friends,
+    let session_stats = match node.get_session_stats().await {
+        Ok(session_stats) => session_stats,
         Err(err) => {
             log::error!("{}", err.to_string());
             return Err(err.to_string());
         }
     };
 
-    Ok(friends.iter().map(|p| p.to_string()).collect())
+    Ok(session_stats)
 }
 
 #[tauri::command]
-async fn get_inbound_friend_requests(state: tauri::State<'_, AppState>) -> Result<Vec<FriendRequest>, String> {
+async fn reset_session_stats(state: tauri::State<'_, AppState>) -> Result<(), String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("get_inbound_friend_requests called but P2P node not started");
+            log::warn!("reset_session_stats called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
 
-    let friend_requests = match node.get_inbound_friend_requests().await {
-        Ok(friend_requests) => friend_requests,
+    if let Err(err) = node.reset_session_stats().await {
+        log::error!("{}", err.to_string());
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+/// Logs every swarm event and command involving `peer_id` with a `[trace:<peer>]` prefix,
+/// whatever the log level, until disabled.
+#[tauri::command]
+async fn trace_peer(state: tauri::State<'_, AppState>, peer_id: String, enabled: bool) -> Result<(), String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("trace_peer called but P2P node not started");
+            return Err("P2P node not started".into());
+        }
+    };
+
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
         Err(err) => {
-            log::error!("{}", err.to_string());
+            log::error!("trace_peer: {}", err.to_string());
             return Err(err.to_string());
         }
     };
 
-    Ok(friend_requests)
+    // Trace lines are logged at debug level, which the logger only lets through for them.
+    if enabled {
+        logger::begin_tracing();
+    }
+
+    match node.trace_peer(peer, enabled).await {
+        Ok(still_tracing) => {
+            if !still_tracing {
+                logger::end_tracing();
+            }
+        },
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            return Err(err.to_string());
+        }
+    }
+
+    Ok(())
 }
 
 #[tauri::command]
-async fn get_direct_messages(state: tauri::State<'_, AppState>, peer_id: String) -> Result<Vec<DirectMessage>, String> {
+async fn get_network_config(state: tauri::State<'_, AppState>) -> Result<NetworkConfigInfo, String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("get_direct_messages called but P2P node not started");
+            log::warn!("get_network_config called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
 
-    let peer_id = match PeerId::from_str(&peer_id) {
-        Ok(p) => p,
+    match node.get_network_config().await {
+        Ok(network_config) => Ok(network_config),
         Err(err) => {
             log::error!("{}", err.to_string());
-            return Err(err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_network_stats(state: tauri::State<'_, AppState>) -> Result<NetworkStats, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("get_network_stats called but P2P node not started");
+            return Err("P2P node not started".into());
         }
     };
 
-    let direct_messages = match node.get_direct_messages(peer_id).await {
-        Ok(dms) => dms,
+    match node.get_network_stats().await {
+        Ok(stats) => Ok(stats),
         Err(err) => {
             log::error!("{}", err.to_string());
-            return Err(err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn sync_all_friends(state: tauri::State<'_, AppState>) -> Result<SyncSummary, String> {
+    let node_guard = state.p2p_node.lock().await;
+
+    let node = match node_guard.as_ref() {
+        Some(node) => node,
+        None => {
+            log::warn!("sync_all_friends called but P2P node not started");
+            return Err("P2P node not started".into());
         }
     };
 
-    Ok(direct_messages)
+    match node.sync_all_friends().await {
+        Ok(Some(summary)) => Ok(summary),
+        Ok(None) => {
+            log::warn!("sync_all_friends called while post sync is paused");
+            Err("Post sync is paused".into())
+        },
+        Err(err) => {
+            log::error!("{}", err.to_string());
+            Err(err.to_string())
+        }
+    }
 }
 
 #[tauri::command]
-async fn load_feed(state: tauri::State<'_, AppState>) -> Result<Vec<Post>, String> {
+async fn get_feed(state: tauri::State<'_, AppState>) -> Result<Vec<Post>, String> {
     let node_guard = state.p2p_node.lock().await;
 
     let node = match node_guard.as_ref() {
         Some(node) => node,
         None => {
-            log::warn!("load_feed called but P2P node not started");
+            log::warn!("get_feed called but P2P node not started");
             return Err("P2P node not started".into());
         }
     };
@@ -403,30 +2290,42 @@ async fn load_feed(state: tauri::State<'_, AppState>) -> Result<Vec<Post>, Strin
     Ok(posts)
 }
 
+/// The feed with each post's author display name already resolved.
 #[tauri::command]
-async fn load_board(state: tauri::State<'_, AppState>, peer_id: String) -> Result<Vec<Post>, String> {
-    let node_guard = state.p2p_node.lock().await;
-
-    let node = match node_guard.as_ref() {
-        Some(node) => node,
-        None => {
-            log::warn!("load_board called but P2P node not started");
-            return Err("P2P node not started".into());
+async fn get_feed_with_authors() -> Result<Vec<PostWithAuthor>, String> {
+    let identity = match db::fetch_identity(db::DATABASE.clone()) {
+        Ok(identity) => identity,
+        Err(err) => {
+            log::error!("get_feed_with_authors: {}", err.to_string());
+            return Err(err.to_string());
         }
     };
 
+    match db::fetch_feed_posts_with_authors(db::DATABASE.clone(), identity.peer_id) {
+        Ok(posts) => Ok(posts),
+        Err(err) => {
+            log::error!("get_feed_with_authors: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
+/// Returns the posts stored locally for a peer, so a friend's board stays readable while
+/// they're offline. This never contacts the peer; use `sync_all_friends` to fetch newer posts.
+#[tauri::command]
+async fn get_board(peer_id: String) -> Result<Vec<Post>, String> {
     let peer_id = match PeerId::from_str(&peer_id) {
         Ok(p) => p,
         Err(err) => {
-            log::error!("{}", err.to_string());
+            log::error!("get_board: {}", err.to_string());
             return Err(err.to_string());
         }
     };
 
-    let posts = match node.load_board(peer_id).await {
+    let posts = match db::fetch_board_posts(db::DATABASE.clone(), peer_id.to_string()) {
         Ok(p) => p,
         Err(err) => {
-            log::error!("{}", err.to_string());
+            log::error!("get_board: {}", err.to_string());
             return Err(err.to_string());
         }
     };
@@ -434,6 +2333,18 @@ async fn load_board(state: tauri::State<'_, AppState>, peer_id: String) -> Resul
     Ok(posts)
 }
 
+/// Finds stored posts containing `query`, newest first, optionally only those by `author`.
+#[tauri::command]
+async fn search_posts(query: String, author: Option<String>) -> Result<Vec<Post>, String> {
+    match db::search_posts(db::DATABASE.clone(), query, author) {
+        Ok(posts) => Ok(posts),
+        Err(err) => {
+            log::error!("search_posts: {}", err.to_string());
+            Err(err.to_string())
+        }
+    }
+}
+
 #[tauri::command]
 async fn connect_to_relay(state: tauri::State<'_, AppState>, relay_address: String) -> Result<(), String> {
     let node_guard = state.p2p_node.lock().await;
@@ -454,7 +2365,7 @@ async fn connect_to_relay(state: tauri::State<'_, AppState>, relay_address: Stri
         }
     };
 
-    let _ = match node.connect_to_relay(address) {
+    let _ = match node.connect_to_relay(address).await {
         Ok(_) => (),
         Err(err) => {
             log::error!("{}", err.to_string());
@@ -477,9 +2388,34 @@ fn main() {
             p2p_node: Arc::new(Mutex::new(None))
         })
         .plugin(tauri_plugin_opener::init())
+        .setup(|app| {
+            let auto_start = db::get_auto_start(db::DATABASE.clone()).unwrap_or_else(|err| {
+                log::error!("get_auto_start: {err}");
+                false
+            });
+
+            if auto_start {
+                log::info!("Auto start is enabled, starting P2P node");
+                let handle = app.handle().clone();
+
+                tauri::async_runtime::spawn(async move {
+                    match start_p2p(handle.state::<AppState>(), handle.clone()).await {
+                        Ok(peer_id) => {
+                            handle.emit("p2p-started", peer_id).ok();
+                        },
+                        Err(err) => log::error!("Auto start failed: {err}")
+                    }
+                });
+            }
+
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             start_p2p,
             get_my_info,
+            get_identity_info,
+            get_initial_state,
+            set_my_nickname,
             send_friend_request,
             accept_friend_request,
             deny_friend_request,
@@ -487,10 +2423,107 @@ fn main() {
             send_direct_message,
             get_friend_list,
             get_inbound_friend_requests,
+            mark_friend_request_seen,
+            get_friend_request_count,
+            clear_all_friend_requests,
             get_direct_messages,
-            load_feed,
-            load_board,
-            connect_to_relay
+            get_pending_message_counts,
+            get_pending_messages,
+            get_messages_in_range,
+            get_dead_letters,
+            get_conversations,
+            get_blocked_conversations,
+            peer_id_to_public_key,
+            is_conversation_encrypted,
+            refresh_peer_address,
+            get_holepunch_status,
+            get_connection_type,
+            prune_orphaned_users,
+            verify_startup_state,
+            block_peer_id,
+            export_block_list,
+            import_block_list,
+            set_max_friends,
+            #[cfg(feature = "debug-crypto")]
+            debug_encrypt,
+            #[cfg(feature = "debug-crypto")]
+            debug_decrypt,
+            #[cfg(feature = "debug-events")]
+            get_recent_events,
+            #[cfg(feature = "net-sim")]
+            set_simulated_latency,
+            #[cfg(feature = "net-sim")]
+            set_packet_loss,
+            retry_dead_letter,
+            set_sync_enabled,
+            sync_all_friends,
+            get_feed,
+            get_board,
+            connect_to_relay,
+            get_relay_info,
+            measure_latency,
+            pause_peer,
+            resume_peer,
+            reload_friend_list,
+            get_auto_start,
+            set_auto_start,
+            get_setting,
+            set_setting,
+            export_account_data,
+            save_draft,
+            get_draft,
+            clear_draft,
+            peer_supports_feature,
+            cancel_sync,
+            set_connection_policy,
+            get_network_config,
+            get_logs_size,
+            clean_old_logs,
+            get_message_activity,
+            add_friend_directly,
+            get_network_stats,
+            repair_identity_peer_id,
+            get_version_info,
+            mark_conversation_read,
+            get_read_receipts_enabled,
+            set_read_receipts_enabled,
+            count_online_friends,
+            is_first_run,
+            complete_onboarding,
+            export_signed_transcript,
+            verify_signed_transcript,
+            get_auto_update_friend_addresses,
+            set_auto_update_friend_addresses,
+            get_retention,
+            set_retention,
+            get_clock_skew,
+            get_quarantined_messages,
+            approve_quarantined_sender,
+            get_pending_work,
+            get_conversation_storage,
+            get_largest_conversations,
+            probe_address,
+            get_max_pending_direct_messages,
+            set_max_pending_direct_messages,
+            get_key_changes,
+            acknowledge_key_change,
+            get_friend_profiles,
+            trace_peer,
+            get_listen_port,
+            set_listen_port,
+            get_explicit_peers,
+            resync_explicit_peers,
+            get_session_stats,
+            reset_session_stats,
+            set_peer_appearance,
+            get_peer_appearance,
+            search_posts,
+            set_peer_alias,
+            get_feed_with_authors,
+            mark_all_read,
+            get_friend_request_history,
+            get_network_namespace,
+            set_network_namespace
         ])
         .run(tauri::generate_context!()) {
             log::error!("Error while running tauri application: {}", err.to_string());
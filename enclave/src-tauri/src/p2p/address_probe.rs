@@ -0,0 +1,134 @@
+use libp2p::swarm::ConnectionId;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot::Sender;
+
+/// How long a probe dial may take before the address is reported unreachable.
+pub const ADDRESS_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AddressProbeStatus {
+    Reachable,
+    Unreachable
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressProbeResult {
+    pub status: AddressProbeStatus,
+    pub elapsed_ms: u64,
+    pub error: Option<String>
+}
+
+struct PendingAddressProbe {
+    started: Instant,
+    sender: Sender<AddressProbeResult>
+}
+
+/// Tracks transient dials made only to check that an address is reachable. Their connections
+/// are closed as soon as they're established and are otherwise ignored, so probing never
+/// creates a user or touches any other connection state.
+#[derive(Default)]
+pub struct AddressProber {
+    pending: HashMap<ConnectionId, PendingAddressProbe>,
+    /// Probe connections that have already been reported, but are still dialling or closing.
+    finished: HashSet<ConnectionId>
+}
+
+impl AddressProber {
+    pub fn start(&mut self, connection_id: ConnectionId, sender: Sender<AddressProbeResult>, now: Instant) {
+        self.pending.insert(connection_id, PendingAddressProbe { started: now, sender });
+    }
+
+    fn resolve(&mut self, connection_id: ConnectionId, status: AddressProbeStatus, error: Option<String>, now: Instant) -> bool {
+        let Some(probe) = self.pending.remove(&connection_id) else {
+            return false;
+        };
+
+        let elapsed_ms = now.saturating_duration_since(probe.started).as_millis() as u64;
+        let _ = probe.sender.send(AddressProbeResult { status, elapsed_ms, error });
+        true
+    }
+
+    /// Returns whether the connection was a probe dial, in which case it should be closed and
+    /// not handled any further.
+    pub fn handle_connection_established(&mut self, connection_id: ConnectionId, now: Instant) -> bool {
+        if self.resolve(connection_id, AddressProbeStatus::Reachable, None, now) {
+            self.finished.insert(connection_id);
+            return true;
+        }
+
+        self.finished.contains(&connection_id)
+    }
+
+    /// Returns whether the closed connection was a probe dial.
+    pub fn handle_connection_closed(&mut self, connection_id: ConnectionId) -> bool {
+        self.finished.remove(&connection_id)
+    }
+
+    pub fn is_probe(&self, connection_id: ConnectionId) -> bool {
+        self.pending.contains_key(&connection_id) || self.finished.contains(&connection_id)
+    }
+
+    pub fn handle_dial_failure(&mut self, connection_id: ConnectionId, error: String, now: Instant) {
+        self.resolve(connection_id, AddressProbeStatus::Unreachable, Some(error), now);
+        self.finished.remove(&connection_id);
+    }
+
+    /// Reports the probe as unreachable if it is still waiting. The dial itself can't be
+    /// cancelled, so a connection that completes afterwards is closed on arrival.
+    pub fn handle_timeout(&mut self, connection_id: ConnectionId, now: Instant) {
+        let error = format!("Timed out after {}s", ADDRESS_PROBE_TIMEOUT.as_secs());
+
+        if self.resolve(connection_id, AddressProbeStatus::Unreachable, Some(error), now) {
+            self.finished.insert(connection_id);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_probe_reports_reachable_and_ignores_its_connection() {
+        let mut prober = AddressProber::default();
+        let connection_id = ConnectionId::new_unchecked(1);
+        let (sender, mut receiver) = tokio::sync::oneshot::channel();
+        let started = Instant::now();
+
+        prober.start(connection_id, sender, started);
+        assert!(prober.handle_connection_established(connection_id, started + Duration::from_millis(40)));
+
+        let result = receiver.try_recv().expect("probe was not resolved");
+        assert_eq!(result.status, AddressProbeStatus::Reachable);
+        assert_eq!(result.elapsed_ms, 40);
+
+        assert!(prober.handle_connection_closed(connection_id));
+        assert!(!prober.handle_connection_closed(connection_id));
+        assert!(!prober.handle_connection_established(ConnectionId::new_unchecked(2), started));
+    }
+
+    #[test]
+    pub fn test_probe_of_unroutable_address_times_out_as_unreachable() {
+        let mut prober = AddressProber::default();
+        let connection_id = ConnectionId::new_unchecked(1);
+        let (sender, mut receiver) = tokio::sync::oneshot::channel();
+        let started = Instant::now();
+
+        prober.start(connection_id, sender, started);
+        prober.handle_timeout(connection_id, started + ADDRESS_PROBE_TIMEOUT);
+
+        let result = receiver.try_recv().expect("probe was not resolved");
+        assert_eq!(result.status, AddressProbeStatus::Unreachable);
+        assert_eq!(result.elapsed_ms, ADDRESS_PROBE_TIMEOUT.as_millis() as u64);
+        assert_eq!(serde_json::to_value(result.status).unwrap(), "unreachable");
+
+        // The dial eventually failing is still recognised as the probe's, but isn't reported twice.
+        assert!(prober.is_probe(connection_id));
+        prober.handle_dial_failure(connection_id, "Connection refused".into(), started);
+        assert!(!prober.is_probe(connection_id));
+    }
+}
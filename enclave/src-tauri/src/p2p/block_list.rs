@@ -0,0 +1,85 @@
+use libp2p::PeerId;
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+use crate::db;
+
+/// Serializes the blocked peer ids as a JSON array, oldest block first, for sharing.
+pub fn export_block_list(db: Arc<Mutex<Connection>>) -> anyhow::Result<String> {
+    let peer_ids = db::fetch_blocked_peer_ids(db)?;
+
+    Ok(serde_json::to_string(&peer_ids)?)
+}
+
+/// Blocks every peer id in a JSON array produced by `export_block_list`, returning the ones that
+/// weren't blocked already. The whole list is validated before anything is blocked.
+pub async fn import_block_list(db: Arc<Mutex<Connection>>, json: &str) -> anyhow::Result<Vec<PeerId>> {
+    let peer_ids = serde_json::from_str::<Vec<String>>(json)?
+        .iter()
+        .map(|peer_id| peer_id.parse::<PeerId>()
+            .map_err(|err| anyhow::anyhow!("Invalid peer id '{peer_id}' in block list: {err}")))
+        .collect::<anyhow::Result<Vec<PeerId>>>()?;
+
+    let mut blocked = Vec::new();
+
+    db::bulk_insert_with_yield(db, peer_ids, db::BULK_WRITE_BATCH_SIZE, |conn, peer_id| {
+        if db::insert_blocked_peer(conn, &peer_id.to_string())?.1 {
+            blocked.push(peer_id);
+        }
+        Ok(())
+    }).await?;
+
+    Ok(blocked)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_block_list_export_round_trips() {
+        let source = db::init_db(":memory:").expect("DB init failed");
+        let peers = [PeerId::random(), PeerId::random()];
+
+        for peer in peers.iter() {
+            db::block_peer_id(source.clone(), peer.to_string()).unwrap();
+        }
+
+        let json = export_block_list(source).expect("export_block_list failed");
+
+        let target = db::init_db(":memory:").expect("DB init failed");
+        assert_eq!(import_block_list(target.clone(), &json).await.expect("import_block_list failed"), peers);
+
+        let expected = peers.iter().map(|peer| peer.to_string()).collect::<Vec<String>>();
+        assert_eq!(db::fetch_blocked_peer_ids(target).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    pub async fn test_import_block_list_skips_existing_blocks() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let already_blocked = PeerId::random();
+        let known_user = PeerId::random();
+        let new_peer = PeerId::random();
+
+        db::block_peer_id(db.clone(), already_blocked.to_string()).unwrap();
+        let known_user_id = db::create_user(db.clone(), known_user.to_string(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+
+        let json = serde_json::to_string(&[already_blocked.to_string(), known_user.to_string(), new_peer.to_string()]).unwrap();
+
+        assert_eq!(import_block_list(db.clone(), &json).await.expect("import_block_list failed"), vec![known_user, new_peer]);
+        assert_eq!(db::fetch_blocked_peer_ids(db.clone()).unwrap().len(), 3);
+        assert!(db::is_user_blocked(db.clone(), known_user_id).unwrap());
+
+        assert!(import_block_list(db, &json).await.expect("import_block_list failed").is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_import_block_list_rejects_invalid_peer_ids() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let json = serde_json::to_string(&[PeerId::random().to_string(), "not-a-peer-id".to_string()]).unwrap();
+
+        let err = import_block_list(db.clone(), &json).await.unwrap_err();
+
+        assert!(err.to_string().contains("not-a-peer-id"));
+        assert!(db::fetch_blocked_peer_ids(db).unwrap().is_empty());
+    }
+}
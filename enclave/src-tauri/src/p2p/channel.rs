@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use crate::p2p::session_stats::SessionStats;
+use crate::p2p::types::P2PEvent;
+#[cfg(feature = "debug-events")]
+use crate::p2p::recent_events::{RecentEvent, RecentEvents, MAX_RECENT_EVENTS};
+
+pub const DEFAULT_EVENT_CHANNEL_CAPACITY: usize = 1024;
+pub const DEFAULT_COMMAND_CHANNEL_CAPACITY: usize = 256;
+
+impl P2PEvent {
+    /// Droppable events are transient notifications the frontend can recover from on its own:
+    /// relay status progress (superseded by the next status change) and `PostSynch` refresh hints.
+    /// Every other event carries state the UI can't re-derive and is never shed.
+    pub fn is_droppable(&self) -> bool {
+        matches!(self, P2PEvent::RelayStatusChanged(_) | P2PEvent::PostSynch)
+    }
+}
+
+/// Events waiting for room in the channel, in the order they were sent. While `forwarding`
+/// is set a single task is moving them into the channel, and new events queue behind them.
+#[derive(Default)]
+struct Overflow {
+    events: VecDeque<P2PEvent>,
+    forwarding: bool
+}
+
+/// Bounded sender for `P2PEvent`s. When the channel is full, events wait in an overflow queue
+/// of the same capacity that one task drains in order, so the event loop never blocks on a
+/// slow consumer. Once the overflow is full too, the oldest droppable event in it is shed to
+/// make room. Important events are never shed, so the overflow only grows past its capacity
+/// when it holds nothing else. Every event sent is counted towards the session's stats.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: mpsc::Sender<P2PEvent>,
+    overflow: Arc<std::sync::Mutex<Overflow>>,
+    overflow_capacity: usize,
+    dropped: Arc<AtomicU64>,
+    session_stats: Arc<std::sync::Mutex<SessionStats>>,
+    #[cfg(feature = "debug-events")]
+    recent_events: Arc<std::sync::Mutex<RecentEvents>>
+}
+
+pub fn event_channel(capacity: usize) -> (EventSender, mpsc::Receiver<P2PEvent>) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    let event_sender = EventSender {
+        sender,
+        overflow: Arc::new(std::sync::Mutex::new(Overflow::default())),
+        overflow_capacity: capacity,
+        dropped: Arc::new(AtomicU64::new(0)),
+        session_stats: Arc::new(std::sync::Mutex::new(SessionStats::default())),
+        #[cfg(feature = "debug-events")]
+        recent_events: Arc::new(std::sync::Mutex::new(RecentEvents::new(MAX_RECENT_EVENTS)))
+    };
+
+    (event_sender, receiver)
+}
+
+impl EventSender {
+    /// Returns `Full` only when `event` itself was shed.
+    pub fn send(&self, event: P2PEvent) -> Result<(), TrySendError<()>> {
+        if let Ok(mut session_stats) = self.session_stats.lock() {
+            session_stats.record(&event);
+        }
+
+        #[cfg(feature = "debug-events")]
+        if let Ok(mut recent_events) = self.recent_events.lock() {
+            recent_events.record(&event, chrono::Utc::now().timestamp_millis());
+        }
+
+        let Ok(mut overflow) = self.overflow.lock() else {
+            return Err(TrySendError::Closed(()));
+        };
+
+        // Sending straight to the channel while older events are still queued would reorder them.
+        let event = if overflow.forwarding {
+            event
+        } else {
+            match self.sender.try_send(event) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(event)) => event,
+                Err(TrySendError::Closed(_)) => return Err(TrySendError::Closed(()))
+            }
+        };
+
+        if overflow.events.len() >= self.overflow_capacity {
+            match overflow.events.iter().position(P2PEvent::is_droppable) {
+                Some(oldest_droppable) => {
+                    if let Some(shed) = overflow.events.remove(oldest_droppable) {
+                        self.record_dropped(&shed);
+                    }
+                },
+                None if event.is_droppable() => {
+                    self.record_dropped(&event);
+                    return Err(TrySendError::Full(()));
+                },
+                None => {}
+            }
+        }
+
+        overflow.events.push_back(event);
+
+        if !overflow.forwarding {
+            overflow.forwarding = true;
+            tokio::spawn(Self::forward_overflow(self.sender.clone(), self.overflow.clone()));
+        }
+
+        Ok(())
+    }
+
+    fn record_dropped(&self, event: &P2PEvent) {
+        let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        log::debug!("Event channel full, dropping {:?} ({} dropped so far)", event, dropped);
+    }
+
+    async fn forward_overflow(sender: mpsc::Sender<P2PEvent>, overflow: Arc<std::sync::Mutex<Overflow>>) {
+        loop {
+            let event = {
+                let Ok(mut overflow) = overflow.lock() else {
+                    return;
+                };
+
+                match overflow.events.pop_front() {
+                    Some(event) => event,
+                    None => {
+                        overflow.forwarding = false;
+                        return;
+                    }
+                }
+            };
+
+            if sender.send(event).await.is_err() {
+                if let Ok(mut overflow) = overflow.lock() {
+                    overflow.events.clear();
+                    overflow.forwarding = false;
+                }
+                return;
+            }
+        }
+    }
+
+    pub fn session_stats(&self) -> SessionStats {
+        self.session_stats.lock()
+            .map(|session_stats| *session_stats)
+            .unwrap_or_default()
+    }
+
+    pub fn reset_session_stats(&self) {
+        if let Ok(mut session_stats) = self.session_stats.lock() {
+            *session_stats = SessionStats::default();
+        }
+    }
+
+    /// Synced posts are stored without an event per post, so they're counted separately.
+    pub fn record_posts_synced(&self, count: usize) {
+        if let Ok(mut session_stats) = self.session_stats.lock() {
+            session_stats.record_posts_synced(count);
+        }
+    }
+
+    #[cfg(feature = "debug-events")]
+    pub fn recent_events(&self, count: usize) -> Vec<RecentEvent> {
+        self.recent_events.lock()
+            .map(|recent_events| recent_events.last(count))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::p2p::types::RelayStatus;
+    use libp2p::PeerId;
+
+    fn relay_attempt(event: Option<P2PEvent>) -> Option<u32> {
+        match event {
+            Some(P2PEvent::RelayStatusChanged(RelayStatus::Connecting { attempt })) => Some(attempt),
+            _ => None
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_event_channel_sheds_oldest_droppable_event_when_full() {
+        let (event_sender, mut event_receiver) = event_channel(1);
+        let peer = PeerId::random();
+        let connecting = |attempt| P2PEvent::RelayStatusChanged(RelayStatus::Connecting { attempt });
+
+        event_sender.send(connecting(0)).expect("send failed");
+        event_sender.send(connecting(1)).expect("send failed");
+        // The overflow is full, so the older attempt 1 makes way for attempt 2, then for the peer.
+        event_sender.send(connecting(2)).expect("send failed");
+        event_sender.send(P2PEvent::PeerConnected(peer)).expect("important event was not accepted");
+        // Nothing droppable is left to evict, so this one is shed itself.
+        assert!(matches!(event_sender.send(connecting(3)), Err(TrySendError::Full(()))));
+
+        assert_eq!(event_receiver.len(), 1);
+        assert_eq!(relay_attempt(event_receiver.recv().await), Some(0));
+        match event_receiver.recv().await {
+            Some(P2PEvent::PeerConnected(connected)) => assert_eq!(connected, peer),
+            _ => panic!("expected P2PEvent::PeerConnected")
+        }
+
+        tokio::task::yield_now().await;
+        assert!(event_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_event_channel_keeps_important_events_in_order_when_full() {
+        let (event_sender, mut event_receiver) = event_channel(1);
+        let peers = (0..5).map(|_| PeerId::random()).collect::<Vec<PeerId>>();
+
+        for peer in &peers {
+            event_sender.send(P2PEvent::PeerConnected(*peer)).expect("important event was not accepted");
+        }
+
+        for peer in &peers {
+            match event_receiver.recv().await {
+                Some(P2PEvent::PeerConnected(connected)) => assert_eq!(connected, *peer),
+                _ => panic!("expected P2PEvent::PeerConnected")
+            }
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_event_channel_defers_important_events_when_full() {
+        let (event_sender, mut event_receiver) = event_channel(1);
+        let peer = PeerId::random();
+
+        event_sender.send(P2PEvent::PostSynch).expect("send failed");
+        event_sender.send(P2PEvent::PeerConnected(peer)).expect("important event was not accepted");
+
+        assert_eq!(event_receiver.len(), 1);
+
+        assert!(matches!(event_receiver.recv().await, Some(P2PEvent::PostSynch)));
+        match event_receiver.recv().await {
+            Some(P2PEvent::PeerConnected(connected)) => assert_eq!(connected, peer),
+            _ => panic!("expected P2PEvent::PeerConnected")
+        }
+    }
+}
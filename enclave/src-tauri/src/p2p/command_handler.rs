@@ -1,13 +1,27 @@
-use libp2p::{PeerId, Multiaddr};
+use libp2p::{PeerId, Multiaddr, request_response::OutboundRequestId};
+use rusqlite::Connection;
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
 use crate::db;
+use crate::db::models::dead_letter::DeadLetter;
+use crate::db::models::friend_request::FriendRequest;
+use crate::db::models::friend_request_history::FriendRequestOutcome;
+use crate::db::models::post::Post;
 use crate::p2p::{types::*};
+use crate::p2p::channel::EventSender;
 use crate::p2p::config::EnclaveNetworkBehaviour;
+use crate::p2p::explicit_peers::ExplicitPeers;
+use crate::p2p::protocol::PeerProtocols;
+use crate::p2p::rate_limit::RateLimiter;
 
 pub struct CommandHandler;
 
+/// Sent to a friend added with `add_friend_directly` so they can add us back.
+pub const RECIPROCATE_FRIEND_MESSAGE: &str = "I added you as a friend directly. Accept to add me back.";
+
 impl CommandHandler {
     pub async fn handle_send_friend_request(
         peer: PeerId,
@@ -16,7 +30,7 @@ impl CommandHandler {
         listen_addrs: &Arc<Mutex<Vec<Multiaddr>>>,
         relay_addr: &Arc<Mutex<Option<Multiaddr>>>,
         swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
-        event_sender: &tokio::sync::mpsc::UnboundedSender<P2PEvent>
+        event_sender: &EventSender
     ) {
         log::info!("Buffering friend request to: {peer} at: {address}");
 
@@ -30,55 +44,67 @@ impl CommandHandler {
         };
 
         if let Err(err) = db::create_friend_request(db::DATABASE.clone(), swarm.local_peer_id().to_string(), from_multiaddr, peer.to_string(), address.to_string(), message) {
-            let _ = event_sender.send(P2PEvent::Error { context: "create_friend_request", error: err.to_string() });
+            let _ = event_sender.send(P2PEvent::Error { context: "create_friend_request", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
         };
 
         if let Err(err) = swarm.dial(address) {
-            let _ = event_sender.send(P2PEvent::Error { context: "swarm.dial", error: format!("Failed to dial peer {}: {}", peer, err.to_string()) });
+            let _ = event_sender.send(P2PEvent::Error { context: "swarm.dial", error: format!("Failed to dial peer {}: {}", peer, err.to_string()), severity: ErrorSeverity::Recoverable });
         }
     }
 
     pub async fn handle_accept_friend_request(
         peer: PeerId,
         friend_list: &mut Vec<PeerId>,
+        explicit_peers: &mut ExplicitPeers,
         pending_responses: &mut HashMap<PeerId, P2PMessage>,
         listen_addrs: &Arc<Mutex<Vec<Multiaddr>>>,
         relay_addr: &Arc<Mutex<Option<Multiaddr>>>,
         swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
-        event_sender: &tokio::sync::mpsc::UnboundedSender<P2PEvent>
+        event_sender: &EventSender
     ) {
         log::info!("Accepting friend request from: {}", peer);
 
         if !friend_list.contains(&peer) {
+            match db::friend_limit_reached(db::DATABASE.clone()) {
+                Ok(Some(limit)) => {
+                    log::warn!("Not accepting friend request from {}: friend limit of {} reached", peer, limit);
+                    let _ = event_sender.send(P2PEvent::FriendLimitReached { peer, limit });
+                    return;
+                },
+                Ok(None) => {},
+                Err(err) => {
+                    let _ = event_sender.send(P2PEvent::Error { context: "friend_limit_reached", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                    return;
+                }
+            }
+
             let user = match db::fetch_user_by_peer_id(db::DATABASE.clone(), peer.to_string()) {
                 Ok(u) => u,
                 Err(err) => {
                     let _ = event_sender.send(P2PEvent::Error {
                         context: "fetch_user_by_peer_id",
-                        error: err.to_string()
+                        error: err.to_string(),
+                        severity: ErrorSeverity::from_db_error(&err)
                     });
                     return;
                 }
             };
 
-            if let Err(err) = db::create_friend(db::DATABASE.clone(), user.id) {
+            if let Err(err) = db::befriend(db::DATABASE.clone(), user.id) {
                 let _ = event_sender.send(P2PEvent::Error {
-                    context: "create_friend",
-                    error: err.to_string()
+                    context: "befriend",
+                    error: err.to_string(),
+                    severity: ErrorSeverity::from_db_error(&err)
                 });
                 return;
             }
 
-            if let Ok(friend_requests) = db::fetch_friend_requests_to_peer(db::DATABASE.clone(), user.peer_id) {
-                if friend_requests.len() > 0 { 
-                    if let Err(err) = db::delete_friend_request(db::DATABASE.clone(), friend_requests[0].id) {
-                        let _ = event_sender.send(P2PEvent::Error { context: "delete_friend_request", error: err.to_string() });
-                    }
-                }
+            if let Err(err) = db::resolve_friend_request(db::DATABASE.clone(), user.peer_id, swarm.local_peer_id().to_string(), FriendRequestOutcome::Accepted) {
+                let _ = event_sender.send(P2PEvent::Error { context: "resolve_friend_request", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
             }
 
             friend_list.push(peer);
-            swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+            explicit_peers.add(&mut swarm.behaviour_mut().gossipsub, &peer);
         }
 
         let local_addresses = listen_addrs.lock().await;
@@ -92,7 +118,8 @@ impl CommandHandler {
 
         let response = P2PMessage::FriendRequestResponse(FriendRequestResponse {
             accepted: true,
-            multiaddr: address_to_send
+            multiaddr: address_to_send,
+            reason: None
         });
 
         if swarm.is_connected(&peer) {
@@ -106,7 +133,8 @@ impl CommandHandler {
                 Err(err) => {
                     let _ = event_sender.send(P2PEvent::Error {
                         context: "fetch_user_by_peer_id",
-                        error: err.to_string()
+                        error: err.to_string(),
+                        severity: ErrorSeverity::from_db_error(&err)
                     });
                     return;
                 }
@@ -117,7 +145,8 @@ impl CommandHandler {
                 if let Err(err) = swarm.dial(peer_addr) {
                     let _ = event_sender.send(P2PEvent::Error {
                         context: "swarm.dial",
-                        error: err.to_string()
+                        error: err.to_string(),
+                        severity: ErrorSeverity::Recoverable
                     });
                     pending_responses.remove(&peer);
                 }
@@ -127,8 +156,9 @@ impl CommandHandler {
 
     pub async fn handle_deny_friend_request(
         peer: PeerId,
+        reason: Option<String>,
         swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
-        event_sender: &tokio::sync::mpsc::UnboundedSender<P2PEvent>
+        event_sender: &EventSender
     ) {
         let user = match db::fetch_user_by_peer_id(db::DATABASE.clone(), peer.to_string()) {
             Ok(u) => u,
@@ -136,44 +166,96 @@ impl CommandHandler {
                 let _ = event_sender.send(P2PEvent::Error {
                     context: "fetch_user_by_peer_id",
                     error: err.to_string(),
+                    severity: ErrorSeverity::from_db_error(&err)
                 });
                 return;
             }
         };
 
-        if let Ok(friend_requests) = db::fetch_friend_requests_to_peer(db::DATABASE.clone(), user.peer_id) {
-            if friend_requests.len() > 0 { 
-                if let Err(err) = db::delete_friend_request(db::DATABASE.clone(), friend_requests[0].id) {
-                    let _ = event_sender.send(P2PEvent::Error { context: "delete_friend_request", error: err.to_string() });
-                }
-            }
+        if let Err(err) = db::resolve_friend_request(db::DATABASE.clone(), user.peer_id, swarm.local_peer_id().to_string(), FriendRequestOutcome::Denied) {
+            let _ = event_sender.send(P2PEvent::Error { context: "resolve_friend_request", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
         }
 
-        let response = P2PMessage::FriendRequestResponse(FriendRequestResponse {
-            accepted: false,
-            multiaddr: String::new()
-        });
+        let response = P2PMessage::FriendRequestResponse(FriendRequestResponse::denied(reason));
 
         swarm.behaviour_mut().request_response.send_request(&peer, response);
     }
 
+    pub fn handle_clear_all_friend_requests(
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        event_sender: &EventSender
+    ) {
+        let local_peer_id = swarm.local_peer_id().to_string();
+
+        let inbound_requests = db::fetch_friend_requests_to_peer(db::DATABASE.clone(), local_peer_id.clone())
+            .unwrap_or_default();
+
+        for request in inbound_requests {
+            let peer = match PeerId::from_str(&request.from_peer_id) {
+                Ok(p) => p,
+                Err(err) => {
+                    let _ = event_sender.send(P2PEvent::Error { context: "PeerId::from_str", error: err.to_string(), severity: ErrorSeverity::Warning });
+                    continue;
+                }
+            };
+
+            if swarm.is_connected(&peer) {
+                let response = P2PMessage::FriendRequestResponse(FriendRequestResponse::denied(None));
+
+                swarm.behaviour_mut().request_response.send_request(&peer, response);
+            }
+        }
+
+        match db::deny_friend_requests_to_peer(db::DATABASE.clone(), local_peer_id) {
+            Ok(deleted) => log::info!("Cleared {} inbound friend requests", deleted),
+            Err(err) => {
+                let _ = event_sender.send(P2PEvent::Error { context: "deny_friend_requests_to_peer", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            }
+        }
+    }
+
     pub async fn handle_send_direct_message(
         peer_id: PeerId,
         address: Multiaddr,
         content: String,
         friend_list: &mut Vec<PeerId>,
+        pending_deliveries: &mut HashMap<OutboundRequestId, i64>,
+        rate_limiter: &mut RateLimiter,
         swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
-        event_sender: &tokio::sync::mpsc::UnboundedSender<P2PEvent>
+        event_sender: &EventSender
     ) {
         log::info!("Sending direct message '{}' to {}", content, peer_id);
+        // Dead-lettered rather than dropped, so the user sees it and can retry once the window passes.
+        if !rate_limiter.check(peer_id, Instant::now()) {
+            log::warn!("Direct message rate limit exceeded for {}", peer_id);
+            Self::persist_dead_letter(
+                db::DATABASE.clone(),
+                swarm.local_peer_id().to_string(),
+                peer_id.to_string(),
+                content,
+                "Rate limit exceeded, too many direct messages".into(),
+                event_sender
+            );
+            return;
+        }
+
         if !friend_list.contains(&peer_id) {
+            log::warn!("Attempted to send a direct message to non-friend peer {}", peer_id);
+            Self::persist_dead_letter(
+                db::DATABASE.clone(),
+                swarm.local_peer_id().to_string(),
+                peer_id.to_string(),
+                content,
+                "Recipient is not a friend".into(),
+                event_sender
+            );
             return;
         }
 
-        let direct_message_id = match db::create_direct_message(db::DATABASE.clone(), swarm.local_peer_id().to_string(), peer_id.to_string(), content) {
+        let direct_message_id = match db::create_outbound_direct_message(db::DATABASE.clone(), swarm.local_peer_id().to_string(), peer_id.to_string(), content) {
             Ok(id) => id,
             Err(err) => {
-                let _ = event_sender.send(P2PEvent::Error { context: "create_direct_message", error: err.to_string() });
+                let _ = event_sender.send(P2PEvent::Error { context: "create_outbound_direct_message", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                 return;
             }
         };
@@ -181,18 +263,21 @@ impl CommandHandler {
         let message = match db::fetch_direct_message_by_id(db::DATABASE.clone(), direct_message_id) {
             Ok(dm) => dm,
             Err(err) => {
-                let _ = event_sender.send(P2PEvent::Error { context: "fetch_direct_message_by_id", error: err.to_string() });
+                let _ = event_sender.send(P2PEvent::Error { context: "fetch_direct_message_by_id", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                 return;
             }
         };
 
         let _ = event_sender.send(P2PEvent::DirectMessageSent(message.clone()));
 
+        Self::dead_letter_pending_overflow(db::DATABASE.clone(), swarm.local_peer_id(), &peer_id, event_sender);
+
         if swarm.is_connected(&peer_id) {
             log::info!("Already connected, sending direct message immediately");
-            swarm.behaviour_mut().request_response.send_request(&peer_id, P2PMessage::DirectMessage(message));
+            let request_id = swarm.behaviour_mut().request_response.send_request(&peer_id, P2PMessage::DirectMessage(message));
+            pending_deliveries.insert(request_id, direct_message_id);
             if let Err(err) = db::update_direct_message(db::DATABASE.clone(), direct_message_id, None, Some(false)) {
-                let _ = event_sender.send(P2PEvent::Error { context: "update_direct_message", error: err.to_string() });
+                let _ = event_sender.send(P2PEvent::Error { context: "update_direct_message", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                 return;
             }
         } else {
@@ -201,40 +286,633 @@ impl CommandHandler {
             if let Err(err) = swarm.dial(address) {
                 let _ = event_sender.send(P2PEvent::Error {
                     context: "swarm.dial",
-                    error: err.to_string()
+                    error: err.to_string(),
+                    severity: ErrorSeverity::Recoverable
                 });
             }
         }
     }
 
+    pub fn handle_broadcast_nickname(
+        nickname: String,
+        friend_list: &[PeerId],
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        event_sender: &EventSender
+    ) {
+        log::info!("Broadcasting nickname '{}' to connected friends", nickname);
+
+        let local_peer_id = swarm.local_peer_id().to_string();
+        let updated = db::fetch_user_by_peer_id(db::DATABASE.clone(), local_peer_id)
+            .and_then(|user| db::update_user(db::DATABASE.clone(), user.id, None, Some(nickname.clone())));
+
+        if let Err(err) = updated {
+            let _ = event_sender.send(P2PEvent::Error { context: "update_user", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            return;
+        }
+
+        let connected_friends = friend_list.iter()
+            .filter(|&peer| swarm.is_connected(peer))
+            .cloned()
+            .collect::<Vec<PeerId>>();
+
+        for peer in connected_friends.iter() {
+            swarm.behaviour_mut()
+                .request_response
+                .send_request(peer, P2PMessage::NicknameUpdate { nickname: nickname.clone() });
+        }
+    }
+
+    pub fn handle_retry_dead_letter(
+        id: i64,
+        friend_list: &[PeerId],
+        pending_deliveries: &mut HashMap<OutboundRequestId, i64>,
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        event_sender: &EventSender
+    ) {
+        log::info!("Retrying dead letter {}", id);
+
+        let direct_message_id = match db::restore_dead_letter(db::DATABASE.clone(), id) {
+            Ok(id) => id,
+            Err(err) => {
+                let _ = event_sender.send(P2PEvent::Error { context: "restore_dead_letter", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                return;
+            }
+        };
+
+        let message = match db::fetch_direct_message_by_id(db::DATABASE.clone(), direct_message_id) {
+            Ok(dm) => dm,
+            Err(err) => {
+                let _ = event_sender.send(P2PEvent::Error { context: "fetch_direct_message_by_id", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                return;
+            }
+        };
+
+        let _ = event_sender.send(P2PEvent::DirectMessageSent(message.clone()));
+
+        let peer_id = match PeerId::from_str(&message.to_peer_id) {
+            Ok(p) => p,
+            Err(err) => {
+                let _ = event_sender.send(P2PEvent::Error { context: "PeerId::from_str", error: err.to_string(), severity: ErrorSeverity::Warning });
+                return;
+            }
+        };
+
+        // Messages to offline friends stay pending and go out on the next connection.
+        if friend_list.contains(&peer_id) && swarm.is_connected(&peer_id) {
+            let request_id = swarm.behaviour_mut().request_response.send_request(&peer_id, P2PMessage::DirectMessage(message));
+            pending_deliveries.insert(request_id, direct_message_id);
+
+            if let Err(err) = db::update_direct_message(db::DATABASE.clone(), direct_message_id, None, Some(false)) {
+                let _ = event_sender.send(P2PEvent::Error { context: "update_direct_message", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            }
+        }
+    }
+
     pub async fn handle_send_post(
         content: String,
+        friend_list: &[PeerId],
+        peer_protocols: &PeerProtocols,
+        topic: &libp2p::gossipsub::IdentTopic,
         swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
-        event_sender: &tokio::sync::mpsc::UnboundedSender<P2PEvent>
+        event_sender: &EventSender
     ) {
         log::info!("Sending post '{}' to all friends", content);
-        let topic = libp2p::gossipsub::IdentTopic::new("enclave-posts");
-        
-        let post_id = match db::create_post(db::DATABASE.clone(), swarm.local_peer_id().to_string(), content) {
-            Ok(p) => p,
+
+        let post = match Self::persist_sent_post(db::DATABASE.clone(), swarm.local_peer_id().to_string(), content, event_sender) {
+            Some(p) => p,
+            None => return
+        };
+
+        // Gossipsub only reaches friends who are online, so hold the post for the rest that can
+        // take buffered posts.
+        let offline_friends = friend_list.iter()
+            .filter(|peer| !swarm.is_connected(peer) && !peer_protocols.is_outdated(peer))
+            .map(|peer| peer.to_string())
+            .collect::<Vec<String>>();
+
+        if let Err(err) = db::buffer_post(db::DATABASE.clone(), post.id, &offline_friends) {
+            let _ = event_sender.send(P2PEvent::Error { context: "buffer_post", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+        }
+
+        if let Ok(data) = serde_json::to_vec(&post) {
+            let _ = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data);
+        }
+    }
+
+    pub fn persist_dead_letter(
+        db: Arc<std::sync::Mutex<Connection>>,
+        from_peer_id: String,
+        to_peer_id: String,
+        content: String,
+        reason: String,
+        event_sender: &EventSender
+    ) -> Option<DeadLetter> {
+        let dead_letter = db::create_dead_letter(db.clone(), from_peer_id, to_peer_id, content, reason)
+            .and_then(|id| db::fetch_dead_letter_by_id(db, id));
+
+        match dead_letter {
+            Ok(dead_letter) => {
+                let _ = event_sender.send(P2PEvent::MessageDeadLettered(dead_letter.clone()));
+                Some(dead_letter)
+            },
+            Err(err) => {
+                let _ = event_sender.send(P2PEvent::Error { context: "create_dead_letter", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                None
+            }
+        }
+    }
+
+    /// Caps the messages waiting for an offline peer at the configured limit, dead-lettering
+    /// the oldest so they can still be retried by hand.
+    pub fn dead_letter_pending_overflow(db: Arc<std::sync::Mutex<Connection>>, local_peer_id: &PeerId, peer_id: &PeerId, event_sender: &EventSender) {
+        let overflow = db::get_max_pending_direct_messages(db.clone())
+            .and_then(|max_pending| db::dead_letter_pending_overflow(db.clone(), local_peer_id.to_string(), peer_id.to_string(), max_pending));
+
+        let dead_letter_ids = match overflow {
+            Ok(ids) => ids,
             Err(err) => {
-                let _ = event_sender.send(P2PEvent::Error { context: "create_post", error: err.to_string() });
+                let _ = event_sender.send(P2PEvent::Error { context: "dead_letter_pending_overflow", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                 return;
             }
         };
 
-        let post = match db::fetch_post_by_id(db::DATABASE.clone(), post_id) {
+        if !dead_letter_ids.is_empty() {
+            log::warn!("Too many pending messages to {}, dead-lettered the oldest {}", peer_id, dead_letter_ids.len());
+        }
+
+        for id in dead_letter_ids {
+            match db::fetch_dead_letter_by_id(db.clone(), id) {
+                Ok(dead_letter) => {
+                    let _ = event_sender.send(P2PEvent::MessageDeadLettered(dead_letter));
+                },
+                Err(err) => {
+                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_dead_letter_by_id", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                }
+            }
+        }
+    }
+
+    /// Asks a friend for their current addresses. A friend we can't reach directly is dialled
+    /// through our relay, since their stored address is the one that has gone stale.
+    pub fn handle_refresh_peer_address(
+        peer: PeerId,
+        relay_addr: Option<Multiaddr>,
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        event_sender: &EventSender
+    ) {
+        log::info!("Requesting fresh addresses from {peer}");
+
+        if !swarm.is_connected(&peer) {
+            let Some(relay_addr) = relay_addr else {
+                let _ = event_sender.send(P2PEvent::Error { context: "handle_refresh_peer_address", error: format!("Peer {peer} is not connected and no relay is configured"), severity: ErrorSeverity::Warning });
+                return;
+            };
+
+            let circuit_addr = relay_addr
+                .with(libp2p::multiaddr::Protocol::P2pCircuit)
+                .with(libp2p::multiaddr::Protocol::P2p(peer));
+
+            if let Err(err) = swarm.dial(circuit_addr) {
+                let _ = event_sender.send(P2PEvent::Error { context: "swarm.dial", error: err.to_string(), severity: ErrorSeverity::Recoverable });
+                return;
+            }
+        }
+
+        swarm.behaviour_mut()
+            .request_response
+            .send_request(&peer, P2PMessage::AddressRequest);
+    }
+
+    /// Composes the identity, friend, friend request and conversation queries into one payload.
+    pub fn build_initial_state(
+        db: Arc<std::sync::Mutex<Connection>>,
+        local_peer_id: &PeerId,
+        friend_list: &[PeerId],
+        is_connected: impl Fn(&PeerId) -> bool
+    ) -> anyhow::Result<InitialState> {
+        let identity = db::fetch_identity(db.clone())?;
+
+        let friends = friend_list.iter()
+            .map(|peer_id| FriendSummary {
+                peer_id: peer_id.to_string(),
+                nickname: db::fetch_user_by_peer_id(db.clone(), peer_id.to_string())
+                    .ok()
+                    .and_then(|user| user.nickname),
+                online: is_connected(peer_id)
+            })
+            .collect();
+
+        Ok(InitialState {
+            identity: IdentityInfo::from_identity(&identity, chrono::Utc::now().timestamp()),
+            friends,
+            inbound_friend_requests: Self::inbound_friend_requests(db.clone(), local_peer_id).unwrap_or_default(),
+            conversations: db::fetch_conversation_summaries(db, local_peer_id.to_string(), false)?
+        })
+    }
+
+    /// Builds every friend's profile from two grouped queries, one for nicknames and one for
+    /// conversation summaries, rather than a round trip per friend.
+    pub fn friend_profiles(
+        db: Arc<std::sync::Mutex<Connection>>,
+        local_peer_id: &PeerId,
+        friend_list: &[PeerId],
+        is_connected: impl Fn(&PeerId) -> bool
+    ) -> anyhow::Result<Vec<FriendProfile>> {
+        let mut nicknames = db::fetch_friend_users(db.clone())?
+            .into_iter()
+            .map(|user| (user.peer_id, user.nickname))
+            .collect::<HashMap<_, _>>();
+
+        let mut appearances = db::fetch_friend_appearances(db.clone())?;
+
+        let mut summaries = db::fetch_conversation_summaries(db, local_peer_id.to_string(), false)?
+            .into_iter()
+            .map(|summary| (summary.peer_id.clone(), summary))
+            .collect::<HashMap<_, _>>();
+
+        Ok(friend_list.iter()
+            .map(|peer_id| {
+                let peer_id_str = peer_id.to_string();
+                let last_message = summaries.remove(&peer_id_str);
+
+                FriendProfile {
+                    nickname: nicknames.remove(&peer_id_str).flatten(),
+                    online: is_connected(peer_id),
+                    unread_count: last_message.as_ref().map_or(0, |summary| summary.unread_count),
+                    last_message,
+                    appearance: appearances.remove(&peer_id_str).unwrap_or_default(),
+                    peer_id: peer_id_str
+                }
+            })
+            .collect())
+    }
+
+    pub fn count_online_friends(friend_list: &[PeerId], is_connected: impl Fn(&PeerId) -> bool) -> usize {
+        friend_list.iter()
+            .filter(|peer_id| is_connected(peer_id))
+            .count()
+    }
+
+    pub fn pending_work(db: Arc<std::sync::Mutex<Connection>>, local_peer_id: &PeerId, active_syncs: usize) -> anyhow::Result<PendingWork> {
+        Ok(PendingWork {
+            undelivered_messages: db::count_pending_direct_messages(db.clone(), local_peer_id.to_string())?.values().sum(),
+            buffered_posts: db::count_buffered_posts(db.clone())?,
+            pending_friend_requests: db::count_pending_outbound_friend_requests(db, local_peer_id.to_string())?,
+            active_syncs
+        })
+    }
+
+    /// Marks the conversation with `peer` read locally, returning the receipt to send them if
+    /// read receipts are enabled and anything was newly read.
+    pub fn mark_conversation_read(db: Arc<std::sync::Mutex<Connection>>, local_peer_id: &PeerId, peer: &PeerId) -> anyhow::Result<(usize, Option<P2PMessage>)> {
+        let marked = db::mark_conversation_read(db.clone(), local_peer_id.to_string(), peer.to_string())?;
+
+        let receipt = (marked > 0 && db::get_read_receipts_enabled(db)?)
+            .then_some(P2PMessage::ReadReceipt);
+
+        Ok((marked, receipt))
+    }
+
+    /// Marks every conversation read, returning the number of messages marked and a read
+    /// receipt for each sender whose messages were marked, if receipts are enabled.
+    pub fn mark_all_read(db: Arc<std::sync::Mutex<Connection>>, local_peer_id: &PeerId) -> anyhow::Result<(usize, Vec<(PeerId, P2PMessage)>)> {
+        let marked = db::mark_all_read(db.clone(), local_peer_id.to_string())?;
+        let total = marked.values().sum();
+
+        let receipts = if total > 0 && db::get_read_receipts_enabled(db)? {
+            marked.keys()
+                .filter_map(|peer_id| peer_id.parse::<PeerId>().ok())
+                .map(|peer| (peer, P2PMessage::ReadReceipt))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok((total, receipts))
+    }
+
+    /// Friend requests sent to us, each carrying every address stored for its sender.
+    pub fn inbound_friend_requests(db: Arc<std::sync::Mutex<Connection>>, local_peer_id: &PeerId) -> anyhow::Result<Vec<FriendRequest>> {
+        db::fetch_friend_requests_to_peer(db.clone(), local_peer_id.to_string())?
+            .into_iter()
+            .map(|request| {
+                let addresses = match db::fetch_user_by_peer_id(db.clone(), request.from_peer_id.clone()) {
+                    Ok(user) => db::fetch_user_addresses(db.clone(), user.id)?
+                        .into_iter()
+                        .map(|address| address.multiaddr)
+                        .collect(),
+                    Err(_) => Vec::new()
+                };
+
+                Ok(request.with_addresses(addresses))
+            })
+            .collect()
+    }
+
+    /// Adds `peer` as a friend without the request/accept handshake, for contacts exchanged
+    /// out of band. This trusts the caller completely: nothing proves the peer at `multiaddr`
+    /// is who the user thinks it is, so the peer id must have come from a trusted channel
+    /// (e.g. read off their screen in person). Blocked peers and the friend limit still apply.
+    pub fn add_friend_directly(
+        db: Arc<std::sync::Mutex<Connection>>,
+        peer: PeerId,
+        multiaddr: &Multiaddr,
+        nickname: Option<String>,
+        friend_list: &mut Vec<PeerId>
+    ) -> anyhow::Result<()> {
+        if friend_list.contains(&peer) {
+            return Err(anyhow::anyhow!("{peer} is already a friend"));
+        }
+
+        if db::is_peer_blocked(db.clone(), peer.to_string())? {
+            return Err(anyhow::anyhow!("{peer} is blocked"));
+        }
+
+        if let Some(limit) = db::friend_limit_reached(db.clone())? {
+            return Err(anyhow::anyhow!("Friend limit of {limit} reached"));
+        }
+
+        let user_id = match db::fetch_user_by_peer_id(db.clone(), peer.to_string()) {
+            Ok(user) => user.id,
+            Err(_) => db::create_user(db.clone(), peer.to_string(), multiaddr.to_string(), false)?
+        };
+
+        db::update_user(db.clone(), user_id, Some(multiaddr.to_string()), nickname)?;
+        db::befriend(db, user_id)?;
+
+        friend_list.push(peer);
+        Ok(())
+    }
+
+    pub fn persist_sent_post(
+        db: Arc<std::sync::Mutex<Connection>>,
+        author_peer_id: String,
+        content: String,
+        event_sender: &EventSender
+    ) -> Option<Post> {
+        let post_id = match db::create_post(db.clone(), author_peer_id, content) {
             Ok(p) => p,
             Err(err) => {
-                let _ = event_sender.send(P2PEvent::Error { context: "fetch_post_by_id", error: err.to_string() });
-                return;
+                let _ = event_sender.send(P2PEvent::Error { context: "create_post", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                return None;
             }
         };
 
-        if let Ok(data) = serde_json::to_vec(&post) {
-            let _ = swarm.behaviour_mut().gossipsub.publish(topic, data);
+        let post = match db::fetch_post_by_id(db, post_id) {
+            Ok(p) => p,
+            Err(err) => {
+                let _ = event_sender.send(P2PEvent::Error { context: "fetch_post_by_id", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                return None;
+            }
+        };
+
+        let _ = event_sender.send(P2PEvent::PostSent(post.clone()));
+
+        Some(post)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_persist_sent_post_emits_post_sent_with_persisted_post() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let (event_sender, mut event_receiver) = crate::p2p::channel::event_channel(1);
+
+        let peer_id = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+
+        let post = CommandHandler::persist_sent_post(db.clone(), peer_id.clone(), "Hello World".into(), &event_sender)
+            .expect("persist_sent_post failed");
+
+        let persisted = db::fetch_post_by_id(db, post.id).expect("fetch_post_by_id failed");
+
+        match event_receiver.try_recv() {
+            Ok(P2PEvent::PostSent(sent)) => {
+                assert_eq!(sent.id, persisted.id);
+                assert_eq!(sent.author_peer_id, peer_id);
+                assert_eq!(sent.content, "Hello World");
+                assert_eq!(sent.created_at, persisted.created_at);
+            },
+            _ => panic!("expected P2PEvent::PostSent")
+        }
+    }
+
+    #[test]
+    pub fn test_add_friend_directly_creates_user_and_friend() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let peer = PeerId::random();
+        let multiaddr: Multiaddr = "/ip4/192.168.1.20/tcp/4001".parse().unwrap();
+        let mut friend_list = Vec::new();
+
+        CommandHandler::add_friend_directly(db.clone(), peer, &multiaddr, Some("Alice".into()), &mut friend_list)
+            .expect("add_friend_directly failed");
+
+        let user = db::fetch_user_by_peer_id(db.clone(), peer.to_string()).expect("user was not created");
+        assert_eq!(user.multiaddr, multiaddr.to_string());
+        assert_eq!(user.nickname.as_deref(), Some("Alice"));
+        assert!(db::fetch_friend_by_user_id(db.clone(), user.id).is_ok());
+        assert_eq!(friend_list, vec![peer]);
+
+        assert!(CommandHandler::add_friend_directly(db, peer, &multiaddr, None, &mut friend_list).is_err());
+    }
+
+    #[test]
+    pub fn test_persist_sent_post_classifies_missing_table_as_recoverable() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        db.lock().unwrap().execute("DROP TABLE tbl_posts;", ()).unwrap();
+        let (event_sender, mut event_receiver) = crate::p2p::channel::event_channel(1);
+
+        let post = CommandHandler::persist_sent_post(db, "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into(), "Hello World".into(), &event_sender);
+
+        assert!(post.is_none());
+        match event_receiver.try_recv() {
+            Ok(P2PEvent::Error { context, severity, .. }) => {
+                assert_eq!(context, "create_post");
+                assert_eq!(severity, ErrorSeverity::Recoverable);
+            },
+            _ => panic!("expected P2PEvent::Error")
+        }
+    }
+
+    #[test]
+    pub fn test_persist_sent_post_classifies_read_only_database_as_fatal() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        db.lock().unwrap().execute_batch("PRAGMA query_only = ON;").unwrap();
+        let (event_sender, mut event_receiver) = crate::p2p::channel::event_channel(1);
+
+        let post = CommandHandler::persist_sent_post(db, "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into(), "Hello World".into(), &event_sender);
+
+        assert!(post.is_none());
+        match event_receiver.try_recv() {
+            Ok(P2PEvent::Error { context, severity, .. }) => {
+                assert_eq!(context, "create_post");
+                assert_eq!(severity, ErrorSeverity::Fatal);
+            },
+            _ => panic!("expected P2PEvent::Error")
+        }
+    }
+
+    #[test]
+    pub fn test_persist_dead_letter_emits_message_dead_lettered() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let (event_sender, mut event_receiver) = crate::p2p::channel::event_channel(1);
+
+        let dead_letter = CommandHandler::persist_dead_letter(
+            db.clone(),
+            "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into(),
+            "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".into(),
+            "Hello World".into(),
+            "Recipient is not a friend".into(),
+            &event_sender
+        ).expect("persist_dead_letter failed");
+
+        assert_eq!(db::fetch_all_dead_letters(db).unwrap().len(), 1);
+        match event_receiver.try_recv() {
+            Ok(P2PEvent::MessageDeadLettered(emitted)) => {
+                assert_eq!(emitted.id, dead_letter.id);
+                assert_eq!(emitted.reason, "Recipient is not a friend");
+            },
+            _ => panic!("expected P2PEvent::MessageDeadLettered")
+        }
+    }
+
+    #[test]
+    pub fn test_build_initial_state_contains_each_section() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let local_peer_id = PeerId::from(keypair.public());
+        let friend_peer_id = PeerId::random();
+        let requester_peer_id = PeerId::random();
+        let multiaddr = "/ip4/127.0.0.1/tcp/4001".to_string();
+
+        db::create_identity(db.clone(), keypair.to_protobuf_encoding().unwrap(), local_peer_id.to_string(), 4001).unwrap();
+        let friend_user_id = db::create_user(db.clone(), friend_peer_id.to_string(), multiaddr.clone(), false).unwrap();
+        db::update_user(db.clone(), friend_user_id, None, Some("Alice".into())).unwrap();
+        db::create_friend(db.clone(), friend_user_id).unwrap();
+        db::create_friend_request(db.clone(), requester_peer_id.to_string(), multiaddr.clone(), local_peer_id.to_string(), multiaddr, "Hi".into()).unwrap();
+        db::create_direct_message(db.clone(), friend_peer_id.to_string(), local_peer_id.to_string(), "Hello".into()).unwrap();
+
+        let state = CommandHandler::build_initial_state(db, &local_peer_id, &[friend_peer_id], |peer| *peer == friend_peer_id)
+            .expect("build_initial_state failed");
+
+        assert_eq!(state.identity.peer_id, local_peer_id.to_string());
+
+        assert_eq!(state.friends.len(), 1);
+        assert_eq!(state.friends[0].peer_id, friend_peer_id.to_string());
+        assert_eq!(state.friends[0].nickname, Some("Alice".into()));
+        assert!(state.friends[0].online);
+
+        assert_eq!(state.inbound_friend_requests.len(), 1);
+        assert_eq!(state.inbound_friend_requests[0].from_peer_id, requester_peer_id.to_string());
+
+        assert_eq!(state.conversations.len(), 1);
+        assert_eq!(state.conversations[0].peer_id, friend_peer_id.to_string());
+    }
+
+    #[test]
+    pub fn test_friend_profiles_populates_each_section() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let local_peer_id = PeerId::random();
+        let alice = PeerId::random();
+        let bob = PeerId::random();
+        let carol = PeerId::random();
+
+        for (peer_id, nickname) in [(alice, Some("Alice")), (bob, None), (carol, Some("Carol"))] {
+            let user_id = db::create_user(db.clone(), peer_id.to_string(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+            if let Some(nickname) = nickname {
+                db::update_user(db.clone(), user_id, None, Some(nickname.into())).unwrap();
+            }
+            db::create_friend(db.clone(), user_id).unwrap();
         }
 
-        let _ = event_sender.send(P2PEvent::PostSent(post));
+        db::create_direct_message(db.clone(), alice.to_string(), local_peer_id.to_string(), "Hi".into()).unwrap();
+        db::create_direct_message(db.clone(), alice.to_string(), local_peer_id.to_string(), "Are you there?".into()).unwrap();
+        db::create_outbound_direct_message(db.clone(), local_peer_id.to_string(), bob.to_string(), "Hello Bob".into()).unwrap();
+
+        let profiles = CommandHandler::friend_profiles(db, &local_peer_id, &[alice, bob, carol], |peer| *peer == alice)
+            .expect("friend_profiles failed");
+
+        assert_eq!(profiles.iter().map(|profile| profile.peer_id.clone()).collect::<Vec<_>>(), vec![alice.to_string(), bob.to_string(), carol.to_string()]);
+
+        assert_eq!(profiles[0].nickname, Some("Alice".into()));
+        assert!(profiles[0].online);
+        assert_eq!(profiles[0].unread_count, 2);
+        assert_eq!(profiles[0].last_message.as_ref().unwrap().last_message, "Are you there?");
+
+        assert_eq!(profiles[1].nickname, None);
+        assert!(!profiles[1].online);
+        assert_eq!(profiles[1].unread_count, 0);
+        assert!(profiles[1].last_message.as_ref().unwrap().from_me);
+
+        assert_eq!(profiles[2].nickname, Some("Carol".into()));
+        assert!(profiles[2].last_message.is_none());
+    }
+
+    #[test]
+    pub fn test_mark_conversation_read_without_receipts_stays_local() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let local_peer_id = PeerId::random();
+        let friend_peer_id = PeerId::random();
+
+        db::create_direct_message(db.clone(), friend_peer_id.to_string(), local_peer_id.to_string(), "Hello".into()).unwrap();
+        db::create_direct_message(db.clone(), friend_peer_id.to_string(), local_peer_id.to_string(), "Are you there?".into()).unwrap();
+        db::set_read_receipts_enabled(db.clone(), false).unwrap();
+
+        let (marked, receipt) = CommandHandler::mark_conversation_read(db.clone(), &local_peer_id, &friend_peer_id)
+            .expect("mark_conversation_read failed");
+
+        assert_eq!(marked, 2);
+        assert!(receipt.is_none());
+        assert!(db::fetch_direct_messages_with_peer(db.clone(), friend_peer_id.to_string()).unwrap().iter().all(|dm| dm.read));
+
+        db::create_direct_message(db.clone(), friend_peer_id.to_string(), local_peer_id.to_string(), "Hello again".into()).unwrap();
+        db::set_read_receipts_enabled(db.clone(), true).unwrap();
+
+        let (marked, receipt) = CommandHandler::mark_conversation_read(db, &local_peer_id, &friend_peer_id).unwrap();
+        assert_eq!(marked, 1);
+        assert!(matches!(receipt, Some(P2PMessage::ReadReceipt)));
+    }
+
+    #[test]
+    pub fn test_count_online_friends_ignores_connected_strangers() {
+        let online_friends = [PeerId::random(), PeerId::random()];
+        let offline_friend = PeerId::random();
+        let stranger = PeerId::random();
+
+        let connected = [online_friends[0], online_friends[1], stranger];
+        let friend_list = vec![online_friends[0], offline_friend, online_friends[1]];
+
+        assert_eq!(CommandHandler::count_online_friends(&friend_list, |peer| connected.contains(peer)), 2);
+        assert_eq!(CommandHandler::count_online_friends(&[], |peer| connected.contains(peer)), 0);
+    }
+
+    #[test]
+    pub fn test_pending_work_counts_seeded_buffered_state() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let local_peer_id = PeerId::random();
+        let offline_friend = PeerId::random();
+        let other_friend = PeerId::random();
+
+        assert_eq!(CommandHandler::pending_work(db.clone(), &local_peer_id, 0).unwrap(), PendingWork::default());
+
+        for content in ["Are you there?", "Call me back"] {
+            let id = db::create_outbound_direct_message(db.clone(), local_peer_id.to_string(), offline_friend.to_string(), content.into()).unwrap();
+            db::update_direct_message(db.clone(), id, None, Some(true)).unwrap();
+        }
+
+        let delivered = db::create_outbound_direct_message(db.clone(), local_peer_id.to_string(), other_friend.to_string(), "Delivered".into()).unwrap();
+        db::update_direct_message(db.clone(), delivered, None, Some(false)).unwrap();
+
+        let post_id = db::create_post(db.clone(), local_peer_id.to_string(), "While you were out".into()).unwrap();
+        db::buffer_post(db.clone(), post_id, &[offline_friend.to_string(), other_friend.to_string()]).unwrap();
+
+        db::create_friend_request(db.clone(), local_peer_id.to_string(), "/ip4/127.0.0.1/tcp/4001".into(), PeerId::random().to_string(), "/ip4/127.0.0.1/tcp/4002".into(), "Hi".into()).unwrap();
+
+        let pending_work = CommandHandler::pending_work(db, &local_peer_id, 1).expect("pending_work failed");
+
+        assert_eq!(pending_work, PendingWork { undelivered_messages: 2, buffered_posts: 2, pending_friend_requests: 1, active_syncs: 1 });
     }
-}
\ No newline at end of file
+}
@@ -1,34 +1,169 @@
 use libp2p::relay::client::Transport;
-use libp2p::{identity::Keypair, PeerId, StreamProtocol, gossipsub, relay, dcutr, ping, request_response as reqres, swarm::NetworkBehaviour};
+use libp2p::{identity::Keypair, PeerId, Swarm, Transport as _, gossipsub, relay, dcutr, ping, request_response as reqres, swarm::NetworkBehaviour};
 use rand::Rng;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use crate::db;
-use crate::p2p::types::P2PMessage;
+use crate::p2p::connection_policy::ConnectionPolicy;
+use crate::p2p::channel::{DEFAULT_COMMAND_CHANNEL_CAPACITY, DEFAULT_EVENT_CHANNEL_CAPACITY};
+use crate::p2p::protocol::{EnclaveCodec, supported_protocols};
+use crate::p2p::rate_limit::{DEFAULT_DIRECT_MESSAGE_RATE_LIMIT, DEFAULT_DIRECT_MESSAGE_RATE_WINDOW, RateLimiter};
 
 #[derive(NetworkBehaviour)]
 pub struct EnclaveNetworkBehaviour {
     pub gossipsub: gossipsub::Behaviour,
-    pub request_response: reqres::cbor::Behaviour<P2PMessage, P2PMessage>,
+    pub request_response: reqres::Behaviour<EnclaveCodec>,
     pub relay_client: relay::client::Behaviour,
     pub dcutr: dcutr::Behaviour,
     pub ping: ping::Behaviour
 }
 
+pub const DEFAULT_NAMESPACE: &str = "enclave";
+pub const NAMESPACE_SETTING: &str = "network_namespace";
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(10);
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(10);
+pub const GOSSIPSUB_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+pub const IDENTITY_BACKUP_DIR: &str = "./identity-backups";
+pub const CORRUPT_KEYPAIR_ERROR: &str = "stored identity keypair is corrupt; consider regenerate_identity";
+
 pub struct NetworkConfig {
     pub keypair: Keypair,
     pub peer_id: PeerId,
-    pub port: i64
+    pub port: i64,
+    pub namespace: String,
+    pub event_channel_capacity: usize,
+    pub command_channel_capacity: usize,
+    pub ping_interval: Duration,
+    pub ping_timeout: Duration,
+    pub direct_message_rate_limit: usize,
+    pub direct_message_rate_window: Duration
+}
+
+/// The network tuning a running node was started with, for support and reproducibility.
+/// Listen addresses, the relay and the connection policy can change while the node runs
+/// and are filled in from its current state when requested.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkConfigInfo {
+    pub namespace: String,
+    pub posts_topic: String,
+    pub port: i64,
+    pub gossipsub_heartbeat_ms: u64,
+    pub ping_interval_ms: u64,
+    pub ping_timeout_ms: u64,
+    pub event_channel_capacity: usize,
+    pub command_channel_capacity: usize,
+    pub direct_message_rate_limit: usize,
+    pub direct_message_rate_window_ms: u64,
+    pub listen_addresses: Vec<String>,
+    pub relay_addresses: Vec<String>,
+    pub connection_policy: ConnectionPolicy
+}
+
+/// Decodes the stored identity keypair. A blob that won't decode is copied into `backup_dir`
+/// first, so it isn't lost if the user regenerates their identity.
+pub fn decode_identity_keypair(encoded: &[u8], backup_dir: &Path) -> anyhow::Result<Keypair> {
+    Keypair::from_protobuf_encoding(encoded).map_err(|err| {
+        log::error!("Stored identity keypair could not be decoded: {err}");
+
+        match backup_corrupt_keypair(encoded, backup_dir) {
+            Ok(path) => anyhow::anyhow!("{CORRUPT_KEYPAIR_ERROR} (a copy was saved to {})", path.display()),
+            Err(backup_err) => {
+                log::error!("Failed to back up corrupt keypair: {backup_err}");
+                anyhow::anyhow!(CORRUPT_KEYPAIR_ERROR)
+            }
+        }
+    })
+}
+
+fn backup_corrupt_keypair(encoded: &[u8], backup_dir: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(backup_dir)?;
+
+    let path = backup_dir.join(format!("corrupt-keypair-{}.bin", chrono::Utc::now().timestamp_millis()));
+    std::fs::write(&path, encoded)?;
+
+    Ok(path)
+}
+
+/// Derives a gossipsub topic name scoped to a network namespace, e.g. `enclave-posts`.
+pub fn topic_name(namespace: &str, topic: &str) -> String {
+    format!("{namespace}-{topic}")
+}
+
+/// The namespace the node joins when it starts, or the default one if none was chosen.
+pub fn load_namespace(db: Arc<Mutex<Connection>>) -> anyhow::Result<String> {
+    Ok(db::get_setting(db, NAMESPACE_SETTING)?.unwrap_or_else(|| DEFAULT_NAMESPACE.to_string()))
+}
+
+/// Namespaces end up in topic names, so only lowercase letters, digits and dashes are allowed.
+pub fn save_namespace(db: Arc<Mutex<Connection>>, namespace: &str) -> anyhow::Result<()> {
+    let valid = !namespace.is_empty()
+        && namespace.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+    if !valid {
+        return Err(anyhow::anyhow!("Invalid namespace: {namespace}"));
+    }
+
+    db::set_setting(db, NAMESPACE_SETTING, namespace)
 }
 
 impl NetworkConfig {
-    pub fn load_or_create() -> anyhow::Result<Self> {
+    pub fn posts_topic(&self) -> gossipsub::IdentTopic {
+        gossipsub::IdentTopic::new(topic_name(&self.namespace, "posts"))
+    }
+
+    pub fn ping_config(&self) -> ping::Config {
+        ping::Config::new()
+            .with_interval(self.ping_interval)
+            .with_timeout(self.ping_timeout)
+    }
+
+    pub fn direct_message_rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.direct_message_rate_limit, self.direct_message_rate_window)
+    }
+
+    pub fn info(&self) -> NetworkConfigInfo {
+        NetworkConfigInfo {
+            namespace: self.namespace.clone(),
+            posts_topic: self.posts_topic().to_string(),
+            port: self.port,
+            gossipsub_heartbeat_ms: GOSSIPSUB_HEARTBEAT_INTERVAL.as_millis() as u64,
+            ping_interval_ms: self.ping_interval.as_millis() as u64,
+            ping_timeout_ms: self.ping_timeout.as_millis() as u64,
+            event_channel_capacity: self.event_channel_capacity,
+            command_channel_capacity: self.command_channel_capacity,
+            direct_message_rate_limit: self.direct_message_rate_limit,
+            direct_message_rate_window_ms: self.direct_message_rate_window.as_millis() as u64,
+            listen_addresses: Vec::new(),
+            relay_addresses: Vec::new(),
+            connection_policy: ConnectionPolicy::default()
+        }
+    }
+
+    pub fn load_or_create(namespace: Option<String>) -> anyhow::Result<Self> {
+        let namespace = namespace.unwrap_or_else(|| DEFAULT_NAMESPACE.to_string());
+
         if let Ok(identity_data) = db::fetch_identity(db::DATABASE.clone()) {
             log::info!("Loading existing identity");
-            let keypair = Keypair::from_protobuf_encoding(&identity_data.keypair)?;
+            let keypair = decode_identity_keypair(&identity_data.keypair, Path::new(IDENTITY_BACKUP_DIR))?;
             let peer_id = PeerId::from_str(&identity_data.peer_id)?;
             let port = identity_data.port_number;
-            Ok(Self { keypair, peer_id, port })
+            Ok(Self {
+                keypair,
+                peer_id,
+                port,
+                namespace,
+                event_channel_capacity: DEFAULT_EVENT_CHANNEL_CAPACITY,
+                command_channel_capacity: DEFAULT_COMMAND_CHANNEL_CAPACITY,
+                ping_interval: DEFAULT_PING_INTERVAL,
+                ping_timeout: DEFAULT_PING_TIMEOUT,
+                direct_message_rate_limit: DEFAULT_DIRECT_MESSAGE_RATE_LIMIT,
+                direct_message_rate_window: DEFAULT_DIRECT_MESSAGE_RATE_WINDOW
+            })
         } else {
             log::info!("Creating new identity");
             let keypair = libp2p::identity::Keypair::generate_ed25519();
@@ -49,14 +184,25 @@ impl NetworkConfig {
                 true
             )?;
             
-            Ok(Self { keypair, peer_id, port })
+            Ok(Self {
+                keypair,
+                peer_id,
+                port,
+                namespace,
+                event_channel_capacity: DEFAULT_EVENT_CHANNEL_CAPACITY,
+                command_channel_capacity: DEFAULT_COMMAND_CHANNEL_CAPACITY,
+                ping_interval: DEFAULT_PING_INTERVAL,
+                ping_timeout: DEFAULT_PING_TIMEOUT,
+                direct_message_rate_limit: DEFAULT_DIRECT_MESSAGE_RATE_LIMIT,
+                direct_message_rate_window: DEFAULT_DIRECT_MESSAGE_RATE_WINDOW
+            })
         }
     }
 }
 
-pub fn create_swarm_behaviour(keypair: &Keypair, peer_id: PeerId) -> anyhow::Result<(EnclaveNetworkBehaviour, Transport)> {
+pub fn create_swarm_behaviour(keypair: &Keypair, peer_id: PeerId, ping_config: ping::Config) -> anyhow::Result<(EnclaveNetworkBehaviour, Transport)> {
     let gossipsub_config = gossipsub::ConfigBuilder::default()
-        .heartbeat_interval(Duration::from_secs(1))
+        .heartbeat_interval(GOSSIPSUB_HEARTBEAT_INTERVAL)
         .validation_mode(gossipsub::ValidationMode::Strict)
         .build()
         .map_err(|e| anyhow::anyhow!("Gossipsub config error: {e}"))?;
@@ -66,14 +212,15 @@ pub fn create_swarm_behaviour(keypair: &Keypair, peer_id: PeerId) -> anyhow::Res
         gossipsub_config
     ).map_err(|err| anyhow::anyhow!(err.to_string()))?;
 
-    let request_response = reqres::cbor::Behaviour::new(
-        [(StreamProtocol::new("/enclave/1.0.0"), reqres::ProtocolSupport::Full)],
+    let request_response = reqres::Behaviour::with_codec(
+        EnclaveCodec::default(),
+        supported_protocols(),
         reqres::Config::default()
     );
 
     let (relay_transport, relay_client) = relay::client::new(peer_id);
     let dcutr = dcutr::Behaviour::new(peer_id);
-    let ping = ping::Behaviour::new(ping::Config::new());
+    let ping = ping::Behaviour::new(ping_config);
 
     let behaviour = EnclaveNetworkBehaviour {
         gossipsub,
@@ -84,4 +231,93 @@ pub fn create_swarm_behaviour(keypair: &Keypair, peer_id: PeerId) -> anyhow::Res
     };
 
     Ok((behaviour, relay_transport))
-}
\ No newline at end of file
+}
+
+pub fn build_swarm(keypair: Keypair, behaviour: EnclaveNetworkBehaviour, relay_transport: Transport) -> anyhow::Result<Swarm<EnclaveNetworkBehaviour>> {
+    let swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
+        .with_tokio()
+        .with_tcp(
+            libp2p::tcp::Config::default(),
+            libp2p::noise::Config::new,
+            libp2p::yamux::Config::default,
+        )?
+        .with_other_transport(|key| {
+            relay_transport
+                .upgrade(libp2p::core::upgrade::Version::V1)
+                .authenticate(libp2p::noise::Config::new(key).unwrap())
+                .multiplex(libp2p::yamux::Config::default())
+        })
+        .map_err(|err| anyhow::anyhow!("Error adding relay transport: {err}"))?
+        .with_behaviour(|_| behaviour)
+        .map_err(|err| anyhow::anyhow!("Error adding behaviour: {err}"))?
+        .with_swarm_config(|c| {
+            c.with_idle_connection_timeout(std::time::Duration::from_secs(u64::MAX))
+        })
+        .build();
+
+    Ok(swarm)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_topic_name_uses_default_namespace() {
+        assert_eq!(topic_name(DEFAULT_NAMESPACE, "posts"), "enclave-posts");
+        assert_eq!(topic_name(DEFAULT_NAMESPACE, "messages"), "enclave-messages");
+    }
+
+    #[test]
+    pub fn test_namespace_round_trips_through_settings() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        assert_eq!(load_namespace(db.clone()).unwrap(), DEFAULT_NAMESPACE);
+
+        save_namespace(db.clone(), "my-community").expect("save_namespace failed");
+        assert_eq!(load_namespace(db.clone()).unwrap(), "my-community");
+
+        assert!(save_namespace(db.clone(), "").is_err());
+        assert!(save_namespace(db.clone(), "My Community").is_err());
+        assert_eq!(load_namespace(db).unwrap(), "my-community");
+    }
+
+    #[test]
+    pub fn test_decode_identity_keypair_reports_corrupt_blob() {
+        let backup_dir = std::env::temp_dir().join(format!("enclave-identity-backups-{}", rand::random::<u64>()));
+        let corrupt = vec![0xde, 0xad, 0xbe, 0xef];
+
+        let err = decode_identity_keypair(&corrupt, &backup_dir).expect_err("corrupt keypair decoded");
+        assert!(err.to_string().starts_with(CORRUPT_KEYPAIR_ERROR));
+
+        let backups = std::fs::read_dir(&backup_dir).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(std::fs::read(backups[0].path()).unwrap(), corrupt);
+
+        let keypair = Keypair::generate_ed25519();
+        let decoded = decode_identity_keypair(&keypair.to_protobuf_encoding().unwrap(), &backup_dir).expect("valid keypair failed to decode");
+        assert_eq!(decoded.public(), keypair.public());
+
+        let _ = std::fs::remove_dir_all(&backup_dir);
+    }
+
+    #[test]
+    pub fn test_topic_name_uses_custom_namespace() {
+        let keypair = Keypair::generate_ed25519();
+        let config = NetworkConfig {
+            peer_id: PeerId::from(keypair.public()),
+            keypair,
+            port: 5555,
+            namespace: "my-community".into(),
+            event_channel_capacity: DEFAULT_EVENT_CHANNEL_CAPACITY,
+            command_channel_capacity: DEFAULT_COMMAND_CHANNEL_CAPACITY,
+            ping_interval: DEFAULT_PING_INTERVAL,
+            ping_timeout: DEFAULT_PING_TIMEOUT,
+            direct_message_rate_limit: DEFAULT_DIRECT_MESSAGE_RATE_LIMIT,
+            direct_message_rate_window: DEFAULT_DIRECT_MESSAGE_RATE_WINDOW
+        };
+
+        assert_eq!(config.posts_topic().to_string(), "my-community-posts");
+        assert_eq!(topic_name(&config.namespace, "messages"), "my-community-messages");
+    }
+}
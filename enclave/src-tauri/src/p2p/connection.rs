@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use libp2p::{Multiaddr, PeerId, core::ConnectedPoint, multiaddr::Protocol, ping, swarm::ConnectionId};
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ConnectionType {
+    Direct,
+    Relayed
+}
+
+pub fn is_relayed_address(address: &Multiaddr) -> bool {
+    address.iter().any(|protocol| protocol == Protocol::P2pCircuit)
+}
+
+/// A listener's relayed connections arrive on its `/p2p-circuit` listen address, while a
+/// dialer's relayed connections go out to one.
+pub fn is_relayed_endpoint(endpoint: &ConnectedPoint) -> bool {
+    match endpoint {
+        ConnectedPoint::Dialer { address, .. } => is_relayed_address(address),
+        ConnectedPoint::Listener { local_addr, send_back_addr } => is_relayed_address(local_addr) || is_relayed_address(send_back_addr)
+    }
+}
+
+/// A timed out or failed ping means the connection is dead, so it is closed straight away
+/// instead of waiting for TCP to notice. Peers that don't speak ping are left alone.
+pub fn is_dead_connection(ping_result: &Result<Duration, ping::Failure>) -> bool {
+    matches!(ping_result, Err(ping::Failure::Timeout | ping::Failure::Other { .. }))
+}
+
+/// Tracks whether each peer is reachable over at least one direct connection. A peer only
+/// counts as relayed while every open connection to it goes through a relay, so a DCUtR
+/// upgrade shows up as a transition to `Direct` when the hole-punched connection opens.
+#[derive(Default)]
+pub struct ConnectionTracker {
+    connections: HashMap<PeerId, HashMap<ConnectionId, ConnectionType>>
+}
+
+impl ConnectionTracker {
+    pub fn connection_type(&self, peer_id: &PeerId) -> Option<ConnectionType> {
+        let connections = self.connections.get(peer_id)?;
+
+        if connections.values().any(|connection_type| *connection_type == ConnectionType::Direct) {
+            Some(ConnectionType::Direct)
+        } else if connections.is_empty() {
+            None
+        } else {
+            Some(ConnectionType::Relayed)
+        }
+    }
+
+    /// Returns the peer's new connection type if it changed.
+    pub fn handle_connection_established(&mut self, peer_id: PeerId, connection_id: ConnectionId, endpoint: &ConnectedPoint) -> Option<ConnectionType> {
+        let previous = self.connection_type(&peer_id);
+
+        let connection_type = if is_relayed_endpoint(endpoint) { ConnectionType::Relayed } else { ConnectionType::Direct };
+        self.connections.entry(peer_id).or_default().insert(connection_id, connection_type);
+
+        let current = self.connection_type(&peer_id);
+        (current != previous).then_some(current).flatten()
+    }
+
+    /// Returns the peer's new connection type if it changed while other connections remain open.
+    pub fn handle_connection_closed(&mut self, peer_id: &PeerId, connection_id: ConnectionId) -> Option<ConnectionType> {
+        let previous = self.connection_type(peer_id);
+
+        if let Some(connections) = self.connections.get_mut(peer_id) {
+            connections.remove(&connection_id);
+
+            if connections.is_empty() {
+                self.connections.remove(peer_id);
+            }
+        }
+
+        let current = self.connection_type(peer_id);
+        (current != previous).then_some(current).flatten()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::core::{Endpoint, transport::PortUse};
+
+    fn dialer(address: &str) -> ConnectedPoint {
+        ConnectedPoint::Dialer { address: address.parse().unwrap(), role_override: Endpoint::Dialer, port_use: PortUse::Reuse }
+    }
+
+    #[test]
+    pub fn test_is_relayed_address_detects_circuit_addresses() {
+        let relayed: Multiaddr = "/ip4/1.2.3.4/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK/p2p-circuit".parse().unwrap();
+        let direct: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+
+        assert!(is_relayed_address(&relayed));
+        assert!(!is_relayed_address(&direct));
+
+        let listener = ConnectedPoint::Listener { local_addr: relayed, send_back_addr: direct };
+        assert!(is_relayed_endpoint(&listener));
+    }
+
+    #[test]
+    pub fn test_is_dead_connection_on_ping_failure() {
+        let other = ping::Failure::Other { error: Box::new(std::io::Error::other("connection reset")) };
+
+        assert!(!is_dead_connection(&Ok(Duration::from_millis(20))));
+        assert!(!is_dead_connection(&Err(ping::Failure::Unsupported)));
+        assert!(is_dead_connection(&Err(ping::Failure::Timeout)));
+        assert!(is_dead_connection(&Err(other)));
+    }
+
+    #[test]
+    pub fn test_connection_tracker_reports_upgrade_to_direct() {
+        let mut tracker = ConnectionTracker::default();
+        let peer = PeerId::random();
+        let relayed_connection = ConnectionId::new_unchecked(1);
+        let direct_connection = ConnectionId::new_unchecked(2);
+
+        let relayed = dialer("/ip4/1.2.3.4/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK/p2p-circuit");
+
+        assert_eq!(tracker.handle_connection_established(peer, relayed_connection, &relayed), Some(ConnectionType::Relayed));
+        assert_eq!(tracker.handle_connection_established(peer, direct_connection, &dialer("/ip4/5.6.7.8/tcp/4001")), Some(ConnectionType::Direct));
+
+        assert_eq!(tracker.handle_connection_closed(&peer, relayed_connection), None);
+        assert_eq!(tracker.connection_type(&peer), Some(ConnectionType::Direct));
+
+        assert_eq!(tracker.handle_connection_closed(&peer, direct_connection), None);
+        assert_eq!(tracker.connection_type(&peer), None);
+    }
+}
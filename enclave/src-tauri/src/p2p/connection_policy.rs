@@ -0,0 +1,231 @@
+use libp2p::PeerId;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::db;
+use crate::p2p::types::P2PMessage;
+
+pub const CONNECTION_POLICY_SETTING: &str = "connection_policy";
+
+/// How long an unknown inbound peer may stay connected without sending a friend request.
+pub const PROBATION_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Which peers may open connections to us.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionPolicy {
+    #[default]
+    Open,
+    /// Only friends and peers we've sent a friend request to may connect. Anyone else may only
+    /// send friend requests until one is accepted, and is disconnected for anything else.
+    FriendsOnly
+}
+
+impl ConnectionPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionPolicy::Open => "Open",
+            ConnectionPolicy::FriendsOnly => "FriendsOnly"
+        }
+    }
+}
+
+impl std::str::FromStr for ConnectionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Open" => Ok(ConnectionPolicy::Open),
+            "FriendsOnly" => Ok(ConnectionPolicy::FriendsOnly),
+            _ => Err(anyhow::anyhow!("Unknown connection policy: {s}"))
+        }
+    }
+}
+
+pub fn load_connection_policy(db: Arc<Mutex<Connection>>) -> anyhow::Result<ConnectionPolicy> {
+    match db::get_setting(db, CONNECTION_POLICY_SETTING)? {
+        Some(policy) => policy.parse(),
+        None => Ok(ConnectionPolicy::default())
+    }
+}
+
+pub fn save_connection_policy(db: Arc<Mutex<Connection>>, policy: ConnectionPolicy) -> anyhow::Result<()> {
+    db::set_setting(db, CONNECTION_POLICY_SETTING, policy.as_str())
+}
+
+/// Peers a `FriendsOnly` node accepts connections from without conditions: friends, and
+/// peers we've asked to be friends with so their response can get through.
+pub fn is_known_peer(db: Arc<Mutex<Connection>>, peer: &PeerId, friend_list: &[PeerId]) -> anyhow::Result<bool> {
+    if friend_list.contains(peer) {
+        return Ok(true);
+    }
+
+    Ok(!db::fetch_friend_requests_to_peer(db, peer.to_string())?.is_empty())
+}
+
+/// Applies the connection policy to inbound connections. Unknown peers are let in on
+/// probation since a friend request needs a connection to arrive over, and are dropped if
+/// their first message is anything else or they send nothing within the grace period. Once
+/// their friend request is in, they may only send further friend requests until they are
+/// a friend.
+#[derive(Default)]
+pub struct ConnectionGate {
+    policy: ConnectionPolicy,
+    probation: HashMap<PeerId, Instant>,
+    awaiting_acceptance: HashSet<PeerId>
+}
+
+impl ConnectionGate {
+    pub fn new(policy: ConnectionPolicy) -> Self {
+        Self { policy, probation: HashMap::new(), awaiting_acceptance: HashSet::new() }
+    }
+
+    pub fn policy(&self) -> ConnectionPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: ConnectionPolicy) {
+        self.policy = policy;
+
+        if policy == ConnectionPolicy::Open {
+            self.probation.clear();
+            self.awaiting_acceptance.clear();
+        }
+    }
+
+    /// Returns `true` when the peer was put on probation, in which case the caller should call
+    /// `expire_probation` once `PROBATION_GRACE_PERIOD` has passed.
+    pub fn handle_connection_established(&mut self, peer: PeerId, inbound: bool, known: bool, now: Instant) -> bool {
+        if self.policy == ConnectionPolicy::FriendsOnly && inbound && !known && !self.awaiting_acceptance.contains(&peer) {
+            self.probation.insert(peer, now);
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns `false` when the peer should be disconnected instead of having `message` handled.
+    pub fn admit_message(&mut self, peer: &PeerId, message: &P2PMessage, friend_list: &[PeerId]) -> bool {
+        let is_friend_request = matches!(message, P2PMessage::FriendRequest(_));
+
+        if self.probation.remove(peer).is_some() {
+            if is_friend_request {
+                self.awaiting_acceptance.insert(*peer);
+            }
+            return is_friend_request;
+        }
+
+        if self.awaiting_acceptance.contains(peer) {
+            if friend_list.contains(peer) {
+                self.awaiting_acceptance.remove(peer);
+                return true;
+            }
+            return is_friend_request;
+        }
+
+        true
+    }
+
+    /// Returns `true` when the peer is still on probation after the grace period and should be
+    /// disconnected. A probation from a later connection that hasn't run its course is kept.
+    pub fn expire_probation(&mut self, peer: &PeerId, now: Instant) -> bool {
+        match self.probation.get(peer) {
+            Some(since) if now.saturating_duration_since(*since) >= PROBATION_GRACE_PERIOD => {
+                self.probation.remove(peer);
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn handle_connection_closed(&mut self, peer: &PeerId) {
+        self.probation.remove(peer);
+        self.awaiting_acceptance.remove(peer);
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::db::models::friend_request::FriendRequest;
+
+    fn friend_request(peer: &PeerId) -> P2PMessage {
+        let multiaddr = "/ip4/127.0.0.1/tcp/4001".to_string();
+        P2PMessage::FriendRequest(FriendRequest::new(0, peer.to_string(), multiaddr.clone(), PeerId::random().to_string(), multiaddr, "Hi".into(), 0, true, false))
+    }
+
+    #[test]
+    pub fn test_friends_only_rejects_unknown_peer() {
+        let mut gate = ConnectionGate::new(ConnectionPolicy::FriendsOnly);
+        let stranger = PeerId::random();
+
+        gate.handle_connection_established(stranger, true, false, Instant::now());
+
+        assert!(!gate.admit_message(&stranger, &P2PMessage::NicknameUpdate { nickname: "Mallory".into() }, &[]));
+    }
+
+    #[test]
+    pub fn test_friends_only_admits_friend_request_and_known_peers() {
+        let mut gate = ConnectionGate::new(ConnectionPolicy::FriendsOnly);
+        let stranger = PeerId::random();
+        let friend = PeerId::random();
+
+        gate.handle_connection_established(stranger, true, false, Instant::now());
+        gate.handle_connection_established(friend, true, true, Instant::now());
+
+        assert!(gate.admit_message(&stranger, &friend_request(&stranger), &[friend]));
+        assert!(gate.admit_message(&friend, &P2PMessage::NicknameUpdate { nickname: "Bob".into() }, &[friend]));
+    }
+
+    #[test]
+    pub fn test_friends_only_rejects_non_friend_traffic_until_accepted() {
+        let mut gate = ConnectionGate::new(ConnectionPolicy::FriendsOnly);
+        let stranger = PeerId::random();
+        let nickname = P2PMessage::NicknameUpdate { nickname: "Mallory".into() };
+
+        gate.handle_connection_established(stranger, true, false, Instant::now());
+
+        assert!(gate.admit_message(&stranger, &friend_request(&stranger), &[]));
+        assert!(!gate.admit_message(&stranger, &nickname, &[]));
+        assert!(gate.admit_message(&stranger, &friend_request(&stranger), &[]));
+
+        assert!(gate.admit_message(&stranger, &nickname, &[stranger]));
+    }
+
+    #[test]
+    pub fn test_friends_only_expires_silent_probation() {
+        let mut gate = ConnectionGate::new(ConnectionPolicy::FriendsOnly);
+        let silent = PeerId::random();
+        let requester = PeerId::random();
+        let connected_at = Instant::now();
+
+        assert!(gate.handle_connection_established(silent, true, false, connected_at));
+        assert!(gate.handle_connection_established(requester, true, false, connected_at));
+        assert!(gate.admit_message(&requester, &friend_request(&requester), &[]));
+
+        assert!(!gate.expire_probation(&silent, connected_at + PROBATION_GRACE_PERIOD / 2));
+        assert!(gate.expire_probation(&silent, connected_at + PROBATION_GRACE_PERIOD));
+        assert!(!gate.expire_probation(&requester, connected_at + PROBATION_GRACE_PERIOD));
+    }
+
+    #[test]
+    pub fn test_open_policy_admits_everyone() {
+        let mut gate = ConnectionGate::new(ConnectionPolicy::Open);
+        let stranger = PeerId::random();
+
+        gate.handle_connection_established(stranger, true, false, Instant::now());
+
+        assert!(gate.admit_message(&stranger, &P2PMessage::NicknameUpdate { nickname: "Mallory".into() }, &[]));
+    }
+
+    #[test]
+    pub fn test_connection_policy_round_trips_through_settings() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        assert_eq!(load_connection_policy(db.clone()).unwrap(), ConnectionPolicy::Open);
+
+        save_connection_policy(db.clone(), ConnectionPolicy::FriendsOnly).expect("save_connection_policy failed");
+        assert_eq!(load_connection_policy(db).unwrap(), ConnectionPolicy::FriendsOnly);
+    }
+}
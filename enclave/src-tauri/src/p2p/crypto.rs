@@ -0,0 +1,177 @@
+use libp2p::{PeerId, identity::PublicKey};
+use serde::Serialize;
+
+/// Multihash code for the identity hash, used when a peer id inlines its public key.
+const IDENTITY_MULTIHASH_CODE: u64 = 0x00;
+
+/// Extracts the ed25519 public key embedded in a peer id, without needing a live connection.
+/// Peer ids for larger key types (e.g. RSA) only carry a SHA-256 digest of the key, so those
+/// must be resolved from the peer via identify instead.
+pub fn peer_id_to_public_key(peer_id: &PeerId) -> anyhow::Result<[u8; 32]> {
+    let multihash = peer_id.as_ref();
+
+    if multihash.code() != IDENTITY_MULTIHASH_CODE {
+        return Err(anyhow::anyhow!("Peer id {peer_id} does not embed its public key. Fetch the key from the peer via identify instead."));
+    }
+
+    let public_key = PublicKey::try_decode_protobuf(multihash.digest())?;
+
+    match public_key.try_into_ed25519() {
+        Ok(ed25519_key) => Ok(ed25519_key.to_bytes()),
+        Err(_) => Err(anyhow::anyhow!("Peer id {peer_id} does not embed an ed25519 public key. Fetch the key from the peer via identify instead."))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptionStatus {
+    pub encrypted: bool,
+    pub reason: Option<String>
+}
+
+pub const MESSAGES_NOT_ENCRYPTED: &str = "Direct messages are not end-to-end encrypted yet.";
+
+/// Direct messages are sent in the clear for now, so no conversation is reported as encrypted.
+/// The reason says whether the peer's public key, which a shared secret would be derived from,
+/// is at least known locally.
+pub fn encryption_status(peer_id: &PeerId) -> EncryptionStatus {
+    match peer_id_to_public_key(peer_id) {
+        Ok(_) => EncryptionStatus { encrypted: false, reason: Some(MESSAGES_NOT_ENCRYPTED.to_string()) },
+        Err(err) => EncryptionStatus { encrypted: false, reason: Some(err.to_string()) }
+    }
+}
+
+/// Derives the symmetric key shared with `peer_id` by converting both ed25519 identities to
+/// X25519 and running Diffie-Hellman, then expanding the result with HKDF-SHA256. Both sides
+/// arrive at the same key because the public keys are bound into the HKDF info in sorted order.
+/// Only the debug-crypto commands use this; direct messages aren't encrypted with it yet.
+#[cfg(feature = "debug-crypto")]
+pub fn derive_shared_key(local_keypair: &libp2p::identity::Keypair, peer_id: &PeerId) -> anyhow::Result<[u8; 32]> {
+    use curve25519_dalek::edwards::CompressedEdwardsY;
+    use sha2::{Digest, Sha256, Sha512};
+
+    let local_keypair = local_keypair.clone().try_into_ed25519()?;
+    let local_public = local_keypair.public().to_bytes();
+    let peer_public = peer_id_to_public_key(peer_id)?;
+
+    let mut local_scalar = [0u8; 32];
+    local_scalar.copy_from_slice(&Sha512::digest(local_keypair.secret().as_ref())[..32]);
+
+    let peer_point = CompressedEdwardsY(peer_public)
+        .decompress()
+        .ok_or_else(|| anyhow::anyhow!("Public key for peer {peer_id} is not a valid ed25519 point."))?;
+
+    let shared_secret = peer_point.to_montgomery().mul_clamped(local_scalar).to_bytes();
+
+    if shared_secret == [0u8; 32] {
+        return Err(anyhow::anyhow!("Key agreement with peer {peer_id} produced a degenerate shared secret."));
+    }
+
+    let (first, second) = if local_public <= peer_public { (local_public, peer_public) } else { (peer_public, local_public) };
+    let info = [b"enclave/direct-message/v1".as_slice(), &first, &second].concat();
+
+    let mut key = [0u8; 32];
+    hkdf::Hkdf::<Sha256>::new(None, &shared_secret)
+        .expand(&info, &mut key)
+        .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    Ok(key)
+}
+
+/// Encrypts `plaintext` for `peer_id`, returning the random nonce followed by the ciphertext.
+#[cfg(feature = "debug-crypto")]
+pub fn encrypt_for_peer(local_keypair: &libp2p::identity::Keypair, peer_id: &PeerId, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit, Nonce};
+
+    let key = derive_shared_key(local_keypair, peer_id)?;
+    let nonce = rand::random::<[u8; 12]>();
+
+    let ciphertext = ChaCha20Poly1305::new(&key.into())
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt message for peer {peer_id}."))?;
+
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+/// Decrypts a payload produced by `encrypt_for_peer` on the other side of the conversation.
+#[cfg(feature = "debug-crypto")]
+pub fn decrypt_from_peer(local_keypair: &libp2p::identity::Keypair, peer_id: &PeerId, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit, Nonce};
+
+    if payload.len() < 12 {
+        return Err(anyhow::anyhow!("Ciphertext is too short to contain a nonce."));
+    }
+
+    let key = derive_shared_key(local_keypair, peer_id)?;
+    let (nonce, ciphertext) = payload.split_at(12);
+
+    ChaCha20Poly1305::new(&key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Failed to decrypt message from peer {peer_id}."))
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    #[test]
+    pub fn test_peer_id_to_public_key_round_trips_ed25519_key() {
+        let keypair = Keypair::ed25519_from_bytes([7u8; 32]).expect("Keypair creation failed");
+        let expected = keypair.public().try_into_ed25519().unwrap().to_bytes();
+
+        let peer_id: PeerId = keypair.public().to_peer_id().to_string().parse().unwrap();
+
+        assert_eq!(peer_id_to_public_key(&peer_id).unwrap(), expected);
+    }
+
+    #[test]
+    pub fn test_peer_id_to_public_key_rejects_hashed_peer_id() {
+        let peer_id: PeerId = "QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N".parse().unwrap();
+
+        let err = peer_id_to_public_key(&peer_id).unwrap_err();
+
+        assert!(err.to_string().contains("identify"));
+    }
+
+    #[test]
+    pub fn test_encryption_status_is_never_encrypted_while_messages_are_plaintext() {
+        let known: PeerId = Keypair::ed25519_from_bytes([7u8; 32]).unwrap().public().to_peer_id();
+        let unknown: PeerId = "QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N".parse().unwrap();
+
+        assert_eq!(encryption_status(&known), EncryptionStatus { encrypted: false, reason: Some(MESSAGES_NOT_ENCRYPTED.to_string()) });
+
+        let status = encryption_status(&unknown);
+        assert!(!status.encrypted);
+        assert!(status.reason.unwrap().contains("does not embed its public key"));
+    }
+
+    #[cfg(feature = "debug-crypto")]
+    #[test]
+    pub fn test_encrypt_for_peer_round_trips() {
+        let alice = Keypair::ed25519_from_bytes([1u8; 32]).unwrap();
+        let bob = Keypair::ed25519_from_bytes([2u8; 32]).unwrap();
+
+        let ciphertext = encrypt_for_peer(&alice, &bob.public().to_peer_id(), b"Hello Bob").unwrap();
+        let plaintext = decrypt_from_peer(&bob, &alice.public().to_peer_id(), &ciphertext).unwrap();
+
+        assert_eq!(plaintext, b"Hello Bob");
+        assert_eq!(
+            derive_shared_key(&alice, &bob.public().to_peer_id()).unwrap(),
+            derive_shared_key(&bob, &alice.public().to_peer_id()).unwrap()
+        );
+    }
+
+    #[cfg(feature = "debug-crypto")]
+    #[test]
+    pub fn test_decrypt_from_wrong_peer_fails() {
+        let alice = Keypair::ed25519_from_bytes([1u8; 32]).unwrap();
+        let bob = Keypair::ed25519_from_bytes([2u8; 32]).unwrap();
+        let carol = Keypair::ed25519_from_bytes([3u8; 32]).unwrap();
+
+        let ciphertext = encrypt_for_peer(&alice, &bob.public().to_peer_id(), b"Hello Bob").unwrap();
+
+        assert!(decrypt_from_peer(&carol, &alice.public().to_peer_id(), &ciphertext).is_err());
+        assert!(decrypt_from_peer(&bob, &carol.public().to_peer_id(), &ciphertext).is_err());
+    }
+}
@@ -1,21 +1,27 @@
-use libp2p::request_response::ResponseChannel;
-use libp2p::{PeerId};
+use libp2p::request_response::{OutboundFailure, OutboundRequestId, ResponseChannel};
+use libp2p::{Multiaddr, PeerId, multiaddr::Protocol};
 use std::collections::HashMap;
-use std::str::FromStr;
-use tokio::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use rusqlite::Connection;
 use crate::db;
 use crate::db::models::direct_message::DirectMessage;
 use crate::db::models::friend_request::FriendRequest;
 use crate::db::models::post::Post;
+use crate::db::models::post_tombstone::PostTombstone;
 use crate::p2p::{types::*};
+use crate::p2p::channel::EventSender;
 use crate::p2p::config::EnclaveNetworkBehaviour;
+use crate::p2p::explicit_peers::ExplicitPeers;
+use crate::p2p::protocol::PeerProtocols;
+use crate::p2p::sanitize::{sanitize_content, sanitize_deny_reason};
+use crate::p2p::sync::{self, CancelFlag};
 
 pub struct EventHandler {
-    pub event_sender: mpsc::UnboundedSender<P2PEvent>
+    pub event_sender: EventSender
 }
 
 impl EventHandler {
-    pub fn new(event_sender: mpsc::UnboundedSender<P2PEvent>) -> Self {
+    pub fn new(event_sender: EventSender) -> Self {
         Self { event_sender }
     }
 
@@ -23,37 +29,46 @@ impl EventHandler {
         &self,
         peer_id: PeerId,
         endpoint: &libp2p_core::connection::ConnectedPoint,
+        listen_addresses: &[Multiaddr],
+        friend_list: &[PeerId],
         pending_responses: &mut HashMap<PeerId, P2PMessage>,
+        pending_deliveries: &mut HashMap<OutboundRequestId, i64>,
         swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>
     ) {
         log::info!("Connected to peer: {peer_id}");
         let _ = self.event_sender.send(P2PEvent::PeerConnected(peer_id));
 
-        let multiaddr = match endpoint {
-            libp2p_core::connection::ConnectedPoint::Dialer { address, .. } => address.clone(),
-            libp2p_core::connection::ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr.clone()
-        };
-
-        if let Err(err) = db::create_user(db::DATABASE.clone(), peer_id.to_string(), multiaddr.to_string(), false) {
+        if let Err(err) = Self::record_connected_peer(db::DATABASE.clone(), peer_id, endpoint, friend_list.contains(&peer_id)) {
             let _ = self.event_sender.send(P2PEvent::Error {
-                context: "create_user",
-                error: err.to_string()
+                context: "record_connected_peer",
+                error: err.to_string(),
+                severity: ErrorSeverity::from_db_error(&err)
             });
         }
 
         if let Ok(pending_friend_requests) = db::fetch_friend_requests_to_peer(db::DATABASE.clone(), peer_id.to_string()) {
             if pending_friend_requests.len() > 0 {
+                let request = pending_friend_requests[0].to_owned();
+                let addresses = advertised_addresses(&request.from_multiaddr, listen_addresses);
+
                 swarm.behaviour_mut()
                     .request_response
-                    .send_request(&peer_id, P2PMessage::FriendRequest(pending_friend_requests[0].to_owned()));
+                    .send_request(&peer_id, P2PMessage::FriendRequest(request.with_addresses(addresses)));
 
                 if let Err(err) = db::update_friend_request(db::DATABASE.clone(), pending_friend_requests[0].id, Some(false)) {
-                    let _ = self.event_sender.send(P2PEvent::Error { context: "update_direct_message", error: err.to_string() });
+                    let _ = self.event_sender.send(P2PEvent::Error { context: "update_direct_message", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                     return;
                 }
             }
         }
 
+        if Self::friend_request_ack_due(db::DATABASE.clone(), &peer_id, swarm.local_peer_id()) {
+            log::info!("Re-sending friend request acknowledgement to {}", peer_id);
+            swarm.behaviour_mut()
+                .request_response
+                .send_request(&peer_id, P2PMessage::FriendRequestAck);
+        }
+
         if let Some(response) = pending_responses.remove(&peer_id) {
             log::info!("Sending buffered friend request response to {}", peer_id);
             swarm.behaviour_mut()
@@ -61,36 +76,157 @@ impl EventHandler {
                 .send_request(&peer_id, response);
         }
 
-        let outbound_direct_messages = match db::fetch_direct_messages_with_peer(db::DATABASE.clone(), peer_id.to_string()) {
-            Ok(dms) => dms,
-            Err(err) => {
-                let _ = self.event_sender.send(P2PEvent::Error { context: "fetch_direct_messages_with_peer", error: err.to_string() });
-                return;
-            }
+        let outbound_direct_messages = db::fetch_pending_direct_messages(db::DATABASE.clone(), swarm.local_peer_id().to_string(), peer_id.to_string())
+            .unwrap_or_default();
+
+        if !outbound_direct_messages.is_empty() && !friend_list.contains(&peer_id) {
+            outbound_direct_messages.iter().for_each(|dm| {
+                self.handle_undeliverable_message(dm.id, "Recipient is no longer a friend".into());
+            });
+            return;
         }
-            .iter()
-            .filter(|&dm| dm.pending)
-            .cloned()
-            .collect::<Vec<DirectMessage>>();
 
         outbound_direct_messages.iter().for_each(|dm| {
-            swarm.behaviour_mut()
+            let request_id = swarm.behaviour_mut()
                 .request_response
                 .send_request(&peer_id, P2PMessage::DirectMessage(dm.to_owned()));
+            pending_deliveries.insert(request_id, dm.id);
 
             if let Err(err) = db::update_direct_message(db::DATABASE.clone(), dm.id, None, Some(false)) {
-                let _ = self.event_sender.send(P2PEvent::Error { context: "update_direct_message", error: err.to_string() });
+                let _ = self.event_sender.send(P2PEvent::Error { context: "update_direct_message", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                 return;
             }
         });
     }
 
+    /// Sends a reconnected friend the posts buffered for them, skipping any already in flight.
+    /// Each stays buffered until its `BufferedPostAck` arrives. Returns how many were sent.
+    pub fn flush_buffered_posts(
+        db: Arc<Mutex<Connection>>,
+        peer_id: PeerId,
+        friend_list: &[PeerId],
+        peer_protocols: &PeerProtocols,
+        pending_buffered_posts: &mut HashMap<OutboundRequestId, (PeerId, i64)>,
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>
+    ) -> anyhow::Result<usize> {
+        if !friend_list.contains(&peer_id) {
+            return Ok(0);
+        }
+
+        // They'd refuse every one of them, and will catch up through sync instead.
+        if peer_protocols.is_outdated(&peer_id) {
+            db::clear_buffered_posts(db, peer_id.to_string())?;
+            return Ok(0);
+        }
+
+        let buffered_posts = db::fetch_buffered_posts(db, peer_id.to_string())?
+            .into_iter()
+            .filter(|post| !pending_buffered_posts.values().any(|(peer, post_id)| *peer == peer_id && *post_id == post.id))
+            .collect::<Vec<Post>>();
+
+        if !buffered_posts.is_empty() {
+            log::info!("Delivering {} buffered posts to {}", buffered_posts.len(), peer_id);
+        }
+
+        for post in &buffered_posts {
+            let request_id = swarm.behaviour_mut()
+                .request_response
+                .send_request(&peer_id, P2PMessage::BufferedPost(post.clone()));
+            pending_buffered_posts.insert(request_id, (peer_id, post.id));
+        }
+
+        Ok(buffered_posts.len())
+    }
+
+    pub fn handle_buffered_post_delivered(&self, peer_id: PeerId, post_id: i64) {
+        if let Err(err) = db::remove_buffered_post(db::DATABASE.clone(), post_id, peer_id.to_string()) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "remove_buffered_post", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+        }
+    }
+
+    /// A peer whose protocol can't carry buffered posts won't ever take them, so stop holding
+    /// any for it. Other failures leave the post buffered for the next connection.
+    pub fn handle_buffered_post_failed(&self, peer_id: PeerId, error: &OutboundFailure, peer_protocols: &mut PeerProtocols) {
+        let unsupported = match error {
+            OutboundFailure::UnsupportedProtocols => true,
+            OutboundFailure::Io(err) => err.kind() == std::io::ErrorKind::Unsupported,
+            _ => false
+        };
+
+        if !unsupported {
+            return;
+        }
+
+        log::warn!("{} can't receive buffered posts, no longer buffering for them", peer_id);
+        peer_protocols.mark_outdated(peer_id);
+
+        if let Err(err) = db::clear_buffered_posts(db::DATABASE.clone(), peer_id.to_string()) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "clear_buffered_posts", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+        }
+    }
+
+    /// Creates a user for a peer we haven't seen before. When a friend reconnects from a new
+    /// address their stored one is updated, unless that's been turned off. Only addresses we
+    /// dialed count: an inbound connection's address has the peer's ephemeral source port, so
+    /// it can't be dialed back.
+    pub fn record_connected_peer(db: Arc<Mutex<Connection>>, peer_id: PeerId, endpoint: &libp2p_core::connection::ConnectedPoint, is_friend: bool) -> anyhow::Result<()> {
+        let mut multiaddr = match endpoint {
+            libp2p_core::connection::ConnectedPoint::Dialer { address, .. } => address.clone(),
+            libp2p_core::connection::ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr.clone()
+        };
+
+        if let Some(Protocol::P2p(address_peer)) = multiaddr.iter().last() {
+            if address_peer == peer_id {
+                multiaddr.pop();
+            }
+        }
+
+        let user = match db::fetch_user_by_peer_id(db.clone(), peer_id.to_string()) {
+            Ok(user) => user,
+            Err(_) => {
+                db::create_user(db, peer_id.to_string(), multiaddr.to_string(), false)?;
+                return Ok(());
+            }
+        };
+
+        if !is_friend || endpoint.is_listener() || user.multiaddr == multiaddr.to_string() {
+            return Ok(());
+        }
+
+        if !db::get_auto_update_friend_addresses(db.clone())? {
+            return Ok(());
+        }
+
+        log::info!("Friend {} reconnected from {}, updating their address", peer_id, multiaddr);
+        db::upsert_user_address(db.clone(), user.id, multiaddr.to_string())?;
+        db::update_user(db, user.id, Some(multiaddr.to_string()), None)
+    }
+
+    /// Moves a direct message that can never be delivered into the dead letter store.
+    pub fn handle_undeliverable_message(&self, direct_message_id: i64, reason: String) {
+        log::warn!("Direct message {} is undeliverable: {}", direct_message_id, reason);
+
+        let dead_letter = db::move_direct_message_to_dead_letters(db::DATABASE.clone(), direct_message_id, reason)
+            .and_then(|id| db::fetch_dead_letter_by_id(db::DATABASE.clone(), id));
+
+        match dead_letter {
+            Ok(dead_letter) => {
+                let _ = self.event_sender.send(P2PEvent::MessageDeadLettered(dead_letter));
+            },
+            Err(err) => {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "move_direct_message_to_dead_letters", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            }
+        }
+    }
+
     pub fn handle_friend_request(
         &self,
         peer: PeerId,
-        request: FriendRequest,
-        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>
+        mut request: FriendRequest,
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        channel: ResponseChannel<P2PMessage>
     ) {
+        request.message = sanitize_content(&request.message);
         log::info!("Received friend request from {}: {}", peer, request.message);
         
         let _ = self.event_sender.send(P2PEvent::FriendRequestReceived {
@@ -98,90 +234,331 @@ impl EventHandler {
             request: request.clone()
         });
 
+        if let Err(err) = Self::store_friend_request_addresses(db::DATABASE.clone(), peer, &request) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "store_friend_request_addresses", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+        }
+
         if let Err(err) = db::create_friend_request(db::DATABASE.clone(), request.from_peer_id, request.from_multiaddr, swarm.local_peer_id().to_string(), request.to_multiaddr, request.message) {
             let _ = self.event_sender.send(P2PEvent::Error {
                 context: "create_friend_request",
-                error: err.to_string()
+                error: err.to_string(),
+                severity: ErrorSeverity::from_db_error(&err)
             });
+            return;
+        }
+
+        // Fails for peers on /enclave/1.0.0, which don't know the ack; they weren't expecting one.
+        if let Err(err) = swarm.behaviour_mut().request_response.send_response(channel, P2PMessage::FriendRequestAck) {
+            log::warn!("Failed to acknowledge friend request from {}: {:?}", peer, err);
         }
     }
 
+    /// Whether `peer` has an unanswered friend request to us, so should be re-sent the
+    /// acknowledgement in case the first one was lost.
+    pub fn friend_request_ack_due(db: Arc<Mutex<Connection>>, peer: &PeerId, local_peer_id: &PeerId) -> bool {
+        db::fetch_friend_requests_from_peer(db, peer.to_string())
+            .unwrap_or_default()
+            .iter()
+            .any(|request| request.to_peer_id == local_peer_id.to_string() && request.pending)
+    }
+
+    /// Marks our requests to `peer` as delivered now that they've confirmed receiving one.
+    pub fn handle_friend_request_ack(&self, peer: PeerId) {
+        log::info!("Friend request to {} was acknowledged", peer);
+
+        for request in db::fetch_friend_requests_to_peer(db::DATABASE.clone(), peer.to_string()).unwrap_or_default() {
+            if let Err(err) = db::update_friend_request(db::DATABASE.clone(), request.id, Some(false)) {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "update_friend_request", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                return;
+            }
+        }
+
+        let _ = self.event_sender.send(P2PEvent::FriendRequestAcknowledged { peer });
+    }
+
+    /// Stores the addresses a friend request advertised so accepting it can dial the best of
+    /// them. Peers that predate multi-address requests only send `from_multiaddr`.
+    pub fn store_friend_request_addresses(db: Arc<Mutex<Connection>>, peer: PeerId, request: &FriendRequest) -> anyhow::Result<usize> {
+        let addresses = if request.addresses.is_empty() {
+            vec![request.from_multiaddr.clone()]
+        } else {
+            request.addresses.clone()
+        };
+
+        Self::apply_address_response(db, peer, addresses)
+    }
+
     pub fn handle_friend_request_response(
         &self,
         peer: PeerId,
         response: FriendRequestResponse,
         friend_list: &mut Vec<PeerId>,
+        explicit_peers: &mut ExplicitPeers,
         swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>
     ) {
         log::info!("Received friend request response from {}: accepted={}", peer, response.accepted);
         
         if response.accepted {
             if !friend_list.contains(&peer) {
+                match db::friend_limit_reached(db::DATABASE.clone()) {
+                    Ok(Some(limit)) => {
+                        log::warn!("Not adding {} as a friend: friend limit of {} reached", peer, limit);
+                        let _ = self.event_sender.send(P2PEvent::FriendLimitReached { peer, limit });
+                        return;
+                    },
+                    Ok(None) => {},
+                    Err(err) => {
+                        let _ = self.event_sender.send(P2PEvent::Error { context: "friend_limit_reached", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                        return;
+                    }
+                }
+
                 let user = match db::fetch_user_by_peer_id(db::DATABASE.clone(), peer.to_string()) {
                     Ok(u) => u,
                     Err(err) => {
                         let _ = self.event_sender.send(P2PEvent::Error {
                             context: "fetch_user_by_peer_id",
-                            error: err.to_string()
+                            error: err.to_string(),
+                            severity: ErrorSeverity::from_db_error(&err)
                         });
                         return;
                     }
                 };
 
-                if let Err(err) = db::create_friend(db::DATABASE.clone(), user.id) {
+                if let Err(err) = db::befriend(db::DATABASE.clone(), user.id) {
                     let _ = self.event_sender.send(P2PEvent::Error {
-                        context: "create_friend",
-                        error: err.to_string()
+                        context: "befriend",
+                        error: err.to_string(),
+                        severity: ErrorSeverity::from_db_error(&err)
                     });
                     return;
                 }
 
                 friend_list.push(peer);
-                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+                explicit_peers.add(&mut swarm.behaviour_mut().gossipsub, &peer);
             }
 
             let _ = self.event_sender.send(P2PEvent::FriendRequestAccepted { peer });
         } else {
-            let _ = self.event_sender.send(P2PEvent::FriendRequestDenied { peer });
+            let _ = self.event_sender.send(P2PEvent::FriendRequestDenied { peer, reason: sanitize_deny_reason(response.reason) });
         }
     }
 
+    /// Handles a direct message from `peer`, the authenticated sender of the request. The
+    /// sender id inside the message is only trusted when it matches.
     pub fn handle_direct_message(
         &self,
-        msg: DirectMessage,
+        peer: PeerId,
+        mut msg: DirectMessage,
         friend_list: &Vec<PeerId>,
         direct_messages: &mut HashMap<PeerId, Vec<DirectMessage>>
     ) {
-        log::info!("Received direct message '{}' from {}", msg.content, msg.from_peer_id);
+        if msg.from_peer_id != peer.to_string() {
+            log::warn!("Dropping direct message from {} claiming to be from {}", peer, msg.from_peer_id);
+            return;
+        }
 
-        let from_peer_id = match PeerId::from_str(&msg.from_peer_id) {
-            Ok(p) => p,
-            Err(err) => {
-                let _ = self.event_sender.send(P2PEvent::Error { context: "PeerId::from_str", error: err.to_string() });
-                return;
-            }
-        };
+        msg.content = sanitize_content(&msg.content);
+        msg.content_type = msg.content_type.received();
+        log::info!("Received direct message '{}' from {}", msg.content, peer);
 
         let identity_peer_id = match db::fetch_identity(db::DATABASE.clone()) {
             Ok(id) => id.peer_id,
             Err(err) => {
-                let _ = self.event_sender.send(P2PEvent::Error { context: "fetch_identity", error: err.to_string() });
+                let _ = self.event_sender.send(P2PEvent::Error { context: "fetch_identity", error: err.to_string(), severity: ErrorSeverity::Fatal });
                 return;
             }
         };
 
-        if friend_list.contains(&from_peer_id) {
-            if let Err(err) = db::create_direct_message(db::DATABASE.clone(), msg.from_peer_id.clone(), identity_peer_id, msg.content.clone()) {
-                let _ = self.event_sender.send(P2PEvent::Error { context: "create_direct_message", error: err.to_string() });
+        if friend_list.contains(&peer) {
+            match Self::store_direct_message(db::DATABASE.clone(), identity_peer_id, &peer, &msg) {
+                Ok(true) => {},
+                Ok(false) => {
+                    log::info!("Dropping direct message from paused peer {}", peer);
+                    return;
+                },
+                Err(err) => {
+                    let _ = self.event_sender.send(P2PEvent::Error { context: "create_direct_message", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                }
             }
 
-            let mut current_messages = direct_messages.remove(&from_peer_id).unwrap_or(vec![]);
+            let mut current_messages = direct_messages.remove(&peer).unwrap_or(vec![]);
             current_messages.push(msg.clone());
 
-            direct_messages.insert(from_peer_id, current_messages);
+            direct_messages.insert(peer, current_messages);
 
             let _ = self.event_sender.send(P2PEvent::DirectMessageReceived(msg));
+        } else {
+            match Self::quarantine_direct_message(db::DATABASE.clone(), &peer, &msg) {
+                Ok(true) => {
+                    log::info!("Quarantined direct message from non-friend {}", peer);
+                    let _ = self.event_sender.send(P2PEvent::DirectMessageQuarantined { peer });
+                },
+                Ok(false) => log::info!("Dropping direct message from blocked peer {}", peer),
+                Err(err) => {
+                    let _ = self.event_sender.send(P2PEvent::Error { context: "create_quarantined_message", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                }
+            }
+        }
+    }
+
+    /// A friend's address answered with another identity, so they may have regenerated their
+    /// keys or the address may now belong to someone else. Either way the user should check
+    /// before trusting the conversation, so it is flagged until acknowledged.
+    pub fn handle_key_change(&self, db: Arc<Mutex<Connection>>, peer: PeerId, new_peer: PeerId) {
+        let notice = db::record_key_change(db.clone(), peer.to_string(), new_peer.to_string())
+            .and_then(|id| id.map(|id| db::fetch_direct_message_by_id(db, id)).transpose());
+
+        match notice {
+            Ok(Some(notice)) => {
+                log::warn!("Safety number changed for {}, their address answered as {}", peer, new_peer);
+                let _ = self.event_sender.send(P2PEvent::SafetyNumberChanged { peer, new_peer, notice });
+            },
+            Ok(None) => {},
+            Err(err) => {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "record_key_change", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            }
+        }
+    }
+
+    /// Holds a direct message from a non-friend until the user approves them, returning
+    /// `false` without storing it when the sender is blocked.
+    pub fn quarantine_direct_message(db: Arc<Mutex<Connection>>, peer: &PeerId, msg: &DirectMessage) -> anyhow::Result<bool> {
+        if db::is_peer_blocked(db.clone(), peer.to_string())? {
+            return Ok(false);
+        }
+
+        db::create_quarantined_message(db, peer.to_string(), msg.content.clone())?;
+        Ok(true)
+    }
+
+    /// Persists an inbound direct message, returning `false` without storing it when the
+    /// sender is paused. A claimed `System` type is stored as text so peers can't forge notices.
+    pub fn store_direct_message(db: Arc<Mutex<Connection>>, identity_peer_id: String, peer: &PeerId, msg: &DirectMessage) -> anyhow::Result<bool> {
+        if db::is_peer_paused(db.clone(), peer.to_string())? {
+            return Ok(false);
+        }
+
+        db::create_typed_direct_message(db, peer.to_string(), identity_peer_id, msg.content.clone(), msg.content_type.received())?;
+        Ok(true)
+    }
+
+    pub fn handle_nickname_update(&self, peer: PeerId, nickname: String, friend_list: &[PeerId]) {
+        log::info!("Received nickname update from {}: {}", peer, nickname);
+
+        if !friend_list.contains(&peer) {
+            log::warn!("Nickname update received from non-friend peer.");
+            return;
+        }
+
+        if let Err(err) = Self::apply_nickname_update(db::DATABASE.clone(), peer, nickname.clone()) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "apply_nickname_update", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            return;
+        }
+
+        let _ = self.event_sender.send(P2PEvent::FriendNicknameChanged { peer, nickname });
+    }
+
+    pub fn handle_read_receipt(&self, peer: PeerId, friend_list: &[PeerId], local_peer_id: &PeerId) {
+        if !friend_list.contains(&peer) {
+            log::warn!("Read receipt received from non-friend peer.");
+            return;
+        }
+
+        match db::mark_outbound_messages_read(db::DATABASE.clone(), local_peer_id.to_string(), peer.to_string()) {
+            Ok(_) => {
+                let _ = self.event_sender.send(P2PEvent::MessagesRead { peer });
+            },
+            Err(err) => {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "mark_outbound_messages_read", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            }
+        }
+    }
+
+    /// Stores the nickname a peer chose for themselves. A local alias set with
+    /// `db::set_peer_alias` takes precedence over this when displaying the peer.
+    pub fn apply_nickname_update(db: Arc<Mutex<Connection>>, peer: PeerId, nickname: String) -> anyhow::Result<()> {
+        let user = db::fetch_user_by_peer_id(db.clone(), peer.to_string())?;
+
+        if user.nickname.as_ref() == Some(&nickname) {
+            return Ok(());
+        }
+
+        db::update_user(db.clone(), user.id, None, Some(nickname.clone()))?;
+        db::create_system_message(db, peer.to_string(), format!("Changed their nickname to {nickname}"))?;
+        Ok(())
+    }
+
+    /// Answers a friend's request for our current addresses. Non-friends get an empty list
+    /// so our network location isn't handed out to strangers.
+    pub fn handle_address_request(
+        &self,
+        peer: PeerId,
+        friend_list: &[PeerId],
+        listen_addresses: &[Multiaddr],
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        channel: ResponseChannel<P2PMessage>
+    ) {
+        log::info!("Received address request from {}", peer);
+
+        let addresses = if friend_list.contains(&peer) {
+            shareable_addresses(listen_addresses)
+        } else {
+            log::warn!("Address request received from non-friend peer.");
+            Vec::new()
+        };
+
+        if let Err(err) = swarm.behaviour_mut().request_response.send_response(channel, P2PMessage::AddressResponse { addresses }) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "send_response", error: format!("{:?}", err), severity: ErrorSeverity::Recoverable });
+        }
+    }
+
+    pub fn handle_latency_probe(
+        &self,
+        nonce: u64,
+        sent_at: i64,
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        channel: ResponseChannel<P2PMessage>
+    ) {
+        if let Err(err) = swarm.behaviour_mut().request_response.send_response(channel, P2PMessage::LatencyProbeReply { nonce, sent_at, replied_at: Some(chrono::Utc::now().timestamp_millis()) }) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "send_response", error: format!("{:?}", err), severity: ErrorSeverity::Recoverable });
+        }
+    }
+
+    pub fn handle_address_response(&self, peer: PeerId, addresses: Vec<String>) {
+        log::info!("Received {} addresses from {}", addresses.len(), peer);
+
+        if let Err(err) = Self::apply_address_response(db::DATABASE.clone(), peer, addresses) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "apply_address_response", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+        }
+    }
+
+    /// Stores the addresses a peer reported and makes the first valid one the address used
+    /// for future dials. Returns how many addresses were stored.
+    pub fn apply_address_response(db: Arc<Mutex<Connection>>, peer: PeerId, addresses: Vec<String>) -> anyhow::Result<usize> {
+        let user = db::fetch_user_by_peer_id(db.clone(), peer.to_string())?;
+
+        let addresses = addresses.iter()
+            .filter_map(|address| address.parse::<Multiaddr>().ok())
+            .map(|mut address| {
+                if let Some(Protocol::P2p(address_peer)) = address.iter().last() {
+                    if address_peer == peer {
+                        address.pop();
+                    }
+                }
+                address.to_string()
+            })
+            .collect::<Vec<String>>();
+
+        for address in addresses.iter().rev() {
+            db::upsert_user_address(db.clone(), user.id, address.clone())?;
+        }
+
+        if let Some(best) = addresses.first() {
+            db::update_user(db, user.id, Some(best.clone()), None)?;
         }
+
+        Ok(addresses.len())
     }
 
     pub fn handle_post(
@@ -198,8 +575,8 @@ impl EventHandler {
             return;
         }
 
-        if let Err(err) = db::create_post(db::DATABASE.clone(), post.author_peer_id.clone(), post.content.clone()) {
-            let _ = self.event_sender.send(P2PEvent::Error { context: "create_post", error: err.to_string() });
+        if let Err(err) = db::create_post_at(db::DATABASE.clone(), post.author_peer_id.clone(), post.content.clone(), post.created_at) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "create_post_at", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
             return;
         };
 
@@ -208,6 +585,38 @@ impl EventHandler {
         let _ = self.event_sender.send(P2PEvent::PostRecieved(post));
     }
 
+    /// Stores a post a friend held for us while we were offline. Friends only buffer their own
+    /// posts, and one may already have arrived through a sync.
+    pub fn handle_buffered_post(
+        &self,
+        src_peer_id: PeerId,
+        post: Post,
+        friend_list: &[PeerId],
+        displayed_posts: &mut Vec<Post>,
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        channel: ResponseChannel<P2PMessage>
+    ) {
+        if !friend_list.contains(&src_peer_id) || post.author_peer_id != src_peer_id.to_string() {
+            log::warn!("Ignoring buffered post from {} authored by {}", src_peer_id, post.author_peer_id);
+            return;
+        }
+
+        if !db::post_exists(db::DATABASE.clone(), post.author_peer_id.clone(), post.content.clone()).unwrap_or(false) {
+            if let Err(err) = db::create_post_at(db::DATABASE.clone(), post.author_peer_id.clone(), post.content.clone(), post.created_at) {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "create_post_at", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                return;
+            }
+
+            displayed_posts.push(post.clone());
+
+            let _ = self.event_sender.send(P2PEvent::PostRecieved(post));
+        }
+
+        if let Err(err) = swarm.behaviour_mut().request_response.send_response(channel, P2PMessage::BufferedPostAck) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "send_response", error: format!("{:?}", err), severity: ErrorSeverity::Recoverable });
+        }
+    }
+
     pub fn handle_synch_request(
         &mut self, 
         since: i64, 
@@ -219,39 +628,532 @@ impl EventHandler {
         let posts = match db::fetch_all_posts(db::DATABASE.clone()) {
             Ok(p) => p,
             Err(err) => {
-                let _ = self.event_sender.send(P2PEvent::Error { context: "fetch_all_posts", error: err.to_string() });
+                let _ = self.event_sender.send(P2PEvent::Error { context: "fetch_all_posts", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                 vec![]
             }
         };
 
-        let created_posts = posts.iter().filter(|&p| p.created_at >= since).cloned().collect::<Vec<Post>>();
-        let edited_posts = posts.iter().filter(|&p| p.edited_at >= Some(since)).cloned().collect::<Vec<Post>>();
+        let (created_posts, edited_posts) = sync::partition_posts_since(posts, since);
 
         let sender = swarm.local_peer_id().to_string();
 
+        // Only our own deletions are authoritative, so those are the only ones we pass on.
+        let deleted_posts = match db::fetch_post_tombstones(db::DATABASE.clone(), sender.clone(), since) {
+            Ok(t) => t,
+            Err(err) => {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "fetch_post_tombstones", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                vec![]
+            }
+        };
+
         if let Err(err) = swarm.behaviour_mut().request_response.send_response(
             channel,
-            P2PMessage::SynchResponse(SynchResponse { created_posts, edited_posts, sender })
+            P2PMessage::SynchResponse(SynchResponse { created_posts, edited_posts, sender, deleted_posts })
         ) {
-            let _ = self.event_sender.send(P2PEvent::Error { context: "send_response", error: format!("{:?}", err) });
+            let _ = self.event_sender.send(P2PEvent::Error { context: "send_response", error: format!("{:?}", err), severity: ErrorSeverity::Recoverable });
         }
     }
 
-    pub fn handle_synch_response(&self, created_posts: Vec<Post>, edited_posts: Vec<Post>, sender: String) {
+    pub async fn handle_synch_response(&self, peer: PeerId, created_posts: Vec<Post>, edited_posts: Vec<Post>, deleted_posts: Vec<PostTombstone>, sender: String, cancel: &CancelFlag) {
         log::info!("Received synch response from '{}'", sender);
-        log::info!("created_posts length: {}, edited_posts length: {}", created_posts.len(), edited_posts.len());
-        for post in created_posts {
-            if let Err(err) = db::create_post(db::DATABASE.clone(), post.author_peer_id, post.content) {
-                let _ = self.event_sender.send(P2PEvent::Error { context: "create_post", error: err.to_string() });
+        log::info!("created_posts length: {}, edited_posts length: {}, deleted_posts length: {}", created_posts.len(), edited_posts.len(), deleted_posts.len());
+        let created_posts = sync::retain_authored_by(created_posts, &peer);
+        let edited_posts = sync::retain_authored_by(edited_posts, &peer);
+        let cursor = sync::advance_cursor(0, &created_posts, &edited_posts, &deleted_posts);
+
+        if cancel.is_cancelled() {
+            log::info!("Sync with {} was cancelled, discarding its response", peer);
+            return;
+        }
+
+        if let Err(err) = Self::apply_deleted_posts(db::DATABASE.clone(), peer, &deleted_posts) {
+            let _ = self.event_sender.send(P2PEvent::Error { context: "apply_deleted_posts", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+        }
+
+        // Periodic and reconnect syncs overlap with posts already received over gossipsub, and a
+        // friend may still hold a copy of a post its author has since deleted, so both are skipped.
+        // A post that fails to insert is reported on its own without holding up the rest.
+        let mut synced = 0;
+        let created = db::bulk_insert_with_yield(db::DATABASE.clone(), created_posts, db::BULK_WRITE_BATCH_SIZE, |conn, post| {
+            if cancel.is_cancelled() {
+                return Ok(());
+            }
+
+            match db::insert_synced_post(conn, &post) {
+                Ok(true) => synced += 1,
+                Ok(false) => {},
+                Err(err) => {
+                    let _ = self.event_sender.send(P2PEvent::Error { context: "insert_synced_post", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                }
+            }
+            Ok(())
+        }).await;
+
+        match &created {
+            Ok(_) => self.event_sender.record_posts_synced(synced),
+            Err(err) => {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "bulk_insert_with_yield", error: err.to_string(), severity: ErrorSeverity::from_db_error(err) });
             }
         }
 
-        for post in edited_posts {
-            if let Err(err) = db::update_post(db::DATABASE.clone(), post.id, post.content) {
-                let _ = self.event_sender.send(P2PEvent::Error { context: "update_post", error: err.to_string() });
+        let completed = created.is_ok() && !cancel.is_cancelled() && sync::apply_in_batches(edited_posts, sync::SYNC_BATCH_SIZE, cancel, |post| {
+            if let Err(err) = db::apply_synced_edit(db::DATABASE.clone(), &post) {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "apply_synced_edit", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            }
+        });
+
+        // A cancelled sync leaves the cursor alone so the next one fetches what was skipped.
+        if completed {
+            if let Err(err) = Self::update_sync_cursor(db::DATABASE.clone(), peer, cursor) {
+                let _ = self.event_sender.send(P2PEvent::Error { context: "update_sync_cursor", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
             }
+        } else {
+            log::info!("Sync with {} was cancelled or failed part way through", peer);
         }
 
         let _ = self.event_sender.send(P2PEvent::PostSynch);
     }
-}
\ No newline at end of file
+
+    /// Applies deletions a friend synced to us. Only tombstones for the friend's own posts are
+    /// honoured, so one friend can't delete another's posts. Returns how many posts were removed.
+    pub fn apply_deleted_posts(db: Arc<Mutex<Connection>>, peer: PeerId, deleted_posts: &[PostTombstone]) -> anyhow::Result<usize> {
+        let mut removed = 0;
+
+        for tombstone in deleted_posts {
+            if tombstone.author_peer_id != peer.to_string() {
+                log::warn!("Ignoring deletion of a post by {} synced from {}", tombstone.author_peer_id, peer);
+                continue;
+            }
+
+            removed += db::apply_post_tombstone(db.clone(), tombstone.author_peer_id.clone(), tombstone.content.clone(), tombstone.deleted_at)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Moves a friend's sync cursor forward to `cursor` once their sync response has been
+    /// applied. The cursor never moves backwards.
+    pub fn update_sync_cursor(db: Arc<Mutex<Connection>>, peer: PeerId, cursor: i64) -> anyhow::Result<()> {
+        let user = db::fetch_user_by_peer_id(db.clone(), peer.to_string())?;
+        let friend = db::fetch_friend_by_user_id(db.clone(), user.id)?;
+
+        if cursor > friend.last_synch {
+            db::update_friend(db, friend.id, Some(cursor))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The addresses to advertise in a friend request: the one it was created with (the relay
+/// circuit when we have one) followed by our other shareable listen addresses.
+fn advertised_addresses(from_multiaddr: &str, listen_addresses: &[Multiaddr]) -> Vec<String> {
+    let mut addresses = Vec::new();
+
+    if !from_multiaddr.is_empty() {
+        addresses.push(from_multiaddr.to_string());
+    }
+
+    for address in shareable_addresses(listen_addresses) {
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    addresses
+}
+
+/// Listen addresses worth sharing with a remote peer, i.e. everything but loopback.
+fn shareable_addresses(listen_addresses: &[Multiaddr]) -> Vec<String> {
+    listen_addresses.iter()
+        .filter(|address| !address.iter().any(|protocol| matches!(protocol, Protocol::Ip4(ip) if ip.is_loopback()) || matches!(protocol, Protocol::Ip6(ip) if ip.is_loopback())))
+        .map(|address| address.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::db::models::direct_message::ContentType;
+    use std::str::FromStr;
+
+    #[test]
+    pub fn test_apply_nickname_update_updates_sender_nickname() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let peer = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK").unwrap();
+        let other = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+
+        db::create_user(db.clone(), peer.to_string(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+        db::create_user(db.clone(), other.clone(), "/ip4/127.0.0.1/tcp/4002".into(), false).unwrap();
+
+        EventHandler::apply_nickname_update(db.clone(), peer, "Alice".into()).expect("apply_nickname_update failed");
+
+        let updated = db::fetch_user_by_peer_id(db.clone(), peer.to_string()).unwrap();
+        let untouched = db::fetch_user_by_peer_id(db.clone(), other).unwrap();
+
+        assert_eq!(updated.nickname, Some("Alice".into()));
+        assert_eq!(untouched.nickname, None);
+
+        EventHandler::apply_nickname_update(db.clone(), peer, "Alice".into()).expect("apply_nickname_update failed");
+
+        let notices = db::fetch_direct_messages_with_peer(db, peer.to_string()).unwrap();
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].content, "Changed their nickname to Alice");
+        assert_eq!(notices[0].content_type, ContentType::System);
+    }
+
+    #[test]
+    pub fn test_store_direct_message_drops_messages_from_paused_peer() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let friend = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        db::create_user(db.clone(), friend.clone(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+
+        let message = |content: &str| DirectMessage::new(0, friend.clone(), identity.clone(), content.into(), 0, None, false, false);
+
+        db::set_peer_paused(db.clone(), friend.clone(), true).expect("set_peer_paused failed");
+        assert!(!EventHandler::store_direct_message(db.clone(), identity.clone(), &friend.parse().unwrap(), &message("While paused")).unwrap());
+        assert!(db::fetch_direct_messages_with_peer(db.clone(), friend.clone()).unwrap_or_default().is_empty());
+
+        db::set_peer_paused(db.clone(), friend.clone(), false).expect("set_peer_paused failed");
+        assert!(EventHandler::store_direct_message(db.clone(), identity.clone(), &friend.parse().unwrap(), &message("After resuming")).unwrap());
+
+        let stored = db::fetch_direct_messages_with_peer(db.clone(), friend.clone()).unwrap();
+        assert_eq!(stored.iter().map(|dm| dm.content.as_str()).collect::<Vec<_>>(), vec!["After resuming"]);
+
+        assert!(db::set_peer_paused(db, "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".into(), true).is_err());
+    }
+
+    #[test]
+    pub fn test_store_direct_message_stores_claimed_system_notice_as_text() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let friend = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        db::create_user(db.clone(), friend.clone(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+
+        let forged = DirectMessage::new(0, friend.clone(), identity.clone(), "You are now friends".into(), 0, None, false, false)
+            .with_content_type(ContentType::System);
+
+        assert!(EventHandler::store_direct_message(db.clone(), identity, &friend.parse().unwrap(), &forged).unwrap());
+
+        let stored = db::fetch_direct_messages_with_peer(db, friend).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].content_type, ContentType::Text);
+    }
+
+    #[test]
+    pub fn test_quarantine_direct_message_holds_non_friend_messages() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let identity = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".to_string();
+        let stranger = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA".to_string();
+        let blocked = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB".to_string();
+        db::block_peer_id(db.clone(), blocked.clone()).unwrap();
+
+        let message = |from: &String, content: &str| DirectMessage::new(0, from.clone(), identity.clone(), content.into(), 0, None, false, false);
+
+        assert!(EventHandler::quarantine_direct_message(db.clone(), &stranger.parse().unwrap(), &message(&stranger, "Hello?")).unwrap());
+        assert!(!EventHandler::quarantine_direct_message(db.clone(), &blocked.parse().unwrap(), &message(&blocked, "Let me in")).unwrap());
+
+        let quarantined = db::fetch_quarantined_messages(db.clone()).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].from_peer_id, stranger);
+        assert_eq!(quarantined[0].content, "Hello?");
+        assert!(db::fetch_direct_messages_with_peer(db, stranger).unwrap_or_default().is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_handle_direct_message_drops_spoofed_sender() {
+        let (event_sender, mut event_receiver) = crate::p2p::channel::event_channel(8);
+        let event_handler = EventHandler::new(event_sender);
+        let friend = PeerId::random();
+        let stranger = PeerId::random();
+        let mut direct_messages = HashMap::new();
+
+        let spoofed = DirectMessage::new(0, friend.to_string(), PeerId::random().to_string(), "It's me, honest".into(), 0, None, false, false);
+        event_handler.handle_direct_message(stranger, spoofed, &vec![friend], &mut direct_messages);
+
+        assert!(direct_messages.is_empty());
+        assert!(event_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    pub fn test_reconnect_resends_ack_for_pending_inbound_friend_request() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let local_peer_id = PeerId::random();
+        let requester = PeerId::random();
+        let multiaddr = "/ip4/127.0.0.1/tcp/4001".to_string();
+
+        assert!(!EventHandler::friend_request_ack_due(db.clone(), &requester, &local_peer_id));
+
+        let request_id = db::create_friend_request(db.clone(), requester.to_string(), multiaddr.clone(), local_peer_id.to_string(), multiaddr.clone(), "Hi".into()).unwrap();
+        assert!(EventHandler::friend_request_ack_due(db.clone(), &requester, &local_peer_id));
+
+        // Our own request to them isn't something we acknowledge.
+        db::create_friend_request(db.clone(), local_peer_id.to_string(), multiaddr.clone(), requester.to_string(), multiaddr, "Hi back".into()).unwrap();
+        assert!(!EventHandler::friend_request_ack_due(db.clone(), &PeerId::random(), &local_peer_id));
+
+        db::delete_friend_request(db.clone(), request_id).unwrap();
+        assert!(!EventHandler::friend_request_ack_due(db, &requester, &local_peer_id));
+    }
+
+    #[tokio::test]
+    pub async fn test_flush_buffered_posts_resends_until_acknowledged() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let local_peer_id = PeerId::from(keypair.public());
+        let (behaviour, relay_transport) = crate::p2p::config::create_swarm_behaviour(&keypair, local_peer_id, libp2p::ping::Config::new()).unwrap();
+        let mut swarm = crate::p2p::config::build_swarm(keypair, behaviour, relay_transport).unwrap();
+
+        let friend = PeerId::random();
+        let post_id = db::create_post(db.clone(), local_peer_id.to_string(), "While you were out".into()).unwrap();
+        db::buffer_post(db.clone(), post_id, &[friend.to_string()]).unwrap();
+
+        let mut peer_protocols = PeerProtocols::default();
+        let mut pending_buffered_posts = HashMap::new();
+        let mut flush = |peer: PeerId, peer_protocols: &PeerProtocols, pending_buffered_posts: &mut HashMap<OutboundRequestId, (PeerId, i64)>| {
+            EventHandler::flush_buffered_posts(db.clone(), peer, &[friend], peer_protocols, pending_buffered_posts, &mut swarm).expect("flush_buffered_posts failed")
+        };
+
+        assert_eq!(flush(PeerId::random(), &peer_protocols, &mut pending_buffered_posts), 0);
+
+        assert_eq!(flush(friend, &peer_protocols, &mut pending_buffered_posts), 1);
+        assert_eq!(pending_buffered_posts.values().collect::<Vec<_>>(), vec![&(friend, post_id)]);
+
+        // A second connection while the delivery is in flight doesn't send it again.
+        assert_eq!(flush(friend, &peer_protocols, &mut pending_buffered_posts), 0);
+
+        // Without an ack the post stays buffered, so a lost delivery goes out on the next connection.
+        pending_buffered_posts.clear();
+        assert_eq!(flush(friend, &peer_protocols, &mut pending_buffered_posts), 1);
+
+        // A peer that can't take buffered posts has them dropped instead.
+        pending_buffered_posts.clear();
+        peer_protocols.mark_outdated(friend);
+        assert_eq!(flush(friend, &peer_protocols, &mut pending_buffered_posts), 0);
+        assert!(pending_buffered_posts.is_empty());
+        assert!(db::fetch_buffered_posts(db, friend.to_string()).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_apply_deleted_posts_propagates_tombstone() {
+        let author = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK").unwrap();
+        let other = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA").unwrap();
+
+        // The author deletes a post on their node.
+        let author_db = db::init_db(":memory:").expect("DB init failed");
+        let post_id = db::create_post(author_db.clone(), author.to_string(), "Regrettable".into()).unwrap();
+        db::delete_post(author_db.clone(), post_id).unwrap();
+
+        let deleted_posts = db::fetch_post_tombstones(author_db, author.to_string(), 0).unwrap();
+        assert_eq!(deleted_posts.len(), 1);
+
+        // A friend still holds copies of the post and of another peer's post with the same content.
+        let friend_db = db::init_db(":memory:").expect("DB init failed");
+        db::create_post(friend_db.clone(), author.to_string(), "Regrettable".into()).unwrap();
+        let other_post_id = db::create_post(friend_db.clone(), other.to_string(), "Regrettable".into()).unwrap();
+
+        let removed = EventHandler::apply_deleted_posts(friend_db.clone(), author, &deleted_posts).expect("apply_deleted_posts failed");
+        assert_eq!(removed, 1);
+        assert!(db::post_is_tombstoned(friend_db.clone(), author.to_string(), "Regrettable".into()).unwrap());
+
+        let remaining = db::fetch_all_posts(friend_db.clone()).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, other_post_id);
+
+        // Another peer can't relay deletions of the author's posts.
+        assert_eq!(EventHandler::apply_deleted_posts(friend_db, other, &deleted_posts).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_deny_reason_round_trips_to_denied_event() {
+        use libp2p::futures::io::Cursor;
+        use libp2p::identity::Keypair;
+        use libp2p::request_response::Codec;
+        use crate::p2p::protocol::{EnclaveCodec, PROTOCOL_V1_0};
+
+        let mut codec = EnclaveCodec::default();
+        let mut io = Cursor::new(Vec::new());
+        let response = P2PMessage::FriendRequestResponse(FriendRequestResponse::denied(Some("Sorry, I don't know you".into())));
+        codec.write_request(&PROTOCOL_V1_0, &mut io, response).await.expect("write_request failed");
+
+        let mut io = Cursor::new(io.into_inner());
+        let response = match codec.read_request(&PROTOCOL_V1_0, &mut io).await.expect("read_request failed") {
+            P2PMessage::FriendRequestResponse(response) => response,
+            _ => panic!("expected P2PMessage::FriendRequestResponse")
+        };
+
+        let keypair = Keypair::generate_ed25519();
+        let local_peer_id = PeerId::from(keypair.public());
+        let (behaviour, relay_transport) = crate::p2p::config::create_swarm_behaviour(&keypair, local_peer_id, libp2p::ping::Config::new()).unwrap();
+        let mut swarm = crate::p2p::config::build_swarm(keypair, behaviour, relay_transport).unwrap();
+        let (event_sender, mut event_receiver) = crate::p2p::channel::event_channel(8);
+
+        let peer = PeerId::random();
+        EventHandler::new(event_sender).handle_friend_request_response(peer, response, &mut vec![], &mut ExplicitPeers::default(), &mut swarm);
+
+        match event_receiver.recv().await {
+            Some(P2PEvent::FriendRequestDenied { peer: denied_by, reason }) => {
+                assert_eq!(denied_by, peer);
+                assert_eq!(reason, Some("Sorry, I don't know you".into()));
+            },
+            _ => panic!("expected P2PEvent::FriendRequestDenied")
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_changed_key_flags_safety_number_once() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let (event_sender, mut event_receiver) = crate::p2p::channel::event_channel(8);
+        let event_handler = EventHandler::new(event_sender);
+        let friend = PeerId::random();
+        let new_identity = PeerId::random();
+
+        event_handler.handle_key_change(db.clone(), friend, new_identity);
+
+        match event_receiver.recv().await {
+            Some(P2PEvent::SafetyNumberChanged { peer, new_peer, notice }) => {
+                assert_eq!(peer, friend);
+                assert_eq!(new_peer, new_identity);
+                assert_eq!(notice.content, db::SAFETY_NUMBER_CHANGED_MESSAGE);
+                assert_eq!(notice.content_type, ContentType::System);
+            },
+            _ => panic!("expected P2PEvent::SafetyNumberChanged")
+        }
+
+        // Seeing the same identity again doesn't repeat the warning until it's acknowledged.
+        event_handler.handle_key_change(db.clone(), friend, new_identity);
+        assert!(event_receiver.try_recv().is_err());
+        assert_eq!(db::fetch_direct_messages_with_peer(db.clone(), friend.to_string()).unwrap().len(), 1);
+
+        assert!(db::acknowledge_key_change(db.clone(), friend.to_string()).unwrap());
+        assert!(db::fetch_key_changes(db.clone()).unwrap().is_empty());
+        assert!(!db::acknowledge_key_change(db, friend.to_string()).unwrap());
+    }
+
+    #[test]
+    pub fn test_update_sync_cursor_only_moves_forward() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let peer = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK").unwrap();
+        let user_id = db::create_user(db.clone(), peer.to_string(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+        let friend_id = db::create_friend(db.clone(), user_id).unwrap();
+        db::update_friend(db.clone(), friend_id, Some(100)).unwrap();
+
+        EventHandler::update_sync_cursor(db.clone(), peer, 150).expect("update_sync_cursor failed");
+        assert_eq!(db::fetch_friend_by_id(db.clone(), friend_id).unwrap().last_synch, 150);
+
+        EventHandler::update_sync_cursor(db.clone(), peer, 120).expect("update_sync_cursor failed");
+        assert_eq!(db::fetch_friend_by_id(db, friend_id).unwrap().last_synch, 150);
+    }
+
+    #[test]
+    pub fn test_apply_nickname_update_fails_for_unknown_peer() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let peer = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK").unwrap();
+
+        assert!(EventHandler::apply_nickname_update(db, peer, "Alice".into()).is_err());
+    }
+
+    #[test]
+    pub fn test_apply_address_response_updates_stored_addresses() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let peer = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK").unwrap();
+        let user_id = db::create_user(db.clone(), peer.to_string(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+
+        let addresses = vec![
+            format!("/ip4/203.0.113.7/tcp/5000/p2p/{peer}"),
+            "not-a-multiaddr".to_string(),
+            "/ip4/198.51.100.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA/p2p-circuit".to_string()
+        ];
+
+        let stored = EventHandler::apply_address_response(db.clone(), peer, addresses).expect("apply_address_response failed");
+
+        assert_eq!(stored, 2);
+
+        let user = db::fetch_user_by_id(db.clone(), user_id).unwrap();
+        assert_eq!(user.multiaddr, "/ip4/203.0.113.7/tcp/5000");
+
+        let stored_addresses = db::fetch_user_addresses(db, user_id).unwrap()
+            .into_iter()
+            .map(|address| address.multiaddr)
+            .collect::<Vec<String>>();
+        assert!(stored_addresses.contains(&"/ip4/203.0.113.7/tcp/5000".to_string()));
+        assert!(stored_addresses.contains(&"/ip4/198.51.100.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA/p2p-circuit".to_string()));
+    }
+
+    #[test]
+    pub fn test_store_friend_request_addresses_stores_every_advertised_address() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let peer = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK").unwrap();
+        let user_id = db::create_user(db.clone(), peer.to_string(), "/ip4/127.0.0.1/tcp/53211".into(), false).unwrap();
+
+        let relay_circuit = format!("/ip4/198.51.100.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA/p2p-circuit/p2p/{peer}");
+        let listen_addresses = vec![
+            "/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap(),
+            "/ip4/192.168.1.20/tcp/4001".parse::<Multiaddr>().unwrap()
+        ];
+        let addresses = advertised_addresses(&relay_circuit, &listen_addresses);
+        assert_eq!(addresses, vec![relay_circuit.clone(), "/ip4/192.168.1.20/tcp/4001".to_string()]);
+
+        let request = FriendRequest::new(0, peer.to_string(), relay_circuit, PeerId::random().to_string(), "/ip4/10.0.0.1/tcp/4001".into(), "Hi".into(), 0, true, false)
+            .with_addresses(addresses);
+
+        assert_eq!(EventHandler::store_friend_request_addresses(db.clone(), peer, &request).expect("store_friend_request_addresses failed"), 2);
+
+        let stored_addresses = db::fetch_user_addresses(db.clone(), user_id).unwrap()
+            .into_iter()
+            .map(|address| address.multiaddr)
+            .collect::<Vec<String>>();
+        assert_eq!(stored_addresses.len(), 2);
+        assert!(stored_addresses.contains(&"/ip4/198.51.100.1/tcp/4001/p2p/12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA/p2p-circuit".to_string()));
+        assert!(stored_addresses.contains(&"/ip4/192.168.1.20/tcp/4001".to_string()));
+    }
+
+    #[test]
+    pub fn test_record_connected_peer_updates_reconnecting_friend_address() {
+        use libp2p::core::{Endpoint, transport::PortUse};
+        use libp2p_core::connection::ConnectedPoint;
+
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let peer = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK").unwrap();
+        let dialer = |address: &str| ConnectedPoint::Dialer { address: address.parse().unwrap(), role_override: Endpoint::Dialer, port_use: PortUse::Reuse };
+
+        EventHandler::record_connected_peer(db.clone(), peer, &dialer("/ip4/203.0.113.7/tcp/4001"), true).unwrap();
+        let user = db::fetch_user_by_peer_id(db.clone(), peer.to_string()).unwrap();
+        assert_eq!(user.multiaddr, "/ip4/203.0.113.7/tcp/4001");
+
+        EventHandler::record_connected_peer(db.clone(), peer, &dialer(&format!("/ip4/198.51.100.9/tcp/5000/p2p/{peer}")), true).unwrap();
+        let user = db::fetch_user_by_peer_id(db.clone(), peer.to_string()).unwrap();
+        assert_eq!(user.multiaddr, "/ip4/198.51.100.9/tcp/5000");
+        assert_eq!(db::fetch_all_users(db.clone()).unwrap().len(), 1);
+
+        let stored_addresses = db::fetch_user_addresses(db.clone(), user.id).unwrap();
+        assert_eq!(stored_addresses[0].multiaddr, "/ip4/198.51.100.9/tcp/5000");
+
+        // An inbound connection's source port isn't dialable, so it never replaces the address.
+        let listener = ConnectedPoint::Listener { local_addr: "/ip4/0.0.0.0/tcp/4001".parse().unwrap(), send_back_addr: "/ip4/192.0.2.1/tcp/61234".parse().unwrap() };
+        EventHandler::record_connected_peer(db.clone(), peer, &listener, true).unwrap();
+        assert_eq!(db::fetch_user_by_peer_id(db.clone(), peer.to_string()).unwrap().multiaddr, "/ip4/198.51.100.9/tcp/5000");
+
+        db::set_auto_update_friend_addresses(db.clone(), false).unwrap();
+        EventHandler::record_connected_peer(db.clone(), peer, &dialer("/ip4/203.0.113.50/tcp/4001"), true).unwrap();
+        assert_eq!(db::fetch_user_by_peer_id(db, peer.to_string()).unwrap().multiaddr, "/ip4/198.51.100.9/tcp/5000");
+    }
+
+    #[test]
+    pub fn test_shareable_addresses_excludes_loopback() {
+        let listen_addresses = vec![
+            "/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap(),
+            "/ip4/192.168.1.20/tcp/4001".parse::<Multiaddr>().unwrap()
+        ];
+
+        assert_eq!(shareable_addresses(&listen_addresses), vec!["/ip4/192.168.1.20/tcp/4001".to_string()]);
+    }
+}
@@ -0,0 +1,91 @@
+use libp2p::{PeerId, gossipsub};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// What `ExplicitPeers::resync` changed.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplicitPeersResync {
+    pub added: Vec<String>,
+    pub removed: Vec<String>
+}
+
+/// The peers we've made explicit gossipsub peers. Gossipsub doesn't expose its own set, so
+/// every change goes through here to keep a record that can be compared to the friend list.
+#[derive(Default)]
+pub struct ExplicitPeers {
+    peers: HashSet<PeerId>
+}
+
+impl ExplicitPeers {
+    pub fn add(&mut self, gossipsub: &mut gossipsub::Behaviour, peer: &PeerId) {
+        gossipsub.add_explicit_peer(peer);
+        self.peers.insert(*peer);
+    }
+
+    pub fn remove(&mut self, gossipsub: &mut gossipsub::Behaviour, peer: &PeerId) {
+        gossipsub.remove_explicit_peer(peer);
+        self.peers.remove(peer);
+    }
+
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.peers.iter().cloned().collect()
+    }
+
+    /// Makes every friend an explicit peer and removes everyone else. Friends are added again
+    /// even if already recorded, in case gossipsub's own set has drifted from ours.
+    pub fn resync(&mut self, gossipsub: &mut gossipsub::Behaviour, friend_list: &[PeerId]) -> ExplicitPeersResync {
+        let mut resync = ExplicitPeersResync::default();
+
+        let strangers = self.peers.iter()
+            .filter(|peer| !friend_list.contains(peer))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for peer in strangers {
+            self.remove(gossipsub, &peer);
+            resync.removed.push(peer.to_string());
+        }
+
+        for peer in friend_list {
+            if !self.peers.contains(peer) {
+                resync.added.push(peer.to_string());
+            }
+
+            self.add(gossipsub, peer);
+        }
+
+        resync
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    #[test]
+    pub fn test_resync_reconciles_desynced_explicit_peers() {
+        let keypair = Keypair::generate_ed25519();
+        let local_peer_id = PeerId::from(keypair.public());
+        let (mut behaviour, _) = crate::p2p::config::create_swarm_behaviour(&keypair, local_peer_id, libp2p::ping::Config::new()).unwrap();
+
+        let mut explicit_peers = ExplicitPeers::default();
+        let friend = PeerId::random();
+        let new_friend = PeerId::random();
+        let former_friend = PeerId::random();
+
+        explicit_peers.add(&mut behaviour.gossipsub, &friend);
+        explicit_peers.add(&mut behaviour.gossipsub, &former_friend);
+
+        let resync = explicit_peers.resync(&mut behaviour.gossipsub, &[friend, new_friend]);
+
+        assert_eq!(resync.added, vec![new_friend.to_string()]);
+        assert_eq!(resync.removed, vec![former_friend.to_string()]);
+
+        let peers = explicit_peers.peers().into_iter().collect::<HashSet<_>>();
+        assert_eq!(peers, HashSet::from([friend, new_friend]));
+
+        assert_eq!(explicit_peers.resync(&mut behaviour.gossipsub, &[friend, new_friend]), ExplicitPeersResync::default());
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use libp2p::{PeerId, dcutr};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum HolepunchStatus {
+    /// No relayed connection to the peer has been seen, so DCUtR has nothing to upgrade.
+    NotAttempted,
+    /// Connected over a relay and waiting for DCUtR to report a result.
+    Pending,
+    Upgraded,
+    Failed { error: String }
+}
+
+/// Tracks the DCUtR outcome for each peer. DCUtR starts an upgrade on its own whenever a
+/// relayed connection is established, so entries follow the peer's connection lifecycle.
+#[derive(Default)]
+pub struct HolepunchTracker {
+    statuses: HashMap<PeerId, HolepunchStatus>
+}
+
+impl HolepunchTracker {
+    pub fn status(&self, peer_id: &PeerId) -> HolepunchStatus {
+        self.statuses.get(peer_id).cloned().unwrap_or(HolepunchStatus::NotAttempted)
+    }
+
+    pub fn handle_connection_established(&mut self, peer_id: PeerId, is_relayed: bool) {
+        if is_relayed && self.status(&peer_id) != HolepunchStatus::Upgraded {
+            self.statuses.insert(peer_id, HolepunchStatus::Pending);
+        }
+    }
+
+    /// Forgets the peer once its last connection has closed.
+    pub fn handle_connection_closed(&mut self, peer_id: &PeerId, num_established: u32) {
+        if num_established == 0 {
+            self.statuses.remove(peer_id);
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &dcutr::Event) -> HolepunchStatus {
+        let status = match &event.result {
+            Ok(_) => HolepunchStatus::Upgraded,
+            Err(err) => HolepunchStatus::Failed { error: err.to_string() }
+        };
+
+        self.statuses.insert(event.remote_peer_id, status.clone());
+        status
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::swarm::ConnectionId;
+
+    #[test]
+    pub fn test_holepunch_tracker_tracks_upgrade_lifecycle() {
+        let mut tracker = HolepunchTracker::default();
+        let peer = PeerId::random();
+
+        assert_eq!(tracker.status(&peer), HolepunchStatus::NotAttempted);
+
+        tracker.handle_connection_established(peer, false);
+        assert_eq!(tracker.status(&peer), HolepunchStatus::NotAttempted);
+
+        tracker.handle_connection_established(peer, true);
+        assert_eq!(tracker.status(&peer), HolepunchStatus::Pending);
+
+        let status = tracker.handle_event(&dcutr::Event { remote_peer_id: peer, result: Ok(ConnectionId::new_unchecked(1)) });
+        assert_eq!(status, HolepunchStatus::Upgraded);
+        assert_eq!(tracker.status(&peer), HolepunchStatus::Upgraded);
+
+        // A further relayed connection doesn't hide a successful upgrade.
+        tracker.handle_connection_established(peer, true);
+        assert_eq!(tracker.status(&peer), HolepunchStatus::Upgraded);
+
+        tracker.handle_connection_closed(&peer, 1);
+        assert_eq!(tracker.status(&peer), HolepunchStatus::Upgraded);
+
+        tracker.handle_connection_closed(&peer, 0);
+        assert_eq!(tracker.status(&peer), HolepunchStatus::NotAttempted);
+    }
+}
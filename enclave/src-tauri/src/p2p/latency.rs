@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use libp2p::{PeerId, request_response::OutboundRequestId};
+use tokio::sync::oneshot::Sender;
+
+/// Offsets beyond this are reported to the UI, as they're large enough to reorder messages.
+pub const CLOCK_SKEW_WARNING_MS: i64 = 60_000;
+
+/// What a probe was sent to measure.
+pub enum ProbeReply {
+    Latency(Sender<anyhow::Result<u64>>),
+    ClockSkew(Sender<anyhow::Result<i64>>)
+}
+
+struct PendingProbe {
+    peer: PeerId,
+    started: Instant,
+    sent_at: i64,
+    reply: ProbeReply
+}
+
+/// How far `peer_time` is ahead of our clock, assuming the peer read its clock halfway
+/// through the round trip. All times are unix milliseconds.
+pub fn clock_skew(sent_at: i64, received_at: i64, peer_time: i64) -> i64 {
+    peer_time - (sent_at + (received_at - sent_at) / 2)
+}
+
+/// Tracks outstanding `LatencyProbe`s by nonce. The round trip is timed locally, so it
+/// includes request-response and codec overhead on both ends, unlike the libp2p ping.
+#[derive(Default)]
+pub struct LatencyTracker {
+    pending: HashMap<u64, PendingProbe>,
+    requests: HashMap<OutboundRequestId, u64>
+}
+
+impl LatencyTracker {
+    /// Registers a probe to `peer` and returns the nonce to send with it.
+    pub fn start(&mut self, peer: PeerId, sender: Sender<anyhow::Result<u64>>, now: Instant) -> u64 {
+        self.start_probe(peer, ProbeReply::Latency(sender), now, chrono::Utc::now().timestamp_millis())
+    }
+
+    /// Registers a probe to `peer` sent at `sent_at` unix milliseconds, returning its nonce.
+    pub fn start_probe(&mut self, peer: PeerId, reply: ProbeReply, now: Instant, sent_at: i64) -> u64 {
+        let mut nonce = rand::random::<u64>();
+        while self.pending.contains_key(&nonce) {
+            nonce = rand::random::<u64>();
+        }
+
+        self.pending.insert(nonce, PendingProbe { peer, started: now, sent_at, reply });
+        nonce
+    }
+
+    pub fn track_request(&mut self, request_id: OutboundRequestId, nonce: u64) {
+        self.requests.insert(request_id, nonce);
+    }
+
+    /// Resolves the probe with the round trip, or with the peer's clock skew if that's what
+    /// it measured, both in milliseconds. Replies with an unknown nonce or from a peer other
+    /// than the one probed are ignored and `None` is returned; otherwise the skew is returned
+    /// whenever the peer reported its clock.
+    pub fn complete_probe(&mut self, peer: PeerId, nonce: u64, now: Instant, received_at: i64, peer_time: Option<i64>) -> Option<Option<i64>> {
+        if self.pending.get(&nonce).is_none_or(|probe| probe.peer != peer) {
+            return None;
+        }
+
+        let probe = self.pending.remove(&nonce)?;
+        self.requests.retain(|_, pending_nonce| *pending_nonce != nonce);
+
+        let skew = peer_time.map(|peer_time| clock_skew(probe.sent_at, received_at, peer_time));
+
+        match probe.reply {
+            ProbeReply::Latency(sender) => {
+                let round_trip = now.saturating_duration_since(probe.started).as_millis() as u64;
+                let _ = sender.send(Ok(round_trip));
+            },
+            ProbeReply::ClockSkew(sender) => {
+                let _ = sender.send(skew.ok_or_else(|| anyhow::anyhow!("Peer {peer} didn't report its clock")));
+            }
+        }
+
+        Some(skew)
+    }
+
+    /// Fails the probe carried by `request_id`, if any.
+    pub fn fail(&mut self, request_id: &OutboundRequestId, error: String) {
+        if let Some(probe) = self.requests.remove(request_id).and_then(|nonce| self.pending.remove(&nonce)) {
+            match probe.reply {
+                ProbeReply::Latency(sender) => { let _ = sender.send(Err(anyhow::anyhow!(error))); },
+                ProbeReply::ClockSkew(sender) => { let _ = sender.send(Err(anyhow::anyhow!(error))); }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn complete(tracker: &mut LatencyTracker, peer: PeerId, nonce: u64, now: Instant) -> bool {
+        tracker.complete_probe(peer, nonce, now, 0, None).is_some()
+    }
+
+    #[test]
+    pub fn test_latency_tracker_matches_reply_by_nonce() {
+        let mut tracker = LatencyTracker::default();
+        let peer = PeerId::random();
+        let started = Instant::now();
+
+        let (first_sender, mut first_receiver) = tokio::sync::oneshot::channel();
+        let (second_sender, mut second_receiver) = tokio::sync::oneshot::channel();
+        let first = tracker.start(peer, first_sender, started);
+        let second = tracker.start(peer, second_sender, started);
+        assert_ne!(first, second);
+
+        let unknown = (0..3).find(|nonce| *nonce != first && *nonce != second).unwrap();
+        assert!(!complete(&mut tracker, peer, unknown, started));
+        assert!(complete(&mut tracker, peer, second, started + Duration::from_millis(42)));
+
+        assert_eq!(second_receiver.try_recv().expect("probe unresolved").expect("probe failed"), 42);
+        assert!(first_receiver.try_recv().is_err());
+
+        assert!(!complete(&mut tracker, peer, second, started + Duration::from_millis(50)));
+    }
+
+    #[test]
+    pub fn test_latency_tracker_ignores_reply_from_other_peer() {
+        let mut tracker = LatencyTracker::default();
+        let peer = PeerId::random();
+        let started = Instant::now();
+
+        let (sender, mut receiver) = tokio::sync::oneshot::channel();
+        let nonce = tracker.start(peer, sender, started);
+
+        assert!(!complete(&mut tracker, PeerId::random(), nonce, started));
+        assert!(receiver.try_recv().is_err());
+
+        assert!(complete(&mut tracker, peer, nonce, started));
+        assert_eq!(receiver.try_recv().expect("probe unresolved").expect("probe failed"), 0);
+    }
+
+    #[test]
+    pub fn test_clock_skew_from_probe_with_known_time_difference() {
+        // Sent at t=1000, replied 200ms later; the peer's clock reads 5 minutes ahead at the midpoint.
+        assert_eq!(clock_skew(1_000, 1_200, 1_100 + 300_000), 300_000);
+        assert_eq!(clock_skew(1_000, 1_200, 1_100 - 2_500), -2_500);
+
+        let mut tracker = LatencyTracker::default();
+        let peer = PeerId::random();
+        let started = Instant::now();
+
+        let (sender, mut receiver) = tokio::sync::oneshot::channel();
+        let nonce = tracker.start_probe(peer, ProbeReply::ClockSkew(sender), started, 1_000);
+
+        assert_eq!(tracker.complete_probe(peer, nonce, started + Duration::from_millis(200), 1_200, Some(1_100 + 90_000)), Some(Some(90_000)));
+        assert_eq!(receiver.try_recv().expect("probe unresolved").expect("probe failed"), 90_000);
+
+        let (sender, mut receiver) = tokio::sync::oneshot::channel();
+        let nonce = tracker.start_probe(peer, ProbeReply::ClockSkew(sender), started, 1_000);
+
+        assert_eq!(tracker.complete_probe(peer, nonce, started, 1_200, None), Some(None));
+        assert!(receiver.try_recv().expect("probe unresolved").is_err());
+    }
+}
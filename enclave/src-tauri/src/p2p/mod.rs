@@ -1,67 +1,94 @@
+pub mod address_probe;
+pub mod block_list;
+pub mod channel;
 pub mod command_handler;
 pub mod config;
+pub mod connection;
+pub mod connection_policy;
+pub mod crypto;
 pub mod event_handler;
+pub mod explicit_peers;
+pub mod holepunch;
+pub mod latency;
+#[cfg(feature = "net-sim")]
+pub mod net_sim;
 pub mod node;
+pub mod protocol;
+pub mod rate_limit;
+#[cfg(feature = "debug-events")]
+pub mod recent_events;
+pub mod relay;
+pub mod sanitize;
+pub mod session_stats;
+pub mod startup;
+pub mod sync;
+pub mod topic_mesh;
+pub mod trace;
+pub mod transcript;
 pub mod types;
+pub mod watchdog;
 
-use libp2p::{Multiaddr, PeerId, Transport, futures::StreamExt, swarm::SwarmEvent};
+use libp2p::{Multiaddr, PeerId, futures::StreamExt, request_response::OutboundRequestId, swarm::SwarmEvent};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::str::FromStr;
 use tokio::sync::{mpsc, Mutex};
-use crate::{db::{self, models::{direct_message::DirectMessage, friend_request::FriendRequest, post::Post, user::User}}, p2p::types::{SynchRequest, SynchResponse}};
+use crate::{db::{self, models::{direct_message::DirectMessage, post::Post, user::User}}, p2p::types::{SynchRequest, SynchResponse}};
 
+use address_probe::AddressProber;
 use config::{NetworkConfig, create_swarm_behaviour};
 use event_handler::EventHandler;
+use explicit_peers::ExplicitPeers;
 use command_handler::CommandHandler;
+use channel::EventSender;
+use connection::ConnectionTracker;
+use connection_policy::ConnectionGate;
+use holepunch::HolepunchTracker;
+use latency::LatencyTracker;
+use protocol::PeerProtocols;
+use topic_mesh::TopicSubscribers;
+use trace::PeerTracer;
+use rate_limit::RateLimiter;
+use relay::RelayConnection;
+use sync::SyncScheduler;
+use watchdog::Heartbeat;
 use types::{SwarmCommand};
 
-pub use types::{P2PMessage, P2PEvent, MyInfo};
+/// How often direct messages past their conversation's retention are deleted.
+const RETENTION_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+pub use types::{P2PMessage, P2PEvent, ErrorSeverity, FriendProfile, MyInfo, IdentityInfo, InitialState, PendingWork};
 pub use node::P2PNode;
+pub use connection::ConnectionType;
+pub use holepunch::HolepunchStatus;
+pub use relay::RelayInfo;
+pub use address_probe::AddressProbeResult;
+pub use sync::SyncSummary;
 
 impl P2PNode {
-    pub async fn new(relay_address: Option<String>) -> anyhow::Result<(Self, mpsc::UnboundedReceiver<P2PEvent>)> {
-        let config = NetworkConfig::load_or_create()?;
+    pub async fn new(relay_address: Option<String>, namespace: Option<String>) -> anyhow::Result<(Self, mpsc::Receiver<P2PEvent>)> {
+        let config = NetworkConfig::load_or_create(namespace)?;
         log::info!("Local peer id: {}", config.peer_id);
 
-        let (behaviour, relay_transport) = create_swarm_behaviour(&config.keypair, config.peer_id)?;
+        let (behaviour, relay_transport) = create_swarm_behaviour(&config.keypair, config.peer_id, config.ping_config())?;
         
-        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(config.keypair.clone())
-            .with_tokio()
-            .with_tcp(
-                libp2p::tcp::Config::default(),
-                libp2p::noise::Config::new,
-                libp2p::yamux::Config::default,
-            )?
-            .with_other_transport(|key| {
-                relay_transport
-                    .upgrade(libp2p::core::upgrade::Version::V1)
-                    .authenticate(libp2p::noise::Config::new(key).unwrap())
-                    .multiplex(libp2p::yamux::Config::default())
-            })
-            .map_err(|err| anyhow::anyhow!("Error adding relay transport: {err}"))?
-            .with_behaviour(|_| behaviour)
-            .map_err(|err| anyhow::anyhow!("Error adding behaviour: {err}"))?
-            .with_swarm_config(|c| {
-                c.with_idle_connection_timeout(std::time::Duration::from_secs(u64::MAX))
-            })
-            .build();
+        let mut swarm = config::build_swarm(config.keypair.clone(), behaviour, relay_transport)?;
 
         swarm.listen_on(format!("/ip4/0.0.0.0/tcp/{}", config.port).parse()?)?;
 
-        let topic = libp2p::gossipsub::IdentTopic::new("enclave-posts");
-        swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+        let posts_topic = config.posts_topic();
+        let network_config = config.info();
+        log::info!("Subscribing to topic: {posts_topic}");
+        swarm.behaviour_mut().gossipsub.subscribe(&posts_topic)?;
 
-        let (event_sender, event_receiver) = mpsc::unbounded_channel();
-        let (swarm_sender, swarm_receiver) = mpsc::unbounded_channel::<SwarmCommand>();
+        let (event_sender, event_receiver) = channel::event_channel(config.event_channel_capacity);
+        let (swarm_sender, swarm_receiver) = mpsc::channel::<SwarmCommand>(config.command_channel_capacity);
 
         let listen_addresses = Arc::new(Mutex::new(Vec::new()));
         let relay_addr = Arc::new(Mutex::new(None));
 
         if let Some(relay_str) = relay_address {
             if let Ok(addr) = relay_str.parse::<Multiaddr>() {
-                log::info!("Connecting to relay: {}", addr);
-                swarm.dial(addr.clone())?;
                 *relay_addr.lock().await = Some(addr);
             }
         }
@@ -77,21 +104,31 @@ impl P2PNode {
         };
         listen_addresses.lock().await.push(first_address);
         
+        let current_timestamp = chrono::Utc::now().timestamp();
+        let mut sync_scheduler = SyncScheduler::new(true);
+
         if let Ok(identity_data) = db::fetch_identity(db::DATABASE.clone()) {
-            friend_synch(identity_data.last_login, &mut swarm, &event_sender);
+            if identity_data.sync_enabled {
+                friend_synch(&mut swarm, &mut sync_scheduler, &event_sender);
+            } else {
+                log::info!("Post sync is paused, skipping startup sync");
+                sync_scheduler = SyncScheduler::new(false);
+            }
 
-            let current_timestamp = chrono::Utc::now().timestamp();
-            db::update_identity(db::DATABASE.clone(), identity_data.id, Some(current_timestamp))?;
+            db::update_identity(db::DATABASE.clone(), identity_data.id, Some(current_timestamp), None)?;
         }
 
-        spawn_event_loop(
-            swarm,
-            swarm_receiver,
-            event_sender.clone(),
+        let state = LoopState::new(
             listen_addresses.clone(),
             relay_addr.clone(),
-        )
-        .await;
+            posts_topic,
+            swarm_sender.clone(),
+            sync_scheduler,
+            config.direct_message_rate_limiter(),
+            &event_sender
+        );
+
+        spawn_event_loop(swarm, swarm_receiver, event_sender.clone(), state).await;
 
         log::info!("Finished starting P2P node.");
 
@@ -102,59 +139,127 @@ impl P2PNode {
                 listen_addresses,
                 relay_address: relay_addr,
                 swarm_sender,
+                network_config,
             },
             event_receiver,
         ))
     }
 }
 
-async fn spawn_event_loop(
-    mut swarm: libp2p::Swarm<config::EnclaveNetworkBehaviour>,
-    mut swarm_receiver: mpsc::UnboundedReceiver<SwarmCommand>,
-    event_sender: mpsc::UnboundedSender<P2PEvent>,
+/// Everything the event loop owns and hands to the swarm event and command handlers.
+struct LoopState {
+    friend_list: Vec<PeerId>,
+    direct_messages: HashMap<PeerId, Vec<DirectMessage>>,
+    displayed_posts: Vec<Post>,
+    pending_responses: HashMap<PeerId, P2PMessage>,
+    pending_deliveries: HashMap<OutboundRequestId, i64>,
+    /// Buffered post deliveries awaiting their ack, by the friend and post they're for.
+    pending_buffered_posts: HashMap<OutboundRequestId, (PeerId, i64)>,
+    relay_connection: RelayConnection,
+    sync_scheduler: SyncScheduler,
+    direct_message_limiter: RateLimiter,
+    holepunch_tracker: HolepunchTracker,
+    connection_tracker: ConnectionTracker,
+    latency_tracker: LatencyTracker,
+    peer_protocols: PeerProtocols,
+    connection_gate: ConnectionGate,
+    topic_subscribers: TopicSubscribers,
+    address_prober: AddressProber,
+    peer_tracer: PeerTracer,
+    explicit_peers: ExplicitPeers,
     listen_addresses: Arc<Mutex<Vec<Multiaddr>>>,
     relay_addr: Arc<Mutex<Option<Multiaddr>>>,
+    posts_topic: libp2p::gossipsub::IdentTopic,
+    command_sender: mpsc::Sender<SwarmCommand>,
+}
+
+impl LoopState {
+    fn new(
+        listen_addresses: Arc<Mutex<Vec<Multiaddr>>>,
+        relay_addr: Arc<Mutex<Option<Multiaddr>>>,
+        posts_topic: libp2p::gossipsub::IdentTopic,
+        command_sender: mpsc::Sender<SwarmCommand>,
+        sync_scheduler: SyncScheduler,
+        direct_message_limiter: RateLimiter,
+        event_sender: &EventSender
+    ) -> Self {
+        Self {
+            friend_list: load_friend_list(event_sender),
+            direct_messages: HashMap::new(),
+            displayed_posts: Vec::new(),
+            pending_responses: HashMap::new(),
+            pending_deliveries: HashMap::new(),
+            pending_buffered_posts: HashMap::new(),
+            relay_connection: RelayConnection::default(),
+            sync_scheduler,
+            direct_message_limiter,
+            holepunch_tracker: HolepunchTracker::default(),
+            connection_tracker: ConnectionTracker::default(),
+            latency_tracker: LatencyTracker::default(),
+            peer_protocols: PeerProtocols::default(),
+            connection_gate: ConnectionGate::new(load_connection_policy(event_sender)),
+            topic_subscribers: TopicSubscribers::default(),
+            address_prober: AddressProber::default(),
+            peer_tracer: PeerTracer::default(),
+            explicit_peers: ExplicitPeers::default(),
+            listen_addresses,
+            relay_addr,
+            posts_topic,
+            command_sender,
+        }
+    }
+}
+
+async fn spawn_event_loop(
+    mut swarm: libp2p::Swarm<config::EnclaveNetworkBehaviour>,
+    mut swarm_receiver: mpsc::Receiver<SwarmCommand>,
+    event_sender: EventSender,
+    mut state: LoopState
 ) {
+    let heartbeat = Heartbeat::new(std::time::Instant::now());
+    let watchdog = watchdog::spawn_watchdog(heartbeat.clone(), event_sender.clone());
+
     tokio::spawn(async move {
-        let mut friend_list = load_friend_list(&event_sender);
-        let inbound_friend_requests = match db::fetch_friend_requests_to_peer(db::DATABASE.clone(), swarm.local_peer_id().to_string()) {
-            Ok(r) => r,
-            Err(_) => vec![]
-        };
-        let mut direct_messages = HashMap::new();
-        let mut displayed_posts = Vec::new();
-        let mut pending_friend_request_responses = HashMap::new();
+        let _watchdog = watchdog;
+        let initial_relay_addr = state.relay_addr.lock().await.clone();
+        if let Some(address) = initial_relay_addr {
+            state.relay_connection.dial(address, &mut swarm, &state.command_sender, &event_sender);
+        }
+        #[cfg(feature = "net-sim")]
+        let mut network_simulator = net_sim::NetworkSimulator::default();
 
         let mut event_handler = EventHandler::new(event_sender.clone());
+        let mut heartbeat_interval = tokio::time::interval(watchdog::HEARTBEAT_INTERVAL);
+        let mut sync_interval = tokio::time::interval_at(tokio::time::Instant::now() + sync::SYNC_INTERVAL, sync::SYNC_INTERVAL);
+        let mut retention_interval = tokio::time::interval(RETENTION_CLEANUP_INTERVAL);
 
         loop {
+            heartbeat.beat(std::time::Instant::now());
+
             tokio::select! {
+                _ = heartbeat_interval.tick() => {},
+                _ = sync_interval.tick() => {
+                    if state.sync_scheduler.is_enabled() {
+                        friend_synch(&mut swarm, &mut state.sync_scheduler, &event_sender);
+                    }
+                },
+                _ = retention_interval.tick() => {
+                    expire_direct_messages(&mut state.direct_messages, &event_sender);
+                },
                 event = swarm.select_next_some() => {
-                    handle_swarm_event(
-                        event,
-                        &mut friend_list,
-                        &mut direct_messages,
-                        &mut displayed_posts,
-                        &mut pending_friend_request_responses,
-                        &mut event_handler,
-                        &mut swarm,
-                        &listen_addresses,
-                    )
-                    .await;
+                    state.peer_tracer.trace_swarm_event(&event);
+
+                    handle_swarm_event(event, &mut state, &mut event_handler, &mut swarm).await;
                 },
                 Some(cmd) = swarm_receiver.recv() => {
-                    handle_swarm_command(
-                        cmd,
-                        &mut friend_list,
-                        &inbound_friend_requests,
-                        &mut pending_friend_request_responses,
-                        &mut direct_messages,
-                        &mut swarm,
-                        &listen_addresses,
-                        &relay_addr,
-                        &event_sender,
-                    )
-                    .await;
+                    #[cfg(feature = "net-sim")]
+                    let Some(cmd) = network_simulator.intercept(cmd, swarm.local_peer_id(), &state.command_sender, &event_sender) else {
+                        continue;
+                    };
+
+                    state.peer_tracer.trace_command(&cmd);
+
+                    handle_swarm_command(cmd, &mut state, &mut swarm, &event_sender).await;
                 }
             }
         }
@@ -163,22 +268,56 @@ async fn spawn_event_loop(
 
 async fn handle_swarm_event(
     event: SwarmEvent<config::EnclaveNetworkBehaviourEvent>,
-    friend_list: &mut Vec<PeerId>,
-    direct_messages: &mut HashMap<PeerId, Vec<DirectMessage>>,
-    displayed_posts: &mut Vec<Post>,
-    pending_responses: &mut HashMap<PeerId, P2PMessage>,
+    state: &mut LoopState,
     event_handler: &mut EventHandler,
-    swarm: &mut libp2p::Swarm<config::EnclaveNetworkBehaviour>,
-    listen_addresses: &Arc<Mutex<Vec<Multiaddr>>>
+    swarm: &mut libp2p::Swarm<config::EnclaveNetworkBehaviour>
 ) {
     use config::EnclaveNetworkBehaviourEvent;
+
+    let LoopState {
+        friend_list,
+        direct_messages,
+        displayed_posts,
+        pending_responses,
+        pending_deliveries,
+        pending_buffered_posts,
+        relay_connection,
+        sync_scheduler,
+        holepunch_tracker,
+        connection_tracker,
+        latency_tracker,
+        peer_protocols,
+        connection_gate,
+        topic_subscribers,
+        address_prober,
+        explicit_peers,
+        listen_addresses,
+        posts_topic,
+        command_sender,
+        ..
+    } = state;
     
     match event {
         SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Gossipsub(gossip_event)) => {
-            if let libp2p::gossipsub::Event::Message { propagation_source, message, .. } = gossip_event {
-                if let Ok(post) = serde_json::from_slice::<Post>(&message.data) {
-                    event_handler.handle_post(propagation_source, post, friend_list, displayed_posts);
-                }
+            match gossip_event {
+                libp2p::gossipsub::Event::Message { propagation_source, message, .. } => {
+                    if let Ok(post) = serde_json::from_slice::<Post>(&message.data) {
+                        event_handler.handle_post(propagation_source, post, friend_list, displayed_posts);
+                    }
+                },
+                libp2p::gossipsub::Event::Subscribed { peer_id, topic } => {
+                    log::info!("{peer_id} subscribed to {topic}");
+                    topic_subscribers.handle_subscribed(peer_id, topic);
+                },
+                libp2p::gossipsub::Event::Unsubscribed { peer_id, topic } => {
+                    log::info!("{peer_id} unsubscribed from {topic}");
+                    topic_subscribers.handle_unsubscribed(&peer_id, &topic);
+
+                    if topic == posts_topic.hash() && friend_list.contains(&peer_id) {
+                        log::warn!("Friend {peer_id} left {topic}, posts will not propagate to them");
+                    }
+                },
+                _ => {}
             }
         },
         SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::RequestResponse(req_event)) => {
@@ -187,25 +326,79 @@ async fn handle_swarm_event(
             match req_event {
                 reqres::Event::Message { peer, message, .. } => {
                     if let reqres::Message::Request { request, channel, .. } = message {
+                        if !connection_gate.admit_message(&peer, &request, friend_list) {
+                            log::warn!("Disconnecting {peer}: only friends may connect and it sent something other than a friend request");
+                            let _ = swarm.disconnect_peer_id(peer);
+                            return;
+                        }
+
+                        peer_protocols.observe(peer, &request);
+
                         match request {
                             P2PMessage::FriendRequest(req) => {
-                                event_handler.handle_friend_request(peer, req, swarm);
+                                event_handler.handle_friend_request(peer, req, swarm, channel);
+                            },
+                            P2PMessage::FriendRequestAck => {
+                                event_handler.handle_friend_request_ack(peer);
                             },
                             P2PMessage::FriendRequestResponse(response) => {
-                                event_handler.handle_friend_request_response(peer, response, friend_list, swarm);
+                                event_handler.handle_friend_request_response(peer, response, friend_list, explicit_peers, swarm);
                             },
                             P2PMessage::DirectMessage(msg) => {
-                                event_handler.handle_direct_message(msg, friend_list, direct_messages);
+                                event_handler.handle_direct_message(peer, msg, friend_list, direct_messages);
+                            },
+                            P2PMessage::NicknameUpdate { nickname } => {
+                                event_handler.handle_nickname_update(peer, nickname, friend_list);
                             },
                             P2PMessage::SynchRequest(SynchRequest{ since, sender }) => {
                                 event_handler.handle_synch_request(since, sender, swarm, channel);
                             },
+                            P2PMessage::AddressRequest => {
+                                let addresses = listen_addresses.lock().await.clone();
+                                event_handler.handle_address_request(peer, friend_list, &addresses, swarm, channel);
+                            },
+                            P2PMessage::BufferedPost(post) => {
+                                event_handler.handle_buffered_post(peer, post, friend_list, displayed_posts, swarm, channel);
+                            },
+                            P2PMessage::ReadReceipt => {
+                                event_handler.handle_read_receipt(peer, friend_list, swarm.local_peer_id());
+                            },
+                            P2PMessage::LatencyProbe { nonce, sent_at } => {
+                                event_handler.handle_latency_probe(nonce, sent_at, swarm, channel);
+                            },
                             _ => {}
                         }
-                    } else if let reqres::Message::Response { response, .. } = message {
+                    } else if let reqres::Message::Response { request_id, response } = message {
+                        pending_deliveries.remove(&request_id);
+                        let buffered_post = pending_buffered_posts.remove(&request_id);
+                        peer_protocols.observe(peer, &response);
+
                         match response {
-                            P2PMessage::SynchResponse(SynchResponse{ created_posts, edited_posts, sender }) => {
-                                event_handler.handle_synch_response(created_posts, edited_posts, sender);
+                            P2PMessage::SynchResponse(SynchResponse{ created_posts, edited_posts, sender, deleted_posts }) => {
+                                let cancel = sync_scheduler.finish_sync(&peer);
+                                event_handler.handle_synch_response(peer, created_posts, edited_posts, deleted_posts, sender, &cancel).await;
+                            },
+                            P2PMessage::AddressResponse { addresses } => {
+                                event_handler.handle_address_response(peer, addresses);
+                            },
+                            P2PMessage::FriendRequestAck => {
+                                event_handler.handle_friend_request_ack(peer);
+                            },
+                            P2PMessage::BufferedPostAck => {
+                                if let Some((peer, post_id)) = buffered_post {
+                                    event_handler.handle_buffered_post_delivered(peer, post_id);
+                                }
+                            },
+                            P2PMessage::LatencyProbeReply { nonce, replied_at, .. } => {
+                                let received_at = std::time::Instant::now();
+                                match latency_tracker.complete_probe(peer, nonce, received_at, chrono::Utc::now().timestamp_millis(), replied_at) {
+                                    None => log::warn!("Ignoring latency probe reply from {} with unknown nonce", peer),
+                                    Some(Some(offset_ms)) if offset_ms.abs() > latency::CLOCK_SKEW_WARNING_MS => {
+                                        log::warn!("Clock of {} is {}ms off from ours", peer, offset_ms);
+                                        let _ = event_handler.event_sender.send(P2PEvent::ClockSkewDetected { peer, offset_ms });
+                                    },
+                                    Some(_) => {}
+                                }
                             },
                             _ => {}
                         }
@@ -213,6 +406,17 @@ async fn handle_swarm_event(
                 },
                 reqres::Event::OutboundFailure { peer, request_id, error, .. } => {
                     log::error!("Outbound request {:?} to {} failed {:?}", request_id, peer, error);
+                    latency_tracker.fail(&request_id, error.to_string());
+
+                    if let Some((peer, _)) = pending_buffered_posts.remove(&request_id) {
+                        event_handler.handle_buffered_post_failed(peer, &error, peer_protocols);
+                    }
+
+                    if let Some(direct_message_id) = pending_deliveries.remove(&request_id) {
+                        if let reqres::OutboundFailure::UnsupportedProtocols = error {
+                            event_handler.handle_undeliverable_message(direct_message_id, format!("Peer does not support the messaging protocol: {error}"));
+                        }
+                    }
                 },
                 reqres::Event::InboundFailure { peer, request_id, error, .. } => {
                     log::error!("Inbound request {:?} from {} failed {:?}", request_id, peer, error);
@@ -222,50 +426,170 @@ async fn handle_swarm_event(
         },
         SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Ping(event)) => {
             log::info!("Ping event {:?}", event);
+
+            if connection::is_dead_connection(&event.result) {
+                log::warn!("Ping to {} failed, closing connection {:?}", event.peer, event.connection);
+                swarm.close_connection(event.connection);
+            }
         },
         SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::RelayClient(event)) => {
             log::info!("Relay client event: {:?}", event);
+            relay_connection.handle_client_event(&event);
         },
         SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Dcutr(event)) => {
             log::info!("DCUTR event {:?}", event);
+            let status = holepunch_tracker.handle_event(&event);
+            let _ = event_handler.event_sender.send(P2PEvent::HolepunchResult { peer: event.remote_peer_id, status });
         },
         SwarmEvent::NewListenAddr { address, .. } => {
             log::info!("Listening on {address}");
             listen_addresses.lock().await.push(address);
         },
-        SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+        SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+            if address_prober.handle_connection_established(connection_id, std::time::Instant::now()) {
+                log::info!("Address probe reached {peer_id}, closing connection");
+                swarm.close_connection(connection_id);
+                return;
+            }
+
+            if db::is_peer_blocked(db::DATABASE.clone(), peer_id.to_string()).unwrap_or(false) {
+                log::warn!("Closing connection to blocked peer {peer_id}");
+                let _ = swarm.disconnect_peer_id(peer_id);
+                return;
+            }
+
+            if relay_connection.is_relay_dial(connection_id) {
+                relay_connection.handle_connected(peer_id, &event_handler.event_sender);
+            }
+
+            let known = connection_policy::is_known_peer(db::DATABASE.clone(), &peer_id, friend_list).unwrap_or(false);
+            if connection_gate.handle_connection_established(peer_id, endpoint.is_listener(), known, std::time::Instant::now()) {
+                let command_sender = command_sender.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(connection_policy::PROBATION_GRACE_PERIOD).await;
+                    let _ = command_sender.send(SwarmCommand::ExpireProbation(peer_id)).await;
+                });
+            }
+
+            holepunch_tracker.handle_connection_established(peer_id, connection::is_relayed_endpoint(&endpoint));
+
+            if let Some(connection_type) = connection_tracker.handle_connection_established(peer_id, connection_id, &endpoint) {
+                let _ = event_handler.event_sender.send(P2PEvent::ConnectionTypeChanged { peer: peer_id, connection_type });
+            }
+
+            let addresses = listen_addresses.lock().await.clone();
             event_handler
                 .handle_connection_established(
                     peer_id,
                     &endpoint,
+                    &addresses,
+                    friend_list,
                     pending_responses,
+                    pending_deliveries,
                     swarm
                 )
                 .await;
+
+            if let Err(err) = EventHandler::flush_buffered_posts(db::DATABASE.clone(), peer_id, friend_list, peer_protocols, pending_buffered_posts, swarm) {
+                let _ = event_handler.event_sender.send(P2PEvent::Error { context: "flush_buffered_posts", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            }
+
+            if friend_list.contains(&peer_id) && sync_scheduler.is_enabled() {
+                if let Some(since) = friend_sync_cursor(&peer_id, &event_handler.event_sender) {
+                    let sender = swarm.local_peer_id().to_string();
+                    swarm.behaviour_mut().request_response.send_request(
+                        &peer_id,
+                        P2PMessage::SynchRequest(SynchRequest { since, sender })
+                    );
+                    sync_scheduler.start_sync(peer_id);
+                }
+            }
         },
-        SwarmEvent::ConnectionClosed { peer_id, .. } => {
+        SwarmEvent::ConnectionClosed { peer_id, connection_id, num_established, .. } => {
+            if address_prober.handle_connection_closed(connection_id) {
+                return;
+            }
+
             log::info!("Disconnected from peer: {peer_id}");
+            holepunch_tracker.handle_connection_closed(&peer_id, num_established);
+            relay_connection.handle_connection_closed(&peer_id, num_established, command_sender, &event_handler.event_sender);
+
+            if num_established == 0 {
+                peer_protocols.remove(&peer_id);
+                topic_subscribers.remove_peer(&peer_id);
+                sync_scheduler.finish_sync(&peer_id);
+                connection_gate.handle_connection_closed(&peer_id);
+            }
+
+            if let Some(connection_type) = connection_tracker.handle_connection_closed(&peer_id, connection_id) {
+                let _ = event_handler.event_sender.send(P2PEvent::ConnectionTypeChanged { peer: peer_id, connection_type });
+            }
+
             let _ = event_handler.event_sender.send(P2PEvent::PeerDisconnected(peer_id));
         },
+        SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if address_prober.is_probe(connection_id) => {
+            log::info!("Address probe failed: {error}");
+            address_prober.handle_dial_failure(connection_id, error.to_string(), std::time::Instant::now());
+        },
+        SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if relay_connection.is_relay_dial(connection_id) => {
+            log::warn!("Failed to connect to relay: {error}");
+            relay_connection.handle_dial_failure(command_sender, &event_handler.event_sender);
+        },
+        SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. } if friend_list.contains(&peer_id) => {
+            log::warn!("Failed to dial friend {peer_id}: {error}");
+
+            if let libp2p::swarm::DialError::WrongPeerId { obtained, .. } = &error {
+                event_handler.handle_key_change(db::DATABASE.clone(), peer_id, *obtained);
+            }
+
+            // Ask the friend for fresh addresses over the relay, unless that is what just failed.
+            if let libp2p::swarm::DialError::Transport(attempts) = &error {
+                if !attempts.iter().any(|(address, _)| connection::is_relayed_address(address)) {
+                    let _ = command_sender.try_send(SwarmCommand::RefreshPeerAddress(peer_id));
+                }
+            }
+        },
         _ => {}
     }
 }
 
 async fn handle_swarm_command(
     cmd: SwarmCommand,
-    friend_list: &mut Vec<PeerId>,
-    inbound_friend_requests: &Vec<FriendRequest>,
-    pending_responses: &mut HashMap<PeerId, P2PMessage>,
-    direct_messages: &mut HashMap<PeerId, Vec<DirectMessage>>,
+    state: &mut LoopState,
     swarm: &mut libp2p::Swarm<config::EnclaveNetworkBehaviour>,
-    listen_addresses: &Arc<Mutex<Vec<Multiaddr>>>,
-    relay_addr: &Arc<Mutex<Option<Multiaddr>>>,
-    event_sender: &mpsc::UnboundedSender<P2PEvent>
+    event_sender: &EventSender
 ) {
+    let LoopState {
+        friend_list,
+        direct_messages,
+        pending_responses,
+        pending_deliveries,
+        relay_connection,
+        sync_scheduler,
+        direct_message_limiter,
+        holepunch_tracker,
+        connection_tracker,
+        latency_tracker,
+        peer_protocols,
+        connection_gate,
+        topic_subscribers,
+        address_prober,
+        peer_tracer,
+        explicit_peers,
+        listen_addresses,
+        relay_addr,
+        posts_topic,
+        command_sender,
+        ..
+    } = state;
+
     match cmd {
         SwarmCommand::SendPost(content) => {
             CommandHandler::handle_send_post(
                 content,
+                friend_list,
+                peer_protocols,
+                posts_topic,
                 swarm,
                 event_sender
             ).await;
@@ -276,6 +600,8 @@ async fn handle_swarm_command(
                 address, 
                 content, 
                 friend_list, 
+                pending_deliveries,
+                direct_message_limiter,
                 swarm,
                 event_sender
             )
@@ -297,6 +623,7 @@ async fn handle_swarm_command(
             CommandHandler::handle_accept_friend_request(
                 peer,
                 friend_list,
+                explicit_peers,
                 pending_responses,
                 listen_addresses,
                 relay_addr,
@@ -305,9 +632,10 @@ async fn handle_swarm_command(
             )
             .await;
         },
-        SwarmCommand::DenyFriendRequest(peer) => {
+        SwarmCommand::DenyFriendRequest { peer, reason } => {
             CommandHandler::handle_deny_friend_request(
                 peer,
+                reason,
                 swarm,
                 event_sender
             )
@@ -316,14 +644,76 @@ async fn handle_swarm_command(
         SwarmCommand::GetFriendList(sender) => {
             let _ = sender.send(friend_list.clone());
         },
+        SwarmCommand::CountOnlineFriends(sender) => {
+            let _ = sender.send(CommandHandler::count_online_friends(friend_list, |peer_id| swarm.is_connected(peer_id)));
+        },
+        SwarmCommand::GetPendingWork(sender) => {
+            let _ = sender.send(CommandHandler::pending_work(db::DATABASE.clone(), swarm.local_peer_id(), sync_scheduler.active_count()));
+        },
         SwarmCommand::GetInboundFriendRequests(sender) => {
-            let _ = sender.send(inbound_friend_requests.clone());
+            let inbound_friend_requests = CommandHandler::inbound_friend_requests(db::DATABASE.clone(), swarm.local_peer_id())
+                .unwrap_or_default();
+
+            let _ = sender.send(inbound_friend_requests);
+        },
+        SwarmCommand::MarkFriendRequestSeen(peer) => {
+            let friend_requests = db::fetch_friend_requests_from_peer(db::DATABASE.clone(), peer.to_string())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|r| r.to_peer_id == swarm.local_peer_id().to_string());
+
+            for friend_request in friend_requests {
+                if let Err(err) = db::mark_friend_request_seen(db::DATABASE.clone(), friend_request.id) {
+                    let _ = event_sender.send(P2PEvent::Error { context: "mark_friend_request_seen", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                }
+            }
+        },
+        SwarmCommand::GetFriendRequestCount(sender) => {
+            let count = match db::count_unseen_friend_requests(db::DATABASE.clone(), swarm.local_peer_id().to_string()) {
+                Ok(c) => c,
+                Err(err) => {
+                    let _ = event_sender.send(P2PEvent::Error { context: "count_unseen_friend_requests", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                    0
+                }
+            };
+
+            let _ = sender.send(count);
+        },
+        SwarmCommand::ClearAllFriendRequests => {
+            CommandHandler::handle_clear_all_friend_requests(swarm, event_sender);
+        },
+        SwarmCommand::MarkConversationRead { sender, peer_id } => {
+            let marked = CommandHandler::mark_conversation_read(db::DATABASE.clone(), swarm.local_peer_id(), &peer_id)
+                .map(|(marked, receipt)| {
+                    // Receipts are best effort: one that can't go out now is superseded by the next.
+                    if let Some(receipt) = receipt {
+                        if swarm.is_connected(&peer_id) {
+                            swarm.behaviour_mut().request_response.send_request(&peer_id, receipt);
+                        }
+                    }
+                    marked
+                });
+
+            let _ = sender.send(marked);
+        },
+        SwarmCommand::MarkAllRead(sender) => {
+            let marked = CommandHandler::mark_all_read(db::DATABASE.clone(), swarm.local_peer_id())
+                .map(|(marked, receipts)| {
+                    for (peer_id, receipt) in receipts {
+                        if swarm.is_connected(&peer_id) {
+                            swarm.behaviour_mut().request_response.send_request(&peer_id, receipt);
+                        }
+                    }
+                    marked
+                });
+
+            let _ = sender.send(marked);
         },
         SwarmCommand::GetDirectMessages { sender, peer_id } => {
             let direct_messages_with_peer = match db::fetch_direct_messages_with_peer(db::DATABASE.clone(), peer_id.to_string()) {
                 Ok(dms) => dms,
                 Err(err) => {
-                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_direct_message_with_user", error: err.to_string() });
+                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_direct_message_with_user", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                     vec![]
                 }
             };
@@ -338,22 +728,101 @@ async fn handle_swarm_command(
             
             let _ = sender.send(peer_direct_messages);
         },
-        SwarmCommand::LoadFeed(sender) => {
-            let posts = match db::fetch_all_posts(db::DATABASE.clone()) {
-                Ok(p) => p,
+        SwarmCommand::GetPendingCounts(sender) => {
+            let counts = match db::count_pending_direct_messages(db::DATABASE.clone(), swarm.local_peer_id().to_string()) {
+                Ok(c) => c,
                 Err(err) => {
-                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_all_posts", error: err.to_string() });
+                    let _ = event_sender.send(P2PEvent::Error { context: "count_pending_direct_messages", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                    HashMap::new()
+                }
+            }
+                .into_iter()
+                .filter_map(|(peer_id, count)| PeerId::from_str(&peer_id).ok().map(|p| (p, count)))
+                .collect::<HashMap<PeerId, usize>>();
+
+            let _ = sender.send(counts);
+        },
+        SwarmCommand::GetPendingMessages { sender, peer_id } => {
+            let pending_messages = db::fetch_pending_direct_messages(db::DATABASE.clone(), swarm.local_peer_id().to_string(), peer_id.to_string())
+                .unwrap_or_default();
+
+            let _ = sender.send(pending_messages);
+        },
+        SwarmCommand::GetMessagesInRange { sender, peer_id, start, end } => {
+            let messages = match db::fetch_direct_messages_in_range(db::DATABASE.clone(), peer_id.to_string(), start, end) {
+                Ok(dms) => dms,
+                Err(err) => {
+                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_direct_messages_in_range", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                     vec![]
                 }
             };
 
-            let _ = sender.send(posts);
+            let _ = sender.send(messages);
+        },
+        SwarmCommand::GetConversations { sender, blocked } => {
+            let conversations = match db::fetch_conversation_summaries(db::DATABASE.clone(), swarm.local_peer_id().to_string(), blocked) {
+                Ok(conversations) => conversations,
+                Err(err) => {
+                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_conversation_summaries", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                    vec![]
+                }
+            };
+
+            let _ = sender.send(conversations);
+        },
+        SwarmCommand::GetHolepunchStatus { sender, peer_id } => {
+            let _ = sender.send(holepunch_tracker.status(&peer_id));
         },
-        SwarmCommand::LoadBoard { sender, peer_id } => {
-            let posts = match db::fetch_posts_from_peer(db::DATABASE.clone(), peer_id.to_string()) {
+        SwarmCommand::GetConnectionType { sender, peer_id } => {
+            let _ = sender.send(connection_tracker.connection_type(&peer_id));
+        },
+        SwarmCommand::PeerSupportsFeature { sender, peer_id, feature } => {
+            let _ = sender.send(protocol::supports_feature(&feature, &peer_protocols.protocol(&peer_id)));
+        },
+        SwarmCommand::GetNetworkStats(sender) => {
+            let unsubscribed_friends = topic_subscribers.unsubscribed_friends(&posts_topic.hash(), friend_list, |peer| swarm.is_connected(peer));
+
+            for friend in &unsubscribed_friends {
+                log::warn!("Friend {friend} is an explicit peer but not subscribed to {posts_topic}, posts will not propagate to them");
+            }
+
+            let _ = sender.send(topic_mesh::NetworkStats {
+                connected_peers: swarm.connected_peers().count(),
+                topic_subscribers: topic_subscribers.subscriber_counts(),
+                unsubscribed_friends: unsubscribed_friends.iter().map(|peer| peer.to_string()).collect()
+            });
+        },
+        SwarmCommand::GetRelayInfo(sender) => {
+            let info = relay_addr.lock().await
+                .as_ref()
+                .map(|address| relay_connection.info(address, swarm));
+            let _ = sender.send(info);
+        },
+        SwarmCommand::GetDeadLetters(sender) => {
+            let dead_letters = match db::fetch_all_dead_letters(db::DATABASE.clone()) {
+                Ok(d) => d,
+                Err(err) => {
+                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_all_dead_letters", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                    vec![]
+                }
+            };
+
+            let _ = sender.send(dead_letters);
+        },
+        SwarmCommand::RetryDeadLetter(id) => {
+            CommandHandler::handle_retry_dead_letter(
+                id,
+                friend_list,
+                pending_deliveries,
+                swarm,
+                event_sender
+            );
+        },
+        SwarmCommand::LoadFeed(sender) => {
+            let posts = match db::fetch_feed_posts(db::DATABASE.clone(), swarm.local_peer_id().to_string()) {
                 Ok(p) => p,
                 Err(err) => {
-                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_posts_from_peer", error: err.to_string() });
+                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_feed_posts", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                     vec![]
                 }
             };
@@ -361,82 +830,356 @@ async fn handle_swarm_command(
             let _ = sender.send(posts);
         },
         SwarmCommand::ConnectToRelay(address) => {
-            log::info!("Connecting to relay: {}", address);
-            let _ = swarm.dial(address.clone());
-            *relay_addr.lock().await = Some(address);
+            *relay_addr.lock().await = Some(address.clone());
+            relay_connection.restart();
+            relay_connection.dial(address, swarm, command_sender, event_sender);
+        },
+        SwarmCommand::BroadcastNickname(nickname) => {
+            CommandHandler::handle_broadcast_nickname(
+                nickname,
+                friend_list,
+                swarm,
+                event_sender
+            );
+        },
+        SwarmCommand::SetSyncEnabled(enabled) => {
+            log::info!("Setting post sync enabled: {}", enabled);
+
+            let updated = db::fetch_identity(db::DATABASE.clone())
+                .and_then(|identity| db::update_identity(db::DATABASE.clone(), identity.id, None, Some(enabled)));
+
+            if let Err(err) = updated {
+                let _ = event_sender.send(P2PEvent::Error { context: "update_identity", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            }
+
+            if sync_scheduler.set_enabled(enabled) {
+                friend_synch(swarm, sync_scheduler, event_sender);
+            }
+        },
+        SwarmCommand::SetConnectionPolicy(policy) => {
+            log::info!("Setting connection policy: {:?}", policy);
+            connection_gate.set_policy(policy);
+        },
+        SwarmCommand::GetConnectionPolicy(sender) => {
+            let _ = sender.send(connection_gate.policy());
+        },
+        SwarmCommand::AddFriendDirectly { sender, peer, address, nickname } => {
+            log::info!("Adding {} as a friend directly", peer);
+            let added = CommandHandler::add_friend_directly(db::DATABASE.clone(), peer, &address, nickname, friend_list);
+
+            if added.is_ok() {
+                explicit_peers.add(&mut swarm.behaviour_mut().gossipsub, &peer);
+
+                // The other side still has to add us, so send a regular friend request for them to accept.
+                CommandHandler::handle_send_friend_request(
+                    peer,
+                    address,
+                    command_handler::RECIPROCATE_FRIEND_MESSAGE.into(),
+                    listen_addresses,
+                    relay_addr,
+                    swarm,
+                    event_sender
+                ).await;
+            }
+
+            let _ = sender.send(added);
+        },
+        SwarmCommand::CancelSync(peer_id) => {
+            if sync_scheduler.cancel_sync(&peer_id) {
+                log::info!("Cancelling sync with {}", peer_id);
+                let _ = event_sender.send(P2PEvent::SyncCancelled { peer: peer_id });
+            } else {
+                log::warn!("No sync in progress with {} to cancel", peer_id);
+            }
+        },
+        SwarmCommand::SyncAllFriends(sender) => {
+            let summary = sync_scheduler.is_enabled()
+                .then(|| friend_synch(swarm, sync_scheduler, event_sender));
+
+            let _ = sender.send(summary);
+        },
+        SwarmCommand::GetInitialState(sender) => {
+            let initial_state = CommandHandler::build_initial_state(
+                db::DATABASE.clone(),
+                swarm.local_peer_id(),
+                friend_list,
+                |peer_id| swarm.is_connected(peer_id)
+            );
+
+            let _ = sender.send(initial_state);
+        },
+        SwarmCommand::GetFriendProfiles(sender) => {
+            let profiles = CommandHandler::friend_profiles(
+                db::DATABASE.clone(),
+                swarm.local_peer_id(),
+                friend_list,
+                |peer_id| swarm.is_connected(peer_id)
+            );
+
+            let _ = sender.send(profiles);
+        },
+        SwarmCommand::RefreshPeerAddress(peer) => {
+            let relay_address = relay_addr.lock().await.clone();
+            CommandHandler::handle_refresh_peer_address(peer, relay_address, swarm, event_sender);
+        },
+        SwarmCommand::MeasureLatency { sender, peer_id } => {
+            if !swarm.is_connected(&peer_id) {
+                let _ = sender.send(Err(anyhow::anyhow!("Peer {peer_id} is not connected")));
+                return;
+            }
+
+            let nonce = latency_tracker.start(peer_id, sender, std::time::Instant::now());
+            let request_id = swarm.behaviour_mut().request_response.send_request(
+                &peer_id,
+                P2PMessage::LatencyProbe { nonce, sent_at: chrono::Utc::now().timestamp_millis() }
+            );
+            latency_tracker.track_request(request_id, nonce);
+        },
+        SwarmCommand::GetClockSkew { sender, peer_id } => {
+            if !swarm.is_connected(&peer_id) {
+                let _ = sender.send(Err(anyhow::anyhow!("Peer {peer_id} is not connected")));
+                return;
+            }
+
+            let sent_at = chrono::Utc::now().timestamp_millis();
+            let nonce = latency_tracker.start_probe(peer_id, latency::ProbeReply::ClockSkew(sender), std::time::Instant::now(), sent_at);
+            let request_id = swarm.behaviour_mut().request_response.send_request(
+                &peer_id,
+                P2PMessage::LatencyProbe { nonce, sent_at }
+            );
+            latency_tracker.track_request(request_id, nonce);
+        },
+        #[cfg(feature = "debug-events")]
+        SwarmCommand::GetRecentEvents { sender, count } => {
+            let _ = sender.send(event_sender.recent_events(count));
+        },
+        SwarmCommand::ReloadFriendList(sender) => {
+            let reloaded = reload_friend_list(db::DATABASE.clone(), friend_list, explicit_peers, swarm);
+
+            match &reloaded {
+                Ok(count) => log::info!("Reloaded friend list with {} friends", count),
+                Err(err) => log::error!("Failed to reload friend list: {}", err)
+            }
+
+            let _ = sender.send(reloaded);
+        },
+        SwarmCommand::DisconnectPeer(peer) => {
+            let _ = swarm.disconnect_peer_id(peer);
+        },
+        // Handled by the network simulator before commands reach here.
+        #[cfg(feature = "net-sim")]
+        SwarmCommand::SetSimulatedLatency(_) | SwarmCommand::SetPacketLoss(_) | SwarmCommand::Simulated(_) => {
+            log::warn!("Network simulation command was not intercepted");
+        },
+        SwarmCommand::ProbeAddress { sender, address } => {
+            let opts = libp2p::swarm::dial_opts::DialOpts::unknown_peer_id().address(address).build();
+            let connection_id = opts.connection_id();
+            address_prober.start(connection_id, sender, std::time::Instant::now());
+
+            if let Err(err) = swarm.dial(opts) {
+                address_prober.handle_dial_failure(connection_id, err.to_string(), std::time::Instant::now());
+                return;
+            }
+
+            let command_sender = command_sender.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(address_probe::ADDRESS_PROBE_TIMEOUT).await;
+                let _ = command_sender.send(SwarmCommand::ExpireAddressProbe(connection_id)).await;
+            });
+        },
+        SwarmCommand::GetExplicitPeers(sender) => {
+            let _ = sender.send(explicit_peers.peers());
+        },
+        SwarmCommand::ResyncExplicitPeers(sender) => {
+            let resync = explicit_peers.resync(&mut swarm.behaviour_mut().gossipsub, friend_list);
+            log::info!("Resynced explicit peers: added {}, removed {}", resync.added.len(), resync.removed.len());
+            let _ = sender.send(resync);
+        },
+        SwarmCommand::GetSessionStats(sender) => {
+            let _ = sender.send(event_sender.session_stats());
+        },
+        SwarmCommand::ResetSessionStats => {
+            log::info!("Resetting session stats");
+            event_sender.reset_session_stats();
+        },
+        SwarmCommand::TracePeer { peer, enabled, sender } => {
+            log::info!("{} protocol tracing for {}", if enabled { "Enabled" } else { "Disabled" }, peer);
+            let _ = sender.send(peer_tracer.set_traced(peer, enabled));
+        },
+        SwarmCommand::ExpireAddressProbe(connection_id) => {
+            address_prober.handle_timeout(connection_id, std::time::Instant::now());
+        },
+        SwarmCommand::ExpireProbation(peer) => {
+            if connection_gate.expire_probation(&peer, std::time::Instant::now()) {
+                log::warn!("Disconnecting {peer}: only friends may connect and it sent no friend request in time");
+                let _ = swarm.disconnect_peer_id(peer);
+            }
+        },
+        SwarmCommand::RetryRelay(generation) => {
+            if !relay_connection.is_current_retry(generation) {
+                return;
+            }
+
+            let address = relay_addr.lock().await.clone();
+            if let Some(address) = address {
+                relay_connection.dial(address, swarm, command_sender, event_sender);
+            }
         }
     }
 }
 
 fn friend_synch(
-    last_login: i64, 
     swarm: &mut libp2p::Swarm<config::EnclaveNetworkBehaviour>,
-    event_sender: &mpsc::UnboundedSender<P2PEvent>
-) {
+    sync_scheduler: &mut SyncScheduler,
+    event_sender: &EventSender
+) -> SyncSummary {
     let friends = match db::fetch_all_friends(db::DATABASE.clone()) {
         Ok(f) => f,
         Err(err) => {
-            let _ = event_sender.send(P2PEvent::Error { context: "fetch_all_friends", error: err.to_string() });
-            return;
+            let _ = event_sender.send(P2PEvent::Error { context: "fetch_all_friends", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            return SyncSummary::default();
         }
     }
         .iter()
         .filter_map(|friend| {
             match db::fetch_user_by_id(db::DATABASE.clone(), friend.user_id) {
-                Ok(u) => Some(u),
+                Ok(u) => Some((u, friend.last_synch)),
                 Err(err) => {
-                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_user_by_id", error: err.to_string() });
+                    let _ = event_sender.send(P2PEvent::Error { context: "fetch_user_by_id", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
                     None
                 }
             }
         })
-        .collect::<Vec<User>>();
+        .collect::<Vec<(User, i64)>>();
+
+    synch_with_friends(friends, swarm, sync_scheduler, event_sender)
+}
+
+/// Deletes direct messages past their conversation's retention and drops them from the cache.
+fn expire_direct_messages(direct_messages: &mut HashMap<PeerId, Vec<DirectMessage>>, event_sender: &EventSender) {
+    let ids = match db::delete_expired_direct_messages(db::DATABASE.clone(), chrono::Utc::now().timestamp()) {
+        Ok(ids) => ids,
+        Err(err) => {
+            let _ = event_sender.send(P2PEvent::Error { context: "delete_expired_direct_messages", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            return;
+        }
+    };
+
+    if ids.is_empty() {
+        return;
+    }
+
+    log::info!("Deleted {} direct messages past their retention", ids.len());
+    for messages in direct_messages.values_mut() {
+        messages.retain(|message| !ids.contains(&message.id));
+    }
+
+    let _ = event_sender.send(P2PEvent::MessagesExpired { ids });
+}
+
+/// Looks up the sync cursor of a single friend, e.g. when they reconnect.
+fn friend_sync_cursor(peer_id: &PeerId, event_sender: &EventSender) -> Option<i64> {
+    let friend = db::fetch_user_by_peer_id(db::DATABASE.clone(), peer_id.to_string())
+        .and_then(|user| db::fetch_friend_by_user_id(db::DATABASE.clone(), user.id));
+
+    match friend {
+        Ok(friend) => Some(friend.last_synch),
+        Err(err) => {
+            let _ = event_sender.send(P2PEvent::Error { context: "fetch_friend_by_user_id", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+            None
+        }
+    }
+}
 
+/// Sends a `SynchRequest` from each friend's sync cursor, dialling any that aren't connected
+/// first. A friend whose stored address is unusable is counted as failed without stopping
+/// the others.
+fn synch_with_friends(
+    friends: Vec<(User, i64)>,
+    swarm: &mut libp2p::Swarm<config::EnclaveNetworkBehaviour>,
+    sync_scheduler: &mut SyncScheduler,
+    event_sender: &EventSender
+) -> SyncSummary {
     log::info!("Synchronising posts from {} friends", friends.len());
 
     let sender = swarm.local_peer_id().to_string();
+    let mut summary = SyncSummary { friends: friends.len(), ..SyncSummary::default() };
 
-    for friend in friends {
+    for (friend, since) in friends {
         let peer_id = match friend.peer_id.parse::<PeerId>() {
             Ok(p) => p,
             Err(err) => {
-                let _ = event_sender.send(P2PEvent::Error { context: "peer_id.parse", error: err.to_string() });
-                return;
+                let _ = event_sender.send(P2PEvent::Error { context: "peer_id.parse", error: err.to_string(), severity: ErrorSeverity::Warning });
+                summary.failed += 1;
+                continue;
             }
         };
 
+        if !friend.has_known_address() && !swarm.is_connected(&peer_id) {
+            log::warn!("No known address for friend {peer_id}, skipping sync");
+            summary.failed += 1;
+            continue;
+        }
+
         let multiaddr = match Multiaddr::from_str(format!("{}/p2p/{}", friend.multiaddr, friend.peer_id).as_str()) {
             Ok(m) => m,
             Err(err) => {
-                let _ = event_sender.send(P2PEvent::Error { context: "Multiaddr::from_str", error: err.to_string() });
-                return;
+                let _ = event_sender.send(P2PEvent::Error { context: "Multiaddr::from_str", error: err.to_string(), severity: ErrorSeverity::Warning });
+                summary.failed += 1;
+                continue;
             }
         };
 
         if !swarm.is_connected(&peer_id) {
             log::info!("Not yet connected: Dialling first");
             if let Err(err) = swarm.dial(multiaddr) {
-                let _ = event_sender.send(P2PEvent::Error { context: "swarm.dial", error: err.to_string() });
-                return;
+                let _ = event_sender.send(P2PEvent::Error { context: "swarm.dial", error: err.to_string(), severity: ErrorSeverity::Recoverable });
+                summary.failed += 1;
+                continue;
             }
+            summary.dialed += 1;
         }
 
         swarm.behaviour_mut().request_response.send_request(
             &peer_id,
             P2PMessage::SynchRequest(SynchRequest {
-                since: last_login,
+                since,
                 sender: sender.clone()
             })
         );
+        sync_scheduler.start_sync(peer_id);
+        summary.initiated += 1;
     }
+
+    summary
 }
 
-fn load_friend_list(event_sender: &mpsc::UnboundedSender<P2PEvent>) -> Vec<PeerId> {
+/// Re-reads the friend list from the database in case the in-memory copy has drifted,
+/// keeping gossipsub's explicit peers in step with it.
+fn reload_friend_list(
+    db: Arc<std::sync::Mutex<rusqlite::Connection>>,
+    friend_list: &mut Vec<PeerId>,
+    explicit_peers: &mut ExplicitPeers,
+    swarm: &mut libp2p::Swarm<config::EnclaveNetworkBehaviour>
+) -> anyhow::Result<usize> {
+    let reloaded = db::fetch_friend_peer_ids(db)?
+        .into_iter()
+        .filter_map(|peer_id| PeerId::from_str(&peer_id).ok())
+        .collect::<Vec<PeerId>>();
+
+    explicit_peers.resync(&mut swarm.behaviour_mut().gossipsub, &reloaded);
+
+    *friend_list = reloaded;
+    Ok(friend_list.len())
+}
+
+fn load_friend_list(event_sender: &EventSender) -> Vec<PeerId> {
     db::fetch_all_friends(db::DATABASE.clone())
         .unwrap_or_else(|err| {
             let _ = event_sender.send(P2PEvent::Error {
                 context: "fetch_all_friends",
-                error: err.to_string()
+                error: err.to_string(),
+                severity: ErrorSeverity::from_db_error(&err)
             });
             Vec::new()
         })
@@ -447,4 +1190,71 @@ fn load_friend_list(event_sender: &mpsc::UnboundedSender<P2PEvent>) -> Vec<PeerI
                 .and_then(|user| PeerId::from_str(&user.peer_id).ok())
         })
         .collect()
-}
\ No newline at end of file
+}
+
+fn load_connection_policy(event_sender: &EventSender) -> connection_policy::ConnectionPolicy {
+    connection_policy::load_connection_policy(db::DATABASE.clone())
+        .unwrap_or_else(|err| {
+            let _ = event_sender.send(P2PEvent::Error {
+                context: "load_connection_policy",
+                error: err.to_string(),
+                severity: ErrorSeverity::Warning
+            });
+            connection_policy::ConnectionPolicy::default()
+        })
+}
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    #[tokio::test]
+    pub async fn test_synch_with_friends_dispatches_request_per_friend() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(keypair.public());
+        let (behaviour, relay_transport) = create_swarm_behaviour(&keypair, peer_id, libp2p::ping::Config::new()).unwrap();
+        let mut swarm = config::build_swarm(keypair, behaviour, relay_transport).unwrap();
+        let (event_sender, _event_receiver) = channel::event_channel(8);
+
+        let friend = |id: i64, peer_id: String, multiaddr: &str| (User::new(id, peer_id, multiaddr.into(), None, false, 0), 0);
+        let friends = vec![
+            friend(1, PeerId::random().to_string(), "/ip4/127.0.0.1/tcp/1"),
+            friend(2, PeerId::random().to_string(), "/ip4/127.0.0.1/tcp/2"),
+            friend(3, PeerId::random().to_string(), "not-a-multiaddr"),
+            friend(4, "not-a-peer-id".into(), "/ip4/127.0.0.1/tcp/4"),
+            friend(5, PeerId::random().to_string(), crate::db::models::user::UNKNOWN_MULTIADDR)
+        ];
+
+        let mut sync_scheduler = SyncScheduler::new(true);
+        let summary = synch_with_friends(friends, &mut swarm, &mut sync_scheduler, &event_sender);
+
+        assert_eq!(summary, SyncSummary { friends: 5, initiated: 2, dialed: 2, failed: 3 });
+    }
+
+    #[tokio::test]
+    pub async fn test_reload_friend_list_reflects_external_db_changes() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(keypair.public());
+        let (behaviour, relay_transport) = create_swarm_behaviour(&keypair, peer_id, libp2p::ping::Config::new()).unwrap();
+        let mut swarm = config::build_swarm(keypair, behaviour, relay_transport).unwrap();
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let alice = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsA").unwrap();
+        let bob = PeerId::from_str("12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsB").unwrap();
+        let stale = PeerId::random();
+
+        let alice_user_id = db::create_user(db.clone(), alice.to_string(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+        db::create_friend(db.clone(), alice_user_id).unwrap();
+
+        let mut friend_list = vec![stale];
+        let mut explicit_peers = ExplicitPeers::default();
+        assert_eq!(reload_friend_list(db.clone(), &mut friend_list, &mut explicit_peers, &mut swarm).unwrap(), 1);
+        assert_eq!(friend_list, vec![alice]);
+
+        let bob_user_id = db::create_user(db.clone(), bob.to_string(), "/ip4/127.0.0.1/tcp/4002".into(), false).unwrap();
+        db::create_friend(db.clone(), bob_user_id).unwrap();
+
+        assert_eq!(reload_friend_list(db.clone(), &mut friend_list, &mut explicit_peers, &mut swarm).unwrap(), 2);
+        assert_eq!(friend_list, vec![alice, bob]);
+    }
+}
@@ -0,0 +1,172 @@
+use libp2p::PeerId;
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use crate::db;
+use crate::p2p::channel::EventSender;
+use crate::p2p::types::{ErrorSeverity, P2PEvent, SwarmCommand};
+
+/// What the simulator does with an outbound send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulatedSend {
+    Deliver,
+    Delay(Duration),
+    Drop
+}
+
+/// Developer-only impairment of outbound sends, applied to commands before the event loop
+/// handles them. Dropped direct messages are stored but never transmitted, so they stay
+/// pending and exercise the resend-on-reconnect path.
+#[derive(Debug, Default)]
+pub struct NetworkSimulator {
+    latency: Duration,
+    packet_loss: u8
+}
+
+impl NetworkSimulator {
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = latency;
+    }
+
+    pub fn set_packet_loss(&mut self, percent: u8) -> anyhow::Result<()> {
+        if percent > 100 {
+            return Err(anyhow::anyhow!("Packet loss must be between 0 and 100%, got {percent}%"));
+        }
+
+        self.packet_loss = percent;
+        Ok(())
+    }
+
+    /// Decides the fate of a send given a roll in `0..100`.
+    pub fn decide(&self, roll: u8) -> SimulatedSend {
+        if roll < self.packet_loss {
+            SimulatedSend::Drop
+        } else if !self.latency.is_zero() {
+            SimulatedSend::Delay(self.latency)
+        } else {
+            SimulatedSend::Deliver
+        }
+    }
+
+    /// Applies the simulated conditions to `cmd`, returning it if it should be handled now.
+    /// Delayed commands come back round wrapped in `SwarmCommand::Simulated` so they aren't
+    /// impaired twice.
+    pub fn intercept(
+        &mut self,
+        cmd: SwarmCommand,
+        local_peer_id: &PeerId,
+        command_sender: &mpsc::Sender<SwarmCommand>,
+        event_sender: &EventSender
+    ) -> Option<SwarmCommand> {
+        let cmd = match cmd {
+            SwarmCommand::SetSimulatedLatency(latency) => {
+                log::info!("Simulating {}ms of latency on outbound sends", latency.as_millis());
+                self.set_latency(latency);
+                return None;
+            },
+            SwarmCommand::SetPacketLoss(percent) => {
+                if let Err(err) = self.set_packet_loss(percent) {
+                    let _ = event_sender.send(P2PEvent::Error { context: "set_packet_loss", error: err.to_string(), severity: ErrorSeverity::Warning });
+                } else {
+                    log::info!("Simulating {}% packet loss on outbound sends", percent);
+                }
+                return None;
+            },
+            SwarmCommand::Simulated(cmd) => return Some(*cmd),
+            SwarmCommand::SendPost(_) | SwarmCommand::SendDirectMessage { .. } | SwarmCommand::SendFriendRequest { .. } => cmd,
+            _ => return Some(cmd)
+        };
+
+        match self.decide(rand::random_range(0..100)) {
+            SimulatedSend::Deliver => Some(cmd),
+            SimulatedSend::Delay(latency) => {
+                let command_sender = command_sender.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(latency).await;
+                    let _ = command_sender.send(SwarmCommand::Simulated(Box::new(cmd))).await;
+                });
+                None
+            },
+            SimulatedSend::Drop => {
+                log::warn!("Simulated packet loss, dropping outbound send");
+
+                if let SwarmCommand::SendDirectMessage { peer, content, .. } = cmd {
+                    match drop_direct_message(db::DATABASE.clone(), local_peer_id, &peer, content) {
+                        Ok(message) => {
+                            let _ = event_sender.send(P2PEvent::DirectMessageSent(message));
+                        },
+                        Err(err) => {
+                            let _ = event_sender.send(P2PEvent::Error { context: "drop_direct_message", error: err.to_string(), severity: ErrorSeverity::from_db_error(&err) });
+                        }
+                    }
+                }
+
+                None
+            }
+        }
+    }
+}
+
+/// Stores a direct message whose send was lost. It is left pending, so it goes out again the
+/// next time a connection to the recipient is established.
+pub fn drop_direct_message(db: Arc<Mutex<Connection>>, local_peer_id: &PeerId, peer: &PeerId, content: String) -> anyhow::Result<crate::db::models::direct_message::DirectMessage> {
+    let id = db::create_outbound_direct_message(db.clone(), local_peer_id.to_string(), peer.to_string(), content)?;
+
+    db::fetch_direct_message_by_id(db, id)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_simulator_decides_by_loss_and_latency() {
+        let mut simulator = NetworkSimulator::default();
+        assert_eq!(simulator.decide(0), SimulatedSend::Deliver);
+
+        simulator.set_packet_loss(30).unwrap();
+        assert_eq!(simulator.decide(29), SimulatedSend::Drop);
+        assert_eq!(simulator.decide(30), SimulatedSend::Deliver);
+
+        simulator.set_latency(Duration::from_millis(250));
+        assert_eq!(simulator.decide(99), SimulatedSend::Delay(Duration::from_millis(250)));
+
+        assert!(simulator.set_packet_loss(101).is_err());
+    }
+
+    #[test]
+    pub fn test_simulated_loss_leaves_direct_message_pending_for_resend() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let local_peer_id = PeerId::random();
+        let peer = PeerId::random();
+
+        let message = drop_direct_message(db.clone(), &local_peer_id, &peer, "Hello".into()).expect("drop_direct_message failed");
+
+        let pending = db::fetch_pending_direct_messages(db, local_peer_id.to_string(), peer.to_string()).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, message.id);
+    }
+
+    #[tokio::test]
+    pub async fn test_simulated_latency_delays_delivery() {
+        let mut simulator = NetworkSimulator::default();
+        simulator.set_latency(Duration::from_millis(50));
+
+        let (command_sender, mut command_receiver) = mpsc::channel(4);
+        let (event_sender, _event_receiver) = crate::p2p::channel::event_channel(4);
+        let sent_at = tokio::time::Instant::now();
+
+        let handled_now = simulator.intercept(SwarmCommand::SendPost("Hello".into()), &PeerId::random(), &command_sender, &event_sender);
+        assert!(handled_now.is_none());
+        assert!(command_receiver.try_recv().is_err());
+
+        let delayed = command_receiver.recv().await.expect("delayed command was not delivered");
+        assert!(sent_at.elapsed() >= Duration::from_millis(50));
+
+        match simulator.intercept(delayed, &PeerId::random(), &command_sender, &event_sender) {
+            Some(SwarmCommand::SendPost(content)) => assert_eq!(content, "Hello"),
+            _ => panic!("expected the delayed SwarmCommand::SendPost")
+        }
+    }
+}
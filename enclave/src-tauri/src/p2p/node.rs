@@ -1,14 +1,16 @@
 use libp2p::{Multiaddr, PeerId, identity::Keypair};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
-use crate::{db::models::{direct_message::DirectMessage, friend_request::FriendRequest, post::Post}, p2p::types::*};
+use crate::{db::models::{conversation_summary::ConversationSummary, dead_letter::DeadLetter, direct_message::DirectMessage, friend_request::FriendRequest, post::Post}, p2p::{address_probe::AddressProbeResult, config::NetworkConfigInfo, connection::ConnectionType, connection_policy::ConnectionPolicy, explicit_peers::ExplicitPeersResync, holepunch::HolepunchStatus, relay::RelayInfo, session_stats::SessionStats, sync::SyncSummary, topic_mesh::NetworkStats, types::*}};
 
 pub struct P2PNode {
     pub peer_id: PeerId,
     pub keypair: Keypair,
     pub listen_addresses: Arc<Mutex<Vec<Multiaddr>>>,
     pub relay_address: Arc<Mutex<Option<Multiaddr>>>,
-    pub swarm_sender: mpsc::UnboundedSender<SwarmCommand>
+    pub swarm_sender: mpsc::Sender<SwarmCommand>,
+    pub network_config: NetworkConfigInfo
 }
 
 impl P2PNode {
@@ -35,63 +37,409 @@ impl P2PNode {
         addresses
     }
 
-    pub fn send_direct_message(&self, peer: PeerId, address: Multiaddr, content: String) -> anyhow::Result<()> {
-        self.swarm_sender.send(SwarmCommand::SendDirectMessage { peer, address, content })?;
+    pub async fn send_direct_message(&self, peer: PeerId, address: Multiaddr, content: String) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::SendDirectMessage { peer, address, content }).await?;
         Ok(())
     }
 
-    pub fn send_post(&self, content: String) -> anyhow::Result<()> {
-        self.swarm_sender.send(SwarmCommand::SendPost(content))?;
+    pub async fn send_post(&self, content: String) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::SendPost(content)).await?;
         Ok(())
     }
 
-    pub fn send_friend_request(&self, peer: PeerId, address: Multiaddr, message: String) -> anyhow::Result<()> {
-        self.swarm_sender.send(SwarmCommand::SendFriendRequest { peer, address, message })?;
+    pub async fn send_friend_request(&self, peer: PeerId, address: Multiaddr, message: String) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::SendFriendRequest { peer, address, message }).await?;
         Ok(())
     }
 
-    pub fn accept_friend_request(&self, peer: PeerId) -> anyhow::Result<()> {
-        self.swarm_sender.send(SwarmCommand::AcceptFriendRequest(peer))?;
+    pub async fn accept_friend_request(&self, peer: PeerId) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::AcceptFriendRequest(peer)).await?;
         Ok(())
     }
 
-    pub fn deny_friend_request(&self, peer: PeerId) -> anyhow::Result<()> {
-        self.swarm_sender.send(SwarmCommand::DenyFriendRequest(peer))?;
+    pub async fn deny_friend_request(&self, peer: PeerId, reason: Option<String>) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::DenyFriendRequest { peer, reason }).await?;
         Ok(())
     }
 
     pub async fn get_friend_list(&self) -> anyhow::Result<Vec<PeerId>> {
         let (sender, receiver) = tokio::sync::oneshot::channel();
-        self.swarm_sender.send(SwarmCommand::GetFriendList(sender))?;
+        self.swarm_sender.send(SwarmCommand::GetFriendList(sender)).await?;
         Ok(receiver.await?)
     }
 
+    pub async fn count_online_friends(&self) -> anyhow::Result<usize> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::CountOnlineFriends(sender)).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_pending_work(&self) -> anyhow::Result<PendingWork> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetPendingWork(sender)).await?;
+        receiver.await?
+    }
+
     pub async fn get_inbound_friend_requests(&self) -> anyhow::Result<Vec<FriendRequest>> {
         let (sender, receiver) = tokio::sync::oneshot::channel();
-        self.swarm_sender.send(SwarmCommand::GetInboundFriendRequests(sender))?;
+        self.swarm_sender.send(SwarmCommand::GetInboundFriendRequests(sender)).await?;
         Ok(receiver.await?)
     }
 
+    pub async fn mark_friend_request_seen(&self, peer: PeerId) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::MarkFriendRequestSeen(peer)).await?;
+        Ok(())
+    }
+
+    pub async fn get_friend_request_count(&self) -> anyhow::Result<usize> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetFriendRequestCount(sender)).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn clear_all_friend_requests(&self) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::ClearAllFriendRequests).await?;
+        Ok(())
+    }
+
     pub async fn get_direct_messages(&self, peer_id: PeerId) -> anyhow::Result<Vec<DirectMessage>> {
         let (sender, receiver) = tokio::sync::oneshot::channel();
-        self.swarm_sender.send(SwarmCommand::GetDirectMessages{ sender, peer_id })?;
+        self.swarm_sender.send(SwarmCommand::GetDirectMessages{ sender, peer_id }).await?;
+        Ok(receiver.await?)
+    }
+
+    /// Marks the conversation with `peer_id` read, returning how many messages were updated.
+    pub async fn mark_conversation_read(&self, peer_id: PeerId) -> anyhow::Result<usize> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::MarkConversationRead { sender, peer_id }).await?;
+        receiver.await?
+    }
+
+    /// Marks every conversation read, returning how many messages were updated.
+    pub async fn mark_all_read(&self) -> anyhow::Result<usize> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::MarkAllRead(sender)).await?;
+        receiver.await?
+    }
+
+    pub async fn get_pending_counts(&self) -> anyhow::Result<HashMap<PeerId, usize>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetPendingCounts(sender)).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_pending_messages(&self, peer_id: PeerId) -> anyhow::Result<Vec<DirectMessage>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetPendingMessages{ sender, peer_id }).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_messages_in_range(&self, peer_id: PeerId, start: i64, end: i64) -> anyhow::Result<Vec<DirectMessage>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetMessagesInRange{ sender, peer_id, start, end }).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_dead_letters(&self) -> anyhow::Result<Vec<DeadLetter>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetDeadLetters(sender)).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_conversations(&self, blocked: bool) -> anyhow::Result<Vec<ConversationSummary>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetConversations { sender, blocked }).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_holepunch_status(&self, peer_id: PeerId) -> anyhow::Result<HolepunchStatus> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetHolepunchStatus { sender, peer_id }).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_connection_type(&self, peer_id: PeerId) -> anyhow::Result<Option<ConnectionType>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetConnectionType { sender, peer_id }).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn peer_supports_feature(&self, peer_id: PeerId, feature: String) -> anyhow::Result<bool> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::PeerSupportsFeature { sender, peer_id, feature }).await?;
+        receiver.await?
+    }
+
+    /// Connected peers and gossipsub topic membership, flagging friends posts won't reach.
+    pub async fn get_network_stats(&self) -> anyhow::Result<NetworkStats> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetNetworkStats(sender)).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_relay_info(&self) -> anyhow::Result<Option<RelayInfo>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetRelayInfo(sender)).await?;
+        Ok(receiver.await?)
+    }
+
+    /// Logs every swarm event and command involving `peer` at debug level while enabled.
+    /// Returns whether any peer is still being traced.
+    pub async fn trace_peer(&self, peer: PeerId, enabled: bool) -> anyhow::Result<bool> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::TracePeer { peer, enabled, sender }).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn get_explicit_peers(&self) -> anyhow::Result<Vec<PeerId>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetExplicitPeers(sender)).await?;
+        Ok(receiver.await?)
+    }
+
+    /// Makes the explicit gossipsub peers match the friend list again.
+    pub async fn resync_explicit_peers(&self) -> anyhow::Result<ExplicitPeersResync> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::ResyncExplicitPeers(sender)).await?;
         Ok(receiver.await?)
     }
 
+    pub async fn get_session_stats(&self) -> anyhow::Result<SessionStats> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetSessionStats(sender)).await?;
+        Ok(receiver.await?)
+    }
+
+    pub async fn reset_session_stats(&self) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::ResetSessionStats).await?;
+        Ok(())
+    }
+
+    pub async fn disconnect_peer(&self, peer: PeerId) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::DisconnectPeer(peer)).await?;
+        Ok(())
+    }
+
+    /// Round trip of an application-level probe to the peer, in milliseconds.
+    pub async fn measure_latency(&self, peer_id: PeerId) -> anyhow::Result<u64> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::MeasureLatency { sender, peer_id }).await?;
+        receiver.await?
+    }
+
+    /// How far `peer_id`'s clock is ahead of ours, in milliseconds.
+    pub async fn get_clock_skew(&self, peer_id: PeerId) -> anyhow::Result<i64> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetClockSkew { sender, peer_id }).await?;
+        receiver.await?
+    }
+
+    /// Dials `address` on a throwaway connection to check that it's reachable.
+    pub async fn probe_address(&self, address: Multiaddr) -> anyhow::Result<AddressProbeResult> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::ProbeAddress { sender, address }).await?;
+        Ok(receiver.await?)
+    }
+
+    #[cfg(feature = "debug-events")]
+    pub async fn get_recent_events(&self, count: usize) -> anyhow::Result<Vec<crate::p2p::recent_events::RecentEvent>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetRecentEvents { sender, count }).await?;
+        Ok(receiver.await?)
+    }
+
+    /// Replaces the event loop's friend list with the one in the database, returning the new count.
+    pub async fn reload_friend_list(&self) -> anyhow::Result<usize> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::ReloadFriendList(sender)).await?;
+        receiver.await?
+    }
+
+    pub async fn retry_dead_letter(&self, id: i64) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::RetryDeadLetter(id)).await?;
+        Ok(())
+    }
+
     pub async fn load_feed(&self) -> anyhow::Result<Vec<Post>> {
         let (sender, receiver) = tokio::sync::oneshot::channel();
-        self.swarm_sender.send(SwarmCommand::LoadFeed(sender))?;
+        self.swarm_sender.send(SwarmCommand::LoadFeed(sender)).await?;
         Ok(receiver.await?)
     }
 
-    pub async fn load_board(&self, peer_id: PeerId) -> anyhow::Result<Vec<Post>> {
+    pub async fn broadcast_nickname(&self, nickname: String) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::BroadcastNickname(nickname)).await?;
+        Ok(())
+    }
+
+    pub async fn set_sync_enabled(&self, enabled: bool) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::SetSyncEnabled(enabled)).await?;
+        Ok(())
+    }
+
+    /// The config the node was started with, plus its current addresses and connection policy.
+    pub async fn get_network_config(&self) -> anyhow::Result<NetworkConfigInfo> {
         let (sender, receiver) = tokio::sync::oneshot::channel();
-        self.swarm_sender.send(SwarmCommand::LoadBoard{ sender, peer_id })?;
+        self.swarm_sender.send(SwarmCommand::GetConnectionPolicy(sender)).await?;
+
+        let mut network_config = self.network_config.clone();
+        network_config.listen_addresses = self.listen_addresses.lock().await.iter().map(|address| address.to_string()).collect();
+        network_config.relay_addresses = self.relay_address.lock().await.iter().map(|address| address.to_string()).collect();
+        network_config.connection_policy = receiver.await?;
+
+        Ok(network_config)
+    }
+
+    #[cfg(feature = "net-sim")]
+    pub async fn set_simulated_latency(&self, latency: std::time::Duration) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::SetSimulatedLatency(latency)).await?;
+        Ok(())
+    }
+
+    #[cfg(feature = "net-sim")]
+    pub async fn set_packet_loss(&self, percent: u8) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::SetPacketLoss(percent)).await?;
+        Ok(())
+    }
+
+    pub async fn set_connection_policy(&self, policy: ConnectionPolicy) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::SetConnectionPolicy(policy)).await?;
+        Ok(())
+    }
+
+    pub async fn add_friend_directly(&self, peer: PeerId, address: Multiaddr, nickname: Option<String>) -> anyhow::Result<()> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::AddFriendDirectly { sender, peer, address, nickname }).await?;
+        receiver.await?
+    }
+
+    pub async fn cancel_sync(&self, peer_id: PeerId) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::CancelSync(peer_id)).await?;
+        Ok(())
+    }
+
+    /// Returns `None` when post sync is paused.
+    pub async fn sync_all_friends(&self) -> anyhow::Result<Option<SyncSummary>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::SyncAllFriends(sender)).await?;
         Ok(receiver.await?)
     }
 
-    pub fn connect_to_relay(&self, address: Multiaddr) -> anyhow::Result<()> {
-        self.swarm_sender.send(SwarmCommand::ConnectToRelay(address))?;
+    pub async fn get_initial_state(&self) -> anyhow::Result<InitialState> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetInitialState(sender)).await?;
+        receiver.await?
+    }
+
+    pub async fn get_friend_profiles(&self) -> anyhow::Result<Vec<FriendProfile>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.swarm_sender.send(SwarmCommand::GetFriendProfiles(sender)).await?;
+        receiver.await?
+    }
+
+    pub async fn refresh_peer_address(&self, peer_id: PeerId) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::RefreshPeerAddress(peer_id)).await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub async fn connect_to_relay(&self, address: Multiaddr) -> anyhow::Result<()> {
+        self.swarm_sender.send(SwarmCommand::ConnectToRelay(address)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::p2p::config::NetworkConfig;
+    use crate::p2p::connection_policy::ConnectionPolicy;
+
+    fn test_network_config() -> NetworkConfig {
+        let keypair = Keypair::generate_ed25519();
+
+        NetworkConfig {
+            peer_id: PeerId::from(keypair.public()),
+            keypair,
+            port: 5555,
+            namespace: "my-community".into(),
+            event_channel_capacity: 64,
+            command_channel_capacity: 32,
+            ping_interval: Duration::from_secs(20),
+            ping_timeout: Duration::from_secs(5),
+            direct_message_rate_limit: 10,
+            direct_message_rate_window: Duration::from_secs(30)
+        }
+    }
+
+    fn create_test_node() -> (P2PNode, mpsc::Receiver<SwarmCommand>) {
+        let keypair = Keypair::generate_ed25519();
+        let (swarm_sender, swarm_receiver) = mpsc::channel(1);
+
+        let node = P2PNode {
+            peer_id: PeerId::from(keypair.public()),
+            keypair,
+            listen_addresses: Arc::new(Mutex::new(Vec::new())),
+            relay_address: Arc::new(Mutex::new(None)),
+            swarm_sender,
+            network_config: test_network_config().info()
+        };
+
+        (node, swarm_receiver)
+    }
+
+    #[tokio::test]
+    pub async fn test_send_post_dispatches_send_post_command() {
+        let (node, mut swarm_receiver) = create_test_node();
+
+        node.send_post("Hello World".into()).await.expect("send_post failed");
+
+        match swarm_receiver.try_recv() {
+            Ok(SwarmCommand::SendPost(content)) => assert_eq!(content, "Hello World"),
+            _ => panic!("expected SwarmCommand::SendPost")
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_send_post_waits_when_command_channel_is_full() {
+        let (node, mut swarm_receiver) = create_test_node();
+
+        node.send_post("First".into()).await.expect("send_post failed");
+        assert!(tokio::time::timeout(Duration::from_millis(50), node.send_post("Second".into())).await.is_err());
+
+        assert!(matches!(swarm_receiver.try_recv(), Ok(SwarmCommand::SendPost(_))));
+        node.send_post("Third".into()).await.expect("send_post failed once the channel had capacity");
+
+        match swarm_receiver.try_recv() {
+            Ok(SwarmCommand::SendPost(content)) => assert_eq!(content, "Third"),
+            _ => panic!("expected SwarmCommand::SendPost")
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_get_network_config_reflects_node_config() {
+        let (node, mut swarm_receiver) = create_test_node();
+        let listen_address: Multiaddr = "/ip4/127.0.0.1/tcp/5555".parse().unwrap();
+        node.listen_addresses.lock().await.push(listen_address.clone());
+
+        tokio::spawn(async move {
+            if let Some(SwarmCommand::GetConnectionPolicy(sender)) = swarm_receiver.recv().await {
+                let _ = sender.send(ConnectionPolicy::FriendsOnly);
+            }
+        });
+
+        let network_config = node.get_network_config().await.expect("get_network_config failed");
+
+        assert_eq!(network_config.namespace, "my-community");
+        assert_eq!(network_config.posts_topic, "my-community-posts");
+        assert_eq!(network_config.port, 5555);
+        assert_eq!(network_config.ping_interval_ms, 20_000);
+        assert_eq!(network_config.ping_timeout_ms, 5_000);
+        assert_eq!(network_config.event_channel_capacity, 64);
+        assert_eq!(network_config.command_channel_capacity, 32);
+        assert_eq!(network_config.direct_message_rate_limit, 10);
+        assert_eq!(network_config.direct_message_rate_window_ms, 30_000);
+        assert_eq!(network_config.listen_addresses, vec![listen_address.to_string()]);
+        assert!(network_config.relay_addresses.is_empty());
+        assert_eq!(network_config.connection_policy, ConnectionPolicy::FriendsOnly);
+    }
+}
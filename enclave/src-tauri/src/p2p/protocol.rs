@@ -0,0 +1,252 @@
+use async_trait::async_trait;
+use libp2p::{PeerId, StreamProtocol, futures::{AsyncRead, AsyncWrite}, request_response::{self as reqres, cbor}};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use crate::p2p::types::P2PMessage;
+
+pub const PROTOCOL_V1_0: StreamProtocol = StreamProtocol::new("/enclave/1.0.0");
+pub const PROTOCOL_V1_1: StreamProtocol = StreamProtocol::new("/enclave/1.1.0");
+
+/// Supported request-response protocols, oldest first.
+pub const PROTOCOL_VERSIONS: [StreamProtocol; 2] = [PROTOCOL_V1_0, PROTOCOL_V1_1];
+
+/// Protocols in the order they are offered during negotiation, newest first.
+pub fn supported_protocols() -> impl Iterator<Item = (StreamProtocol, reqres::ProtocolSupport)> {
+    PROTOCOL_VERSIONS.into_iter()
+        .rev()
+        .map(|protocol| (protocol, reqres::ProtocolSupport::Full))
+}
+
+impl P2PMessage {
+    /// The oldest protocol version whose peers can decode this message.
+    pub fn min_protocol(&self) -> StreamProtocol {
+        match self {
+            P2PMessage::NicknameUpdate { .. }
+            | P2PMessage::AddressRequest
+            | P2PMessage::AddressResponse { .. }
+            | P2PMessage::LatencyProbe { .. }
+            | P2PMessage::LatencyProbeReply { .. }
+            | P2PMessage::BufferedPost(_)
+            | P2PMessage::BufferedPostAck
+            | P2PMessage::ReadReceipt
+            | P2PMessage::FriendRequestAck => PROTOCOL_V1_1,
+            _ => PROTOCOL_V1_0
+        }
+    }
+}
+
+fn protocol_rank(protocol: &StreamProtocol) -> Option<usize> {
+    PROTOCOL_VERSIONS.iter().position(|p| p == protocol)
+}
+
+pub fn is_supported(message: &P2PMessage, protocol: &StreamProtocol) -> bool {
+    match (protocol_rank(protocol), protocol_rank(&message.min_protocol())) {
+        (Some(negotiated), Some(required)) => negotiated >= required,
+        _ => false
+    }
+}
+
+/// The oldest protocol version that carries the messages behind a user-facing feature.
+pub fn feature_min_protocol(feature: &str) -> Option<StreamProtocol> {
+    match feature {
+        "direct_messages" | "friend_requests" | "post_sync" => Some(PROTOCOL_V1_0),
+        "nicknames" | "address_exchange" | "latency_probe" | "buffered_posts" | "read_receipts" | "friend_request_ack" => Some(PROTOCOL_V1_1),
+        _ => None
+    }
+}
+
+pub fn supports_feature(feature: &str, protocol: &StreamProtocol) -> anyhow::Result<bool> {
+    let required = feature_min_protocol(feature)
+        .ok_or_else(|| anyhow::anyhow!("Unknown feature: {feature}"))?;
+
+    Ok(match (protocol_rank(protocol), protocol_rank(&required)) {
+        (Some(negotiated), Some(required)) => negotiated >= required,
+        _ => false
+    })
+}
+
+/// The newest protocol version each connected peer is known to speak. Request-response
+/// doesn't report the negotiated protocol, so it is inferred from the messages a peer sends:
+/// a message can only arrive over a protocol at least as new as its `min_protocol`. Peers
+/// that haven't sent anything newer are assumed to be on the oldest version.
+#[derive(Default)]
+pub struct PeerProtocols {
+    protocols: HashMap<PeerId, StreamProtocol>,
+    /// Peers whose codec refused a message for being too new. Unlike `protocols` this survives
+    /// disconnects, so we don't hold messages for them while they're offline.
+    outdated: HashSet<PeerId>
+}
+
+impl PeerProtocols {
+    pub fn observe(&mut self, peer: PeerId, message: &P2PMessage) {
+        let observed = message.min_protocol();
+        if observed != PROTOCOL_V1_0 {
+            self.outdated.remove(&peer);
+        }
+
+        let known = self.protocols.entry(peer).or_insert(PROTOCOL_V1_0);
+
+        if protocol_rank(&observed) > protocol_rank(known) {
+            *known = observed;
+        }
+    }
+
+    pub fn protocol(&self, peer: &PeerId) -> StreamProtocol {
+        self.protocols.get(peer).cloned().unwrap_or(PROTOCOL_V1_0)
+    }
+
+    /// A peer may reconnect running a different version, so forget it once fully disconnected.
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.protocols.remove(peer);
+    }
+
+    pub fn mark_outdated(&mut self, peer: PeerId) {
+        self.outdated.insert(peer);
+    }
+
+    pub fn is_outdated(&self, peer: &PeerId) -> bool {
+        self.outdated.contains(peer)
+    }
+}
+
+fn unsupported_error(message: &P2PMessage, protocol: &StreamProtocol) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Message requires {} but peer negotiated {}", message.min_protocol(), protocol)
+    )
+}
+
+/// CBOR codec that refuses to send messages the negotiated protocol version can't carry,
+/// so newer message types fail the single request instead of confusing older peers.
+/// Messages that fail to decode (e.g. variants from a newer peer) surface as an
+/// `InboundFailure` for that request only.
+#[derive(Clone, Default)]
+pub struct EnclaveCodec {
+    inner: cbor::codec::Codec<P2PMessage, P2PMessage>
+}
+
+#[async_trait]
+impl reqres::Codec for EnclaveCodec {
+    type Protocol = StreamProtocol;
+    type Request = P2PMessage;
+    type Response = P2PMessage;
+
+    async fn read_request<T>(&mut self, protocol: &StreamProtocol, io: &mut T) -> io::Result<P2PMessage>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        self.inner.read_request(protocol, io).await
+    }
+
+    async fn read_response<T>(&mut self, protocol: &StreamProtocol, io: &mut T) -> io::Result<P2PMessage>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        self.inner.read_response(protocol, io).await
+    }
+
+    async fn write_request<T>(&mut self, protocol: &StreamProtocol, io: &mut T, request: P2PMessage) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        if !is_supported(&request, protocol) {
+            return Err(unsupported_error(&request, protocol));
+        }
+
+        self.inner.write_request(protocol, io, request).await
+    }
+
+    async fn write_response<T>(&mut self, protocol: &StreamProtocol, io: &mut T, response: P2PMessage) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        if !is_supported(&response, protocol) {
+            return Err(unsupported_error(&response, protocol));
+        }
+
+        self.inner.write_response(protocol, io, response).await
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::futures::io::Cursor;
+    use libp2p::request_response::Codec;
+    use serde::{Deserialize, Serialize};
+    use crate::p2p::types::SynchRequest;
+
+    /// The message set understood by a peer still on `/enclave/1.0.0`.
+    #[derive(Debug, Serialize, Deserialize)]
+    enum LegacyP2PMessage {
+        SynchRequest(SynchRequest)
+    }
+
+    #[test]
+    pub fn test_is_supported_gates_new_variants_behind_v1_1() {
+        let nickname_update = P2PMessage::NicknameUpdate { nickname: "Alice".into() };
+        let synch_request = P2PMessage::SynchRequest(SynchRequest { since: 0, sender: "peer".into() });
+
+        assert!(!is_supported(&nickname_update, &PROTOCOL_V1_0));
+        assert!(is_supported(&nickname_update, &PROTOCOL_V1_1));
+        assert!(is_supported(&synch_request, &PROTOCOL_V1_0));
+        assert!(is_supported(&synch_request, &PROTOCOL_V1_1));
+    }
+
+    #[test]
+    pub fn test_supported_protocols_prefers_newest_version() {
+        let protocols = supported_protocols().map(|(p, _)| p).collect::<Vec<_>>();
+
+        assert_eq!(protocols, vec![PROTOCOL_V1_1, PROTOCOL_V1_0]);
+    }
+
+    #[test]
+    pub fn test_supports_feature_checks_advertised_version() {
+        assert!(supports_feature("direct_messages", &PROTOCOL_V1_0).unwrap());
+        assert!(!supports_feature("nicknames", &PROTOCOL_V1_0).unwrap());
+        assert!(supports_feature("nicknames", &PROTOCOL_V1_1).unwrap());
+        assert!(supports_feature("teleportation", &PROTOCOL_V1_1).is_err());
+    }
+
+    #[test]
+    pub fn test_peer_protocols_tracks_newest_observed_version() {
+        let mut peer_protocols = PeerProtocols::default();
+        let peer = PeerId::random();
+
+        assert_eq!(peer_protocols.protocol(&peer), PROTOCOL_V1_0);
+
+        peer_protocols.observe(peer, &P2PMessage::NicknameUpdate { nickname: "Alice".into() });
+        peer_protocols.observe(peer, &P2PMessage::SynchRequest(SynchRequest { since: 0, sender: "peer".into() }));
+        assert_eq!(peer_protocols.protocol(&peer), PROTOCOL_V1_1);
+        assert!(supports_feature("read_receipts", &peer_protocols.protocol(&peer)).unwrap());
+
+        peer_protocols.remove(&peer);
+        assert_eq!(peer_protocols.protocol(&peer), PROTOCOL_V1_0);
+    }
+
+    #[tokio::test]
+    pub async fn test_codec_refuses_to_send_new_variant_over_v1_0() {
+        let mut codec = EnclaveCodec::default();
+        let mut io = Cursor::new(Vec::new());
+
+        let result = codec.write_request(&PROTOCOL_V1_0, &mut io, P2PMessage::NicknameUpdate { nickname: "Alice".into() }).await;
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        assert!(io.into_inner().is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_old_peer_rejects_new_variant_without_panicking() {
+        let mut codec = EnclaveCodec::default();
+        let mut io = Cursor::new(Vec::new());
+
+        codec.write_request(&PROTOCOL_V1_1, &mut io, P2PMessage::NicknameUpdate { nickname: "Alice".into() })
+            .await
+            .expect("write_request failed");
+
+        let mut legacy_codec = cbor::codec::Codec::<LegacyP2PMessage, LegacyP2PMessage>::default();
+        let mut io = Cursor::new(io.into_inner());
+
+        assert!(legacy_codec.read_request(&PROTOCOL_V1_0, &mut io).await.is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use libp2p::PeerId;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_DIRECT_MESSAGE_RATE_LIMIT: usize = 20;
+pub const DEFAULT_DIRECT_MESSAGE_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Sliding-window limiter allowing at most `max_per_window` actions per peer within `window`.
+pub struct RateLimiter {
+    max_per_window: usize,
+    window: Duration,
+    history: HashMap<PeerId, VecDeque<Instant>>
+}
+
+impl RateLimiter {
+    pub fn new(max_per_window: usize, window: Duration) -> Self {
+        Self { max_per_window, window, history: HashMap::new() }
+    }
+
+    /// Records an action for `peer` at `now`, returning `false` without recording it when the
+    /// peer has already reached the limit for the current window.
+    pub fn check(&mut self, peer: PeerId, now: Instant) -> bool {
+        let timestamps = self.history.entry(peer).or_default();
+
+        while timestamps.front().is_some_and(|&sent_at| now.duration_since(sent_at) >= self.window) {
+            timestamps.pop_front();
+        }
+
+        if timestamps.len() >= self.max_per_window {
+            return false;
+        }
+
+        timestamps.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_rate_limiter_rejects_burst_over_limit() {
+        let mut limiter = RateLimiter::new(20, Duration::from_secs(1));
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        let allowed = (0..50).filter(|_| limiter.check(peer, now)).count();
+
+        assert_eq!(allowed, 20);
+        assert!(limiter.check(PeerId::random(), now));
+    }
+
+    #[test]
+    pub fn test_rate_limiter_allows_again_after_window() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(1));
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        assert!(limiter.check(peer, now));
+        assert!(limiter.check(peer, now + Duration::from_millis(500)));
+        assert!(!limiter.check(peer, now + Duration::from_millis(900)));
+
+        assert!(limiter.check(peer, now + Duration::from_millis(1000)));
+        assert!(!limiter.check(peer, now + Duration::from_millis(1200)));
+        assert!(limiter.check(peer, now + Duration::from_millis(1500)));
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use serde::Serialize;
+use crate::p2p::types::P2PEvent;
+
+/// Upper bound on how many events are retained for bug reports.
+pub const MAX_RECENT_EVENTS: usize = 500;
+
+/// A `P2PEvent` reduced to what is useful in a bug report. Message, post, friend request
+/// and nickname contents are never recorded; only the event kind and the peers involved.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentEvent {
+    pub timestamp: i64,
+    pub kind: &'static str,
+    pub detail: String
+}
+
+impl RecentEvent {
+    pub fn redacted(event: &P2PEvent, timestamp: i64) -> Self {
+        let (kind, detail) = match event {
+            P2PEvent::DirectMessageReceived(msg) => ("DirectMessageReceived", format!("from {}", msg.from_peer_id)),
+            P2PEvent::DirectMessageSent(msg) => ("DirectMessageSent", format!("to {}", msg.to_peer_id)),
+            P2PEvent::PostRecieved(post) => ("PostReceived", format!("author {}", post.author_peer_id)),
+            P2PEvent::PostSent(post) => ("PostSent", format!("author {}", post.author_peer_id)),
+            P2PEvent::PeerConnected(peer) => ("PeerConnected", peer.to_string()),
+            P2PEvent::PeerDisconnected(peer) => ("PeerDisconnected", peer.to_string()),
+            P2PEvent::FriendRequestReceived { from, .. } => ("FriendRequestReceived", format!("from {}", from)),
+            P2PEvent::FriendRequestAccepted { peer } => ("FriendRequestAccepted", peer.to_string()),
+            P2PEvent::FriendRequestAcknowledged { peer } => ("FriendRequestAcknowledged", peer.to_string()),
+            P2PEvent::FriendRequestDenied { peer, .. } => ("FriendRequestDenied", peer.to_string()),
+            P2PEvent::FriendLimitReached { peer, limit } => ("FriendLimitReached", format!("{} (limit {})", peer, limit)),
+            P2PEvent::Error { context, error, severity } => ("Error", format!("{:?} in {}: {}", severity, context, error)),
+            P2PEvent::PostSynch => ("PostSynch", String::new()),
+            P2PEvent::RelayStatusChanged(status) => ("RelayStatusChanged", format!("{:?}", status)),
+            P2PEvent::NodeStalled { silent_for_secs } => ("NodeStalled", format!("silent for {}s", silent_for_secs)),
+            P2PEvent::FriendNicknameChanged { peer, .. } => ("FriendNicknameChanged", peer.to_string()),
+            P2PEvent::MessageDeadLettered(dead_letter) => ("MessageDeadLettered", format!("to {}: {}", dead_letter.to_peer_id, dead_letter.reason)),
+            P2PEvent::HolepunchResult { peer, status } => ("HolepunchResult", format!("{}: {:?}", peer, status)),
+            P2PEvent::ConnectionTypeChanged { peer, connection_type } => ("ConnectionTypeChanged", format!("{}: {:?}", peer, connection_type)),
+            P2PEvent::SyncCancelled { peer } => ("SyncCancelled", peer.to_string()),
+            P2PEvent::MessagesRead { peer } => ("MessagesRead", peer.to_string()),
+            P2PEvent::MessagesExpired { ids } => ("MessagesExpired", format!("{} messages", ids.len())),
+            P2PEvent::ClockSkewDetected { peer, offset_ms } => ("ClockSkewDetected", format!("{}: {}ms", peer, offset_ms)),
+            P2PEvent::DirectMessageQuarantined { peer } => ("DirectMessageQuarantined", peer.to_string()),
+            P2PEvent::SafetyNumberChanged { peer, new_peer, .. } => ("SafetyNumberChanged", format!("{} now answers as {}", peer, new_peer))
+        };
+
+        Self { timestamp, kind, detail }
+    }
+}
+
+/// Ring buffer of the most recent events, oldest first.
+pub struct RecentEvents {
+    capacity: usize,
+    events: VecDeque<RecentEvent>
+}
+
+impl RecentEvents {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.min(MAX_RECENT_EVENTS);
+        Self { capacity, events: VecDeque::with_capacity(capacity) }
+    }
+
+    pub fn record(&mut self, event: &P2PEvent, timestamp: i64) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(RecentEvent::redacted(event, timestamp));
+    }
+
+    /// The last `count` events, oldest first.
+    pub fn last(&self, count: usize) -> Vec<RecentEvent> {
+        self.events.iter()
+            .skip(self.events.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::PeerId;
+    use crate::db::models::direct_message::DirectMessage;
+
+    #[test]
+    pub fn test_recent_events_retains_only_last_n() {
+        let mut recent_events = RecentEvents::new(3);
+
+        for silent_for_secs in 0..5 {
+            recent_events.record(&P2PEvent::NodeStalled { silent_for_secs }, silent_for_secs as i64);
+        }
+
+        let timestamps = recent_events.last(10).iter().map(|event| event.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![2, 3, 4]);
+
+        let timestamps = recent_events.last(2).iter().map(|event| event.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![3, 4]);
+    }
+
+    #[test]
+    pub fn test_recent_events_caps_capacity() {
+        let mut recent_events = RecentEvents::new(usize::MAX);
+
+        for _ in 0..MAX_RECENT_EVENTS + 10 {
+            recent_events.record(&P2PEvent::PostSynch, 0);
+        }
+
+        assert_eq!(recent_events.last(usize::MAX).len(), MAX_RECENT_EVENTS);
+    }
+
+    #[test]
+    pub fn test_recent_events_redacts_message_content() {
+        let peer = PeerId::random();
+        let msg = DirectMessage::new(1, peer.to_string(), PeerId::random().to_string(), "Top secret".into(), 0, None, false, false);
+
+        let event = RecentEvent::redacted(&P2PEvent::DirectMessageReceived(msg), 0);
+
+        assert_eq!(event.kind, "DirectMessageReceived");
+        assert!(event.detail.contains(&peer.to_string()));
+        assert!(!event.detail.contains("Top secret"));
+    }
+}
@@ -0,0 +1,252 @@
+use libp2p::{Multiaddr, PeerId, multiaddr::Protocol, swarm::{ConnectionId, dial_opts::DialOpts}};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use crate::p2p::types::*;
+use crate::p2p::channel::EventSender;
+use crate::p2p::config::EnclaveNetworkBehaviour;
+
+pub const RELAY_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+pub const RELAY_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+pub const RELAY_MAX_RETRIES: u32 = 8;
+
+/// Returns the delay before the given (zero-based) relay retry attempt,
+/// or `None` once the retry budget has been used up.
+pub fn relay_retry_delay(attempt: u32) -> Option<Duration> {
+    if attempt >= RELAY_MAX_RETRIES {
+        return None;
+    }
+
+    let delay = RELAY_RETRY_BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt));
+
+    Some(delay.min(RELAY_RETRY_MAX_DELAY))
+}
+
+/// Extracts the relay's peer id from a relay address, i.e. the `/p2p/` component that
+/// precedes `/p2p-circuit` (or the last one when the address isn't a circuit address).
+pub fn relay_peer_id(address: &Multiaddr) -> Option<PeerId> {
+    let mut peer_id = None;
+
+    for protocol in address.iter() {
+        match protocol {
+            Protocol::P2p(id) => peer_id = Some(id),
+            Protocol::P2pCircuit => break,
+            _ => {}
+        }
+    }
+
+    peer_id
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayInfo {
+    pub address: String,
+    pub peer_id: Option<String>,
+    pub connected: bool,
+    pub status: Option<RelayStatus>,
+    pub reservation_accepted: bool
+}
+
+#[derive(Default)]
+pub struct RelayConnection {
+    pub connection_id: Option<ConnectionId>,
+    pub attempt: u32,
+    /// Bumped whenever the backoff restarts or the relay connects, so retries scheduled
+    /// before that are ignored when they fire.
+    generation: u32,
+    pub peer_id: Option<PeerId>,
+    pub status: Option<RelayStatus>,
+    pub reservation_accepted: bool
+}
+
+impl RelayConnection {
+    fn set_status(&mut self, status: RelayStatus, event_sender: &EventSender) {
+        self.status = Some(status.clone());
+        let _ = event_sender.send(P2PEvent::RelayStatusChanged(status));
+    }
+
+    /// Describes the configured relay, preferring the peer id seen on connection over the
+    /// one parsed from the address.
+    pub fn info(&self, address: &Multiaddr, swarm: &libp2p::Swarm<EnclaveNetworkBehaviour>) -> RelayInfo {
+        let peer_id = self.peer_id.or_else(|| relay_peer_id(address));
+
+        RelayInfo {
+            address: address.to_string(),
+            peer_id: peer_id.map(|peer_id| peer_id.to_string()),
+            connected: peer_id.is_some_and(|peer_id| swarm.is_connected(&peer_id)),
+            status: self.status.clone(),
+            reservation_accepted: self.reservation_accepted
+        }
+    }
+
+    pub fn handle_client_event(&mut self, event: &libp2p::relay::client::Event) {
+        if let libp2p::relay::client::Event::ReservationReqAccepted { relay_peer_id, .. } = event {
+            log::info!("Relay {} accepted our reservation", relay_peer_id);
+            self.peer_id = Some(*relay_peer_id);
+            self.reservation_accepted = true;
+        }
+    }
+
+    /// Starts connecting to a relay afresh, dropping any retry already scheduled.
+    pub fn restart(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.attempt = 0;
+    }
+
+    /// Whether a `RetryRelay` scheduled in `generation` should still be acted on.
+    pub fn is_current_retry(&self, generation: u32) -> bool {
+        self.generation == generation
+    }
+
+    /// Losing the relay starts the backoff over, so the reservation is renewed once it's back.
+    pub fn handle_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        num_established: u32,
+        command_sender: &mpsc::Sender<SwarmCommand>,
+        event_sender: &EventSender
+    ) {
+        if num_established == 0 && self.peer_id.as_ref() == Some(peer_id) {
+            log::warn!("Lost connection to relay {}", peer_id);
+            self.reservation_accepted = false;
+            self.restart();
+            self.schedule_retry(command_sender, event_sender);
+        }
+    }
+
+    pub fn is_relay_dial(&self, connection_id: ConnectionId) -> bool {
+        self.connection_id == Some(connection_id)
+    }
+
+    pub fn dial(
+        &mut self,
+        address: Multiaddr,
+        swarm: &mut libp2p::Swarm<EnclaveNetworkBehaviour>,
+        command_sender: &mpsc::Sender<SwarmCommand>,
+        event_sender: &EventSender
+    ) {
+        log::info!("Connecting to relay: {} (attempt {})", address, self.attempt + 1);
+
+        let opts = DialOpts::unknown_peer_id().address(address).build();
+        self.connection_id = Some(opts.connection_id());
+
+        self.set_status(RelayStatus::Connecting { attempt: self.attempt + 1 }, event_sender);
+
+        if let Err(err) = swarm.dial(opts) {
+            let _ = event_sender.send(P2PEvent::Error { context: "swarm.dial", error: format!("Failed to dial relay: {err}"), severity: ErrorSeverity::Recoverable });
+            self.handle_dial_failure(command_sender, event_sender);
+        }
+    }
+
+    pub fn handle_connected(&mut self, peer_id: PeerId, event_sender: &EventSender) {
+        log::info!("Connected to relay");
+        self.connection_id = None;
+        self.restart();
+        self.peer_id = Some(peer_id);
+
+        self.set_status(RelayStatus::Connected, event_sender);
+    }
+
+    pub fn handle_dial_failure(
+        &mut self,
+        command_sender: &mpsc::Sender<SwarmCommand>,
+        event_sender: &EventSender
+    ) {
+        self.connection_id = None;
+        log::warn!("Relay connection failed");
+        self.schedule_retry(command_sender, event_sender);
+    }
+
+    fn schedule_retry(
+        &mut self,
+        command_sender: &mpsc::Sender<SwarmCommand>,
+        event_sender: &EventSender
+    ) {
+        match relay_retry_delay(self.attempt) {
+            Some(delay) => {
+                self.attempt += 1;
+                log::warn!("Retrying relay in {}s", delay.as_secs());
+
+                self.set_status(RelayStatus::Retrying {
+                    attempt: self.attempt,
+                    delay_secs: delay.as_secs()
+                }, event_sender);
+
+                let command_sender = command_sender.clone();
+                let generation = self.generation;
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = command_sender.send(SwarmCommand::RetryRelay(generation)).await;
+                });
+            },
+            None => {
+                log::warn!("Giving up on relay after {} attempts, continuing without relay", self.attempt);
+                self.set_status(RelayStatus::Failed, event_sender);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_relay_retry_delay_backs_off_exponentially() {
+        assert_eq!(relay_retry_delay(0), Some(Duration::from_secs(1)));
+        assert_eq!(relay_retry_delay(1), Some(Duration::from_secs(2)));
+        assert_eq!(relay_retry_delay(2), Some(Duration::from_secs(4)));
+        assert_eq!(relay_retry_delay(5), Some(Duration::from_secs(32)));
+    }
+
+    #[test]
+    pub fn test_relay_retry_delay_is_capped() {
+        assert_eq!(relay_retry_delay(6), Some(RELAY_RETRY_MAX_DELAY));
+        assert_eq!(relay_retry_delay(RELAY_MAX_RETRIES - 1), Some(RELAY_RETRY_MAX_DELAY));
+    }
+
+    #[test]
+    pub fn test_relay_retry_delay_gives_up_after_max_retries() {
+        assert_eq!(relay_retry_delay(RELAY_MAX_RETRIES), None);
+        assert_eq!(relay_retry_delay(u32::MAX), None);
+    }
+
+    #[tokio::test]
+    pub async fn test_losing_relay_restarts_backoff_and_drops_stale_retries() {
+        let (event_sender, _event_receiver) = crate::p2p::channel::event_channel(8);
+        let (command_sender, mut command_receiver) = mpsc::channel(8);
+        let relay = PeerId::random();
+        let mut connection = RelayConnection { attempt: 3, ..Default::default() };
+
+        connection.handle_connected(relay, &event_sender);
+        connection.handle_connection_closed(&relay, 0, &command_sender, &event_sender);
+
+        assert_eq!(connection.attempt, 1);
+        assert!(matches!(connection.status, Some(RelayStatus::Retrying { attempt: 1, .. })));
+
+        let retry = tokio::time::timeout(Duration::from_secs(5), command_receiver.recv()).await.expect("no retry scheduled");
+        let generation = match retry {
+            Some(SwarmCommand::RetryRelay(generation)) => generation,
+            _ => panic!("expected SwarmCommand::RetryRelay")
+        };
+        assert!(connection.is_current_retry(generation));
+
+        connection.restart();
+        assert!(!connection.is_current_retry(generation));
+    }
+
+    #[test]
+    pub fn test_relay_peer_id_parses_circuit_address() {
+        let relay = PeerId::random();
+        let target = PeerId::random();
+
+        let circuit: Multiaddr = format!("/ip4/1.2.3.4/tcp/4001/p2p/{relay}/p2p-circuit/p2p/{target}").parse().unwrap();
+        let plain: Multiaddr = format!("/ip4/1.2.3.4/tcp/4001/p2p/{relay}").parse().unwrap();
+        let bare: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+
+        assert_eq!(relay_peer_id(&circuit), Some(relay));
+        assert_eq!(relay_peer_id(&plain), Some(relay));
+        assert_eq!(relay_peer_id(&bare), None);
+    }
+}
@@ -0,0 +1,69 @@
+/// Upper bound, in characters, on text received from peers before it is persisted.
+pub const MAX_CONTENT_LENGTH: usize = 4096;
+/// Upper bound, in characters, on the reason attached to a denied friend request.
+pub const MAX_DENY_REASON_LENGTH: usize = 280;
+
+/// Bidirectional overrides and isolates can make text render in a different order than it
+/// was written, e.g. to disguise a link.
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Strips control characters (keeping newlines and tabs) and bidi overrides from text received
+/// from a peer and truncates it to `MAX_CONTENT_LENGTH` characters. This is defense in depth;
+/// the frontend still escapes everything it renders.
+pub fn sanitize_content(content: &str) -> String {
+    content.chars()
+        .filter(|&c| c == '\n' || c == '\t' || !(c.is_control() || is_bidi_control(c)))
+        .take(MAX_CONTENT_LENGTH)
+        .collect()
+}
+
+/// Sanitizes a friend request denial reason and caps it at `MAX_DENY_REASON_LENGTH`
+/// characters. A reason that is blank once sanitized is dropped.
+pub fn sanitize_deny_reason(reason: Option<String>) -> Option<String> {
+    let reason = sanitize_content(reason?.trim())
+        .chars()
+        .take(MAX_DENY_REASON_LENGTH)
+        .collect::<String>();
+
+    let reason = reason.trim();
+    (!reason.is_empty()).then(|| reason.to_string())
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_sanitize_content_strips_control_characters() {
+        assert_eq!(sanitize_content("hi\u{0}\u{7}\u{1b}[31mthere\r"), "hi[31mthere");
+        assert_eq!(sanitize_content("line one\nline\ttwo"), "line one\nline\ttwo");
+        assert_eq!(sanitize_content("evil\u{202E}gpj.exe"), "evilgpj.exe");
+    }
+
+    #[test]
+    pub fn test_sanitize_content_caps_length() {
+        let long = "a".repeat(MAX_CONTENT_LENGTH + 100);
+
+        assert_eq!(sanitize_content(&long).chars().count(), MAX_CONTENT_LENGTH);
+
+        let long_emoji = "😀".repeat(MAX_CONTENT_LENGTH + 1);
+        assert_eq!(sanitize_content(&long_emoji), "😀".repeat(MAX_CONTENT_LENGTH));
+    }
+
+    #[test]
+    pub fn test_sanitize_deny_reason_caps_and_drops_blank_reasons() {
+        assert_eq!(sanitize_deny_reason(Some("  Don't know you\u{0}  ".into())), Some("Don't know you".into()));
+        assert_eq!(sanitize_deny_reason(Some("x".repeat(1000))).unwrap().chars().count(), MAX_DENY_REASON_LENGTH);
+        assert_eq!(sanitize_deny_reason(Some(" \u{7} ".into())), None);
+        assert_eq!(sanitize_deny_reason(None), None);
+    }
+
+    #[test]
+    pub fn test_sanitize_content_preserves_unicode() {
+        let text = "こんにちは 世界 👋🏽 👨‍👩‍👧 café";
+
+        assert_eq!(sanitize_content(text), text);
+    }
+}
@@ -0,0 +1,73 @@
+use serde::Serialize;
+use crate::p2p::types::P2PEvent;
+
+/// Activity since the node started, as opposed to the lifetime counts kept in the database.
+/// Byte counts cover message and post content only, since request-response doesn't report
+/// how much went over the wire.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStats {
+    pub direct_messages_sent: u64,
+    pub direct_messages_received: u64,
+    pub friend_requests_handled: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub posts_synced: u64
+}
+
+impl SessionStats {
+    pub fn record(&mut self, event: &P2PEvent) {
+        match event {
+            P2PEvent::DirectMessageSent(msg) => {
+                self.direct_messages_sent += 1;
+                self.bytes_sent += msg.content.len() as u64;
+            },
+            P2PEvent::DirectMessageReceived(msg) => {
+                self.direct_messages_received += 1;
+                self.bytes_received += msg.content.len() as u64;
+            },
+            P2PEvent::PostSent(post) => self.bytes_sent += post.content.len() as u64,
+            P2PEvent::PostRecieved(post) => self.bytes_received += post.content.len() as u64,
+            P2PEvent::FriendRequestReceived { .. }
+            | P2PEvent::FriendRequestAccepted { .. }
+            | P2PEvent::FriendRequestDenied { .. } => self.friend_requests_handled += 1,
+            _ => {}
+        }
+    }
+
+    pub fn record_posts_synced(&mut self, count: usize) {
+        self.posts_synced += count as u64;
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::PeerId;
+    use crate::db::models::direct_message::DirectMessage;
+
+    #[test]
+    pub fn test_session_stats_count_activity_and_reset() {
+        let (event_sender, _event_receiver) = crate::p2p::channel::event_channel(8);
+        let peer = PeerId::random();
+        let message = DirectMessage::new(1, peer.to_string(), "me".into(), "Hello".into(), 0, None, false, false);
+
+        let _ = event_sender.send(P2PEvent::DirectMessageReceived(message.clone()));
+        let _ = event_sender.send(P2PEvent::DirectMessageSent(message));
+        let _ = event_sender.send(P2PEvent::FriendRequestAccepted { peer });
+        let _ = event_sender.send(P2PEvent::PeerConnected(peer));
+        event_sender.record_posts_synced(3);
+
+        assert_eq!(event_sender.session_stats(), SessionStats {
+            direct_messages_sent: 1,
+            direct_messages_received: 1,
+            friend_requests_handled: 1,
+            bytes_sent: 5,
+            bytes_received: 5,
+            posts_synced: 3
+        });
+
+        event_sender.reset_session_stats();
+        assert_eq!(event_sender.session_stats(), SessionStats::default());
+    }
+}
@@ -0,0 +1,135 @@
+use libp2p::{PeerId, identity::Keypair};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use crate::db;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupReport {
+    /// `false` when the stored peer id doesn't match the one derived from the stored keypair.
+    pub identity_consistent: bool,
+    pub dangling_friends_removed: usize,
+    /// Problems that need the user's attention, each with a suggested fix.
+    pub issues: Vec<String>
+}
+
+/// Checks the stored identity against its keypair and removes friend rows whose user no
+/// longer exists. A missing identity (first run) is not an issue.
+pub fn verify_startup_state(db: Arc<Mutex<Connection>>) -> anyhow::Result<StartupReport> {
+    let mut report = StartupReport { identity_consistent: true, ..StartupReport::default() };
+
+    if let Ok(identity) = db::fetch_identity(db.clone()) {
+        match Keypair::from_protobuf_encoding(&identity.keypair) {
+            Ok(keypair) => {
+                let derived_peer_id = PeerId::from(keypair.public());
+
+                if derived_peer_id.to_string() != identity.peer_id {
+                    log::warn!("Stored peer id {} does not match keypair peer id {}", identity.peer_id, derived_peer_id);
+                    report.identity_consistent = false;
+                    report.issues.push(format!(
+                        "Stored peer id {} does not match the peer id {} derived from the stored keypair. The identity may be corrupted or tampered with; restore it from a backup.",
+                        identity.peer_id, derived_peer_id
+                    ));
+                }
+            },
+            Err(err) => {
+                log::warn!("Stored keypair could not be decoded: {}", err);
+                report.identity_consistent = false;
+                report.issues.push(format!("Stored keypair could not be decoded ({err}). Restore the identity from a backup."));
+            }
+        }
+    }
+
+    report.dangling_friends_removed = db::delete_dangling_friends(db)?;
+
+    if report.dangling_friends_removed > 0 {
+        log::warn!("Removed {} friends referencing missing users", report.dangling_friends_removed);
+    }
+
+    Ok(report)
+}
+
+/// Re-derives the peer id from the stored keypair and stores it if it differs, returning the
+/// corrected peer id. The keypair is the source of truth, so it is never touched.
+pub fn repair_identity_peer_id(db: Arc<Mutex<Connection>>) -> anyhow::Result<Option<String>> {
+    let identity = db::fetch_identity(db.clone())?;
+    let keypair = Keypair::from_protobuf_encoding(&identity.keypair)
+        .map_err(|err| anyhow::anyhow!("Stored keypair could not be decoded: {err}"))?;
+
+    let derived_peer_id = PeerId::from(keypair.public()).to_string();
+
+    if derived_peer_id == identity.peer_id {
+        return Ok(None);
+    }
+
+    log::warn!("Repairing stored peer id {} to match keypair peer id {}", identity.peer_id, derived_peer_id);
+    db::update_identity_peer_id(db, identity.id, derived_peer_id.clone())?;
+
+    Ok(Some(derived_peer_id))
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_verify_startup_state_detects_mismatched_identity() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let keypair = Keypair::generate_ed25519();
+        let other_peer_id = PeerId::random();
+
+        db::create_identity(db.clone(), keypair.to_protobuf_encoding().unwrap(), other_peer_id.to_string(), 4001).unwrap();
+
+        let report = verify_startup_state(db).expect("verify_startup_state failed");
+
+        assert!(!report.identity_consistent);
+        assert_eq!(report.issues.len(), 1);
+        assert!(report.issues[0].contains(&other_peer_id.to_string()));
+    }
+
+    #[test]
+    pub fn test_repair_identity_peer_id_corrects_mismatched_peer_id() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let keypair = Keypair::generate_ed25519();
+        let derived_peer_id = PeerId::from(keypair.public()).to_string();
+        let wrong_peer_id = PeerId::random().to_string();
+
+        db::create_identity(db.clone(), keypair.to_protobuf_encoding().unwrap(), wrong_peer_id.clone(), 4001).unwrap();
+        db::create_user(db.clone(), wrong_peer_id, "/ip4/0.0.0.0/tcp/4001".into(), true).unwrap();
+
+        assert_eq!(repair_identity_peer_id(db.clone()).unwrap(), Some(derived_peer_id.clone()));
+        assert_eq!(db::fetch_identity(db.clone()).unwrap().peer_id, derived_peer_id);
+        assert!(db::fetch_user_by_peer_id(db.clone(), derived_peer_id).unwrap().is_identity);
+        assert!(verify_startup_state(db.clone()).unwrap().identity_consistent);
+
+        assert_eq!(repair_identity_peer_id(db).unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_verify_startup_state_removes_dangling_friends() {
+        let db = db::init_db(":memory:").expect("DB init failed");
+
+        let keypair = Keypair::generate_ed25519();
+        db::create_identity(db.clone(), keypair.to_protobuf_encoding().unwrap(), PeerId::from(keypair.public()).to_string(), 4001).unwrap();
+
+        let user_id = db::create_user(db.clone(), PeerId::random().to_string(), "/ip4/127.0.0.1/tcp/4001".into(), false).unwrap();
+        db::create_friend(db.clone(), user_id).unwrap();
+
+        {
+            let db_guard = db.lock().unwrap();
+            db_guard.execute_batch("PRAGMA foreign_keys = OFF;").unwrap();
+            db_guard.execute("INSERT INTO tbl_friends (user_id, created_at, last_synch) VALUES (999, 0, 0);", ()).unwrap();
+            db_guard.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+        }
+
+        let report = verify_startup_state(db.clone()).expect("verify_startup_state failed");
+
+        assert!(report.identity_consistent);
+        assert!(report.issues.is_empty());
+        assert_eq!(report.dangling_friends_removed, 1);
+        assert_eq!(db::fetch_all_friends(db).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,249 @@
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use crate::db::models::{post::Post, post_tombstone::PostTombstone};
+
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Posts applied from a `SynchResponse` between checks of its cancellation flag.
+pub const SYNC_BATCH_SIZE: usize = 50;
+
+/// Outcome of a sync round across the friend list.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncSummary {
+    pub friends: usize,
+    pub initiated: usize,
+    pub dialed: usize,
+    pub failed: usize
+}
+
+/// Set when the user aborts a sync that is still in flight.
+#[derive(Debug, Default, Clone)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks whether post syncing is enabled, along with the syncs still awaiting a response.
+/// Each friend's sync cursor lives in `tbl_friends.last_synch` and only advances when a
+/// response is fully applied, so a paused or cancelled sync catches up from where it left off.
+pub struct SyncScheduler {
+    enabled: bool,
+    active: HashMap<PeerId, CancelFlag>
+}
+
+impl SyncScheduler {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, active: HashMap::new() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Updates the flag, returning `true` when sync was just re-enabled and a catch-up is due.
+    pub fn set_enabled(&mut self, enabled: bool) -> bool {
+        let resumed = enabled && !self.enabled;
+        self.enabled = enabled;
+        resumed
+    }
+
+    /// Records a `SynchRequest` sent to `peer`, replacing any earlier one.
+    pub fn start_sync(&mut self, peer: PeerId) {
+        self.active.insert(peer, CancelFlag::default());
+    }
+
+    /// Flags the sync with `peer` as cancelled, returning `false` if none is in progress.
+    pub fn cancel_sync(&mut self, peer: &PeerId) -> bool {
+        match self.active.get(peer) {
+            Some(flag) => {
+                flag.cancel();
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.active.len()
+    }
+
+    /// Stops tracking the sync with `peer`, handing back its flag so the response can still
+    /// be abandoned if it was cancelled.
+    pub fn finish_sync(&mut self, peer: &PeerId) -> CancelFlag {
+        self.active.remove(peer).unwrap_or_default()
+    }
+}
+
+/// Applies `items` a batch at a time, checking `cancel` before each batch. Returns `false`
+/// if the sync was cancelled before every item was applied.
+pub fn apply_in_batches<T>(items: Vec<T>, batch_size: usize, cancel: &CancelFlag, mut apply: impl FnMut(T)) -> bool {
+    let mut items = items.into_iter().peekable();
+
+    while items.peek().is_some() {
+        if cancel.is_cancelled() {
+            return false;
+        }
+
+        items.by_ref().take(batch_size).for_each(&mut apply);
+    }
+
+    true
+}
+
+/// Splits posts into those created at or after the cursor and those created before it but
+/// edited at or after it. Timestamps are in seconds, so posts from the cursor's own second are
+/// sent again in case they came after the last response; the receiver skips ones it has. A
+/// post both created and edited since the cursor is only sent as created, since it already
+/// carries the edited content.
+pub fn partition_posts_since(posts: Vec<Post>, since: i64) -> (Vec<Post>, Vec<Post>) {
+    let (created_posts, older_posts): (Vec<Post>, Vec<Post>) = posts.into_iter()
+        .partition(|post| post.created_at >= since);
+
+    let edited_posts = older_posts.into_iter()
+        .filter(|post| post.edited_at.is_some_and(|edited_at| edited_at >= since))
+        .collect();
+
+    (created_posts, edited_posts)
+}
+
+/// Keeps the synced posts `peer` wrote. Friends only hand out their own posts through sync,
+/// so anything else is dropped rather than trusted.
+pub fn retain_authored_by(posts: Vec<Post>, peer: &PeerId) -> Vec<Post> {
+    let author = peer.to_string();
+    let (authored, others): (Vec<Post>, Vec<Post>) = posts.into_iter()
+        .partition(|post| post.author_peer_id == author);
+
+    if !others.is_empty() {
+        log::warn!("Ignoring {} synced posts from {} written by someone else", others.len(), peer);
+    }
+
+    authored
+}
+
+/// The cursor to store once a sync response has been applied: the newest timestamp the
+/// response carried, never moving backwards. Timestamps come from the responder's clock,
+/// so clock skew between peers can't cause posts to be skipped.
+pub fn advance_cursor(cursor: i64, created_posts: &[Post], edited_posts: &[Post], deleted_posts: &[PostTombstone]) -> i64 {
+    created_posts.iter()
+        .chain(edited_posts)
+        .map(|post| post.edited_at.map_or(post.created_at, |edited_at| edited_at.max(post.created_at)))
+        .chain(deleted_posts.iter().map(|tombstone| tombstone.deleted_at))
+        .fold(cursor, i64::max)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn post(id: i64, created_at: i64, edited_at: Option<i64>) -> Post {
+        Post::new(id, "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into(), format!("Post {id}"), created_at, edited_at)
+    }
+
+    #[test]
+    pub fn test_scheduler_reports_enabled_state() {
+        let scheduler = SyncScheduler::new(false);
+        assert!(!scheduler.is_enabled());
+
+        let scheduler = SyncScheduler::new(true);
+        assert!(scheduler.is_enabled());
+    }
+
+    #[test]
+    pub fn test_scheduler_catches_up_from_pause_when_re_enabled() {
+        let mut scheduler = SyncScheduler::new(true);
+
+        assert!(!scheduler.set_enabled(false));
+        assert!(!scheduler.is_enabled());
+
+        assert!(scheduler.set_enabled(true));
+        assert!(!scheduler.set_enabled(true));
+        assert!(scheduler.is_enabled());
+    }
+
+    #[test]
+    pub fn test_scheduler_only_cancels_active_syncs() {
+        let mut scheduler = SyncScheduler::new(true);
+        let peer = PeerId::random();
+
+        assert!(!scheduler.cancel_sync(&peer));
+
+        scheduler.start_sync(peer);
+        assert!(scheduler.cancel_sync(&peer));
+        assert!(scheduler.finish_sync(&peer).is_cancelled());
+        assert!(!scheduler.finish_sync(&peer).is_cancelled());
+    }
+
+    #[test]
+    pub fn test_cancel_flag_short_circuits_batches() {
+        let cancel = CancelFlag::default();
+        let mut applied = Vec::new();
+
+        let completed = apply_in_batches((0..10).collect(), 3, &cancel, |item| {
+            applied.push(item);
+            if item == 4 {
+                cancel.cancel();
+            }
+        });
+
+        assert!(!completed);
+        assert_eq!(applied, vec![0, 1, 2, 3, 4, 5]);
+
+        let mut applied = Vec::new();
+        assert!(apply_in_batches((0..10).collect(), 3, &CancelFlag::default(), |item| applied.push(item)));
+        assert_eq!(applied.len(), 10);
+    }
+
+    #[test]
+    pub fn test_partition_posts_since_splits_around_cursor() {
+        let posts = vec![
+            post(1, 50, None),
+            post(2, 100, None),
+            post(3, 101, None),
+            post(4, 50, Some(100)),
+            post(5, 50, Some(150)),
+            post(6, 120, Some(130))
+        ];
+
+        let (created_posts, edited_posts) = partition_posts_since(posts, 100);
+
+        assert_eq!(created_posts.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2, 3, 6]);
+        assert_eq!(edited_posts.iter().map(|p| p.id).collect::<Vec<_>>(), vec![4, 5]);
+    }
+
+    #[test]
+    pub fn test_retain_authored_by_drops_posts_by_other_authors() {
+        let author: PeerId = "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".parse().unwrap();
+        let mut forged = post(2, 100, None);
+        forged.author_peer_id = PeerId::random().to_string();
+
+        let posts = retain_authored_by(vec![post(1, 100, None), forged], &author);
+
+        assert_eq!(posts.iter().map(|p| p.id).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    pub fn test_advance_cursor_uses_newest_timestamp() {
+        let created_posts = vec![post(1, 120, None), post(2, 110, Some(115))];
+        let edited_posts = vec![post(3, 50, Some(140))];
+
+        let deleted_posts = vec![PostTombstone::new(1, "12D3KooWHGLsSWMsiU35gg5zUD9zmHpLrdwpnftASGFwpArLkTsK".into(), "Gone".into(), 170)];
+
+        assert_eq!(advance_cursor(100, &created_posts, &edited_posts, &[]), 140);
+        assert_eq!(advance_cursor(100, &created_posts, &[], &[]), 120);
+        assert_eq!(advance_cursor(100, &[post(4, 101, Some(160))], &[], &[]), 160);
+        assert_eq!(advance_cursor(100, &created_posts, &edited_posts, &deleted_posts), 170);
+        assert_eq!(advance_cursor(200, &created_posts, &edited_posts, &[]), 200);
+        assert_eq!(advance_cursor(100, &[], &[], &[]), 100);
+    }
+}
@@ -0,0 +1,106 @@
+use libp2p::{PeerId, gossipsub::TopicHash};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Snapshot of the node's gossipsub membership, as seen by the frontend.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkStats {
+    pub connected_peers: usize,
+    /// Number of remote peers subscribed to each topic we know about.
+    pub topic_subscribers: HashMap<String, usize>,
+    /// Connected friends that aren't subscribed to the posts topic, so won't receive posts.
+    pub unsubscribed_friends: Vec<String>
+}
+
+/// Remote peers subscribed to each gossipsub topic, maintained from `Subscribed` and
+/// `Unsubscribed` events.
+#[derive(Default)]
+pub struct TopicSubscribers {
+    topics: HashMap<TopicHash, HashSet<PeerId>>
+}
+
+impl TopicSubscribers {
+    pub fn handle_subscribed(&mut self, peer: PeerId, topic: TopicHash) {
+        self.topics.entry(topic).or_default().insert(peer);
+    }
+
+    pub fn handle_unsubscribed(&mut self, peer: &PeerId, topic: &TopicHash) {
+        if let Some(subscribers) = self.topics.get_mut(topic) {
+            subscribers.remove(peer);
+
+            if subscribers.is_empty() {
+                self.topics.remove(topic);
+            }
+        }
+    }
+
+    /// Gossipsub drops a peer's subscriptions once it fully disconnects without reporting them.
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.topics.retain(|_, subscribers| {
+            subscribers.remove(peer);
+            !subscribers.is_empty()
+        });
+    }
+
+    pub fn is_subscribed(&self, peer: &PeerId, topic: &TopicHash) -> bool {
+        self.topics.get(topic).is_some_and(|subscribers| subscribers.contains(peer))
+    }
+
+    pub fn subscriber_counts(&self) -> HashMap<String, usize> {
+        self.topics.iter()
+            .map(|(topic, subscribers)| (topic.to_string(), subscribers.len()))
+            .collect()
+    }
+
+    /// Friends in `connected` that posts on `topic` won't propagate to.
+    pub fn unsubscribed_friends(&self, topic: &TopicHash, friend_list: &[PeerId], connected: impl Fn(&PeerId) -> bool) -> Vec<PeerId> {
+        friend_list.iter()
+            .filter(|friend| connected(friend) && !self.is_subscribed(friend, topic))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use libp2p::gossipsub::IdentTopic;
+
+    #[test]
+    pub fn test_topic_subscribers_follow_subscription_events() {
+        let mut subscribers = TopicSubscribers::default();
+        let posts = IdentTopic::new("posts").hash();
+        let other = IdentTopic::new("other").hash();
+        let alice = PeerId::random();
+        let bob = PeerId::random();
+
+        subscribers.handle_subscribed(alice, posts.clone());
+        subscribers.handle_subscribed(bob, posts.clone());
+        subscribers.handle_subscribed(bob, other.clone());
+        assert_eq!(subscribers.subscriber_counts().get(posts.as_str()), Some(&2));
+
+        subscribers.handle_unsubscribed(&alice, &posts);
+        assert!(!subscribers.is_subscribed(&alice, &posts));
+        assert!(subscribers.is_subscribed(&bob, &posts));
+
+        subscribers.remove_peer(&bob);
+        assert!(subscribers.subscriber_counts().is_empty());
+    }
+
+    #[test]
+    pub fn test_unsubscribed_friends_only_lists_connected_friends() {
+        let mut subscribers = TopicSubscribers::default();
+        let posts = IdentTopic::new("posts").hash();
+        let subscribed = PeerId::random();
+        let unsubscribed = PeerId::random();
+        let offline = PeerId::random();
+
+        subscribers.handle_subscribed(subscribed, posts.clone());
+
+        let friend_list = vec![subscribed, unsubscribed, offline];
+        let missing = subscribers.unsubscribed_friends(&posts, &friend_list, |peer| *peer != offline);
+
+        assert_eq!(missing, vec![unsubscribed]);
+    }
+}
@@ -0,0 +1,224 @@
+use libp2p::{PeerId, request_response, swarm::SwarmEvent};
+use std::collections::HashSet;
+use crate::logger::TRACE_TARGET;
+use crate::p2p::config::EnclaveNetworkBehaviourEvent;
+use crate::p2p::types::{P2PMessage, SwarmCommand};
+
+/// Peers whose swarm events and commands are logged at debug level, so a single friend's
+/// connectivity can be debugged without verbose logging for everyone. Commands are traced
+/// before they run, which covers the database work done on the peer's behalf. Events are
+/// summarised rather than dumped, so message contents never end up in the log files.
+#[derive(Default)]
+pub struct PeerTracer {
+    peers: HashSet<PeerId>
+}
+
+impl PeerTracer {
+    /// Returns whether any peer is still being traced.
+    pub fn set_traced(&mut self, peer: PeerId, enabled: bool) -> bool {
+        if enabled {
+            self.peers.insert(peer);
+        } else {
+            self.peers.remove(&peer);
+        }
+
+        !self.peers.is_empty()
+    }
+
+    pub fn is_traced(&self, peer: &PeerId) -> bool {
+        self.peers.contains(peer)
+    }
+
+    /// The trace line for `peer`, or `None` if it isn't being traced.
+    pub fn line(&self, peer: &PeerId, detail: impl FnOnce() -> String) -> Option<String> {
+        self.is_traced(peer).then(|| format!("[trace:{peer}] {}", detail()))
+    }
+
+    pub fn trace_swarm_event(&self, event: &SwarmEvent<EnclaveNetworkBehaviourEvent>) {
+        if self.peers.is_empty() {
+            return;
+        }
+
+        if let Some(line) = swarm_event_peer(event).and_then(|peer| self.line(&peer, || describe_swarm_event(event))) {
+            log::debug!(target: TRACE_TARGET, "{line}");
+        }
+    }
+
+    pub fn trace_command(&self, cmd: &SwarmCommand) {
+        if self.peers.is_empty() {
+            return;
+        }
+
+        if let Some(line) = command_peer(cmd).and_then(|(peer, name)| self.line(&peer, || format!("command {name}"))) {
+            log::debug!(target: TRACE_TARGET, "{line}");
+        }
+    }
+}
+
+fn swarm_event_peer(event: &SwarmEvent<EnclaveNetworkBehaviourEvent>) -> Option<PeerId> {
+    match event {
+        SwarmEvent::ConnectionEstablished { peer_id, .. }
+        | SwarmEvent::ConnectionClosed { peer_id, .. } => Some(*peer_id),
+        SwarmEvent::OutgoingConnectionError { peer_id, .. }
+        | SwarmEvent::Dialing { peer_id, .. } => *peer_id,
+        SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::RequestResponse(event)) => match event {
+            request_response::Event::Message { peer, .. }
+            | request_response::Event::OutboundFailure { peer, .. }
+            | request_response::Event::InboundFailure { peer, .. }
+            | request_response::Event::ResponseSent { peer, .. } => Some(*peer)
+        },
+        SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Gossipsub(event)) => match event {
+            libp2p::gossipsub::Event::Message { propagation_source, .. } => Some(*propagation_source),
+            libp2p::gossipsub::Event::Subscribed { peer_id, .. }
+            | libp2p::gossipsub::Event::Unsubscribed { peer_id, .. } => Some(*peer_id),
+            _ => None
+        },
+        SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Ping(event)) => Some(event.peer),
+        SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Dcutr(event)) => Some(event.remote_peer_id),
+        _ => None
+    }
+}
+
+/// A summary of `event` for the trace, leaving out anything a friend sent us.
+fn describe_swarm_event(event: &SwarmEvent<EnclaveNetworkBehaviourEvent>) -> String {
+    match event {
+        SwarmEvent::ConnectionEstablished { endpoint, num_established, .. } => {
+            let direction = if endpoint.is_dialer() { "outbound" } else { "inbound" };
+            format!("connection established ({direction}, {num_established} open)")
+        },
+        SwarmEvent::ConnectionClosed { cause, num_established, .. } => match cause {
+            Some(cause) => format!("connection closed ({num_established} open): {cause}"),
+            None => format!("connection closed ({num_established} open)")
+        },
+        SwarmEvent::OutgoingConnectionError { error, .. } => format!("outgoing connection error: {error}"),
+        SwarmEvent::Dialing { .. } => "dialing".into(),
+        SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::RequestResponse(event)) => match event {
+            request_response::Event::Message { message: request_response::Message::Request { request_id, request, .. }, .. } => {
+                format!("request {request_id}: {}", message_kind(request))
+            },
+            request_response::Event::Message { message: request_response::Message::Response { request_id, response }, .. } => {
+                format!("response {request_id}: {}", message_kind(response))
+            },
+            request_response::Event::OutboundFailure { request_id, error, .. } => format!("request {request_id} failed: {error}"),
+            request_response::Event::InboundFailure { request_id, error, .. } => format!("inbound request {request_id} failed: {error}"),
+            request_response::Event::ResponseSent { request_id, .. } => format!("response sent for {request_id}")
+        },
+        SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Gossipsub(event)) => match event {
+            libp2p::gossipsub::Event::Message { message, .. } => {
+                format!("gossipsub message on {} ({} bytes)", message.topic, message.data.len())
+            },
+            libp2p::gossipsub::Event::Subscribed { topic, .. } => format!("subscribed to {topic}"),
+            libp2p::gossipsub::Event::Unsubscribed { topic, .. } => format!("unsubscribed from {topic}"),
+            _ => "gossipsub event".into()
+        },
+        SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Ping(event)) => match &event.result {
+            Ok(rtt) => format!("ping {rtt:?}"),
+            Err(err) => format!("ping failed: {err}")
+        },
+        SwarmEvent::Behaviour(EnclaveNetworkBehaviourEvent::Dcutr(event)) => match &event.result {
+            Ok(_) => "hole punch succeeded".into(),
+            Err(err) => format!("hole punch failed: {err}")
+        },
+        _ => "swarm event".into()
+    }
+}
+
+/// The variant of `message`, without its contents.
+fn message_kind(message: &P2PMessage) -> &'static str {
+    match message {
+        P2PMessage::FriendRequest(_) => "FriendRequest",
+        P2PMessage::FriendRequestResponse(_) => "FriendRequestResponse",
+        P2PMessage::DirectMessage(_) => "DirectMessage",
+        P2PMessage::SynchRequest(_) => "SynchRequest",
+        P2PMessage::SynchResponse(_) => "SynchResponse",
+        P2PMessage::NicknameUpdate { .. } => "NicknameUpdate",
+        P2PMessage::AddressRequest => "AddressRequest",
+        P2PMessage::AddressResponse { .. } => "AddressResponse",
+        P2PMessage::LatencyProbe { .. } => "LatencyProbe",
+        P2PMessage::LatencyProbeReply { .. } => "LatencyProbeReply",
+        P2PMessage::BufferedPost(_) => "BufferedPost",
+        P2PMessage::BufferedPostAck => "BufferedPostAck",
+        P2PMessage::FriendRequestAck => "FriendRequestAck",
+        P2PMessage::ReadReceipt => "ReadReceipt"
+    }
+}
+
+/// The peer a command acts on, and the command's name for the trace.
+fn command_peer(cmd: &SwarmCommand) -> Option<(PeerId, &'static str)> {
+    match cmd {
+        SwarmCommand::SendDirectMessage { peer, .. } => Some((*peer, "SendDirectMessage")),
+        SwarmCommand::SendFriendRequest { peer, .. } => Some((*peer, "SendFriendRequest")),
+        SwarmCommand::AcceptFriendRequest(peer) => Some((*peer, "AcceptFriendRequest")),
+        SwarmCommand::DenyFriendRequest { peer, .. } => Some((*peer, "DenyFriendRequest")),
+        SwarmCommand::MarkFriendRequestSeen(peer) => Some((*peer, "MarkFriendRequestSeen")),
+        SwarmCommand::GetDirectMessages { peer_id, .. } => Some((*peer_id, "GetDirectMessages")),
+        SwarmCommand::MarkConversationRead { peer_id, .. } => Some((*peer_id, "MarkConversationRead")),
+        SwarmCommand::GetPendingMessages { peer_id, .. } => Some((*peer_id, "GetPendingMessages")),
+        SwarmCommand::GetMessagesInRange { peer_id, .. } => Some((*peer_id, "GetMessagesInRange")),
+        SwarmCommand::GetHolepunchStatus { peer_id, .. } => Some((*peer_id, "GetHolepunchStatus")),
+        SwarmCommand::GetConnectionType { peer_id, .. } => Some((*peer_id, "GetConnectionType")),
+        SwarmCommand::PeerSupportsFeature { peer_id, .. } => Some((*peer_id, "PeerSupportsFeature")),
+        SwarmCommand::CancelSync(peer) => Some((*peer, "CancelSync")),
+        SwarmCommand::AddFriendDirectly { peer, .. } => Some((*peer, "AddFriendDirectly")),
+        SwarmCommand::RefreshPeerAddress(peer) => Some((*peer, "RefreshPeerAddress")),
+        SwarmCommand::DisconnectPeer(peer) => Some((*peer, "DisconnectPeer")),
+        SwarmCommand::MeasureLatency { peer_id, .. } => Some((*peer_id, "MeasureLatency")),
+        SwarmCommand::GetClockSkew { peer_id, .. } => Some((*peer_id, "GetClockSkew")),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_trace_lines_are_prefixed_and_limited_to_traced_peer() {
+        let mut tracer = PeerTracer::default();
+        let traced = PeerId::random();
+        let other = PeerId::random();
+
+        assert!(tracer.set_traced(traced, true));
+
+        let line = command_peer(&SwarmCommand::DisconnectPeer(traced))
+            .and_then(|(peer, name)| tracer.line(&peer, || format!("command {name}")));
+        assert_eq!(line, Some(format!("[trace:{traced}] command DisconnectPeer")));
+
+        let line = command_peer(&SwarmCommand::DisconnectPeer(other))
+            .and_then(|(peer, name)| tracer.line(&peer, || format!("command {name}")));
+        assert_eq!(line, None);
+
+        assert!(!tracer.set_traced(traced, false));
+        assert!(tracer.line(&traced, String::new).is_none());
+    }
+
+    #[test]
+    pub fn test_trace_lines_reach_log_file_below_its_level() {
+        use log::{Level, LevelFilter, Log, Record};
+        use crate::logger::Logger;
+
+        let path = std::env::temp_dir().join(format!("enclave-trace-{}.log", rand::random::<u64>()));
+        let logger = Logger::new(path.to_str().unwrap(), LevelFilter::Info).expect("Logger::new failed");
+        let mut tracer = PeerTracer::default();
+        let traced = PeerId::random();
+
+        tracer.set_traced(traced, true);
+
+        let line = tracer.line(&traced, || format!("request 1: {}", message_kind(&P2PMessage::ReadReceipt))).unwrap();
+        logger.log(&Record::builder().target(TRACE_TARGET).level(Level::Debug).args(format_args!("{line}")).build());
+        logger.log(&Record::builder().target("enclave").level(Level::Debug).args(format_args!("untraced")).build());
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, format!("[DEBUG] [trace:{traced}] request 1: ReadReceipt\n"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    pub fn test_message_kind_leaves_out_contents() {
+        let nickname = P2PMessage::NicknameUpdate { nickname: "Secret Alias".into() };
+
+        assert_eq!(message_kind(&nickname), "NicknameUpdate");
+        assert_eq!(message_kind(&P2PMessage::ReadReceipt), "ReadReceipt");
+    }
+}
@@ -0,0 +1,169 @@
+use libp2p::{PeerId, identity::{Keypair, ed25519}};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use crate::db;
+use crate::p2p::crypto::peer_id_to_public_key;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptMessage {
+    pub from_peer_id: String,
+    pub to_peer_id: String,
+    pub content: String,
+    pub created_at: i64,
+    pub edited_at: Option<i64>
+}
+
+/// A conversation as recorded by `signer_peer_id`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transcript {
+    pub signer_peer_id: String,
+    pub peer_id: String,
+    pub exported_at: i64,
+    pub messages: Vec<TranscriptMessage>
+}
+
+/// A transcript signed by its exporter's identity key. The signature only proves what the
+/// signer recorded, not that the other peer agrees with it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedTranscript {
+    pub transcript: Transcript,
+    /// Hex-encoded ed25519 signature over the JSON encoding of `transcript`.
+    pub signature: String
+}
+
+pub fn sign_transcript(keypair: &Keypair, transcript: Transcript) -> anyhow::Result<SignedTranscript> {
+    let signature = keypair.sign(&serde_json::to_vec(&transcript)?)?;
+
+    Ok(SignedTranscript { transcript, signature: encode_hex(&signature) })
+}
+
+/// Builds the conversation with `peer_id` from the local database and signs it with the
+/// stored identity keypair, returning the signed transcript as JSON.
+pub fn export_signed_transcript(db: Arc<Mutex<Connection>>, peer_id: &PeerId, exported_at: i64) -> anyhow::Result<String> {
+    let identity = db::fetch_identity(db.clone())?;
+    let keypair = Keypair::from_protobuf_encoding(&identity.keypair)?;
+
+    let mut direct_messages = db::fetch_direct_messages_with_peer(db, peer_id.to_string())?;
+    direct_messages.sort_by_key(|dm| (dm.created_at, dm.id));
+
+    let transcript = Transcript {
+        signer_peer_id: PeerId::from(keypair.public()).to_string(),
+        peer_id: peer_id.to_string(),
+        exported_at,
+        messages: direct_messages.into_iter()
+            .map(|dm| TranscriptMessage {
+                from_peer_id: dm.from_peer_id,
+                to_peer_id: dm.to_peer_id,
+                content: dm.content,
+                created_at: dm.created_at,
+                edited_at: dm.edited_at
+            })
+            .collect()
+    };
+
+    Ok(serde_json::to_string(&sign_transcript(&keypair, transcript)?)?)
+}
+
+/// Checks a signed transcript's signature against the public key embedded in its claimed
+/// signer's peer id. Returns `false` if the transcript or signature was altered; malformed
+/// input is an error.
+pub fn verify_signed_transcript(data: &str) -> anyhow::Result<bool> {
+    let signed = serde_json::from_str::<SignedTranscript>(data)?;
+
+    let signer = signed.transcript.signer_peer_id.parse::<PeerId>()?;
+    let public_key = ed25519::PublicKey::try_from_bytes(&peer_id_to_public_key(&signer)?)?;
+    let signature = decode_hex(&signed.signature)?;
+
+    Ok(public_key.verify(&serde_json::to_vec(&signed.transcript)?, &signature))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!("Signature has an odd number of hex digits"));
+    }
+
+    // Working on bytes keeps a multibyte character from splitting a slice mid-codepoint.
+    let digit = |byte: u8| (byte as char).to_digit(16);
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match (digit(pair[0]), digit(pair[1])) {
+            (Some(high), Some(low)) => Ok((high << 4 | low) as u8),
+            _ => Err(anyhow::anyhow!("Signature is not valid hex"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn export(db: &Arc<Mutex<Connection>>, peer: &PeerId) -> String {
+        export_signed_transcript(db.clone(), peer, 1_738_324_800).expect("export_signed_transcript failed")
+    }
+
+    fn setup() -> (Arc<Mutex<Connection>>, PeerId) {
+        let db = db::init_db(":memory:").expect("DB init failed");
+        let keypair = Keypair::generate_ed25519();
+        let local_peer_id = PeerId::from(keypair.public());
+        let peer = PeerId::random();
+
+        db::create_identity(db.clone(), keypair.to_protobuf_encoding().unwrap(), local_peer_id.to_string(), 4001).unwrap();
+        db::create_direct_message(db.clone(), peer.to_string(), local_peer_id.to_string(), "You owe me 10".into()).unwrap();
+        db::create_outbound_direct_message(db.clone(), local_peer_id.to_string(), peer.to_string(), "I paid you yesterday".into()).unwrap();
+
+        (db, peer)
+    }
+
+    #[test]
+    pub fn test_signed_transcript_round_trips() {
+        let (db, peer) = setup();
+        let data = export(&db, &peer);
+
+        assert!(verify_signed_transcript(&data).expect("verify_signed_transcript failed"));
+
+        let signed = serde_json::from_str::<SignedTranscript>(&data).unwrap();
+        assert_eq!(signed.transcript.peer_id, peer.to_string());
+        assert_eq!(signed.transcript.messages.len(), 2);
+    }
+
+    #[test]
+    pub fn test_tampered_transcript_fails_verification() {
+        let (db, peer) = setup();
+        let mut signed = serde_json::from_str::<SignedTranscript>(&export(&db, &peer)).unwrap();
+
+        signed.transcript.messages[1].content = "I never paid you".into();
+        assert!(!verify_signed_transcript(&serde_json::to_string(&signed).unwrap()).unwrap());
+    }
+
+    #[test]
+    pub fn test_transcript_signed_by_another_key_fails_verification() {
+        let (db, peer) = setup();
+        let mut signed = serde_json::from_str::<SignedTranscript>(&export(&db, &peer)).unwrap();
+
+        let impostor = Keypair::generate_ed25519();
+        signed.transcript.signer_peer_id = PeerId::from(impostor.public()).to_string();
+        assert!(!verify_signed_transcript(&serde_json::to_string(&signed).unwrap()).unwrap());
+
+        assert!(verify_signed_transcript("not a transcript").is_err());
+    }
+
+    #[test]
+    pub fn test_decode_hex_rejects_malformed_input() {
+        assert_eq!(decode_hex("0aff").unwrap(), vec![0x0a, 0xff]);
+
+        assert!(decode_hex("abc").is_err());
+        assert!(decode_hex("zz").is_err());
+        assert!(decode_hex("+f").is_err());
+        assert!(decode_hex("aéa").is_err());
+        assert!(decode_hex("éé").is_err());
+    }
+}
@@ -1,8 +1,11 @@
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tokio::sync::oneshot::Sender;
 
-use crate::db::models::{direct_message::DirectMessage, friend_request::FriendRequest, post::Post};
+use crate::p2p::sanitize::sanitize_deny_reason;
+use crate::p2p::{connection::ConnectionType, holepunch::HolepunchStatus, relay::RelayInfo, sync::SyncSummary};
+use crate::db::models::{conversation_summary::ConversationSummary, dead_letter::DeadLetter, direct_message::DirectMessage, friend_request::FriendRequest, identity::Identity, peer_appearance::PeerAppearance, post::Post, post_tombstone::PostTombstone};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -16,14 +19,30 @@ pub struct SynchRequest {
 pub struct SynchResponse {
     pub created_posts: Vec<Post>,
     pub edited_posts: Vec<Post>,
-    pub sender: String
+    pub sender: String,
+    /// Missing from responses sent by older peers.
+    #[serde(default)]
+    pub deleted_posts: Vec<PostTombstone>
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FriendRequestResponse {
     pub accepted: bool,
-    pub multiaddr: String
+    pub multiaddr: String,
+    /// Optional explanation for a denial. Missing from responses sent by older peers.
+    #[serde(default)]
+    pub reason: Option<String>
+}
+
+impl FriendRequestResponse {
+    pub fn denied(reason: Option<String>) -> Self {
+        Self {
+            accepted: false,
+            multiaddr: String::new(),
+            reason: sanitize_deny_reason(reason)
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,13 +53,126 @@ pub struct MyInfo {
     pub multiaddr: String
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityInfo {
+    pub peer_id: String,
+    pub created_at: i64,
+    pub account_age_secs: i64
+}
+
+impl IdentityInfo {
+    pub fn from_identity(identity: &Identity, now: i64) -> Self {
+        Self {
+            peer_id: identity.peer_id.clone(),
+            created_at: identity.created_at,
+            account_age_secs: (now - identity.created_at).max(0)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FriendSummary {
+    pub peer_id: String,
+    pub nickname: Option<String>,
+    pub online: bool
+}
+
+/// A friend as shown in the friend list, with their conversation's latest message.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FriendProfile {
+    pub peer_id: String,
+    pub nickname: Option<String>,
+    pub online: bool,
+    pub unread_count: usize,
+    pub last_message: Option<ConversationSummary>,
+    pub appearance: PeerAppearance
+}
+
+/// Everything the frontend needs to render its first screen, fetched in one round trip.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitialState {
+    pub identity: IdentityInfo,
+    pub friends: Vec<FriendSummary>,
+    pub inbound_friend_requests: Vec<FriendRequest>,
+    pub conversations: Vec<ConversationSummary>
+}
+
+/// Work that would be lost or delayed if the app closed now.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingWork {
+    /// Direct messages waiting for their recipient to come online.
+    pub undelivered_messages: usize,
+    /// Deliveries of posts to friends who were offline when they were published.
+    pub buffered_posts: usize,
+    /// Friend requests we've sent that haven't been answered.
+    pub pending_friend_requests: usize,
+    pub active_syncs: usize
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum RelayStatus {
+    Connecting { attempt: u32 },
+    Connected,
+    Retrying { attempt: u32, delay_secs: u64 },
+    Failed
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum P2PMessage {
     FriendRequest(FriendRequest),
     FriendRequestResponse(FriendRequestResponse),
     DirectMessage(DirectMessage),
     SynchRequest(SynchRequest),
-    SynchResponse(SynchResponse)
+    SynchResponse(SynchResponse),
+    NicknameUpdate { nickname: String },
+    AddressRequest,
+    AddressResponse { addresses: Vec<String> },
+    LatencyProbe { nonce: u64, sent_at: i64 },
+    /// `replied_at` is the responder's clock in unix milliseconds, absent from older peers.
+    LatencyProbeReply { nonce: u64, sent_at: i64, #[serde(default)] replied_at: Option<i64> },
+    /// A post published while the recipient was offline, delivered when they reconnect.
+    BufferedPost(Post),
+    /// The recipient has stored a buffered post, sent as the response to it.
+    BufferedPostAck,
+    /// The recipient has stored our friend request. Sent as the response to the request, and
+    /// again on reconnect while the request is unanswered in case that response was lost.
+    FriendRequestAck,
+    /// The recipient has read every message delivered to them so far.
+    ReadReceipt
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ErrorSeverity {
+    Warning,
+    Recoverable,
+    Fatal
+}
+
+impl ErrorSeverity {
+    /// Storage failures the app can't continue past (a corrupt, unreadable or full database)
+    /// are fatal; any other database error is recoverable.
+    pub fn from_db_error(err: &anyhow::Error) -> Self {
+        use rusqlite::ErrorCode;
+
+        match err.downcast_ref::<rusqlite::Error>() {
+            Some(rusqlite::Error::SqliteFailure(failure, _)) => match failure.code {
+                ErrorCode::DatabaseCorrupt
+                | ErrorCode::NotADatabase
+                | ErrorCode::CannotOpen
+                | ErrorCode::DiskFull
+                | ErrorCode::ReadOnly
+                | ErrorCode::SystemIoFailure => Self::Fatal,
+                _ => Self::Recoverable
+            },
+            _ => Self::Recoverable
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -53,9 +185,23 @@ pub enum P2PEvent {
     PeerDisconnected(PeerId),
     FriendRequestReceived { from: PeerId, request: FriendRequest },
     FriendRequestAccepted { peer: PeerId },
-    FriendRequestDenied { peer: PeerId },
-    Error { context: &'static str, error: String },
-    PostSynch
+    FriendRequestAcknowledged { peer: PeerId },
+    FriendRequestDenied { peer: PeerId, reason: Option<String> },
+    FriendLimitReached { peer: PeerId, limit: usize },
+    Error { context: &'static str, error: String, severity: ErrorSeverity },
+    PostSynch,
+    RelayStatusChanged(RelayStatus),
+    NodeStalled { silent_for_secs: u64 },
+    FriendNicknameChanged { peer: PeerId, nickname: String },
+    MessageDeadLettered(DeadLetter),
+    HolepunchResult { peer: PeerId, status: HolepunchStatus },
+    ConnectionTypeChanged { peer: PeerId, connection_type: ConnectionType },
+    SyncCancelled { peer: PeerId },
+    MessagesRead { peer: PeerId },
+    MessagesExpired { ids: Vec<i64> },
+    ClockSkewDetected { peer: PeerId, offset_ms: i64 },
+    DirectMessageQuarantined { peer: PeerId },
+    SafetyNumberChanged { peer: PeerId, new_peer: PeerId, notice: DirectMessage }
 }
 
 pub(crate) enum SwarmCommand {
@@ -63,11 +209,60 @@ pub(crate) enum SwarmCommand {
     SendDirectMessage { peer: PeerId, address: libp2p::Multiaddr, content: String },
     SendFriendRequest { peer: PeerId, address: libp2p::Multiaddr, message: String },
     AcceptFriendRequest(PeerId),
-    DenyFriendRequest(PeerId),
+    DenyFriendRequest { peer: PeerId, reason: Option<String> },
     GetFriendList(Sender<Vec<PeerId>>),
+    CountOnlineFriends(Sender<usize>),
+    GetPendingWork(Sender<anyhow::Result<PendingWork>>),
     GetInboundFriendRequests(Sender<Vec<FriendRequest>>),
+    MarkFriendRequestSeen(PeerId),
+    GetFriendRequestCount(Sender<usize>),
+    ClearAllFriendRequests,
     GetDirectMessages { sender: Sender<Vec<DirectMessage>>, peer_id: PeerId },
+    MarkConversationRead { sender: Sender<anyhow::Result<usize>>, peer_id: PeerId },
+    MarkAllRead(Sender<anyhow::Result<usize>>),
+    GetPendingCounts(Sender<HashMap<PeerId, usize>>),
+    GetPendingMessages { sender: Sender<Vec<DirectMessage>>, peer_id: PeerId },
+    GetMessagesInRange { sender: Sender<Vec<DirectMessage>>, peer_id: PeerId, start: i64, end: i64 },
+    GetDeadLetters(Sender<Vec<DeadLetter>>),
+    GetConversations { sender: Sender<Vec<ConversationSummary>>, blocked: bool },
+    GetHolepunchStatus { sender: Sender<HolepunchStatus>, peer_id: PeerId },
+    GetConnectionType { sender: Sender<Option<ConnectionType>>, peer_id: PeerId },
+    PeerSupportsFeature { sender: Sender<anyhow::Result<bool>>, peer_id: PeerId, feature: String },
+    GetNetworkStats(Sender<crate::p2p::topic_mesh::NetworkStats>),
+    GetRelayInfo(Sender<Option<RelayInfo>>),
+    RetryDeadLetter(i64),
     LoadFeed(Sender<Vec<Post>>),
-    LoadBoard { sender: Sender<Vec<Post>>, peer_id: PeerId },
-    ConnectToRelay(libp2p::Multiaddr)
+    ConnectToRelay(libp2p::Multiaddr),
+    BroadcastNickname(String),
+    SetSyncEnabled(bool),
+    SyncAllFriends(Sender<Option<SyncSummary>>),
+    CancelSync(PeerId),
+    AddFriendDirectly { sender: Sender<anyhow::Result<()>>, peer: PeerId, address: libp2p::Multiaddr, nickname: Option<String> },
+    SetConnectionPolicy(crate::p2p::connection_policy::ConnectionPolicy),
+    GetConnectionPolicy(Sender<crate::p2p::connection_policy::ConnectionPolicy>),
+    RefreshPeerAddress(PeerId),
+    GetInitialState(Sender<anyhow::Result<InitialState>>),
+    GetFriendProfiles(Sender<anyhow::Result<Vec<FriendProfile>>>),
+    DisconnectPeer(PeerId),
+    ReloadFriendList(Sender<anyhow::Result<usize>>),
+    #[cfg(feature = "debug-events")]
+    GetRecentEvents { sender: Sender<Vec<crate::p2p::recent_events::RecentEvent>>, count: usize },
+    MeasureLatency { sender: Sender<anyhow::Result<u64>>, peer_id: PeerId },
+    GetClockSkew { sender: Sender<anyhow::Result<i64>>, peer_id: PeerId },
+    ProbeAddress { sender: Sender<crate::p2p::address_probe::AddressProbeResult>, address: libp2p::Multiaddr },
+    ExpireAddressProbe(libp2p::swarm::ConnectionId),
+    ExpireProbation(PeerId),
+    TracePeer { peer: PeerId, enabled: bool, sender: Sender<bool> },
+    GetExplicitPeers(Sender<Vec<PeerId>>),
+    ResyncExplicitPeers(Sender<crate::p2p::explicit_peers::ExplicitPeersResync>),
+    GetSessionStats(Sender<crate::p2p::session_stats::SessionStats>),
+    ResetSessionStats,
+    #[cfg(feature = "net-sim")]
+    SetSimulatedLatency(std::time::Duration),
+    #[cfg(feature = "net-sim")]
+    SetPacketLoss(u8),
+    #[cfg(feature = "net-sim")]
+    Simulated(Box<SwarmCommand>),
+    /// Fired by a relay backoff timer, tagged with the retry generation that scheduled it.
+    RetryRelay(u32)
 }
\ No newline at end of file
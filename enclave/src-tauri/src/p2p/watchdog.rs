@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use crate::p2p::channel::EventSender;
+use crate::p2p::types::P2PEvent;
+
+/// How often the event loop beats even when there is no swarm activity.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+pub const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+pub const WATCHDOG_STALL_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// When the event loop last iterated, as milliseconds since the heartbeat was created. Kept
+/// relative to an `Instant` so wall-clock adjustments can't fake or hide a stall.
+#[derive(Clone)]
+pub struct Heartbeat {
+    start: Instant,
+    last_beat: Arc<AtomicU64>
+}
+
+impl Heartbeat {
+    pub fn new(start: Instant) -> Self {
+        Self { start, last_beat: Arc::new(AtomicU64::new(0)) }
+    }
+
+    pub fn beat(&self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.start).as_millis() as u64;
+        self.last_beat.store(elapsed, Ordering::Relaxed);
+    }
+
+    pub fn silent_for(&self, now: Instant) -> Duration {
+        let last_beat = self.start + Duration::from_millis(self.last_beat.load(Ordering::Relaxed));
+        now.saturating_duration_since(last_beat)
+    }
+}
+
+pub struct Watchdog {
+    heartbeat: Heartbeat,
+    threshold: Duration,
+    stalled: bool
+}
+
+impl Watchdog {
+    pub fn new(heartbeat: Heartbeat, threshold: Duration) -> Self {
+        Self { heartbeat, threshold, stalled: false }
+    }
+
+    /// Returns how long the loop has been silent the first time it crosses the threshold.
+    /// Further checks stay quiet until the heartbeat resumes.
+    pub fn check(&mut self, now: Instant) -> Option<Duration> {
+        let silent_for = self.heartbeat.silent_for(now);
+
+        if silent_for <= self.threshold {
+            self.stalled = false;
+            return None;
+        }
+
+        if self.stalled {
+            return None;
+        }
+
+        self.stalled = true;
+        Some(silent_for)
+    }
+}
+
+/// Stops the watchdog when dropped, so it goes away with the event loop that owns it.
+pub struct WatchdogHandle(JoinHandle<()>);
+
+impl Drop for WatchdogHandle {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+pub fn spawn_watchdog(heartbeat: Heartbeat, event_sender: EventSender) -> WatchdogHandle {
+    WatchdogHandle(tokio::spawn(async move {
+        let mut watchdog = Watchdog::new(heartbeat, WATCHDOG_STALL_THRESHOLD);
+        let mut interval = tokio::time::interval(WATCHDOG_CHECK_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Some(silent_for) = watchdog.check(Instant::now()) {
+                log::error!("P2P event loop has not responded for {}s", silent_for.as_secs());
+                let _ = event_sender.send(P2PEvent::NodeStalled { silent_for_secs: silent_for.as_secs() });
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_watchdog_ignores_recent_heartbeat() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(Heartbeat::new(start), Duration::from_secs(30));
+
+        assert_eq!(watchdog.check(start), None);
+        assert_eq!(watchdog.check(start + Duration::from_secs(30)), None);
+    }
+
+    #[test]
+    pub fn test_watchdog_reports_stale_heartbeat_once() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(Heartbeat::new(start), Duration::from_secs(30));
+
+        assert_eq!(watchdog.check(start + Duration::from_millis(30_001)), Some(Duration::from_millis(30_001)));
+        assert_eq!(watchdog.check(start + Duration::from_secs(59)), None);
+    }
+
+    #[test]
+    pub fn test_watchdog_rearms_after_heartbeat_resumes() {
+        let start = Instant::now();
+        let heartbeat = Heartbeat::new(start);
+        let mut watchdog = Watchdog::new(heartbeat.clone(), Duration::from_secs(30));
+
+        assert!(watchdog.check(start + Duration::from_secs(39)).is_some());
+
+        heartbeat.beat(start + Duration::from_secs(40));
+        assert_eq!(watchdog.check(start + Duration::from_secs(41)), None);
+
+        assert!(watchdog.check(start + Duration::from_secs(79)).is_some());
+    }
+
+    #[tokio::test]
+    pub async fn test_watchdog_stops_when_handle_is_dropped() {
+        let (event_sender, _event_receiver) = crate::p2p::channel::event_channel(8);
+        let heartbeat = Heartbeat::new(Instant::now());
+
+        let handle = spawn_watchdog(heartbeat.clone(), event_sender);
+        tokio::task::yield_now().await;
+        assert_eq!(Arc::strong_count(&heartbeat.last_beat), 2);
+
+        drop(handle);
+        tokio::task::yield_now().await;
+        assert_eq!(Arc::strong_count(&heartbeat.last_beat), 1);
+    }
+}
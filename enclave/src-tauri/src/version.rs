@@ -0,0 +1,40 @@
+use serde::Serialize;
+use crate::p2p::protocol::PROTOCOL_VERSIONS;
+
+/// Versions shown on the About screen and attached to bug reports.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    pub version: String,
+    /// The newest request-response protocol this build speaks.
+    pub protocol_version: String,
+    /// Only known when built from a git checkout.
+    pub git_commit: Option<String>,
+    /// Unix timestamp (seconds) of the build.
+    pub build_timestamp: Option<i64>
+}
+
+pub fn version_info() -> VersionInfo {
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSIONS[PROTOCOL_VERSIONS.len() - 1].to_string(),
+        git_commit: option_env!("ENCLAVE_GIT_COMMIT").map(str::to_string),
+        build_timestamp: option_env!("ENCLAVE_BUILD_TIMESTAMP").and_then(|timestamp| timestamp.parse().ok())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_version_info_reports_semver_crate_version() {
+        let info = version_info();
+
+        let parts = info.version.split('.').collect::<Vec<&str>>();
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())));
+
+        assert_eq!(info.protocol_version, "/enclave/1.1.0");
+    }
+}
@@ -1,17 +1,204 @@
 export interface Post {
+    id: number;
     authorPeerId: string;
     content: string;
     createdAt: number;
     editedAt: number;
 }
 
+export interface PostWithAuthor extends Post {
+    authorDisplayName: string;
+}
+
 export interface DirectMessage {
+    id: number;
     fromPeerId: string;
     toPeerId: string;
     content: string;
     createdAt: number;
     editedAt: number;
     read: boolean;
+    contentType: ContentType;
+}
+
+export interface ConversationStorage {
+    peerId: string;
+    messageCount: number;
+    estimatedBytes: number;
+}
+
+export interface MessageActivity {
+    day: string;
+    count: number;
+}
+
+export interface Draft {
+    peerId: string;
+    content: string;
+    updatedAt: number;
+}
+
+export interface DeadLetter {
+    id: number;
+    fromPeerId: string;
+    toPeerId: string;
+    content: string;
+    createdAt: number;
+    failedAt: number;
+    reason: string;
+}
+
+export interface QuarantinedMessage {
+    id: number;
+    fromPeerId: string;
+    content: string;
+    receivedAt: number;
+}
+
+export interface KeyChange {
+    peerId: string;
+    newPeerId: string;
+    detectedAt: number;
+}
+
+export type ContentType = 'text' | 'image_ref' | 'system';
+
+export type DeliveryStatus = 'pending' | 'delivered' | 'received';
+
+export interface ConversationSummary {
+    peerId: string;
+    lastMessage: string;
+    lastMessageLength: number;
+    lastMessageAt: number;
+    fromMe: boolean;
+    unread: boolean;
+    unreadCount: number;
+    status: DeliveryStatus;
+}
+
+export interface EncryptionStatus {
+    encrypted: boolean;
+    reason: string | null;
+}
+
+export type ConnectionType = 'Direct' | 'Relayed';
+
+export type ConnectionPolicy = 'Open' | 'FriendsOnly';
+
+export type HolepunchStatus =
+    | { status: 'notAttempted' }
+    | { status: 'pending' }
+    | { status: 'upgraded' }
+    | { status: 'failed'; error: string };
+
+export type RelayStatus =
+    | { status: 'connecting'; attempt: number }
+    | { status: 'connected' }
+    | { status: 'retrying'; attempt: number; delaySecs: number }
+    | { status: 'failed' };
+
+export interface RelayInfo {
+    address: string;
+    peerId: string | null;
+    connected: boolean;
+    status: RelayStatus | null;
+    reservationAccepted: boolean;
+}
+
+export interface ExplicitPeersResync {
+    added: string[];
+    removed: string[];
+}
+
+export interface SessionStats {
+    directMessagesSent: number;
+    directMessagesReceived: number;
+    friendRequestsHandled: number;
+    bytesSent: number;
+    bytesReceived: number;
+    postsSynced: number;
+}
+
+export type AddressProbeStatus = "reachable" | "unreachable";
+
+export interface AddressProbeResult {
+    status: AddressProbeStatus;
+    elapsedMs: number;
+    error: string | null;
+}
+
+export interface NetworkConfigInfo {
+    namespace: string;
+    postsTopic: string;
+    port: number;
+    gossipsubHeartbeatMs: number;
+    pingIntervalMs: number;
+    pingTimeoutMs: number;
+    eventChannelCapacity: number;
+    commandChannelCapacity: number;
+    directMessageRateLimit: number;
+    directMessageRateWindowMs: number;
+    listenAddresses: string[];
+    relayAddresses: string[];
+    connectionPolicy: ConnectionPolicy;
+}
+
+export interface NetworkStats {
+    connectedPeers: number;
+    topicSubscribers: Record<string, number>;
+    unsubscribedFriends: string[];
+}
+
+export interface VersionInfo {
+    version: string;
+    protocolVersion: string;
+    gitCommit: string | null;
+    buildTimestamp: number | null;
+}
+
+export interface LogFilesSummary {
+    files: number;
+    bytes: number;
+}
+
+export interface FriendSummary {
+    peerId: string;
+    nickname: string | null;
+    online: boolean;
+}
+
+export interface FriendProfile {
+    peerId: string;
+    nickname: string | null;
+    online: boolean;
+    unreadCount: number;
+    lastMessage: ConversationSummary | null;
+    appearance: PeerAppearance;
+}
+
+export interface PeerAppearance {
+    avatar: string | null;
+    color: string | null;
+}
+
+export interface PendingWork {
+    undeliveredMessages: number;
+    bufferedPosts: number;
+    pendingFriendRequests: number;
+    activeSyncs: number;
+}
+
+export interface InitialState {
+    identity: IdentityInfo;
+    friends: FriendSummary[];
+    inboundFriendRequests: FriendRequest[];
+    conversations: ConversationSummary[];
+}
+
+export interface StartupReport {
+    identityConsistent: boolean;
+    danglingFriendsRemoved: number;
+    issues: string[];
 }
 
 export interface NodeInfo {
@@ -20,10 +207,30 @@ export interface NodeInfo {
     isFriend: boolean;
 }
 
+export interface IdentityInfo {
+    peerId: string;
+    createdAt: number;
+    accountAgeSecs: number;
+}
+
 export interface FriendRequest {
     fromPeerId: string;
     fromMultiaddr: string;
     message: string;
+    seen: boolean;
+    addresses: string[];
+}
+
+export type FriendRequestOutcome = "accepted" | "denied";
+
+export interface FriendRequestHistoryEntry {
+    id: number;
+    fromPeerId: string;
+    toPeerId: string;
+    message: string;
+    outcome: FriendRequestOutcome;
+    requestedAt: number;
+    resolvedAt: number;
 }
 
 export interface AppState {
@@ -53,4 +260,4 @@ export interface AppState {
     directMessageInput: string;
     openBoardPeerId: string | null;
     posts: Post[];
-}
\ No newline at end of file
+}